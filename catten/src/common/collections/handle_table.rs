@@ -0,0 +1,217 @@
+//! # Handle Table
+//!
+//! A fixed capacity table of shared, lockable objects identified by handles. A handle combines the
+//! index of the object's slot with the generation of the slot, which is advanced each time an
+//! object is removed from it. A stale handle therefore never refers to the object that reuses its
+//! slot. No handle is ever 0, so 0 can stand for an object that is kept outside of a table.
+//!
+//! Lookups take no locks. The table's reference to a removed object is only dropped after an RCU
+//! grace period, so a lookup that found the object before it was removed can still take its own
+//! reference. Inserting and removing objects is serialized.
+
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicPtr, AtomicU32, AtomicUsize, Ordering};
+
+use crate::cpu::scheduler::sync::lockdep::RwLock;
+use crate::cpu::scheduler::sync::rcu::{call_rcu, rcu_read_lock};
+use crate::cpu::scheduler::sync::spinlock::SpinLock;
+
+pub type Handle = usize;
+
+/// The number of low handle bits that hold the slot index
+const INDEX_BITS: u32 = 32;
+const INDEX_MASK: usize = (1 << INDEX_BITS) - 1;
+/// The largest number of slots a table can have
+pub const MAX_CAPACITY: usize = INDEX_MASK + 1;
+
+/// The table has no free slot left. Holds the object that could not be inserted.
+pub struct Full<T>(pub T);
+
+impl<T> core::fmt::Debug for Full<T> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str("Full")
+    }
+}
+
+struct Slot<T> {
+    /// Starts at 1 and is advanced when the object is removed
+    generation: AtomicU32,
+    /// A pointer from `Arc::into_raw` or null if the slot is free
+    object: AtomicPtr<RwLock<T>>,
+}
+
+struct FreeSlots {
+    /// Slots that held an object before
+    indices: Vec<u32>,
+    /// The slots from here on have never been used
+    next_unused: usize,
+}
+
+pub struct HandleTable<T> {
+    slots: Box<[Slot<T>]>,
+    free: SpinLock<FreeSlots>,
+    len: AtomicUsize,
+}
+
+// SAFETY: The objects are shared through `Arc<RwLock<T>>`s, which are `Send` and `Sync` if `T` is
+// `Send` and `Sync`.
+unsafe impl<T: Send + Sync> Send for HandleTable<T> {}
+unsafe impl<T: Send + Sync> Sync for HandleTable<T> {}
+
+/// The table's reference to a removed object waiting for the end of a grace period
+struct Retired<T>(*const RwLock<T>);
+
+// SAFETY: See the `Send` implementation of the table.
+unsafe impl<T: Send + Sync> Send for Retired<T> {}
+
+fn make_handle(index: usize, generation: u32) -> Handle {
+    (generation as usize) << INDEX_BITS | index
+}
+
+fn split_handle(handle: Handle) -> (usize, u32) {
+    (handle & INDEX_MASK, (handle >> INDEX_BITS) as u32)
+}
+
+impl<T: Send + Sync + 'static> HandleTable<T> {
+    /// Create a table with room for `capacity` objects, clamped to `MAX_CAPACITY`
+    pub fn new(capacity: usize) -> Self {
+        let slots = (0..capacity.min(MAX_CAPACITY))
+            .map(|_| Slot {
+                generation: AtomicU32::new(1),
+                object: AtomicPtr::new(core::ptr::null_mut()),
+            })
+            .collect();
+        HandleTable {
+            slots,
+            free: SpinLock::new(FreeSlots {
+                indices: Vec::new(),
+                next_unused: 0,
+            }),
+            len: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.slots.len()
+    }
+
+    pub fn len(&self) -> usize {
+        self.len.load(Ordering::Relaxed)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Add an object and return its handle
+    #[track_caller]
+    pub fn insert(&self, object: T) -> Result<Handle, Full<T>> {
+        let mut free = self.free.lock();
+        let index = match free.indices.pop() {
+            Some(index) => index as usize,
+            None if free.next_unused < self.slots.len() => {
+                free.next_unused += 1;
+                free.next_unused - 1
+            }
+            None => return Err(Full(object)),
+        };
+        let slot = &self.slots[index];
+        // the generation was advanced when the previous object was removed
+        let generation = slot.generation.load(Ordering::SeqCst);
+        slot.object
+            .store(Arc::into_raw(Arc::new(RwLock::new(object))).cast_mut(), Ordering::SeqCst);
+        self.len.fetch_add(1, Ordering::Relaxed);
+        Ok(make_handle(index, generation))
+    }
+
+    /// The object the handle refers to or `None` if it has been removed
+    pub fn get(&self, handle: Handle) -> Option<Arc<RwLock<T>>> {
+        let (index, generation) = split_handle(handle);
+        self.get_slot(index, generation)
+    }
+
+    fn get_slot(&self, index: usize, generation: u32) -> Option<Arc<RwLock<T>>> {
+        let slot = self.slots.get(index)?;
+        let _guard = rcu_read_lock();
+        if slot.generation.load(Ordering::SeqCst) != generation {
+            return None;
+        }
+        let object = slot.object.load(Ordering::SeqCst);
+        // the slot may have been reused since its generation was checked
+        if object.is_null() || slot.generation.load(Ordering::SeqCst) != generation {
+            return None;
+        }
+        // SAFETY: The table's reference is only dropped after a grace period, which the read
+        // guard delays.
+        unsafe {
+            Arc::increment_strong_count(object);
+            Some(Arc::from_raw(object))
+        }
+    }
+
+    pub fn contains(&self, handle: Handle) -> bool {
+        self.get(handle).is_some()
+    }
+
+    /// Remove the object the handle refers to. Returns whether there was one.
+    ///
+    /// The object is released after an RCU grace period through `call_rcu`, so this must be called
+    /// from thread context.
+    pub fn remove(&self, handle: Handle) -> bool {
+        let (index, generation) = split_handle(handle);
+        let Some(slot) = self.slots.get(index) else {
+            return false;
+        };
+        let mut free = self.free.lock();
+        if slot.generation.load(Ordering::SeqCst) != generation
+            || slot.object.load(Ordering::SeqCst).is_null()
+        {
+            return false;
+        }
+        // lookups stop accepting the handle before the slot is emptied
+        let next_generation = generation.wrapping_add(1);
+        slot.generation.store(next_generation, Ordering::SeqCst);
+        let object = slot.object.swap(core::ptr::null_mut(), Ordering::SeqCst);
+        // a slot whose generations have run out is never used again
+        if next_generation != 0 {
+            free.indices.push(index as u32);
+        }
+        drop(free);
+        self.len.fetch_sub(1, Ordering::Relaxed);
+        let retired = Retired(object);
+        call_rcu(Box::new(move || {
+            let retired = retired;
+            // SAFETY: No lookup can still be taking a reference from the slot.
+            drop(unsafe { Arc::from_raw(retired.0) });
+        }));
+        true
+    }
+
+    /// The handles and objects in the table. Objects that are inserted or removed while
+    /// iterating may or may not be included.
+    pub fn iter(&self) -> impl Iterator<Item = (Handle, Arc<RwLock<T>>)> + '_ {
+        self.slots.iter().enumerate().filter_map(|(index, slot)| {
+            let generation = slot.generation.load(Ordering::SeqCst);
+            self.get_slot(index, generation).map(|object| (make_handle(index, generation), object))
+        })
+    }
+
+    /// The handles of all objects currently in the table
+    pub fn handles(&self) -> Vec<Handle> {
+        self.iter().map(|(handle, _)| handle).collect()
+    }
+}
+
+impl<T> Drop for HandleTable<T> {
+    fn drop(&mut self) {
+        for slot in self.slots.iter_mut() {
+            let object = *slot.object.get_mut();
+            if !object.is_null() {
+                // SAFETY: A table that is being dropped can not have any readers left.
+                drop(unsafe { Arc::from_raw(object) });
+            }
+        }
+    }
+}
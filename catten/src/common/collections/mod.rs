@@ -1,3 +1,3 @@
 pub mod boxed_slice;
 pub mod circular_queue;
-pub mod id_table;
+pub mod handle_table;
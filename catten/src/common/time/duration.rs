@@ -1,6 +1,6 @@
 use crate::common::constants::{MICROS_PER_SEC, MILLIS_PER_SEC, NANOS_PER_SEC, PICOS_PER_SEC};
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
 pub struct ExtDuration {
     picos: u128,
 }
@@ -55,6 +55,42 @@ impl ExtDuration {
     pub fn as_picos(&self) -> u128 {
         self.picos
     }
+
+    pub fn saturating_sub(self, rhs: Self) -> Self {
+        ExtDuration {
+            picos: self.picos.saturating_sub(rhs.picos),
+        }
+    }
+}
+
+impl core::ops::Add for ExtDuration {
+    type Output = ExtDuration;
+
+    fn add(self, rhs: Self) -> Self::Output {
+        ExtDuration {
+            picos: self.picos + rhs.picos,
+        }
+    }
+}
+
+impl core::ops::Sub for ExtDuration {
+    type Output = ExtDuration;
+
+    fn sub(self, rhs: Self) -> Self::Output {
+        ExtDuration {
+            picos: self.picos - rhs.picos,
+        }
+    }
+}
+
+impl core::ops::Mul<u128> for ExtDuration {
+    type Output = ExtDuration;
+
+    fn mul(self, rhs: u128) -> Self::Output {
+        ExtDuration {
+            picos: self.picos * rhs,
+        }
+    }
 }
 
 impl core::ops::Div for ExtDuration {
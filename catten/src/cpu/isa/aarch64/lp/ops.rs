@@ -79,3 +79,57 @@ pub fn get_lp_local_base() -> crate::memory::VAddr {
     }
     crate::memory::VAddr::from(addr)
 }
+
+/// Whether IRQs are unmasked on the calling LP
+pub fn are_interrupts_enabled() -> bool {
+    const DAIF_I: u64 = 1 << 7;
+    let daif: u64;
+    unsafe {
+        core::arch::asm!(
+            "mrs {daif}, daif",
+            daif = out(reg) daif,
+            options(nomem, nostack, preserves_flags)
+        );
+    }
+    daif & DAIF_I == 0
+}
+
+/// The frame pointer of the caller's frame. Frame records start with the previous frame pointer,
+/// followed by the return address.
+#[inline(always)]
+pub fn get_frame_pointer() -> usize {
+    let fp: usize;
+    unsafe {
+        core::arch::asm!(
+            "mov {fp}, x29",
+            fp = out(reg) fp,
+            options(nomem, nostack, preserves_flags)
+        );
+    }
+    fp
+}
+
+/// Run `f` with interrupts masked on the calling LP, restoring the previous state afterwards.
+pub fn without_interrupts<R>(f: impl FnOnce() -> R) -> R {
+    let daif: u64;
+    unsafe {
+        core::arch::asm!(
+            "mrs {daif}, daif",
+            "msr daifset, 0b0010",
+            daif = out(reg) daif,
+            options(nostack, preserves_flags)
+        );
+    }
+    let result = f();
+    unsafe {
+        core::arch::asm!("msr daif, {daif}", daif = in(reg) daif, options(nostack));
+    }
+    result
+}
+
+/// Entry point of the idle context an LP runs while it has no thread to execute.
+pub extern "C" fn idle_lp() -> ! {
+    loop {
+        unsafe { core::arch::asm!("wfi", options(nomem, nostack, preserves_flags)) }
+    }
+}
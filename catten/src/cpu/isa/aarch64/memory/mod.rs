@@ -7,7 +7,12 @@ use address::paddr::PAddr;
 use address::vaddr::VAddr;
 
 use crate::cpu::isa::interface::memory::address::{Address, PhysicalAddress, VirtualAddress};
-use crate::cpu::isa::interface::memory::{AddressSpaceInterface, MemoryInterface, MemoryMapping};
+use crate::cpu::isa::interface::memory::{
+    AddressSpaceInterface,
+    MappedRange,
+    MemoryInterface,
+    MemoryMapping,
+};
 
 pub struct MemoryInterfaceImpl;
 
@@ -43,6 +48,10 @@ impl AddressSpaceInterface for AddressSpace {
         }
     }
 
+    fn is_active(&self) -> bool {
+        todo!()
+    }
+
     fn load(&self) -> Result<(), <MemoryInterfaceImpl as MemoryInterface>::Error> {
         unsafe {
             asm!("msr ttbr0_el1, {}", in(reg) self.ttbr0_el1);
@@ -116,6 +125,57 @@ impl AddressSpaceInterface for AddressSpace {
             ))
         }
     }
+
+    fn test_and_clear_accessed(
+        &mut self,
+        vaddr: VAddr,
+    ) -> Result<bool, <MemoryInterfaceImpl as MemoryInterface>::Error> {
+        todo!()
+    }
+
+    fn is_dirty(
+        &mut self,
+        vaddr: VAddr,
+    ) -> Result<bool, <MemoryInterfaceImpl as MemoryInterface>::Error> {
+        todo!()
+    }
+
+    fn swap_out_page(
+        &mut self,
+        vaddr: VAddr,
+        swap_slot: usize,
+    ) -> Result<PAddr, <MemoryInterfaceImpl as MemoryInterface>::Error> {
+        todo!()
+    }
+
+    fn get_swap_slot(
+        &mut self,
+        vaddr: VAddr,
+    ) -> Result<Option<usize>, <MemoryInterfaceImpl as MemoryInterface>::Error> {
+        todo!()
+    }
+
+    fn set_page_writable(
+        &mut self,
+        vaddr: VAddr,
+        writable: bool,
+    ) -> Result<bool, <MemoryInterfaceImpl as MemoryInterface>::Error> {
+        todo!()
+    }
+
+    fn remap_page(
+        &mut self,
+        vaddr: VAddr,
+        frame: PAddr,
+    ) -> Result<PAddr, <MemoryInterfaceImpl as MemoryInterface>::Error> {
+        todo!()
+    }
+
+    fn mappings(&self) -> impl Iterator<Item = MappedRange> + '_ {
+        todo!();
+        #[allow(unreachable_code)]
+        core::iter::empty()
+    }
 }
 
 const PAR_EL1_PADDR_MASK: u64 = 0x0000fffffffff000;
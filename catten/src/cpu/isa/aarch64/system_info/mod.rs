@@ -4,7 +4,7 @@ use core::fmt::Display;
 
 use isa_extensions::*;
 
-use crate::cpu::isa::interface::system_info::CpuInfoIfce;
+use crate::cpu::isa::interface::system_info::{CpuInfoIfce, LpTopology};
 
 #[derive(Debug)]
 pub enum Vendor {
@@ -183,4 +183,30 @@ impl CpuInfoIfce for CpuInfo {
             IsaExtension::FeatNMI => check_feat::nmi(),
         }
     }
+
+    fn get_local_topology() -> LpTopology {
+        let mut mpidr: u64;
+        unsafe {
+            core::arch::asm!("mrs {}, mpidr_el1", out(reg) mpidr);
+        }
+        // Aff3 moved down next to Aff2
+        let affinity = ((mpidr & 0xff_ffff) | ((mpidr >> 8) & 0xff00_0000)) as u32;
+        // with multithreading Aff0 identifies the thread within its core
+        let is_multithreaded = mpidr & (1 << 24) != 0;
+        let core_id = if is_multithreaded {
+            affinity >> 8
+        } else {
+            affinity
+        };
+        LpTopology {
+            core_id,
+            // assume that the LPs of a cluster share the last level cache
+            llc_id: if is_multithreaded {
+                affinity >> 16
+            } else {
+                affinity >> 8
+            },
+            numa_node: 0,
+        }
+    }
 }
@@ -11,8 +11,14 @@ pub trait InterruptManagerIfce {
 /// # Local Interrupt Controller Interface
 pub trait LocalIntCtlrIfce {
     type Error;
+    /// Enable the calling logical processor's local interrupt controller and make it reachable by
+    /// inter-processor interrupts. The kernel heap must be initialized before this is called.
+    fn init_local();
     /// Send an inter-processor interrupt to the specified logical processor
     fn send_unicast_ipi(target_lp: LpId) -> Result<(), Self::Error>;
+    /// Send the inter-processor interrupt that makes a halted logical processor pick up work from
+    /// its local scheduler
+    fn send_wake_lp_ipi(target_lp: LpId) -> Result<(), Self::Error>;
     /// Signal End of Interrupt
     fn signal_eoi();
 }
@@ -3,7 +3,7 @@ pub mod address;
 use crate::cpu::isa::memory::MemoryInterfaceImpl;
 use crate::cpu::isa::memory::address::paddr::PAddr;
 use crate::cpu::isa::memory::address::vaddr::VAddr;
-pub use crate::memory::linear::{MemoryMapping, PageType};
+pub use crate::memory::linear::{MappedRange, MemoryMapping, PageTableFrame, PageType};
 
 pub trait MemoryInterface {
     type VAddr: address::VirtualAddress;
@@ -14,8 +14,13 @@ pub trait MemoryInterface {
     const PAGE_SIZE: usize;
 }
 
+/// Methods that change or remove a mapping leave the TLBs alone. The caller invalidates the
+/// affected translations on every LP with `ipi::flush_tlb` before the frame that was mapped can be
+/// reused.
 pub trait AddressSpaceInterface {
     fn get_current() -> Self;
+    /// Whether this address space is the one currently loaded on the calling LP
+    fn is_active(&self) -> bool;
     fn load(&self) -> Result<(), <MemoryInterfaceImpl as MemoryInterface>::Error>;
     fn find_free_region(
         &mut self,
@@ -38,4 +43,52 @@ pub trait AddressSpaceInterface {
         &mut self,
         vaddr: VAddr,
     ) -> Result<PAddr, <MemoryInterfaceImpl as MemoryInterface>::Error>;
+    /// Read and clear the accessed flag of the page mapped at `vaddr`.
+    fn test_and_clear_accessed(
+        &mut self,
+        vaddr: VAddr,
+    ) -> Result<bool, <MemoryInterfaceImpl as MemoryInterface>::Error>;
+    /// Whether the page mapped at `vaddr` has been written to since it was mapped.
+    fn is_dirty(
+        &mut self,
+        vaddr: VAddr,
+    ) -> Result<bool, <MemoryInterfaceImpl as MemoryInterface>::Error>;
+    /// Replace the mapping of `vaddr` with a non-present entry recording `swap_slot` and return
+    /// the frame that was mapped there. The page tables backing the entry are kept alive until it
+    /// is replaced by a regular mapping again.
+    fn swap_out_page(
+        &mut self,
+        vaddr: VAddr,
+        swap_slot: usize,
+    ) -> Result<PAddr, <MemoryInterfaceImpl as MemoryInterface>::Error>;
+    /// The swap slot recorded for `vaddr` by `swap_out_page` if the page is currently swapped out.
+    fn get_swap_slot(
+        &mut self,
+        vaddr: VAddr,
+    ) -> Result<Option<usize>, <MemoryInterfaceImpl as MemoryInterface>::Error>;
+    /// Grant or revoke write access to the page mapped at `vaddr` and return whether it was
+    /// writable before.
+    fn set_page_writable(
+        &mut self,
+        vaddr: VAddr,
+        writable: bool,
+    ) -> Result<bool, <MemoryInterfaceImpl as MemoryInterface>::Error>;
+    /// Point the mapping of `vaddr` at `frame` while keeping all of its attributes and return the
+    /// frame that was mapped there before.
+    fn remap_page(
+        &mut self,
+        vaddr: VAddr,
+        frame: PAddr,
+    ) -> Result<PAddr, <MemoryInterfaceImpl as MemoryInterface>::Error>;
+    /// Iterate over every present mapping in ascending virtual address order with contiguous
+    /// mappings of identical page size and attributes coalesced into a single range.
+    ///
+    /// Only leaf mappings are reported. Tearing down or cloning an address space also has to free
+    /// or copy the tables that hold them, which `page_tables` reports.
+    fn mappings(&self) -> impl Iterator<Item = MappedRange> + '_;
+    /// Iterate over the frames of every page table of the address space, including the top level
+    /// one. Each table is reported after all of the tables below it, so a teardown can free it as
+    /// soon as it is reported. The tables that map the kernel half are shared by all address
+    /// spaces and must be left alone.
+    fn page_tables(&self) -> impl Iterator<Item = PageTableFrame> + '_;
 }
@@ -3,6 +3,17 @@ pub enum Error {
     UnableToDetermine,
 }
 
+/// Where an LP sits in the processor topology. LPs that have the same ID at some level share the
+/// resources of that level.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct LpTopology {
+    /// LPs on the same core are SMT siblings
+    pub core_id: u32,
+    /// LPs that share the last level cache
+    pub llc_id: u32,
+    pub numa_node: u32,
+}
+
 pub trait CpuInfoIfce {
     type IsaExtension;
     type Vendor;
@@ -13,4 +24,6 @@ pub trait CpuInfoIfce {
     fn get_vaddr_sig_bits() -> u8;
     fn get_paddr_sig_bits() -> u8;
     fn is_extension_supported(extension: Self::IsaExtension) -> bool;
+    /// The topology of the calling LP
+    fn get_local_topology() -> LpTopology;
 }
@@ -15,6 +15,11 @@ pub trait LpTimerIfce {
 
     const NAME: &'static str;
 
+    /// Create the calling LP's timer and route its expiry to the context switch interrupt
+    fn new_preemption_timer() -> Self
+    where
+        Self: Sized;
+
     type Divisor;
     type TickCount;
     type IntDispatchNum;
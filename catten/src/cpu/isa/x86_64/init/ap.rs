@@ -5,8 +5,10 @@ use spin::lazy::Lazy;
 
 use super::{INTERRUPT_STACK_SIZE, gdt};
 use crate::cpu::isa::interrupts::idt::{Idt, asm_load_idt};
-use crate::cpu::isa::lp::ops::get_lp_id;
+use crate::cpu::isa::lp::ops::{enable_fsgsbase, get_lp_id};
+use crate::cpu::isa::lp::xstate;
 use crate::cpu::multiprocessor::get_lp_count;
+use crate::cpu::scheduler::sync::lockdep::Mutex;
 use crate::logln;
 
 static AP_INTERRUPT_STACKS: Lazy<Vec<[u8; INTERRUPT_STACK_SIZE]>> = Lazy::new(|| {
@@ -69,6 +71,7 @@ pub static AP_IDTS: Lazy<Vec<crate::cpu::isa::interrupts::idt::Idt>> = Lazy::new
         let mut idt = crate::cpu::isa::interrupts::idt::Idt::new();
         logln!("LP{}: Registering fixed interrupt gates.", (get_lp_id()));
         crate::cpu::isa::interrupts::fixed::register_fixed_isr_gates(&mut idt);
+        crate::cpu::isa::interrupts::irq::register_irq_gates(&mut idt);
         logln!("LP{}: Pushing the initialized IDT to the vector.", (get_lp_id()));
         idts.push(idt);
     }
@@ -91,9 +94,9 @@ pub static AP_IDTRS: Lazy<Vec<crate::cpu::isa::interrupts::idt::Idtr>> = Lazy::n
     idtrs
 });
 
-pub static INIT_MUTEX: Lazy<spin::Mutex<()>> = Lazy::new(|| {
+pub static INIT_MUTEX: Lazy<Mutex<()>> = Lazy::new(|| {
     logln!("LP{}: Creating the AP init mutex.", (get_lp_id()));
-    spin::Mutex::new(())
+    Mutex::new(())
 });
 
 pub fn init_ap() {
@@ -111,5 +114,7 @@ pub fn init_ap() {
         gdt::reload_segment_regs();
     }
     unsafe { asm_load_idt(&raw const AP_IDTRS[ap_index]) };
+    enable_fsgsbase();
+    xstate::init_local();
     crate::logln!("AP{}: x86-64 logical processor initialization complete", lp_id);
 }
@@ -4,6 +4,9 @@ use super::INTERRUPT_STACK_SIZE;
 use super::gdt::*;
 use crate::cpu::isa::interrupts::fixed::register_fixed_isr_gates;
 use crate::cpu::isa::interrupts::idt::Idt;
+use crate::cpu::isa::interrupts::irq::register_irq_gates;
+use crate::cpu::isa::lp::ops::enable_fsgsbase;
+use crate::cpu::isa::lp::xstate;
 use crate::logln;
 
 static mut BSP_INTERRUPT_STACK: [u8; INTERRUPT_STACK_SIZE] = [0u8; INTERRUPT_STACK_SIZE];
@@ -15,6 +18,7 @@ static BSP_GDT: Lazy<Gdt> = Lazy::new(|| Gdt::new(&BSP_TSS));
 pub static BSP_IDT: Lazy<Idt> = Lazy::new(|| {
     let mut idt = Idt::new();
     register_fixed_isr_gates(&mut idt);
+    register_irq_gates(&mut idt);
     idt
 });
 
@@ -24,5 +28,7 @@ pub fn init_bsp() {
         reload_segment_regs();
     }
     BSP_IDT.load();
+    enable_fsgsbase();
+    xstate::init_local();
     logln!("BSP: x86-64 logical processor initialization complete");
 }
@@ -2,6 +2,10 @@
 
 .global isr_context_switch
 isr_context_switch:
+    test qword ptr [rsp + 8], 3  # Swap in the kernel GS base if the outgoing thread was in user mode
+    jz 1f
+    swapgs
+1:
     push rax
     push rbx
     push rcx
@@ -17,9 +21,11 @@ isr_context_switch:
     push r13
     push r14
     push r15
-    mov fs:[0], rsp  # Save current stack pointer to the current thread's context
-    call set_next_thread  # Call scheduler to get the next thread and set fs:[0] to its context base
-    mov rsp, fs:[0]  # Load the next thread's stack pointer from its context
+    mov rax, gs:[0]  # Load the current thread's context pointer from the LP's local area
+    mov [rax], rsp  # Save current stack pointer to the current thread's context
+    call ih_context_switch  # Signal EOI then set gs:[0] to the context base of the next thread
+    mov rax, gs:[0]
+    mov rsp, [rax]  # Load the next thread's stack pointer from its context
     pop r15
     pop r14
     pop r13
@@ -35,12 +41,38 @@ isr_context_switch:
     pop rcx
     pop rbx
     pop rax
+    test qword ptr [rsp + 8], 3  # Swap the user GS base back in if the next thread is in user mode
+    jz 2f
+    swapgs
+2:
     iretq
 
 .global isr_wake_lp
 isr_wake_lp:
-    call set_next_thread  # Call scheduler to get the next thread and set fs:[0] to its context base
-    mov rsp, fs:[0]  # Load the next thread's stack pointer from its context
+    test qword ptr [rsp + 8], 3  # Swap in the kernel GS base if the outgoing thread was in user mode
+    jz 3f
+    swapgs
+3:
+    push rax
+    push rbx
+    push rcx
+    push rdx
+    push rsi
+    push rdi
+    push rbp
+    push r8
+    push r9
+    push r10
+    push r11
+    push r12
+    push r13
+    push r14
+    push r15
+    mov rax, gs:[0]  # Load the current thread's context pointer from the LP's local area
+    mov [rax], rsp  # Save current stack pointer to the current thread's context
+    call ih_wake_lp  # Signal EOI then set gs:[0] to the context base of the next thread
+    mov rax, gs:[0]
+    mov rsp, [rax]  # Load the next thread's stack pointer from its context
     pop r15
     pop r14
     pop r13
@@ -56,4 +88,8 @@ isr_wake_lp:
     pop rcx
     pop rbx
     pop rax
+    test qword ptr [rsp + 8], 3
+    jz 4f
+    swapgs
+4:
     iretq
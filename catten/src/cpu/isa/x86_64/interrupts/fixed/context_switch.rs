@@ -4,23 +4,91 @@ unsafe extern "custom" {
 }
 core::arch::global_asm!(include_str!("context_switch.asm"));
 
-use crate::cpu::isa::lp::ops::set_thread_context_ptr;
+use crate::cpu::isa::interface::interrupts::LocalIntCtlrIfce;
+use crate::cpu::isa::interface::memory::address::VirtualAddress;
+use crate::cpu::isa::interrupts::LocalIntCtlr;
+use crate::cpu::isa::lp::ops::{get_thread_context_ptr, set_thread_context_ptr};
+use crate::cpu::isa::lp::thread_context::ThreadContext;
+use crate::cpu::isa::lp::xstate;
+use crate::cpu::isa::timers::get_monotonic_time;
+use crate::cpu::scheduler::deferred::softirq::{self, SoftIrq};
+use crate::cpu::scheduler::lp_schedulers::SwitchReason;
+use crate::cpu::scheduler::sync::lockdep::IrqContext;
+use crate::cpu::scheduler::sync::rcu;
 use crate::cpu::scheduler::system_scheduler::SYSTEM_SCHEDULER;
+use crate::cpu::scheduler::threads::MASTER_THREAD_TABLE;
+use crate::cpu::scheduler::{idle, preemption};
 
 #[unsafe(no_mangle)]
 pub extern "C" fn check_idle_lp() -> bool {
     SYSTEM_SCHEDULER.get_local_scheduler().lock().is_idle()
 }
 
+/// Called on expiry of the LP's timer, which fires at the end of the time slice and for
+/// high-resolution timers
 #[unsafe(no_mangle)]
-pub extern "C" fn set_next_thread() {
-    let next_tid = SYSTEM_SCHEDULER.get_local_scheduler().lock().next();
+pub extern "C" fn ih_context_switch() {
+    let _irq_context = IrqContext::enter();
+    LocalIntCtlr::signal_eoi();
+    let is_slice_over =
+        SYSTEM_SCHEDULER.get_local_scheduler().lock().is_slice_over(get_monotonic_time());
+    if !is_slice_over {
+        // the running thread keeps its time slice
+        softirq::raise(SoftIrq::Timer);
+    }
+    let is_switch_requested = softirq::drain();
+    if (is_slice_over || is_switch_requested) && preemption::try_begin_switch() {
+        set_next_thread(
+            if is_slice_over {
+                SwitchReason::QuantumExpired
+            } else {
+                SwitchReason::Rescheduled
+            },
+        );
+    }
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn ih_wake_lp() {
+    let _irq_context = IrqContext::enter();
+    LocalIntCtlr::signal_eoi();
+    // the LP switches threads anyway
+    softirq::drain();
+    if preemption::try_begin_switch() {
+        set_next_thread(SwitchReason::Rescheduled);
+    }
+}
+
+/// Point the LP's local area at the context of the thread the LP switches to
+fn set_next_thread(reason: SwitchReason) {
+    let prev_context = get_thread_context_ptr().into_mut::<ThreadContext>();
+    if !prev_context.is_null() {
+        unsafe { (*prev_context).save_user_bases() };
+    }
+    unsafe { xstate::save_on_switch() };
+    SYSTEM_SCHEDULER.release_prev_thread();
+    SYSTEM_SCHEDULER.expire_timers();
+    SYSTEM_SCHEDULER.balance_lp();
+    let local_scheduler = SYSTEM_SCHEDULER.get_local_scheduler();
+    let mut local_scheduler = local_scheduler.lock();
+    // LPs queueing threads from now on find the LP's idle context no longer waiting on its flag
+    idle::note_context_switch();
+    let next_tid = local_scheduler.next(reason);
+    rcu::note_context_switch(next_tid.is_none());
+    let context_addr = match next_tid {
+        Some(tid) => {
+            let thread = MASTER_THREAD_TABLE
+                .get(tid)
+                .expect("The local scheduler picked a thread that does not exist");
+            let thread = thread.read();
+            thread.context.load_address_space();
+            unsafe { thread.get_context_vaddr() }
+        }
+        // run the idle context until a thread is submitted to this LP
+        None => local_scheduler.get_idle_context_vaddr(),
+    };
     unsafe {
-        let context_addr = crate::cpu::scheduler::threads::MASTER_THREAD_TABLE
-            .try_get_element_arc(next_tid)
-            .unwrap()
-            .read()
-            .get_context_vaddr();
+        (*context_addr.into_mut::<ThreadContext>()).load_user_bases();
         set_thread_context_ptr(context_addr);
     }
 }
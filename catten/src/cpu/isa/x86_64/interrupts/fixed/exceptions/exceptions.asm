@@ -26,9 +26,62 @@
 .extern ih_vmm_communication
 .extern ih_security_exception
 
+//Swap in the kernel GS base when entering from user mode and the user GS base back before
+//returning to it. cs_offset is the offset of the interrupted CS from rsp in the interrupt frame.
+.macro swapgs_if_user cs_offset
+	test qword ptr [rsp + \cs_offset], 3
+	jz 1f
+	swapgs
+1:
+.endm
+
+//Entry for exceptions that can interrupt the kernel while the user GS base is loaded even though
+//CS is already the kernel's, such as between entering from user mode and its swapgs. The GS base
+//is compared against the LP's local area instead and rbx is left nonzero if it was swapped in.
+//rbx is pushed first and stays pushed until paranoid_swapgs_exit since handlers preserve it.
+.macro paranoid_swapgs_entry
+	push rbx
+	push rax
+	push rcx
+	push rdx
+	xor ebx, ebx
+	// the local areas are not set up yet so no user mode code has run
+	mov rax, qword ptr [rip + LP_LOCAL_AREA_TABLE]
+	test rax, rax
+	jz 1f
+	// rdtscp leaves the LP ID from TSC_AUX in rcx
+	mov rbx, rax
+	rdtscp
+	mov rbx, qword ptr [rbx + rcx * 8]
+	// read the IA32_GS_BASE MSR
+	mov ecx, 0xc0000101
+	rdmsr
+	shl rdx, 32
+	or rax, rdx
+	cmp rax, rbx
+	mov ebx, 0
+	je 1f
+	swapgs
+	mov ebx, 1
+1:
+	pop rdx
+	pop rcx
+	pop rax
+.endm
+
+//Undo paranoid_swapgs_entry before returning from the exception
+.macro paranoid_swapgs_exit
+	test rbx, rbx
+	jz 1f
+	swapgs
+1:
+	pop rbx
+.endm
+
 //The actual ISRs
 .global isr_divide_by_zero
 isr_divide_by_zero:
+	swapgs_if_user 8
 	// save the caller saved registers
 	push rax
 	push rdi
@@ -52,10 +105,12 @@ isr_divide_by_zero:
 	pop rsi
 	pop rax
 
+	swapgs_if_user 8
 	iretq
 
 .global isr_double_fault
 isr_double_fault:
+	swapgs_if_user 16
 	//Registers are not saved since this exception is an abort
 	pop rdi //pop the error code (should always be 0)
 	call ih_double_fault
@@ -63,6 +118,7 @@ isr_double_fault:
 
 .global isr_general_protection_fault
 isr_general_protection_fault:
+	swapgs_if_user 16
 	pop rdi //pop the error code
 		// save the caller saved registers
 	push rax
@@ -88,10 +144,12 @@ isr_general_protection_fault:
 	pop rsi
 	pop rax
 
+	swapgs_if_user 8
 	iretq
 
 .global isr_page_fault
 isr_page_fault:
+	swapgs_if_user 16
 		// save the caller saved registers
 	push rax
 	push rdi
@@ -103,8 +161,10 @@ isr_page_fault:
 	push r10
 	push r11
 
-	pop rdi //pop the error code
+	mov rdi, [rsp + 72] // load the error code pushed by the CPU below the saved registers
+	sub rsp, 8 // realign the stack to 16 bytes for the call
 	call ih_page_fault
+	add rsp, 8
 
 	// restore the caller saved registers
 	pop r11
@@ -112,14 +172,18 @@ isr_page_fault:
 	pop r9
 	pop r8
 	pop rcx
-	pop rdi
+	pop rdx
 	pop rsi
+	pop rdi
 	pop rax
 
+	add rsp, 8 // Clean up the error code from the stack
+	swapgs_if_user 8
 	iretq
 
 .global isr_segment_not_present
 isr_segment_not_present:
+	swapgs_if_user 16
 		// save the caller saved registers
 	push rax
 	push rdi
@@ -146,10 +210,12 @@ isr_segment_not_present:
 	pop rax
 
 	add rsp, 8 // Clean up the error code from the stack
+	swapgs_if_user 8
 	iretq
 
 .global isr_debug
 isr_debug:
+	swapgs_if_user 8
 		// save the caller saved registers
 	push rax
 	push rdi
@@ -173,11 +239,13 @@ isr_debug:
 	pop rsi
 	pop rax
 
+	swapgs_if_user 8
 	iretq
 
 .global isr_non_maskable_interrupt
 isr_non_maskable_interrupt:
-		// save the caller saved registers
+	paranoid_swapgs_entry
+	// save the caller saved registers
 	push rax
 	push rdi
 	push rsi
@@ -196,14 +264,17 @@ isr_non_maskable_interrupt:
 	pop r9
 	pop r8
 	pop rcx
-	pop rdi
+	pop rdx
 	pop rsi
+	pop rdi
 	pop rax
 
+	paranoid_swapgs_exit
 	iretq
 
 .global isr_breakpoint
 isr_breakpoint:
+	swapgs_if_user 8
 		// save the caller saved registers
 	push rax
 	push rdi
@@ -227,11 +298,13 @@ isr_breakpoint:
 	pop rsi
 	pop rax
 
+	swapgs_if_user 8
 	iretq
 
 
 .global isr_overflow
 isr_overflow:
+	swapgs_if_user 8
 		// save the caller saved registers
 	push rax
 	push rdi
@@ -255,10 +328,12 @@ isr_overflow:
 	pop rsi
 	pop rax
 
+	swapgs_if_user 8
 	iretq
 
 .global isr_bound_range_exceeded
 isr_bound_range_exceeded:
+	swapgs_if_user 8
 		// save the caller saved registers
 	push rax
 	push rdi
@@ -282,10 +357,12 @@ isr_bound_range_exceeded:
 	pop rsi
 	pop rax
 
+	swapgs_if_user 8
 	iretq
 
 .global isr_invalid_opcode
 isr_invalid_opcode:
+	swapgs_if_user 8
 		// save the caller saved registers
 	push rax
 	push rdi
@@ -309,10 +386,12 @@ isr_invalid_opcode:
 	pop rsi
 	pop rax
 
+	swapgs_if_user 8
 	iretq
 
 .global isr_device_not_available
 isr_device_not_available:
+	swapgs_if_user 8
 		// save the caller saved registers
 	push rax
 	push rdi
@@ -332,14 +411,17 @@ isr_device_not_available:
 	pop r9
 	pop r8
 	pop rcx
-	pop rdi
+	pop rdx
 	pop rsi
+	pop rdi
 	pop rax
 
+	swapgs_if_user 8
 	iretq
 
 .global isr_invalid_tss
 isr_invalid_tss:
+	swapgs_if_user 16
 		// save the caller saved registers
 	push rax
 	push rdi
@@ -366,10 +448,12 @@ isr_invalid_tss:
 	pop rax
 
 	add rsp, 8
+	swapgs_if_user 8
 	iretq
 
 .global isr_stack_segment_fault
 isr_stack_segment_fault:
+	swapgs_if_user 16
 		// save the caller saved registers
 	push rax
 	push rdi
@@ -396,10 +480,12 @@ isr_stack_segment_fault:
 	pop rax
 
 	add rsp, 8
+	swapgs_if_user 8
 	iretq
 
 .global isr_x87_floating_point
 isr_x87_floating_point:
+	swapgs_if_user 8
 		// save the caller saved registers
 	push rax
 	push rdi
@@ -423,10 +509,12 @@ isr_x87_floating_point:
 	pop rsi
 	pop rax
 
+	swapgs_if_user 8
 	iretq
 
 .global isr_alignment_check
 isr_alignment_check:
+	swapgs_if_user 16
 		// save the caller saved registers
 	push rax
 	push rdi
@@ -453,10 +541,12 @@ isr_alignment_check:
 	pop rax
 
 	add rsp, 8
+	swapgs_if_user 8
 	iretq
 
 .global isr_machine_check
 isr_machine_check:
+	paranoid_swapgs_entry
 	// Registers are not saved since this exception is an abort
 	// Unlike Double Fault, Machine Check does not push an error code
 	call ih_machine_check
@@ -464,6 +554,7 @@ isr_machine_check:
 
 .global isr_simd_floating_point
 isr_simd_floating_point:
+	swapgs_if_user 8
 		// save the caller saved registers
 	push rax
 	push rdi
@@ -487,10 +578,12 @@ isr_simd_floating_point:
 	pop rsi
 	pop rax
 
+	swapgs_if_user 8
 	iretq
 
 .global isr_virtualization
 isr_virtualization:
+	swapgs_if_user 8
 		// save the caller saved registers
 	push rax
 	push rdi
@@ -514,10 +607,12 @@ isr_virtualization:
 	pop rsi
 	pop rax
 
+	swapgs_if_user 8
 	iretq
 
 .global isr_control_protection
 isr_control_protection:
+	swapgs_if_user 16
 		// save the caller saved registers
 	push rax
 	push rdi
@@ -544,10 +639,12 @@ isr_control_protection:
 	pop rax
 
 	add rsp, 8
+	swapgs_if_user 8
 	iretq
 
 .global isr_hypervisor_injection
 isr_hypervisor_injection:
+	swapgs_if_user 8
 		// save the caller saved registers
 	push rax
 	push rdi
@@ -571,10 +668,12 @@ isr_hypervisor_injection:
 	pop rsi
 	pop rax
 
+	swapgs_if_user 8
 	iretq
 
 .global isr_vmm_communication
 isr_vmm_communication:
+	swapgs_if_user 16
 		// save the caller saved registers
 	push rax
 	push rdi
@@ -601,10 +700,12 @@ isr_vmm_communication:
 	pop rax
 
 	add rsp, 8 // Clean up the error code from the stack
+	swapgs_if_user 8
 	iretq
 
 .global isr_security_exception
 isr_security_exception:
+	swapgs_if_user 16
 		// save the caller saved registers
 	push rax
 	push rdi
@@ -631,4 +732,5 @@ isr_security_exception:
 	pop rax
 
 	add rsp, 8 // Clean up the error code from the stack
+	swapgs_if_user 8
 	iretq
@@ -1,6 +1,15 @@
 use crate::cpu::isa::init::gdt;
 use crate::cpu::isa::interrupts::idt::Idt;
+use crate::cpu::isa::lp::xstate;
 use crate::logln;
+use crate::memory::VAddr;
+use crate::memory::fault::{PageFault, resolve_page_fault};
+
+/// Page fault error code bits
+const PF_ERR_PRESENT: u64 = 1 << 0;
+const PF_ERR_WRITE: u64 = 1 << 1;
+const PF_ERR_USER: u64 = 1 << 2;
+const PF_ERR_INSTRUCTION_FETCH: u64 = 1 << 4;
 
 pub fn load_exceptions(idt: &mut Idt) {
     idt.set_gate(0, isr_divide_by_zero, gdt::KERNEL_CODE_SELECTOR, true, true);
@@ -10,7 +19,8 @@ pub fn load_exceptions(idt: &mut Idt) {
     idt.set_gate(4, isr_overflow, gdt::KERNEL_CODE_SELECTOR, true, false);
     idt.set_gate(5, isr_bound_range_exceeded, gdt::KERNEL_CODE_SELECTOR, true, false);
     idt.set_gate(6, isr_invalid_opcode, gdt::KERNEL_CODE_SELECTOR, true, false);
-    idt.set_gate(7, isr_device_not_available, gdt::KERNEL_CODE_SELECTOR, true, false);
+    // an interrupt gate so that the LP can not switch threads while the registers are restored
+    idt.set_gate(7, isr_device_not_available, gdt::KERNEL_CODE_SELECTOR, false, true);
     idt.set_gate(8, isr_double_fault, gdt::KERNEL_CODE_SELECTOR, true, true);
     idt.set_gate(10, isr_invalid_tss, gdt::KERNEL_CODE_SELECTOR, true, false);
     idt.set_gate(11, isr_segment_not_present, gdt::KERNEL_CODE_SELECTOR, true, true);
@@ -107,8 +117,8 @@ extern "C" fn ih_invalid_opcode() {
 
 #[unsafe(no_mangle)]
 extern "C" fn ih_device_not_available() {
-    logln!("Device not available exception occurred!");
-    panic!("Device not available");
+    // raised by the first x87 or SIMD instruction a thread executes after being switched to
+    xstate::restore_on_first_use();
 }
 
 #[unsafe(no_mangle)]
@@ -137,13 +147,23 @@ extern "C" fn ih_general_protection_fault(_error_code: u64) {
 
 #[unsafe(no_mangle)]
 extern "C" fn ih_page_fault(error_code: u64) {
-    logln!("Page fault occurred with error code {:X}!", error_code);
     let pf_addr: u64;
     unsafe {
         core::arch::asm!("mov {0}, cr2", out(reg) pf_addr);
     }
-    logln!("Page fault address: {:x}", pf_addr);
-    panic!("Page fault");
+    let fault = PageFault {
+        vaddr: VAddr::from(pf_addr),
+        is_present: error_code & PF_ERR_PRESENT != 0,
+        is_write: error_code & PF_ERR_WRITE != 0,
+        is_user: error_code & PF_ERR_USER != 0,
+        is_instruction_fetch: error_code & PF_ERR_INSTRUCTION_FETCH != 0,
+    };
+    if let Err(err) = resolve_page_fault(&fault) {
+        logln!("Page fault occurred with error code {:X}!", error_code);
+        logln!("Page fault address: {:x}", pf_addr);
+        logln!("The page fault could not be resolved: {:?}", err);
+        panic!("Page fault");
+    }
 }
 
 #[unsafe(no_mangle)]
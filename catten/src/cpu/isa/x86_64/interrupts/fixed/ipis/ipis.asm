@@ -3,5 +3,35 @@
 .section .text
 .global isr_interprocessor_interrupt
 isr_interprocessor_interrupt:
-//todo: implement IPI handling
-iretq
\ No newline at end of file
+    # Swap in the kernel GS base if the interrupt was taken in user mode
+    test qword ptr [rsp + 8], 3
+    jz 1f
+    swapgs
+1:
+    # Save the caller saved registers; the handler preserves the rest
+    push rax
+    push rcx
+    push rdx
+    push rsi
+    push rdi
+    push r8
+    push r9
+    push r10
+    push r11
+    cld
+    # The 9 pushes above leave the stack 16 byte aligned for the call
+    call ih_interprocessor_interrupt
+    pop r11
+    pop r10
+    pop r9
+    pop r8
+    pop rdi
+    pop rsi
+    pop rdx
+    pop rcx
+    pop rax
+    test qword ptr [rsp + 8], 3
+    jz 2f
+    swapgs
+2:
+    iretq
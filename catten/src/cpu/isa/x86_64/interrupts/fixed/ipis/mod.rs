@@ -1,5 +1,5 @@
 core::arch::global_asm!(include_str!("ipis.asm"));
 
-unsafe extern "C" {
-    pub fn isr_interprocessor_interrupt();
+unsafe extern "custom" {
+    pub unsafe fn isr_interprocessor_interrupt();
 }
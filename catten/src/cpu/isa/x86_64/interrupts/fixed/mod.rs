@@ -28,4 +28,11 @@ pub fn register_fixed_isr_gates(idt: &mut Idt) {
         true,
     );
     idt.set_gate(WAKE_LP_VECTOR, context_switch::isr_wake_lp, KERNEL_CODE_SELECTOR, false, true);
+    idt.set_gate(
+        UNICAST_IPI_VECTOR,
+        ipis::isr_interprocessor_interrupt,
+        KERNEL_CODE_SELECTOR,
+        false,
+        true,
+    );
 }
@@ -6,4 +6,8 @@ pub const UNICAST_IPI_VECTOR: u8 = 34;
 pub const MULTICAST_IPI_VECTOR: u8 = 35;
 pub const BROADCAST_IPI_VECTOR: u8 = 36;
 /* Others go here */
+/// The first of the vectors that are handed out to device interrupts
+pub const FIRST_IRQ_VECTOR: u8 = 48;
+/// The last of the vectors that are handed out to device interrupts
+pub const LAST_IRQ_VECTOR: u8 = 239;
 pub const SPURIOUS_INTERRUPT_VECTOR: u8 = 255;
@@ -0,0 +1,163 @@
+//! # I/O Advanced Programmable Interrupt Controller
+//!
+//! The I/O APICs deliver device interrupts to the LPs. Each of their inputs is a global system
+//! interrupt (GSI) with a redirection entry that selects the vector it is delivered as and the LP
+//! it is delivered to. Every input is masked when the I/O APICs are initialized and is only
+//! unmasked once it has been routed.
+//!
+//! Entries use physical destination mode, which can only address LPs with an x2APIC ID below 256
+//! without interrupt remapping.
+
+use alloc::vec::Vec;
+
+use spin::Lazy;
+
+use super::x2apic::X2Apic;
+use crate::cpu::isa::interface::io::OReg8Ifce;
+use crate::cpu::isa::interface::memory::address::VirtualAddress;
+use crate::cpu::isa::io::IoReg8;
+use crate::cpu::isa::lp::LpId;
+use crate::cpu::isa::memory::paging::PAGE_SIZE;
+use crate::cpu::scheduler::sync::spinlock::IrqSpinLock;
+use crate::environment::acpi::madt;
+use crate::memory::linear::address_map::{LA_MAP, RegionType};
+use crate::memory::linear::{MemoryMapping, PageType};
+use crate::memory::{AddressSpaceInterface, KERNEL_AS, PAddr};
+
+const IOREGSEL_OFFSET: usize = 0x00;
+const IOWIN_OFFSET: usize = 0x10;
+const VERSION_REG: u32 = 0x01;
+const REDIRECTION_TABLE_REG: u32 = 0x10;
+const REDIRECTION_MASKED: u64 = 1 << 16;
+const REDIRECTION_LEVEL_TRIGGERED: u64 = 1 << 15;
+const REDIRECTION_ACTIVE_LOW: u64 = 1 << 13;
+const REDIRECTION_DEST_SHIFT: u64 = 56;
+const PIC_MASTER_DATA_PORT: u16 = 0x21;
+const PIC_SLAVE_DATA_PORT: u16 = 0xa1;
+
+#[derive(Debug)]
+pub enum Error {
+    /// No I/O APIC has an input with the given GSI
+    InvalidGsi,
+    /// The LP has not initialized its local APIC or can not be addressed by an I/O APIC
+    InvalidLpId,
+}
+
+struct IoApic {
+    regs: *mut u32,
+    gsi_base: u32,
+    n_inputs: u32,
+}
+
+// The registers are only accessed through `IO_APICS`
+unsafe impl Send for IoApic {}
+
+impl IoApic {
+    fn read(&self, reg: u32) -> u32 {
+        unsafe {
+            self.regs.byte_add(IOREGSEL_OFFSET).write_volatile(reg);
+            self.regs.byte_add(IOWIN_OFFSET).read_volatile()
+        }
+    }
+
+    fn write(&self, reg: u32, value: u32) {
+        unsafe {
+            self.regs.byte_add(IOREGSEL_OFFSET).write_volatile(reg);
+            self.regs.byte_add(IOWIN_OFFSET).write_volatile(value);
+        }
+    }
+
+    fn set_redirection(&self, input: u32, entry: u64) {
+        let reg = REDIRECTION_TABLE_REG + input * 2;
+        // the entry is masked while its halves disagree
+        self.write(reg, REDIRECTION_MASKED as u32);
+        self.write(reg + 1, (entry >> 32) as u32);
+        self.write(reg, entry as u32);
+    }
+
+    fn contains(&self, gsi: u32) -> bool {
+        gsi >= self.gsi_base && gsi - self.gsi_base < self.n_inputs
+    }
+}
+
+/// The I/O APICs listed in the MADT, each with all of its inputs masked
+static IO_APICS: Lazy<IrqSpinLock<Vec<IoApic>>> = Lazy::new(|| {
+    if madt::has_legacy_pics() {
+        // the PICs would otherwise deliver ISA IRQs on vectors of their own choosing
+        unsafe {
+            IoReg8::IoPort(PIC_MASTER_DATA_PORT).write(0xff);
+            IoReg8::IoPort(PIC_SLAVE_DATA_PORT).write(0xff);
+        }
+    }
+    let io_apics = madt::get_io_apics()
+        .iter()
+        .filter_map(|entry| {
+            let io_apic = IoApic {
+                regs: map_registers(entry.paddr as usize)?,
+                gsi_base: entry.gsi_base,
+                n_inputs: 0,
+            };
+            let n_inputs = ((io_apic.read(VERSION_REG) >> 16) & 0xff) + 1;
+            for input in 0..n_inputs {
+                io_apic.set_redirection(input, REDIRECTION_MASKED);
+            }
+            Some(IoApic {
+                n_inputs,
+                ..io_apic
+            })
+        })
+        .collect();
+    IrqSpinLock::new(io_apics)
+});
+
+/// Map the registers of an I/O APIC uncached into the kernel allocator arena
+fn map_registers(paddr: usize) -> Option<*mut u32> {
+    let page_offset = paddr % PAGE_SIZE;
+    let arena = LA_MAP.get_region(RegionType::KernelAllocatorArena);
+    let mut kas = KERNEL_AS.lock();
+    let vaddr = kas.find_free_region(1, (arena.base, arena.base + arena.length)).ok()?;
+    kas.map_page(MemoryMapping {
+        vaddr,
+        paddr: PAddr::from((paddr - page_offset) as u64),
+        page_type: PageType::Mmio,
+    })
+    .ok()?;
+    Some((vaddr + page_offset).into_mut())
+}
+
+/// Mask the legacy PICs and every I/O APIC input. Requires the kernel heap.
+pub fn init() {
+    Lazy::force(&IO_APICS);
+}
+
+/// Deliver the given GSI to `target_lp` as `vector` and unmask it
+pub fn route(
+    gsi: u32,
+    vector: u8,
+    target_lp: LpId,
+    is_active_low: bool,
+    is_level_triggered: bool,
+) -> Result<(), Error> {
+    let dest = X2Apic::get_physical_id(target_lp)
+        .filter(|&dest| dest <= u8::MAX as u32)
+        .ok_or(Error::InvalidLpId)?;
+    let mut entry = vector as u64 | (dest as u64) << REDIRECTION_DEST_SHIFT;
+    if is_active_low {
+        entry |= REDIRECTION_ACTIVE_LOW;
+    }
+    if is_level_triggered {
+        entry |= REDIRECTION_LEVEL_TRIGGERED;
+    }
+    let io_apics = IO_APICS.lock();
+    let io_apic = io_apics.iter().find(|io_apic| io_apic.contains(gsi)).ok_or(Error::InvalidGsi)?;
+    io_apic.set_redirection(gsi - io_apic.gsi_base, entry);
+    Ok(())
+}
+
+/// Stop delivering the given GSI
+pub fn mask(gsi: u32) {
+    let io_apics = IO_APICS.lock();
+    if let Some(io_apic) = io_apics.iter().find(|io_apic| io_apic.contains(gsi)) {
+        io_apic.set_redirection(gsi - io_apic.gsi_base, REDIRECTION_MASKED);
+    }
+}
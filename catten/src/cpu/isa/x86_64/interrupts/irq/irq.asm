@@ -0,0 +1,54 @@
+.code64
+
+.section .text
+// One stub per IRQ vector, each 16 bytes long, that pushes its vector and jumps to the
+// common entry
+.global isr_irq_stubs
+.balign 16
+isr_irq_stubs:
+.set vector, {first_vector}
+.rept {n_vectors}
+    push vector
+    jmp isr_irq_common
+    .balign 16
+.set vector, vector + 1
+.endr
+
+isr_irq_common:
+    # Swap in the kernel GS base if the interrupt was taken in user mode
+    test qword ptr [rsp + 16], 3
+    jz 1f
+    swapgs
+1:
+    # Save the caller saved registers; the handler preserves the rest
+    push rax
+    push rcx
+    push rdx
+    push rsi
+    push rdi
+    push r8
+    push r9
+    push r10
+    push r11
+    cld
+    mov rdi, [rsp + 72]
+    # The vector and the 9 pushes above leave the stack 8 bytes short of 16 byte alignment
+    sub rsp, 8
+    call ih_irq
+    add rsp, 8
+    pop r11
+    pop r10
+    pop r9
+    pop r8
+    pop rdi
+    pop rsi
+    pop rdx
+    pop rcx
+    pop rax
+    # Drop the vector
+    add rsp, 8
+    test qword ptr [rsp + 8], 3
+    jz 2f
+    swapgs
+2:
+    iretq
@@ -0,0 +1,135 @@
+//! # Device Interrupts
+//!
+//! The vectors from `FIRST_IRQ_VECTOR` to `LAST_IRQ_VECTOR` are handed out to device interrupts.
+//! Each of them has an `EventSource` that its ISR raises, so a driver waits for its device's
+//! interrupt like for any other event, e.g. with `wait_any`, instead of polling the device.
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use crate::cpu::isa::init::gdt::KERNEL_CODE_SELECTOR;
+use crate::cpu::isa::interface::interrupts::LocalIntCtlrIfce;
+use crate::cpu::isa::interrupts::fixed::vector_assignments::{FIRST_IRQ_VECTOR, LAST_IRQ_VECTOR};
+use crate::cpu::isa::interrupts::idt::Idt;
+use crate::cpu::isa::interrupts::{LocalIntCtlr, ioapic};
+use crate::cpu::isa::lp::LpId;
+use crate::cpu::scheduler::sync::lockdep::IrqContext;
+use crate::environment::acpi::madt;
+use crate::event::EventSource;
+
+const N_IRQ_VECTORS: usize = (LAST_IRQ_VECTOR - FIRST_IRQ_VECTOR) as usize + 1;
+/// The size that each stub in `isr_irq_stubs` is padded to
+const IRQ_STUB_SIZE: usize = 16;
+
+core::arch::global_asm!(
+    include_str!("irq.asm"),
+    first_vector = const FIRST_IRQ_VECTOR,
+    n_vectors = const N_IRQ_VECTORS,
+);
+
+unsafe extern "custom" {
+    unsafe fn isr_irq_stubs();
+}
+
+static IRQ_EVENTS: [EventSource; N_IRQ_VECTORS] = [const { EventSource::new() }; N_IRQ_VECTORS];
+static IS_VECTOR_ALLOCATED: [AtomicBool; N_IRQ_VECTORS] =
+    [const { AtomicBool::new(false) }; N_IRQ_VECTORS];
+
+#[derive(Debug)]
+pub enum Error {
+    /// All IRQ vectors are in use
+    NoFreeVector,
+    /// The ISR acknowledges the interrupt before its observers have quieted the device, so a
+    /// level-triggered line would interrupt again right away
+    LevelTriggered,
+    IoApicError(ioapic::Error),
+}
+
+impl From<ioapic::Error> for Error {
+    fn from(err: ioapic::Error) -> Self {
+        Error::IoApicError(err)
+    }
+}
+
+pub fn register_irq_gates(idt: &mut Idt) {
+    for index in 0..N_IRQ_VECTORS {
+        let stub = unsafe {
+            core::mem::transmute::<*const (), unsafe extern "custom" fn()>(
+                (isr_irq_stubs as *const ()).byte_add(index * IRQ_STUB_SIZE),
+            )
+        };
+        idt.set_gate(FIRST_IRQ_VECTOR + index as u8, stub, KERNEL_CODE_SELECTOR, false, true);
+    }
+}
+
+/// Reserve an IRQ vector that no other device interrupt is delivered as
+pub fn allocate_vector() -> Result<u8, Error> {
+    IS_VECTOR_ALLOCATED
+        .iter()
+        .position(|is_allocated| !is_allocated.swap(true, Ordering::AcqRel))
+        .map(|index| FIRST_IRQ_VECTOR + index as u8)
+        .ok_or(Error::NoFreeVector)
+}
+
+/// Give back a vector once nothing is delivered as it anymore
+pub fn free_vector(vector: u8) {
+    if let Some(index) = vector.checked_sub(FIRST_IRQ_VECTOR) {
+        IS_VECTOR_ALLOCATED[index as usize].store(false, Ordering::Release);
+    }
+}
+
+/// The event raised each time an interrupt arrives on the given IRQ vector
+pub fn get_vector_event(vector: u8) -> Option<&'static EventSource> {
+    IRQ_EVENTS.get(vector.checked_sub(FIRST_IRQ_VECTOR)? as usize)
+}
+
+/// A legacy ISA IRQ routed through the I/O APICs to a vector of its own. The IRQ is masked and its
+/// vector freed when this is dropped.
+pub struct IsaIrq {
+    vector: u8,
+    gsi: u32,
+}
+
+impl IsaIrq {
+    /// Route the IRQ to `target_lp` and unmask it. The device's own interrupt enable is left to
+    /// its driver.
+    pub fn route(irq: u8, target_lp: LpId) -> Result<Self, Error> {
+        let route = madt::get_isa_irq_route(irq);
+        if route.is_level_triggered {
+            return Err(Error::LevelTriggered);
+        }
+        let vector = allocate_vector()?;
+        if let Err(err) = ioapic::route(route.gsi, vector, target_lp, route.is_active_low, false) {
+            free_vector(vector);
+            return Err(err.into());
+        }
+        Ok(IsaIrq {
+            vector,
+            gsi: route.gsi,
+        })
+    }
+
+    pub fn get_vector(&self) -> u8 {
+        self.vector
+    }
+
+    /// The event raised each time the IRQ arrives
+    pub fn get_event(&self) -> &'static EventSource {
+        &IRQ_EVENTS[(self.vector - FIRST_IRQ_VECTOR) as usize]
+    }
+}
+
+impl Drop for IsaIrq {
+    fn drop(&mut self) {
+        ioapic::mask(self.gsi);
+        free_vector(self.vector);
+    }
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn ih_irq(vector: u64) {
+    let _irq_context = IrqContext::enter();
+    if let Some(event) = get_vector_event(vector as u8) {
+        event.raise();
+    }
+    LocalIntCtlr::signal_eoi();
+}
@@ -2,19 +2,19 @@
 
 pub mod fixed;
 pub mod idt;
+pub mod ioapic;
+pub mod irq;
 pub mod x2apic;
 
 use idt::*;
-use spin::{Lazy, Mutex};
+use spin::Mutex;
 
 use crate::cpu::isa::interface::interrupts::InterruptManagerIfce;
 use crate::cpu::isa::lp::LpId;
-use crate::memory::IdTable;
 
 pub type LocalIntCtlr = x2apic::X2Apic;
 
 pub static BSP_IDT: Mutex<Idt> = Mutex::new(Idt::new());
-pub static IDT_TABLE: Lazy<IdTable<LpId, Mutex<Idt>>> = Lazy::new(IdTable::new);
 
 pub struct IsrDesc {
     pub target_lp: LpId,
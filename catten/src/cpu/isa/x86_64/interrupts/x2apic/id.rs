@@ -1,8 +1,9 @@
-use alloc::collections::btree_map::BTreeMap;
-
 use crate::cpu::isa::lp::LpId;
+use crate::cpu::isa::x86_64::lp::msrs;
+use crate::cpu::scheduler::sync::rcu::hash_map::RcuHashMap;
 
-pub(super) static mut X2APIC_ID_TABLE: BTreeMap<LpId, LapicId> = BTreeMap::new();
+/// Written by each LP as it initializes and read when sending IPIs
+pub(super) static X2APIC_ID_TABLE: RcuHashMap<LpId, LapicId> = RcuHashMap::new();
 
 /// x2APIC MSR space docs: AAPM 16.11.1 and ISDM 12.12.1.2
 pub static X2APIC_ID_REG: u32 = 0x802;
@@ -17,23 +18,18 @@ pub struct LapicId {
 
 impl LapicId {
     pub fn get_local() -> Self {
-        let physical: PhysicalLapicId;
-        let logical: u32;
-        unsafe {
-            core::arch::asm! {
-                "mov ecx, X2APIC_ID_REG", // x2APIC ID Register
-                "rdmsr",
-                "mov [{phys:e}], eax",
-                "mov ecx, X2APIC_LOGICAL_DEST_REG", // x2APIC Logical Destination Register
-                "rdmsr",
-                "mov [{log:e}], eax",
-                phys = out(reg) physical,
-                log = out(reg) logical,
-            }
-        }
+        let (physical, logical) = unsafe {
+            (
+                msrs::read(X2APIC_ID_REG) as PhysicalLapicId,
+                msrs::read(X2APIC_LOGICAL_DEST_REG) as u32,
+            )
+        };
         LapicId {
             physical,
-            logical: unsafe { core::mem::transmute(logical) },
+            logical: LogicalLapicId {
+                cluster_id: (logical >> 16) as u16,
+                apic_bitmask: logical as u16,
+            },
         }
     }
 }
@@ -43,6 +43,14 @@ impl X2Apic {
     /// # Initialize the local APIC in x2APIC mode
     /// Ref: AMD APM 16.4.7
     fn new(timer_int_vec: <ApicTimer as LpTimerIfce>::IntDispatchNum) -> Self {
+        Self::enable();
+        X2Apic {
+            timer: ApicTimer::new(timer_int_vec),
+        }
+    }
+
+    /// # Software enable the local APIC of the calling LP
+    pub fn enable() {
         // Set the Spurious Interrupt Vector Register (SIVR) to enable the APIC with Focused CPU
         // Core Checking and set the spurious interrupt vector to 32
         const FCC_BIT_SHIFT: u64 = 9;
@@ -54,19 +62,19 @@ impl X2Apic {
         unsafe {
             msrs::write(msrs::APIC_SPURIOUS_INTERRUPT_VECTOR, sivr_val);
         }
-        X2Apic {
-            timer: ApicTimer::new(timer_int_vec),
-        }
     }
 
     pub fn record_id() {
-        unsafe {
-            id::X2APIC_ID_TABLE.insert(get_lp_id(), id::LapicId::get_local());
-        }
+        id::X2APIC_ID_TABLE.insert(get_lp_id(), id::LapicId::get_local());
     }
 
     fn translate_lp_id(lp_id: LpId) -> Option<id::LapicId> {
-        unsafe { id::X2APIC_ID_TABLE.get(&lp_id).cloned() }
+        id::X2APIC_ID_TABLE.get_cloned(&lp_id)
+    }
+
+    /// The physical x2APIC ID of the given LP if it has initialized its local APIC
+    pub fn get_physical_id(lp_id: LpId) -> Option<u32> {
+        Self::translate_lp_id(lp_id).map(|id| id.physical)
     }
 
     fn make_icr_low(
@@ -91,42 +99,16 @@ impl X2Apic {
             | ((dest_shorthand as u32) << DEST_SHORTHAND_SHIFT)
     }
 
-    pub fn set_timer_lvt_entry(
-        interrupt_vector: <ApicTimer as LpTimerIfce>::IntDispatchNum,
-        periodic: bool,
-    ) {
-        const TIMER_MODE_SHIFT: u64 = 17;
-        const TIMER_MODE_PERIODIC: u64 = 0b1;
-        const TIMER_MODE_ONE_SHOT: u64 = 0b0;
-        const MASK_BIT_SHIFT: u64 = 16;
-        const TIMER_VECTOR_MASK: u64 = 0xff;
-
-        let timer_lvt_entry = (interrupt_vector as u64 & TIMER_VECTOR_MASK)
-            | (if periodic {
-                TIMER_MODE_PERIODIC
-            } else {
-                TIMER_MODE_ONE_SHOT
-            } << TIMER_MODE_SHIFT)
-                & !(1u64 << MASK_BIT_SHIFT); // Unmask the timer interrupt
-        unsafe {
-            msrs::write(msrs::APIC_TIMER_LVTR, timer_lvt_entry);
-        }
-    }
-}
-
-impl LocalIntCtlrIfce for X2Apic {
-    type Error = Error;
-
-    /// # Send a unicast IPI to the target logical processor
+    /// # Send an IPI with the given vector to the target logical processor
     ///
     /// Ref: Intel SDM Vol.3 12.12.10.1
-    fn send_unicast_ipi(target_lp: LpId) -> Result<(), Error> {
+    pub fn send_fixed_ipi(target_lp: LpId, vector: u8) -> Result<(), Error> {
         if let Some(apic_id) = Self::translate_lp_id(target_lp) {
             // Get the physical APIC ID for the target LP
             let dest = apic_id.physical;
             // Construct the ICR low dword
             let icr_low = Self::make_icr_low(
-                UNICAST_IPI_VECTOR,
+                vector,
                 IcrDeliveryMode::Fixed,
                 false,
                 true,
@@ -150,6 +132,46 @@ impl LocalIntCtlrIfce for X2Apic {
         }
     }
 
+    pub fn set_timer_lvt_entry(
+        interrupt_vector: <ApicTimer as LpTimerIfce>::IntDispatchNum,
+        periodic: bool,
+    ) {
+        const TIMER_MODE_SHIFT: u64 = 17;
+        const TIMER_MODE_PERIODIC: u64 = 0b1;
+        const TIMER_MODE_ONE_SHOT: u64 = 0b0;
+        const MASK_BIT_SHIFT: u64 = 16;
+        const TIMER_VECTOR_MASK: u64 = 0xff;
+
+        let timer_lvt_entry = (interrupt_vector as u64 & TIMER_VECTOR_MASK)
+            | (if periodic {
+                TIMER_MODE_PERIODIC
+            } else {
+                TIMER_MODE_ONE_SHOT
+            } << TIMER_MODE_SHIFT)
+                & !(1u64 << MASK_BIT_SHIFT); // Unmask the timer interrupt
+        unsafe {
+            msrs::write(msrs::APIC_TIMER_LVTR, timer_lvt_entry);
+        }
+    }
+}
+
+impl LocalIntCtlrIfce for X2Apic {
+    type Error = Error;
+
+    fn init_local() {
+        Self::enable();
+        Self::record_id();
+    }
+
+    /// # Send a unicast IPI to the target logical processor
+    fn send_unicast_ipi(target_lp: LpId) -> Result<(), Error> {
+        Self::send_fixed_ipi(target_lp, UNICAST_IPI_VECTOR)
+    }
+
+    fn send_wake_lp_ipi(target_lp: LpId) -> Result<(), Error> {
+        Self::send_fixed_ipi(target_lp, WAKE_LP_VECTOR)
+    }
+
     fn signal_eoi() {
         unsafe {
             msrs::write(APIC_EOI_REGISTER, 0);
@@ -2,5 +2,6 @@
 pub mod msrs;
 pub mod ops;
 pub mod thread_context;
+pub mod xstate;
 
 pub type LpId = u32;
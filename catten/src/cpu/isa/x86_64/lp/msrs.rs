@@ -51,3 +51,10 @@ pub const APIC_TIMER_CURRENT_COUNT: u32 = 0x839;
 pub const APIC_TIMER_DIVIDE_CONFIGURATION: u32 = 0x83e;
 /// # TSC_AUX MSR
 pub const TSC_AUX: u32 = 0xc000_0103;
+/// # IA32_XSS MSR
+/// Selects the supervisor state components saved by XSAVES
+pub const XSS: u32 = 0xda0;
+/// # KERNEL_GS_BASE MSR
+/// Exchanged with the GS base by `swapgs`, so it holds the user GS base while the LP is in the
+/// kernel
+pub const KERNEL_GS_BASE: u32 = 0xc000_0102;
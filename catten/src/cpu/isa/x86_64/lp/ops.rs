@@ -85,6 +85,7 @@ pub fn get_lp_id() -> LpId {
     id as crate::cpu::isa::lp::LpId
 }
 
+use crate::cpu::isa::interface::memory::address::Address;
 use crate::memory::VAddr;
 
 #[inline]
@@ -111,26 +112,190 @@ pub extern "C" fn set_lp_local_base(base: VAddr) {
     }
 }
 
+/// The context of the thread the calling LP is running, read from the LP's local area. Null
+/// before the local area has been set up or before the LP has yielded to the scheduler.
 #[inline]
 pub extern "C" fn get_thread_context_ptr() -> VAddr {
+    if get_lp_local_base().is_null() {
+        return VAddr::from(0u64);
+    }
     let ret: u64;
     unsafe {
         core::arch::asm!(
-            "rdfsbase {}",
+            "mov {}, gs:[0]",
             out(reg) ret,
-            options(nomem, nostack, preserves_flags)
+            options(nostack, preserves_flags, readonly)
         );
     }
     VAddr::from(ret)
 }
 
+/// Set the context that the context switch ISRs save the interrupted state to and load the next
+/// state from
+///
+/// # Safety
+/// The LP's local area must have been set up and the context must stay valid until it is replaced.
 #[inline]
 pub unsafe extern "C" fn set_thread_context_ptr(ctx_ptr: VAddr) {
     unsafe {
         core::arch::asm!(
-            "wrfsbase {}",
+            "mov gs:[0], {}",
             in(reg) <VAddr as Into<u64>>::into(ctx_ptr),
-            options(nomem, nostack, preserves_flags)
+            options(nostack, preserves_flags)
         )
     };
 }
+
+/// Allow the FS and GS base registers to be accessed with `rdfsbase`, `wrfsbase`, `rdgsbase` and
+/// `wrgsbase` on the calling LP
+pub fn enable_fsgsbase() {
+    const CR4_FSGSBASE: u64 = 1 << 16;
+    unsafe {
+        core::arch::asm!(
+            "mov {tmp}, cr4",
+            "or {tmp}, {bit}",
+            "mov cr4, {tmp}",
+            tmp = out(reg) _,
+            bit = in(reg) CR4_FSGSBASE,
+            options(nomem, nostack, preserves_flags),
+        );
+    }
+}
+
+/// Whether interrupts are unmasked on the calling LP
+pub fn are_interrupts_enabled() -> bool {
+    const RFLAGS_IF: u64 = 1 << 9;
+    let rflags: u64;
+    unsafe {
+        core::arch::asm!(
+            "pushfq",
+            "pop {}",
+            out(reg) rflags,
+            options(preserves_flags)
+        );
+    }
+    rflags & RFLAGS_IF != 0
+}
+
+/// The frame pointer of the caller's frame. Frame records start with the previous frame pointer,
+/// followed by the return address.
+#[inline(always)]
+pub fn get_frame_pointer() -> usize {
+    let rbp: usize;
+    unsafe {
+        core::arch::asm!(
+            "mov {}, rbp",
+            out(reg) rbp,
+            options(nomem, nostack, preserves_flags)
+        );
+    }
+    rbp
+}
+
+/// Run `f` with interrupts masked on the calling LP, restoring the previous state afterwards.
+pub fn without_interrupts<R>(f: impl FnOnce() -> R) -> R {
+    const RFLAGS_IF: u64 = 1 << 9;
+    let rflags: u64;
+    unsafe {
+        core::arch::asm!(
+            "pushfq",
+            "pop {}",
+            "cli",
+            out(reg) rflags,
+        );
+    }
+    let result = f();
+    if rflags & RFLAGS_IF != 0 {
+        unsafe {
+            core::arch::asm!("sti", options(nostack));
+        }
+    }
+    result
+}
+
+/// Arm address monitoring for the cache line containing `addr`. A later `mwait` returns once the
+/// line is written to.
+///
+/// # Safety
+/// The LP must support `monitor` and `mwait`.
+#[inline]
+pub unsafe fn monitor(addr: *const u8) {
+    unsafe {
+        core::arch::asm!(
+            "monitor",
+            in("rax") addr,
+            in("ecx") 0,
+            in("edx") 0,
+            options(nostack, preserves_flags)
+        );
+    }
+}
+
+/// Wait in the C-state given by `hint` until the monitored cache line is written to or an
+/// interrupt arrives. Returns right away if no monitoring is armed.
+///
+/// # Safety
+/// The LP must support `monitor` and `mwait`.
+#[inline]
+pub unsafe fn mwait(hint: u32) {
+    unsafe {
+        core::arch::asm!(
+            "mwait",
+            in("eax") hint,
+            in("ecx") 0,
+            options(nostack, preserves_flags)
+        );
+    }
+}
+
+use core::sync::atomic::Ordering;
+
+use spin::Lazy;
+
+use crate::cpu::isa::interface::interrupts::LocalIntCtlrIfce;
+use crate::cpu::isa::interface::system_info::CpuInfoIfce;
+use crate::cpu::isa::interrupts::LocalIntCtlr;
+use crate::cpu::isa::system_info::{CpuInfo, IsaExtension};
+use crate::cpu::scheduler::idle;
+
+/// Whether idle LPs can wait on their wake flags
+static IS_MWAIT_SUPPORTED: Lazy<bool> =
+    Lazy::new(|| CpuInfo::is_extension_supported(IsaExtension::MonitorMwait));
+/// Whether the local APIC timer keeps counting in C-states deeper than C1
+static IS_ARAT_SUPPORTED: Lazy<bool> =
+    Lazy::new(|| CpuInfo::is_extension_supported(IsaExtension::Arat));
+/// The `mwait` hint for C1, used when the firmware does not describe the LP's C-states
+const MWAIT_HINT_C1: u32 = 0;
+
+/// Entry point of the idle context an LP runs while it has no thread to execute. Interrupts are
+/// enabled by the interrupt stack frame the context is entered through.
+///
+/// With `monitor` and `mwait` the LP waits on its wake flag as well as for interrupts, in the
+/// deepest C-state that pays off before its timer fires. Otherwise it halts until an interrupt.
+pub extern "C" fn idle_lp() -> ! {
+    if !*IS_MWAIT_SUPPORTED {
+        halt!()
+    }
+    loop {
+        let hint = idle::pick_cstate(*IS_ARAT_SUPPORTED)
+            .and_then(|cstate| cstate.hint)
+            .unwrap_or(MWAIT_HINT_C1);
+        let Some(wake_flag) = idle::begin_monitoring() else {
+            halt!()
+        };
+        unsafe { monitor(wake_flag.as_ptr() as *const u8) };
+        // the flag may have been set before the monitoring was armed
+        if !wake_flag.load(Ordering::SeqCst) {
+            unsafe { mwait(hint) };
+        }
+        if idle::take_wake() {
+            // The switch path saves the outgoing context from the frame the wake ISR is entered
+            // with, signals EOI to the local APIC and resumes the next thread with `iretq`, so it
+            // can not be called from here. A software `int` would have the ISR retire an interrupt
+            // that is not in service, so the LP sends itself a wake IPI instead. The IPI never
+            // leaves its local APIC and is taken right away since the idle context runs with
+            // interrupts enabled.
+            let _ = LocalIntCtlr::send_wake_lp_ipi(get_lp_id());
+        }
+    }
+}
@@ -1,8 +1,13 @@
+use core::arch::asm;
 use core::mem::offset_of;
 
+use super::msrs;
+use super::xstate::XstateArea;
 use crate::memory::allocators::stack_allocator;
 
 const INIT_KERNEL_STACK_PAGES: usize = 16;
+/// Index of RDI, the first argument register, in `InterruptStackFrame::gprs`
+const RDI_IDX: usize = 9;
 
 use crate::cpu::isa::init::gdt::{
     KERNEL_CODE_SELECTOR,
@@ -11,16 +16,17 @@ use crate::cpu::isa::init::gdt::{
     USER_DATA_SELECTOR,
 };
 use crate::cpu::isa::interface::memory::address::VirtualAddress;
-use crate::cpu::isa::memory::paging::PAGE_SIZE;
 use crate::memory::allocators::stack_allocator::allocate_stack;
-use crate::memory::{ADDRESS_SPACE_TABLE, AddressSpaceId, KERNEL_ASID, VAddr};
+use crate::memory::{AddressSpaceId, KERNEL_ASID, VAddr, with_address_space};
 
 /// # Interrupt stack frame structure for x86_64 architecture
+///
+/// This is the layout the context switch ISRs leave on a thread's kernel stack: the general
+/// purpose registers in the order they are popped followed by the frame consumed by `iretq`.
 /// Note: must be 16 byte aligned as per `AMD APM 8.9.3`
 #[repr(C, align(16))]
 struct InterruptStackFrame {
     gprs: [u64; 15],
-    padding: u64,
     rip: u64,
     cs: u64,
     rflags: u64,
@@ -32,7 +38,6 @@ impl InterruptStackFrame {
     fn new(is_user: bool, entry_point: VAddr, return_rsp: VAddr, flags: u64) -> Self {
         InterruptStackFrame {
             gprs: [0u64; 15],
-            padding: 0,
             rip: <VAddr as Into<u64>>::into(entry_point),
             cs: if is_user {
                 USER_CODE_SELECTOR
@@ -59,17 +64,30 @@ impl InterruptStackFrame {
     }
 }
 
+/// The saved state of a thread.
+///
+/// The LP's local area points at the context of the thread running on the LP and the context
+/// switch ISRs save and restore the kernel stack pointer through that pointer so `rsp_cpl0` must
+/// stay the first field.
 #[derive(Debug, Clone, Default)]
+#[repr(C)]
 pub struct ThreadContext {
-    cr3: u64,
     rsp_cpl0: u64,
+    cr3: u64,
     kernel_stack_buf: VAddr,
     user_stack_buf: Option<VAddr>,
+    /// The x87 and SIMD registers while the thread is not using them
+    xstate: Option<XstateArea>,
+    /// The FS and GS bases the thread set for its thread local storage
+    user_fs_base: u64,
+    user_gs_base: u64,
 }
 #[derive(Debug)]
 pub enum Error {
     AddressSpaceNotFound,
     StackAllocError(stack_allocator::Error),
+    /// There is not enough memory for the XSAVE area
+    XstateAllocError,
 }
 
 impl From<stack_allocator::Error> for Error {
@@ -82,33 +100,116 @@ impl ThreadContext {
     pub fn new(asid: AddressSpaceId, entry_point: VAddr) -> Result<Self, Error> {
         let mut tctx = ThreadContext {
             rsp_cpl0: 0,
-            cr3: ADDRESS_SPACE_TABLE
-                .try_get_element_arc(asid)
-                .ok_or(Error::AddressSpaceNotFound)?
-                .read()
-                .get_cr3(),
+            cr3: with_address_space(asid, |aspace| aspace.get_cr3())
+                .ok_or(Error::AddressSpaceNotFound)?,
             kernel_stack_buf: allocate_stack(INIT_KERNEL_STACK_PAGES)?,
             user_stack_buf: if asid != KERNEL_ASID {
                 Some(allocate_stack(INIT_KERNEL_STACK_PAGES)?)
             } else {
                 None
             },
+            xstate: Some(XstateArea::new().ok_or(Error::XstateAllocError)?),
+            user_fs_base: 0,
+            user_gs_base: 0,
         };
-        let isf = InterruptStackFrame::new(
-            asid != KERNEL_ASID,
-            entry_point,
-            if asid != KERNEL_ASID {
-                tctx.user_stack_buf.unwrap() + INIT_KERNEL_STACK_PAGES * PAGE_SIZE
-            } else {
-                tctx.kernel_stack_buf + INIT_KERNEL_STACK_PAGES * PAGE_SIZE
-            },
-            0x202, // IF=1
-        );
-        tctx.rsp_cpl0 = <VAddr as Into<u64>>::into(InterruptStackFrame::push_to_stack(
-            tctx.kernel_stack_buf + INIT_KERNEL_STACK_PAGES * PAGE_SIZE,
+        tctx.reset(asid != KERNEL_ASID, entry_point);
+        Ok(tctx)
+    }
+
+    /// Discard the saved state so that the thread starts over at `entry_point` the next time it is
+    /// switched to
+    pub fn reset(&mut self, is_user: bool, entry_point: VAddr) {
+        // `allocate_stack` returns the top of the stack
+        let return_stack_top = if is_user {
+            self.user_stack_buf.unwrap_or(self.kernel_stack_buf)
+        } else {
+            self.kernel_stack_buf
+        };
+        // the entry point is entered as if it had been called
+        let isf = InterruptStackFrame::new(is_user, entry_point, return_stack_top - 8usize, 0x202); // IF=1
+        self.rsp_cpl0 = <VAddr as Into<u64>>::into(InterruptStackFrame::push_to_stack(
+            self.kernel_stack_buf,
             isf,
         ));
-        Ok(tctx)
+    }
+
+    /// Pass `arg` to the entry point in its first argument register. This only has an effect before
+    /// the thread first runs after being created or reset.
+    pub fn set_entry_arg(&mut self, arg: u64) {
+        unsafe {
+            (*(self.rsp_cpl0 as *mut InterruptStackFrame)).gprs[RDI_IDX] = arg;
+        }
+    }
+
+    /// Give the thread's stacks back to the stack allocator
+    ///
+    /// # Safety
+    /// The thread must never run again and no LP may still be running on its kernel stack.
+    pub unsafe fn deallocate_stacks(&mut self) -> Result<(), stack_allocator::Error> {
+        if let Some(user_stack_buf) = self.user_stack_buf.take() {
+            stack_allocator::deallocate_stack(user_stack_buf)?;
+        }
+        stack_allocator::deallocate_stack(self.kernel_stack_buf)?;
+        self.rsp_cpl0 = 0;
+        Ok(())
+    }
+
+    /// Save the x87 and SIMD registers of the calling LP, which must hold the thread's state
+    ///
+    /// # Safety
+    /// CR0.TS must be clear.
+    pub unsafe fn save_xstate(&mut self) {
+        if let Some(xstate) = &mut self.xstate {
+            unsafe { xstate.save() };
+        }
+    }
+
+    /// Load the thread's x87 and SIMD registers on the calling LP
+    ///
+    /// # Safety
+    /// CR0.TS must be clear.
+    pub unsafe fn restore_xstate(&self) {
+        if let Some(xstate) = &self.xstate {
+            unsafe { xstate.restore() };
+        }
+    }
+
+    /// Save the FS and GS bases of the calling LP, which must hold the thread's user bases. The
+    /// kernel does not use the FS base and the user GS base is swapped into the kernel GS base MSR
+    /// while the LP is in the kernel.
+    pub fn save_user_bases(&mut self) {
+        unsafe {
+            asm!(
+                "rdfsbase {}",
+                out(reg) self.user_fs_base,
+                options(nomem, nostack, preserves_flags)
+            );
+            self.user_gs_base = msrs::read(msrs::KERNEL_GS_BASE);
+        }
+    }
+
+    /// Load the thread's user FS and GS bases on the calling LP. The GS base is swapped in when
+    /// the thread returns to user mode.
+    pub fn load_user_bases(&self) {
+        unsafe {
+            asm!(
+                "wrfsbase {}",
+                in(reg) self.user_fs_base,
+                options(nomem, nostack, preserves_flags)
+            );
+            msrs::write(msrs::KERNEL_GS_BASE, self.user_gs_base);
+        }
+    }
+
+    /// Switch to the thread's address space unless it is already the current one
+    pub fn load_address_space(&self) {
+        let curr_cr3: u64;
+        unsafe {
+            asm!("mov {}, cr3", out(reg) curr_cr3, options(nomem, nostack, preserves_flags));
+            if curr_cr3 != self.cr3 {
+                asm!("mov cr3, {}", in(reg) self.cr3, options(nostack, preserves_flags));
+            }
+        }
     }
 }
 
@@ -0,0 +1,345 @@
+//! # Extended Processor State
+//!
+//! The x87, SSE, AVX and AVX-512 registers of each thread are kept in an XSAVE area in its
+//! context. They are saved eagerly when an LP switches away from a thread that has used them since
+//! it was switched to but restored lazily: every switch sets CR0.TS so that the first x87 or SIMD
+//! instruction of the incoming thread raises `#NM`, whose handler loads the thread's registers.
+//! Threads that never use those registers never pay for them. CR0.TS being clear thus means that
+//! the registers hold the state of the current thread.
+//!
+//! The kernel itself is built without SIMD so kernel code that uses the registers anyway must be
+//! bracketed with `with_simd`.
+//!
+//! The area is sized from CPUID leaf 0xD and saved with XSAVES, XSAVEOPT or XSAVE, whichever is
+//! the best one supported. Processors without XSAVE fall back to FXSAVE, which covers x87 and SSE.
+
+use alloc::alloc::{Layout, alloc_zeroed, dealloc};
+use core::arch::asm;
+use core::arch::x86_64::__cpuid_count;
+use core::ptr::NonNull;
+
+use spin::Lazy;
+
+use super::msrs;
+use super::ops::{get_thread_context_ptr, without_interrupts};
+use super::thread_context::ThreadContext;
+use crate::cpu::isa::interface::memory::address::VirtualAddress;
+
+pub static XSTATE_FORMAT: Lazy<XstateFormat> = Lazy::new(XstateFormat::detect);
+
+/// x87, SSE, AVX, AVX-512 opmask, AVX-512 upper ZMM0-15 and AVX-512 ZMM16-31
+const USER_COMPONENTS: u64 = 0b1110_0111;
+/// The legacy FXSAVE region followed by the XSAVE header
+const LEGACY_AREA_SIZE: usize = 512;
+const XSAVE_HEADER_SIZE: usize = 64;
+const XSAVE_ALIGN: usize = 64;
+const FCW_OFFSET: usize = 0;
+const MXCSR_OFFSET: usize = 24;
+const XCOMP_BV_OFFSET: usize = LEGACY_AREA_SIZE + 8;
+/// The x87 control word and MXCSR after reset: all exceptions masked, round to nearest
+const FCW_DEFAULT: u16 = 0x037f;
+const MXCSR_DEFAULT: u32 = 0x1f80;
+const XCOMP_BV_COMPACTED: u64 = 1 << 63;
+const CR0_MP: u64 = 1 << 1;
+const CR0_EM: u64 = 1 << 2;
+const CR0_TS: u64 = 1 << 3;
+const CR0_NE: u64 = 1 << 5;
+const CR4_OSFXSR: u64 = 1 << 9;
+const CR4_OSXMMEXCPT: u64 = 1 << 10;
+const CR4_OSXSAVE: u64 = 1 << 18;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SaveInstr {
+    Fxsave,
+    Xsave,
+    /// Skips components that have not been modified since the area was last restored
+    Xsaveopt,
+    /// Like XSAVEOPT but uses the compacted format, which leaves out disabled components
+    Xsaves,
+}
+
+#[derive(Debug)]
+pub struct XstateFormat {
+    /// The state components that are enabled in XCR0
+    pub components: u64,
+    /// The size of an area in bytes
+    pub size: usize,
+    pub save_instr: SaveInstr,
+}
+
+impl XstateFormat {
+    fn detect() -> Self {
+        let has_xsave = unsafe { __cpuid_count(1, 0) }.ecx & (1 << 26) != 0;
+        if !has_xsave {
+            return XstateFormat {
+                components: 0b11,
+                size: LEGACY_AREA_SIZE,
+                save_instr: SaveInstr::Fxsave,
+            };
+        }
+        let supported = unsafe { __cpuid_count(0xd, 0) };
+        let components = ((supported.edx as u64) << 32 | supported.eax as u64) & USER_COMPONENTS;
+        let save_instrs = unsafe { __cpuid_count(0xd, 1) }.eax;
+        let save_instr = if save_instrs & (1 << 3) != 0 {
+            SaveInstr::Xsaves
+        } else if save_instrs & 1 != 0 {
+            SaveInstr::Xsaveopt
+        } else {
+            SaveInstr::Xsave
+        };
+        XstateFormat {
+            components,
+            size: get_area_size(components, save_instr == SaveInstr::Xsaves),
+            save_instr,
+        }
+    }
+}
+
+/// The size of an XSAVE area for the given components from their sizes and offsets in CPUID leaf
+/// 0xD
+fn get_area_size(components: u64, is_compacted: bool) -> usize {
+    let mut size = LEGACY_AREA_SIZE + XSAVE_HEADER_SIZE;
+    // x87 and SSE live in the legacy region
+    for component in (2..64).filter(|component| components & (1 << component) != 0) {
+        let info = unsafe { __cpuid_count(0xd, component) };
+        if is_compacted {
+            if info.ecx & (1 << 1) != 0 {
+                size = size.next_multiple_of(XSAVE_ALIGN);
+            }
+            size += info.eax as usize;
+        } else {
+            size = size.max((info.ebx + info.eax) as usize);
+        }
+    }
+    size
+}
+
+/// Enable the x87, SSE and XSAVE features on the calling LP. The first use of the registers raises
+/// `#NM` until the LP runs a thread.
+pub fn init_local() {
+    let format = &*XSTATE_FORMAT;
+    let cr4_bits = if format.save_instr == SaveInstr::Fxsave {
+        CR4_OSFXSR | CR4_OSXMMEXCPT
+    } else {
+        CR4_OSFXSR | CR4_OSXMMEXCPT | CR4_OSXSAVE
+    };
+    unsafe {
+        asm!(
+            "mov {tmp}, cr0",
+            "and {tmp}, {clear}",
+            "or {tmp}, {set}",
+            "mov cr0, {tmp}",
+            "mov {tmp}, cr4",
+            "or {tmp}, {cr4_bits}",
+            "mov cr4, {tmp}",
+            tmp = out(reg) _,
+            clear = in(reg) !CR0_EM,
+            set = in(reg) CR0_MP | CR0_NE | CR0_TS,
+            cr4_bits = in(reg) cr4_bits,
+            options(nomem, nostack, preserves_flags),
+        );
+        if format.save_instr != SaveInstr::Fxsave {
+            asm!(
+                "xsetbv",
+                in("ecx") 0,
+                in("eax") format.components as u32,
+                in("edx") (format.components >> 32) as u32,
+                options(nomem, nostack, preserves_flags),
+            );
+        }
+        if format.save_instr == SaveInstr::Xsaves {
+            // no supervisor state components are used
+            msrs::write(msrs::XSS, 0);
+        }
+    }
+}
+
+/// Run `f`, which may use the x87 and SIMD registers. The kernel is built without SIMD so any
+/// kernel code that uses those registers must run inside of this.
+///
+/// The registers of the thread the LP is running are saved first and restored when the thread
+/// next uses them. Interrupts are masked while `f` runs.
+pub fn with_simd<R>(f: impl FnOnce() -> R) -> R {
+    without_interrupts(|| {
+        unsafe {
+            save_loaded();
+            asm!("clts", options(nomem, nostack, preserves_flags));
+        }
+        let result = f();
+        set_ts();
+        result
+    })
+}
+
+/// Save the registers of the context the LP is switching away from if they are loaded and make
+/// the next context's first use of them raise `#NM`
+///
+/// # Safety
+/// Must be called during a context switch before the thread context pointer is changed.
+pub unsafe fn save_on_switch() {
+    unsafe { save_loaded() };
+    set_ts();
+}
+
+/// Load the registers of the current thread when it first uses them after being switched to.
+/// Called by the `#NM` handler.
+///
+/// Must run with interrupts masked. A switch after `TS` is cleared and before the registers are
+/// restored would save the registers of whichever context was loaded last into this thread's area.
+pub fn restore_on_first_use() {
+    let context = get_thread_context_ptr().into_mut::<ThreadContext>();
+    if context.is_null() {
+        panic!("An x87 or SIMD instruction was used outside of a thread and outside of with_simd");
+    }
+    unsafe {
+        asm!("clts", options(nomem, nostack, preserves_flags));
+        (*context).restore_xstate();
+    }
+}
+
+/// Save the registers to the current context if they hold its state
+unsafe fn save_loaded() {
+    if !is_ts_set() {
+        unsafe { (*get_thread_context_ptr().into_mut::<ThreadContext>()).save_xstate() };
+    }
+}
+
+fn is_ts_set() -> bool {
+    let cr0: u64;
+    unsafe {
+        asm!("mov {}, cr0", out(reg) cr0, options(nomem, nostack, preserves_flags));
+    }
+    cr0 & CR0_TS != 0
+}
+
+fn set_ts() {
+    unsafe {
+        asm!(
+            "mov {tmp}, cr0",
+            "or {tmp}, {ts}",
+            "mov cr0, {tmp}",
+            tmp = out(reg) _,
+            ts = in(reg) CR0_TS,
+            options(nomem, nostack, preserves_flags),
+        );
+    }
+}
+
+/// A buffer that holds the x87 and SIMD registers of one thread in the format of `XSTATE_FORMAT`
+pub struct XstateArea {
+    ptr: NonNull<u8>,
+}
+
+impl XstateArea {
+    /// An area that holds the initial state of the registers. Returns `None` if there is not
+    /// enough memory.
+    pub fn new() -> Option<Self> {
+        let ptr = NonNull::new(unsafe { alloc_zeroed(Self::layout()) })?;
+        unsafe {
+            ptr.add(FCW_OFFSET).cast::<u16>().write(FCW_DEFAULT);
+            ptr.add(MXCSR_OFFSET).cast::<u32>().write(MXCSR_DEFAULT);
+            // XRSTORS refuses areas that are not marked as compacted
+            if XSTATE_FORMAT.save_instr == SaveInstr::Xsaves {
+                ptr.add(XCOMP_BV_OFFSET)
+                    .cast::<u64>()
+                    .write(XCOMP_BV_COMPACTED | XSTATE_FORMAT.components);
+            }
+        }
+        Some(XstateArea {
+            ptr,
+        })
+    }
+
+    fn layout() -> Layout {
+        Layout::from_size_align(XSTATE_FORMAT.size, XSAVE_ALIGN).unwrap()
+    }
+
+    /// Save the registers of the calling LP to the area
+    ///
+    /// # Safety
+    /// CR0.TS must be clear.
+    pub unsafe fn save(&mut self) {
+        let ptr = self.ptr.as_ptr();
+        unsafe {
+            match XSTATE_FORMAT.save_instr {
+                SaveInstr::Fxsave => asm!("fxsave64 [{}]", in(reg) ptr, options(nostack)),
+                SaveInstr::Xsave => asm!(
+                    "xsave64 [{}]",
+                    in(reg) ptr,
+                    in("eax") u32::MAX,
+                    in("edx") u32::MAX,
+                    options(nostack),
+                ),
+                SaveInstr::Xsaveopt => asm!(
+                    "xsaveopt64 [{}]",
+                    in(reg) ptr,
+                    in("eax") u32::MAX,
+                    in("edx") u32::MAX,
+                    options(nostack),
+                ),
+                SaveInstr::Xsaves => asm!(
+                    "xsaves64 [{}]",
+                    in(reg) ptr,
+                    in("eax") u32::MAX,
+                    in("edx") u32::MAX,
+                    options(nostack),
+                ),
+            }
+        }
+    }
+
+    /// Load the registers of the calling LP from the area
+    ///
+    /// # Safety
+    /// CR0.TS must be clear and the registers' current contents are lost.
+    pub unsafe fn restore(&self) {
+        let ptr = self.ptr.as_ptr();
+        unsafe {
+            match XSTATE_FORMAT.save_instr {
+                SaveInstr::Fxsave => asm!("fxrstor64 [{}]", in(reg) ptr, options(nostack)),
+                SaveInstr::Xsave | SaveInstr::Xsaveopt => asm!(
+                    "xrstor64 [{}]",
+                    in(reg) ptr,
+                    in("eax") u32::MAX,
+                    in("edx") u32::MAX,
+                    options(nostack),
+                ),
+                SaveInstr::Xsaves => asm!(
+                    "xrstors64 [{}]",
+                    in(reg) ptr,
+                    in("eax") u32::MAX,
+                    in("edx") u32::MAX,
+                    options(nostack),
+                ),
+            }
+        }
+    }
+}
+
+impl Clone for XstateArea {
+    fn clone(&self) -> Self {
+        let ptr = NonNull::new(unsafe { alloc_zeroed(Self::layout()) })
+            .expect("Failed to allocate an XSAVE area");
+        unsafe {
+            ptr.copy_from_nonoverlapping(self.ptr, XSTATE_FORMAT.size);
+        }
+        XstateArea {
+            ptr,
+        }
+    }
+}
+
+impl Drop for XstateArea {
+    fn drop(&mut self) {
+        unsafe { dealloc(self.ptr.as_ptr(), Self::layout()) };
+    }
+}
+
+impl core::fmt::Debug for XstateArea {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "XstateArea({:?})", self.ptr)
+    }
+}
+
+unsafe impl Send for XstateArea {}
+unsafe impl Sync for XstateArea {}
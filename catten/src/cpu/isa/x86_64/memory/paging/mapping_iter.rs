@@ -0,0 +1,212 @@
+//! # Page Table Mapping Iterator
+//!
+//! This module walks every present entry of a 4-level x86-64 page table hierarchy in ascending
+//! virtual address order. Leaf entries at any level (4 KiB pages, 2 MiB large pages and 1 GiB huge
+//! pages) are reported with the effective permissions of the whole translation path and runs of
+//! leaves that continue each other both virtually and physically are coalesced into one
+//! `MappedRange`.
+//!
+//! The same walk also reports the frames of the page tables themselves, which is what tearing down
+//! or cloning an address space needs on top of its leaf mappings. Each table is reported once all
+//! of the entries in it have been walked, so it can be freed as soon as it is reported.
+
+use super::pth_walker::CR3_ADDRESS_MASK;
+use super::{AddressSpace, N_PAGE_TABLE_ENTRIES, PAGE_SIZE, PageTable};
+use crate::cpu::isa::interface::memory::address::Address;
+use crate::memory::linear::{MappedRange, PageFlags, PageTableFrame};
+use crate::memory::{PAddr, VAddr};
+
+const N_LEVELS: usize = 4;
+/// Amount of linear address space covered by a single entry at each level of the hierarchy
+const LEVEL_ENTRY_SPANS: [usize; N_LEVELS] =
+    [PAGE_SIZE << 27, PAGE_SIZE << 18, PAGE_SIZE << 9, PAGE_SIZE];
+const LEVEL_INDEX_SHIFTS: [usize; N_LEVELS] = [39, 30, 21, 12];
+
+/// An entry found by walking the page table hierarchy
+enum WalkEntry {
+    Leaf(MappedRange),
+    Table(PageTableFrame),
+}
+
+/// Walks the page table hierarchy of an x86-64 address space depth first
+struct Walker<'vas> {
+    _address_space: &'vas AddressSpace,
+    tables: [*const PageTable; N_LEVELS],
+    table_frames: [PAddr; N_LEVELS],
+    indices: [usize; N_LEVELS],
+    /// Permissions granted by the entries above each level
+    inherited: [PageFlags; N_LEVELS],
+    depth: usize,
+}
+
+impl<'vas> Walker<'vas> {
+    fn new(address_space: &'vas AddressSpace) -> Self {
+        let pml4_paddr = PAddr::try_from((address_space.get_cr3() & CR3_ADDRESS_MASK) as usize)
+            .ok()
+            .filter(|pml4_paddr| !pml4_paddr.is_null());
+        let pml4_ptr: *const PageTable = match pml4_paddr {
+            Some(pml4_paddr) => pml4_paddr.into(),
+            None => core::ptr::null(),
+        };
+        let unrestricted = PageFlags {
+            writable: true,
+            user_accessible: true,
+            no_execute: false,
+            global: false,
+        };
+        // An address space without a top level table has no mappings at all
+        let first_pml4_index = if pml4_ptr.is_null() {
+            N_PAGE_TABLE_ENTRIES
+        } else {
+            0
+        };
+        Walker {
+            _address_space: address_space,
+            tables: [pml4_ptr, core::ptr::null(), core::ptr::null(), core::ptr::null()],
+            table_frames: [pml4_paddr.unwrap_or_default(); N_LEVELS],
+            indices: [first_pml4_index, 0, 0, 0],
+            inherited: [unrestricted; N_LEVELS],
+            depth: 0,
+        }
+    }
+
+    /// The address mapped by the current entry at each level down to and including `depth`
+    fn entry_vaddr(&self, depth: usize) -> VAddr {
+        let mut raw = 0usize;
+        for level in 0..=depth {
+            raw |= self.indices[level] << LEVEL_INDEX_SHIFTS[level];
+        }
+        // VAddr::from sign extends the address into canonical form
+        VAddr::from(raw)
+    }
+
+    /// Advance to the next present leaf entry, returned as a single page range, or to the next
+    /// table whose entries have all been walked
+    fn next_entry(&mut self) -> Option<WalkEntry> {
+        loop {
+            let depth = self.depth;
+            if self.indices[depth] == N_PAGE_TABLE_ENTRIES {
+                if self.tables[depth].is_null() {
+                    return None;
+                }
+                let table = PageTableFrame {
+                    vaddr: match depth {
+                        0 => VAddr::from(0usize),
+                        _ => self.entry_vaddr(depth - 1),
+                    },
+                    paddr: self.table_frames[depth],
+                    level: depth,
+                };
+                if depth == 0 {
+                    // the walk is over once the top level table has been reported
+                    self.tables[0] = core::ptr::null();
+                } else {
+                    self.depth -= 1;
+                    self.indices[self.depth] += 1;
+                }
+                return Some(WalkEntry::Table(table));
+            }
+            let entry = unsafe { &(*self.tables[depth])[self.indices[depth]] };
+            let frame = match entry.try_get_frame() {
+                Ok(frame) if entry.is_present() => frame,
+                _ => {
+                    self.indices[depth] += 1;
+                    continue;
+                }
+            };
+            let parent = self.inherited[depth];
+            let flags = PageFlags {
+                writable: parent.writable && entry.is_writable(),
+                user_accessible: parent.user_accessible && entry.is_user_accessible(),
+                no_execute: parent.no_execute || entry.is_execute_disabled(),
+                global: entry.is_global(),
+            };
+            // The page size bit is only meaningful in PDPT and PD entries
+            let is_leaf = depth == N_LEVELS - 1 || (depth > 0 && entry.get_page_size());
+            if is_leaf {
+                let page_size = LEVEL_ENTRY_SPANS[depth];
+                let leaf = MappedRange {
+                    vaddr: self.entry_vaddr(depth),
+                    // bit 12 of a large page entry is the PAT bit rather than part of the frame
+                    paddr: frame.prev_aligned_to(page_size),
+                    length: page_size,
+                    page_size,
+                    flags,
+                };
+                self.indices[depth] += 1;
+                return Some(WalkEntry::Leaf(leaf));
+            }
+            self.inherited[depth + 1] = flags;
+            self.tables[depth + 1] = frame.into();
+            self.table_frames[depth + 1] = frame;
+            self.indices[depth + 1] = 0;
+            self.depth += 1;
+        }
+    }
+
+    fn next_leaf(&mut self) -> Option<MappedRange> {
+        loop {
+            if let WalkEntry::Leaf(leaf) = self.next_entry()? {
+                return Some(leaf);
+            }
+        }
+    }
+}
+
+/// Iterator over the coalesced present mappings of an x86-64 address space
+pub struct MappingIter<'vas> {
+    walker:  Walker<'vas>,
+    pending: Option<MappedRange>,
+}
+
+impl<'vas> MappingIter<'vas> {
+    pub fn new(address_space: &'vas AddressSpace) -> Self {
+        MappingIter {
+            walker:  Walker::new(address_space),
+            pending: None,
+        }
+    }
+}
+
+impl Iterator for MappingIter<'_> {
+    type Item = MappedRange;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut range = match self.pending.take() {
+            Some(range) => range,
+            None => self.walker.next_leaf()?,
+        };
+        while let Some(leaf) = self.walker.next_leaf() {
+            if !range.try_coalesce(&leaf) {
+                self.pending = Some(leaf);
+                break;
+            }
+        }
+        Some(range)
+    }
+}
+
+/// Iterator over the page tables of an x86-64 address space, each one after the tables below it
+pub struct PageTableIter<'vas> {
+    walker: Walker<'vas>,
+}
+
+impl<'vas> PageTableIter<'vas> {
+    pub fn new(address_space: &'vas AddressSpace) -> Self {
+        PageTableIter {
+            walker: Walker::new(address_space),
+        }
+    }
+}
+
+impl Iterator for PageTableIter<'_> {
+    type Item = PageTableFrame;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let WalkEntry::Table(table) = self.walker.next_entry()? {
+                return Some(table);
+            }
+        }
+    }
+}
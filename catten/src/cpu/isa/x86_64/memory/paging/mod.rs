@@ -1,3 +1,4 @@
+pub mod mapping_iter;
 pub mod pte;
 pub mod pth_walker;
 
@@ -10,7 +11,13 @@ use spin::Mutex;
 
 use super::MemoryInterfaceImpl;
 use super::address::vaddr::VAddr;
-use crate::cpu::isa::interface::memory::{AddressSpaceInterface, MemoryInterface, MemoryMapping};
+use crate::cpu::isa::interface::memory::{
+    AddressSpaceInterface,
+    MappedRange,
+    MemoryInterface,
+    MemoryMapping,
+    PageTableFrame,
+};
 use crate::logln;
 use crate::memory::{AddressSpaceId, PAddr};
 
@@ -47,7 +54,8 @@ pub type PageTable = [pte::PageTableEntry; N_PAGE_TABLE_ENTRIES];
 pub fn is_pagetable_unused(table_ptr: NonNull<PageTable>) -> bool {
     unsafe {
         for i in 0..N_PAGE_TABLE_ENTRIES {
-            if (table_ptr.as_ref())[i].is_present() {
+            // swap entries must outlive the pages they replaced
+            if (table_ptr.as_ref())[i].is_present() || (table_ptr.as_ref())[i].is_swap_entry() {
                 return false;
             }
         }
@@ -78,6 +86,11 @@ impl AddressSpaceInterface for AddressSpace {
         }
     }
 
+    fn is_active(&self) -> bool {
+        let current = Self::get_current();
+        current.cr3 & pth_walker::CR3_ADDRESS_MASK == self.cr3 & pth_walker::CR3_ADDRESS_MASK
+    }
+
     fn load(&self) -> Result<(), <MemoryInterfaceImpl as MemoryInterface>::Error> {
         unsafe {
             // Set the top level page table base register
@@ -171,4 +184,93 @@ impl AddressSpaceInterface for AddressSpace {
         let paddr = unsafe { (*(walker.pt_ptr))[vaddr.pt_index()].try_get_frame()?.into() };
         Ok(paddr)
     }
+
+    fn test_and_clear_accessed(
+        &mut self,
+        vaddr: VAddr,
+    ) -> Result<bool, <MemoryInterfaceImpl as MemoryInterface>::Error> {
+        let mut walker = pth_walker::PthWalker::new(self, vaddr);
+        walker.walk()?;
+        let pte = unsafe { &mut (*walker.pt_ptr)[vaddr.pt_index()] };
+        let accessed = pte.is_accessed();
+        if accessed {
+            pte.set_accessed(false);
+        }
+        Ok(accessed)
+    }
+
+    fn is_dirty(
+        &mut self,
+        vaddr: VAddr,
+    ) -> Result<bool, <MemoryInterfaceImpl as MemoryInterface>::Error> {
+        let mut walker = pth_walker::PthWalker::new(self, vaddr);
+        walker.walk()?;
+        Ok(unsafe { (*walker.pt_ptr)[vaddr.pt_index()].is_dirty() })
+    }
+
+    fn swap_out_page(
+        &mut self,
+        vaddr: VAddr,
+        swap_slot: usize,
+    ) -> Result<PAddr, <MemoryInterfaceImpl as MemoryInterface>::Error> {
+        let mut walker = pth_walker::PthWalker::new(self, vaddr);
+        walker.walk()?;
+        let pte = unsafe { &mut (*walker.pt_ptr)[vaddr.pt_index()] };
+        let frame = pte.try_get_frame()?;
+        pte.set_swap_slot(swap_slot);
+        Ok(frame)
+    }
+
+    fn get_swap_slot(
+        &mut self,
+        vaddr: VAddr,
+    ) -> Result<Option<usize>, <MemoryInterfaceImpl as MemoryInterface>::Error> {
+        let mut walker = pth_walker::PthWalker::new(self, vaddr);
+        match walker.walk() {
+            Ok(_) => Ok(None),
+            // the walk stops at the first missing level so the page table only exists if the
+            // walker got as far as the last level
+            Err(<MemoryInterfaceImpl as MemoryInterface>::Error::Unmapped)
+                if !walker.pt_ptr.is_null() =>
+            {
+                Ok(unsafe { (*walker.pt_ptr)[vaddr.pt_index()].get_swap_slot() })
+            }
+            Err(<MemoryInterfaceImpl as MemoryInterface>::Error::Unmapped) => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    fn set_page_writable(
+        &mut self,
+        vaddr: VAddr,
+        writable: bool,
+    ) -> Result<bool, <MemoryInterfaceImpl as MemoryInterface>::Error> {
+        let mut walker = pth_walker::PthWalker::new(self, vaddr);
+        walker.walk()?;
+        let pte = unsafe { &mut (*walker.pt_ptr)[vaddr.pt_index()] };
+        let was_writable = pte.is_writable();
+        pte.set_writable(writable);
+        Ok(was_writable)
+    }
+
+    fn remap_page(
+        &mut self,
+        vaddr: VAddr,
+        frame: PAddr,
+    ) -> Result<PAddr, <MemoryInterfaceImpl as MemoryInterface>::Error> {
+        let mut walker = pth_walker::PthWalker::new(self, vaddr);
+        walker.walk()?;
+        let pte = unsafe { &mut (*walker.pt_ptr)[vaddr.pt_index()] };
+        let old_frame = pte.try_get_frame()?;
+        pte.set_frame(frame);
+        Ok(old_frame)
+    }
+
+    fn mappings(&self) -> impl Iterator<Item = MappedRange> + '_ {
+        mapping_iter::MappingIter::new(self)
+    }
+
+    fn page_tables(&self) -> impl Iterator<Item = PageTableFrame> + '_ {
+        mapping_iter::PageTableIter::new(self)
+    }
 }
@@ -16,6 +16,11 @@ const ACCESSED_BIT_INDEX: u64 = 5;
 const DIRTY_BIT_INDEX: u64 = 6;
 const PAGE_SIZE_BIT_INDEX: u64 = 7; // only for PTEs pointing to a 2 MiB or 1 GiB page
 const GLOBAL_BIT_INDEX: u64 = 8;
+/// Software defined bit marking a non-present entry as referring to a page that has been swapped
+/// out. Bits 1 to 63 of a non-present entry are ignored by the MMU.
+const SWAPPED_BIT_INDEX: u64 = 9;
+/// Swap slot numbers are stored where the frame address would be in a present entry
+const SWAP_SLOT_SHIFT: u64 = 12;
 
 static FRAME_ADDR_MASK: Lazy<u64> =
     Lazy::new(|| 0xfffffffffffff000 & *super::super::address::PADDR_MASK as u64);
@@ -44,6 +49,12 @@ impl PageTableEntry {
         pte
     }
 
+    /// Reset the entry to the all zero, not present state
+    pub fn clear(&mut self) -> &mut Self {
+        self.0 = 0;
+        self
+    }
+
     pub fn is_present(&self) -> bool {
         self.0 & (1 << PRESENT_BIT_INDEX) != 0
     }
@@ -173,6 +184,26 @@ impl PageTableEntry {
         self
     }
 
+    /// Whether this entry refers to a swapped out page rather than a frame
+    pub fn is_swap_entry(&self) -> bool {
+        !self.is_present() && self.0 & (1 << SWAPPED_BIT_INDEX) != 0
+    }
+
+    pub fn get_swap_slot(&self) -> Option<usize> {
+        if self.is_swap_entry() {
+            Some(((self.0 & *FRAME_ADDR_MASK) >> SWAP_SLOT_SHIFT) as usize)
+        } else {
+            None
+        }
+    }
+
+    /// Turn this entry into a non-present swap entry referring to `slot`, discarding everything
+    /// else it contained.
+    pub fn set_swap_slot(&mut self, slot: usize) -> &mut Self {
+        self.0 = (1 << SWAPPED_BIT_INDEX) | (((slot as u64) << SWAP_SLOT_SHIFT) & *FRAME_ADDR_MASK);
+        self
+    }
+
     pub fn is_uncached(&self) -> bool {
         self.0 & (0b11 << PAT_INDEX_0) == 0
     }
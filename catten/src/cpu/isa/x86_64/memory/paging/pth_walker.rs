@@ -7,14 +7,14 @@
 
 use core::ptr::NonNull;
 
-use super::{PAGE_SIZE, is_pagetable_unused};
+use super::is_pagetable_unused;
 use crate::cpu::isa::interface::memory::address::VirtualAddress;
 use crate::cpu::isa::interface::memory::{AddressSpaceInterface, MemoryInterface};
 use crate::cpu::isa::x86_64::memory::address::paddr::PAddr;
 use crate::cpu::isa::x86_64::memory::address::vaddr::VAddr;
-use crate::memory::PHYSICAL_FRAME_ALLOCATOR;
+use crate::memory::{PHYSICAL_FRAME_ALLOCATOR, reclaim};
 
-const CR3_ADDRESS_MASK: u64 = 0x000ffffffffff000;
+pub(super) const CR3_ADDRESS_MASK: u64 = 0x000ffffffffff000;
 
 pub struct PthWalker<'vas> {
     pub address_space: &'vas mut super::AddressSpace,
@@ -91,7 +91,7 @@ impl<'vas> PthWalker<'vas> {
                     // table as they are all required to map the kernel and
                     // higher half memory.
                     if self.address_space.cr3 & CR3_ADDRESS_MASK == 0 {
-                        let new_pml4 = PHYSICAL_FRAME_ALLOCATOR.lock().allocate_frame().unwrap();
+                        let new_pml4 = reclaim::allocate_zeroed_frame()?;
                         self.address_space.cr3 =
                             <PAddr as Into<u64>>::into(new_pml4) & CR3_ADDRESS_MASK;
                         self.address_space.load().expect("Error reloading the CR3 register");
@@ -100,13 +100,12 @@ impl<'vas> PthWalker<'vas> {
                         PAddr::try_from((self.address_space.cr3 & CR3_ADDRESS_MASK) as usize)
                             .unwrap()
                             .into();
-                    unsafe {
-                        core::ptr::write_bytes(self.pml4_ptr, 0, PAGE_SIZE);
-                    }
                 }
                 if self.pdpt_ptr.is_null() {
-                    // Allocate a new page table for the PDPT
-                    let new_pdpt = PHYSICAL_FRAME_ALLOCATOR.lock().allocate_frame().unwrap();
+                    // Allocate a new page table for the PDPT; page tables are allocated through
+                    // reclaim like any other frame so running low on memory does not fail the
+                    // mapping before the shrinkers have run
+                    let new_pdpt = reclaim::allocate_zeroed_frame()?;
                     unsafe {
                         (*self.pml4_ptr)[self.vaddr.pml4_index()]
                             .set_frame(new_pdpt)
@@ -116,13 +115,10 @@ impl<'vas> PthWalker<'vas> {
                             .set_execute_disabled(no_execute);
                     }
                     self.pdpt_ptr = new_pdpt.into();
-                    unsafe {
-                        core::ptr::write_bytes(self.pdpt_ptr, 0, PAGE_SIZE);
-                    }
                 }
                 if self.pd_ptr.is_null() {
                     // Allocate a new page table for the PD
-                    let new_pd = PHYSICAL_FRAME_ALLOCATOR.lock().allocate_frame().unwrap();
+                    let new_pd = reclaim::allocate_zeroed_frame()?;
                     unsafe {
                         (*self.pdpt_ptr)[self.vaddr.pdpt_index()]
                             .set_frame(new_pd)
@@ -132,13 +128,10 @@ impl<'vas> PthWalker<'vas> {
                             .set_execute_disabled(no_execute);
                     }
                     self.pd_ptr = new_pd.into();
-                    unsafe {
-                        core::ptr::write_bytes(self.pd_ptr, 0, PAGE_SIZE);
-                    }
                 }
                 if self.pt_ptr.is_null() {
                     // Allocate a new page table for the PT
-                    let new_pt = PHYSICAL_FRAME_ALLOCATOR.lock().allocate_frame().unwrap();
+                    let new_pt = reclaim::allocate_zeroed_frame()?;
                     unsafe {
                         (*self.pd_ptr)[self.vaddr.pd_index()]
                             .set_frame(new_pt)
@@ -148,22 +141,21 @@ impl<'vas> PthWalker<'vas> {
                             .set_execute_disabled(no_execute);
                     }
                     self.pt_ptr = new_pt.into();
-                    unsafe {
-                        core::ptr::write_bytes(self.pt_ptr, 0, PAGE_SIZE);
-                    }
                 }
                 // Map the page frame
                 unsafe {
+                    // start from a clean entry so no stale accessed, dirty or swap state survives
                     (*self.pt_ptr)[self.vaddr.pt_index()]
+                        .clear()
                         .set_frame(frame)
                         .set_present(true)
                         .set_writable(writable)
                         .set_user_accessible(user_accessible)
                         .set_execute_disabled(no_execute);
-                    // for those who may not immediately see it, this is the Rust equivalent of
-                    // memset being used to clear the newly mapped page
-                    core::ptr::write_bytes(<PAddr as Into<*mut u8>>::into(frame), 0, PAGE_SIZE);
                 }
+                // The frame is mapped as is. Clearing freshly allocated frames is the job of the
+                // allocation site since frames shared between address spaces or belonging to
+                // firmware tables must keep their contents.
                 self.address_space.load().expect("Failed to reload the address space");
                 unsafe {
                     // Get rid of any stale TLB entries referring to the linear address space
@@ -222,7 +214,6 @@ impl<'vas> PthWalker<'vas> {
                             .unwrap();
                         (*pml4e).set_present(false);
                     }
-                    //super::tlb::invalidate_page(self.address_space, self.vaddr);
                     Ok(paddr)
                 }
             }
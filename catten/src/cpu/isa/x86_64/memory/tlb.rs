@@ -1,34 +1,43 @@
 use core::arch::asm;
 
-use crate::cpu::isa::memory::paging::PAGE_SIZE;
+use crate::cpu::isa::lp::ops::{get_lp_id, without_interrupts};
+use crate::cpu::isa::memory::paging::{HwAsid, PAGE_SIZE};
 use crate::cpu::scheduler::system_scheduler::SYSTEM_SCHEDULER;
 use crate::memory::{AddressSpaceId, VAddr};
 
+/// Invalidate the calling LP's translations of `size` pages starting at `base` in the address
+/// space identified by `asid`, whether or not it is the loaded one
 pub fn inval_range_user(asid: AddressSpaceId, base: VAddr, size: usize) {
-    // SAFETY: This is safe because we are executing in an interrupt context where
-    // preemption is disabled, and we are not modifying any data structures that
-    // could be accessed by other threads.
-    if let Some(pcid) = SYSTEM_SCHEDULER.get_local_scheduler().lock().asid_to_hwasid(asid) {
-        let raw_base = <VAddr as Into<usize>>::into(base);
-        for page in (raw_base..raw_base + size * PAGE_SIZE).step_by(PAGE_SIZE) {
-            let descriptor: [u64; 2] = [page as u64, pcid.get_inner() as u64];
-            unsafe {
-                asm!(
-                    "invpcid {mode:r}, [{desc_ptr}]",
-                    mode = in(reg) 0,
-                    desc_ptr = in(reg) &descriptor,
-                    options(nostack, preserves_flags),
-                );
-            }
+    let Some(pcid) = get_pcid(asid) else {
+        inval_all_user();
+        return;
+    };
+    let Some(pcid) = pcid else {
+        // Without a PCID the TLB only holds translations of the loaded address space, so
+        // invalidating the pages there is enough even if a different one is loaded.
+        inval_range_kernel(base, size);
+        return;
+    };
+    let raw_base = <VAddr as Into<usize>>::into(base);
+    for page in (raw_base..raw_base + size * PAGE_SIZE).step_by(PAGE_SIZE) {
+        let descriptor: [u64; 2] = [page as u64, pcid.get_inner() as u64];
+        unsafe {
+            asm!(
+                "invpcid {mode:r}, [{desc_ptr}]",
+                mode = in(reg) 0,
+                desc_ptr = in(reg) &descriptor,
+                options(nostack, preserves_flags),
+            );
         }
     }
 }
 
 pub fn inval_asid(asid: AddressSpaceId) {
-    // SAFETY: This is safe because we are executing in an interrupt context where
-    // preemption is disabled, and we are not modifying any data structures that
-    // could be accessed by other threads.
-    if let Some(pcid) = SYSTEM_SCHEDULER.get_local_scheduler().lock().asid_to_hwasid(asid) {
+    let Some(pcid) = get_pcid(asid) else {
+        inval_all_user();
+        return;
+    };
+    if let Some(pcid) = pcid {
         let descriptor: [u64; 2] = [0, pcid.get_inner() as u64];
         unsafe {
             asm!(
@@ -41,6 +50,18 @@ pub fn inval_asid(asid: AddressSpaceId) {
     }
 }
 
+/// Invalidate every translation of the calling LP that is not global by reloading CR3
+pub fn inval_all_user() {
+    unsafe {
+        asm!(
+            "mov {tmp}, cr3",
+            "mov cr3, {tmp}",
+            tmp = out(reg) _,
+            options(nostack, preserves_flags),
+        );
+    }
+}
+
 pub fn inval_range_kernel(base: VAddr, num_pages: usize) {
     let raw_base = <VAddr as Into<usize>>::into(base);
     let len_bytes = num_pages * PAGE_SIZE;
@@ -54,3 +75,14 @@ pub fn inval_range_kernel(base: VAddr, num_pages: usize) {
         }
     }
 }
+
+/// The PCID that `asid` is tagged with on the calling LP. Returns `None` if the LP has not
+/// registered with the system scheduler yet, in which case it is unknown which address spaces the
+/// LP has loaded.
+fn get_pcid(asid: AddressSpaceId) -> Option<Option<HwAsid>> {
+    without_interrupts(|| {
+        SYSTEM_SCHEDULER
+            .get_lp_scheduler(get_lp_id())
+            .map(|local_scheduler| local_scheduler.lock().asid_to_hwasid(asid))
+    })
+}
@@ -5,7 +5,8 @@ use core::mem::transmute;
 
 use spin::lazy::Lazy;
 
-use crate::cpu::isa::interface::system_info::CpuInfoIfce;
+use crate::cpu::isa::interface::system_info::{CpuInfoIfce, LpTopology};
+use crate::environment::acpi::srat;
 
 pub static IS_CPUID_SUPPORTED: Lazy<bool> = Lazy::new(is_cpuid_supported);
 
@@ -29,6 +30,41 @@ fn is_cpuid_supported() -> bool {
     }
 }
 
+/// The number of low order x2APIC ID bits that differ between LPs sharing the last level cache,
+/// from CPUID leaf 4 on Intel and leaf 0x8000001D on AMD
+fn get_llc_sharing_shift() -> Option<u32> {
+    let leaf = match CpuInfo::get_vendor().as_str() {
+        "AuthenticAMD" | "HygonGenuine" => {
+            // the leaf is only valid with the topology extensions
+            let has_topology_ext = unsafe { __cpuid_count(0x8000_0001, 0) }.ecx & (1 << 22) != 0;
+            if !has_topology_ext {
+                return None;
+            }
+            0x8000_001d
+        }
+        _ => {
+            if unsafe { __cpuid_count(0, 0) }.eax < 4 {
+                return None;
+            }
+            4
+        }
+    };
+    let mut llc: Option<(u32, u32)> = None;
+    for subleaf in 0.. {
+        let cache_params = unsafe { __cpuid_count(leaf, subleaf) };
+        // a cache type of 0 means that there are no more caches
+        if cache_params.eax & 0x1f == 0 {
+            break;
+        }
+        let level = (cache_params.eax >> 5) & 0x7;
+        let n_sharing = ((cache_params.eax >> 14) & 0xfff) + 1;
+        if llc.is_none_or(|(llc_level, _)| level > llc_level) {
+            llc = Some((level, n_sharing));
+        }
+    }
+    llc.map(|(_, n_sharing)| n_sharing.next_power_of_two().trailing_zeros())
+}
+
 pub enum IsaExtension {
     /* indicates support for 5-level paging i.e. 57 bit linear addresses */
     La57,
@@ -38,6 +74,10 @@ pub enum IsaExtension {
     InvariantTsc,
     /* TSC_AUX MSR and RDPID instruction */
     Rdpid,
+    /* `monitor` and `mwait`, which wait for a write to a monitored cache line */
+    MonitorMwait,
+    /* Always Running APIC Timer i.e. the local APIC timer keeps counting in deep C-states */
+    Arat,
 }
 
 pub struct CpuInfo;
@@ -125,6 +165,42 @@ impl CpuInfoIfce for CpuInfo {
                 let cpuid_result = __cpuid_count(0x0000_0007, 0);
                 (cpuid_result.ecx & 1 << 22) != 0
             },
+            IsaExtension::MonitorMwait => unsafe {
+                let cpuid_result = __cpuid_count(0x0000_0001, 0);
+                (cpuid_result.ecx & 1 << 3) != 0
+            },
+            IsaExtension::Arat => unsafe {
+                if __cpuid_count(0, 0).eax < 6 {
+                    return false;
+                }
+                let cpuid_result = __cpuid_count(0x0000_0006, 0);
+                (cpuid_result.eax & 1 << 2) != 0
+            },
+        }
+    }
+
+    fn get_local_topology() -> LpTopology {
+        // leaf 0xB gives the full x2APIC ID and the width of its SMT level
+        let (x2apic_id, smt_shift) = if unsafe { __cpuid_count(0, 0) }.eax >= 0xb {
+            let topology = unsafe { __cpuid_count(0xb, 0) };
+            let is_smt_level = (topology.ecx >> 8) & 0xff == 1;
+            (
+                topology.edx,
+                if is_smt_level {
+                    topology.eax & 0x1f
+                } else {
+                    0
+                },
+            )
+        } else {
+            (unsafe { __cpuid_count(1, 0) }.ebx >> 24, 0)
+        };
+        let llc_shift = get_llc_sharing_shift().unwrap_or(smt_shift);
+        LpTopology {
+            core_id: x2apic_id >> smt_shift,
+            llc_id: x2apic_id >> llc_shift,
+            // without an SRAT all LPs are assumed to be in the same node
+            numa_node: srat::get_proximity_domain(x2apic_id).unwrap_or(0),
         }
     }
 }
@@ -4,6 +4,7 @@ use super::tsc::TSC_CYCLE_PERIOD;
 use crate::common::time::duration::ExtDuration;
 use crate::cpu::isa::interface::interrupts::LocalIntCtlrIfce;
 use crate::cpu::isa::interface::timers::{LpTimerError, LpTimerIfce};
+use crate::cpu::isa::interrupts::fixed::vector_assignments::CONTEXT_SWITCH_VECTOR;
 use crate::cpu::isa::interrupts::x2apic::X2Apic;
 //use crate::cpu::isa::interrupts::x2apic::X2Apic;
 use crate::cpu::isa::timers::tsc::rdtsc;
@@ -83,6 +84,13 @@ impl LpTimerIfce for ApicTimer {
 
     const NAME: &'static str = "x86-64 x2APIC Timer";
 
+    fn new_preemption_timer() -> Self {
+        let timer = Self::new(CONTEXT_SWITCH_VECTOR);
+        // calibration leaves the timer masked
+        X2Apic::set_timer_lvt_entry(CONTEXT_SWITCH_VECTOR, false);
+        timer
+    }
+
     fn get_resolution(&self) -> Result<ExtDuration, LpTimerError> {
         Ok(self.resolution)
     }
@@ -3,3 +3,13 @@ pub mod i8254;
 pub mod tsc;
 
 pub use apic_timer::LpTimer;
+
+use crate::common::constants::PICOS_PER_SEC;
+use crate::common::time::duration::ExtDuration;
+
+/// The time elapsed since the timestamp counter was reset
+///
+/// It never goes backwards on a given LP and it is consistent across LPs if the TSC is invariant.
+pub fn get_monotonic_time() -> ExtDuration {
+    ExtDuration::from_picos(tsc::rdtsc() as u128 * PICOS_PER_SEC / *tsc::TSC_FREQUENCY_HZ as u128)
+}
@@ -6,19 +6,21 @@
 //! logical processors) IPIs. The implementation is kept as architecture indepent as possible.
 
 use alloc::boxed::Box;
-use alloc::collections::vec_deque::VecDeque;
 use alloc::vec::Vec;
-use core::sync::atomic::AtomicPtr;
 use core::sync::atomic::Ordering::*;
+use core::sync::atomic::{AtomicPtr, AtomicU64, AtomicUsize};
 
-use spin::barrier::Barrier;
+use spin::Once;
 use spin::rwlock::RwLock;
-use spin::{Lazy, Mutex};
 
 use crate::common::collections::boxed_slice::make_boxed_slice;
+use crate::cpu::isa::interface::interrupts::LocalIntCtlrIfce;
+use crate::cpu::isa::interrupts::LocalIntCtlr;
 use crate::cpu::isa::lp::LpId;
 use crate::cpu::isa::memory::tlb;
 use crate::cpu::multiprocessor::get_lp_count;
+use crate::cpu::scheduler::deferred::softirq::{self, SoftIrq};
+use crate::cpu::scheduler::sync::lockdep::IrqContext;
 use crate::cpu::scheduler::system_scheduler::SYSTEM_SCHEDULER;
 use crate::cpu::scheduler::threads::ThreadId;
 use crate::get_lp_id;
@@ -27,18 +29,23 @@ use crate::memory::{AddressSpaceId, KERNEL_ASID};
 
 pub struct IpiRpcReq {
     pub sender_lp_id: LpId,
-    pub recipient_lp_ids: Vec<LpId>,
     pub request_id: u64,
     pub rpc: IpiRpc,
     pub hash: u64,
-    pub completion_barrier: Option<Barrier>,
+    /// The number of recipients that have not finished running the RPC yet. The request must stay
+    /// alive until this reaches zero.
+    pub pending_acks: AtomicUsize,
 }
 
+#[derive(Debug)]
 pub enum Error {
     MailboxBusy,
+    /// The recipient can not be sent IPIs yet
+    RecipientUnreachable,
 }
 
-pub static IPI_RPC_MAILBOXES: Lazy<IpiRpcMailbox> = Lazy::new(IpiRpcMailbox::new);
+pub static IPI_RPC_MAILBOXES: Once<IpiRpcMailbox> = Once::new();
+static NEXT_REQUEST_ID: AtomicU64 = AtomicU64::new(0);
 
 pub struct IpiRpcMailbox {
     unicast: Box<[AtomicPtr<IpiRpcReq>]>,
@@ -86,6 +93,19 @@ impl IpiRpcMailbox {
         }
     }
 
+    /// Remove `req` from the unicast mailbox of `dest` unless the recipient has already taken it.
+    /// Returns whether the request was withdrawn.
+    pub fn withdraw_unicast(&self, dest: LpId, req: *mut IpiRpcReq) -> bool {
+        self.unicast[dest as usize]
+            .compare_exchange(req, core::ptr::null_mut(), AcqRel, Acquire)
+            .is_ok()
+    }
+
+    /// Take the request out of the calling LP's unicast mailbox, leaving it free for the next one
+    pub fn take_own_unicast(&self) -> *mut IpiRpcReq {
+        self.unicast[get_lp_id() as usize].swap(core::ptr::null_mut(), AcqRel)
+    }
+
     pub fn read_own_unicast(&self) -> *mut IpiRpcReq {
         self.unicast[get_lp_id() as usize].load(Acquire)
     }
@@ -111,21 +131,134 @@ pub enum IpiRpc {
     AbortAsThreads(AddressSpaceId),
 }
 
-#[unsafe(no_mangle)]
-pub extern "C" fn ih_interprocessor_interrupt(ipi_queue: &'static mut Mutex<VecDeque<IpiRpc>>) {
-    while let Some(ipi) = ipi_queue.lock().pop_front() {
-        match ipi {
-            IpiRpc::VMemInval(asid, base, size) => {
-                if asid == KERNEL_ASID {
-                    tlb::inval_range_kernel(base, size);
-                } else {
-                    tlb::inval_range_user(asid, base, size);
-                }
-            }
-            IpiRpc::AsidInval(asid) => tlb::inval_asid(asid),
-            IpiRpc::TerminateThreads(tids) => SYSTEM_SCHEDULER.terminate_threads(tids),
-            IpiRpc::AbortThreads(tids) => SYSTEM_SCHEDULER.abort_threads(tids),
-            IpiRpc::AbortAsThreads(asid) => SYSTEM_SCHEDULER.abort_as_threads(asid),
+/// Create the RPC mailboxes. Requires the kernel heap.
+pub fn init() {
+    IPI_RPC_MAILBOXES.call_once(IpiRpcMailbox::new);
+}
+
+/// Invalidate the translations of `n_pages` pages starting at `base` in the address space
+/// identified by `asid` on every LP, the calling one included, and wait until all of them have
+/// done so.
+///
+/// This must be called after changing or removing a mapping and before the frame it pointed to is
+/// reused.
+pub fn flush_tlb(asid: AddressSpaceId, base: VAddr, n_pages: usize) {
+    inval_range(asid, base, n_pages);
+    tlb_shootdown(asid, base, n_pages);
+}
+
+/// Invalidate the translations of `n_pages` pages starting at `base` in the address space
+/// identified by `asid` on every other LP and wait until all of them have done so.
+///
+/// The caller is responsible for invalidating the translations on its own LP.
+pub fn tlb_shootdown(asid: AddressSpaceId, base: VAddr, n_pages: usize) {
+    let sender_lp_id = get_lp_id();
+    let recipient_lp_ids = (0..get_lp_count()).filter(move |&id| id != sender_lp_id);
+    // An LP that is not known to the local interrupt controller yet has not finished
+    // initialization and holds no translations that need to be invalidated.
+    let _ = run_remote_rpc(recipient_lp_ids, IpiRpc::VMemInval(asid, base, n_pages));
+}
+
+/// Run an RPC on another LP and wait until it has done so
+pub fn send_unicast_rpc(dest: LpId, rpc: IpiRpc) -> Result<(), Error> {
+    if run_remote_rpc(core::iter::once(dest), rpc) == 0 {
+        Ok(())
+    } else {
+        Err(Error::RecipientUnreachable)
+    }
+}
+
+/// Run an RPC on each of the recipients and wait until all of them have done so. Returns the
+/// number of recipients that could not be sent an IPI, which have not run it.
+///
+/// Nothing is allocated so that TLB shootdowns can be sent while the kernel heap is locked.
+fn run_remote_rpc(recipient_lp_ids: impl Iterator<Item = LpId> + Clone, rpc: IpiRpc) -> usize {
+    let n_recipients = recipient_lp_ids.clone().count();
+    if n_recipients == 0 {
+        return 0;
+    }
+    // no LP can be sent an RPC before the mailboxes exist
+    let Some(mailboxes) = IPI_RPC_MAILBOXES.get() else {
+        return n_recipients;
+    };
+    let req = IpiRpcReq {
+        sender_lp_id: get_lp_id(),
+        pending_acks: AtomicUsize::new(n_recipients),
+        request_id: NEXT_REQUEST_ID.fetch_add(1, Relaxed),
+        rpc,
+        hash: 0,
+    };
+    // recipients only ever access the request through a shared reference
+    let req_ptr = &req as *const IpiRpcReq as *mut IpiRpcReq;
+    let mut n_unreachable = 0;
+    for dest in recipient_lp_ids {
+        while mailboxes.try_write_unicast(dest, req_ptr).is_err() {
+            // the recipient may be waiting for this LP to acknowledge a request of its own
+            service_own_mailbox();
+            core::hint::spin_loop();
         }
+        if LocalIntCtlr::send_unicast_ipi(dest).is_err()
+            && mailboxes.withdraw_unicast(dest, req_ptr)
+        {
+            n_unreachable += 1;
+            req.pending_acks.fetch_sub(1, Release);
+        }
+    }
+    while req.pending_acks.load(Acquire) != 0 {
+        service_own_mailbox();
+        core::hint::spin_loop();
+    }
+    n_unreachable
+}
+
+/// Run the RPC waiting in the calling LP's unicast mailbox if there is one.
+///
+/// This is called from the IPI handler but also by code spinning on another LP since that LP may
+/// in turn be waiting for the spinning one to service its request, including LPs spinning on locks
+/// that mask interrupts.
+pub fn service_own_mailbox() {
+    // no RPC can have been sent before the mailboxes exist
+    let Some(mailboxes) = IPI_RPC_MAILBOXES.get() else {
+        return;
+    };
+    let req_ptr = mailboxes.take_own_unicast();
+    if req_ptr.is_null() {
+        return;
+    }
+    // SAFETY: The sender keeps the request alive until every recipient has acknowledged it.
+    let req = unsafe { &*req_ptr };
+    run_rpc(req.rpc.clone());
+    // the request may be gone as soon as the last acknowledgement is in
+    req.pending_acks.fetch_sub(1, Release);
+}
+
+fn run_rpc(rpc: IpiRpc) {
+    match rpc {
+        IpiRpc::VMemInval(asid, base, size) => inval_range(asid, base, size),
+        IpiRpc::AsidInval(asid) => tlb::inval_asid(asid),
+        IpiRpc::TerminateThreads(tids) => SYSTEM_SCHEDULER.terminate_threads(tids),
+        IpiRpc::AbortThreads(tids) => SYSTEM_SCHEDULER.abort_threads(tids),
+        IpiRpc::AbortAsThreads(asid) => SYSTEM_SCHEDULER.abort_as_threads(asid),
+    }
+}
+
+/// Invalidate translations on the calling LP
+fn inval_range(asid: AddressSpaceId, base: VAddr, n_pages: usize) {
+    if asid == KERNEL_ASID {
+        tlb::inval_range_kernel(base, n_pages);
+    } else {
+        tlb::inval_range_user(asid, base, n_pages);
+    }
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn ih_interprocessor_interrupt() {
+    let _irq_context = IrqContext::enter();
+    LocalIntCtlr::signal_eoi();
+    // the mailbox may also have been serviced by the time the softirq runs
+    softirq::raise(SoftIrq::IpiRpc);
+    if softirq::drain() {
+        // this handler can not switch threads, so the switch is left to the wake ISR
+        let _ = LocalIntCtlr::send_wake_lp_ipi(get_lp_id());
     }
 }
@@ -1,30 +1,48 @@
 use alloc::boxed::Box;
-use alloc::collections::vec_deque::VecDeque;
 use alloc::format;
-use alloc::sync::Arc;
+use core::mem::offset_of;
+use core::sync::atomic::{AtomicPtr, AtomicU64, Ordering};
 
-use spin::mutex::Mutex;
+use spin::Once;
 
+use crate::common::collections::boxed_slice::make_boxed_slice;
 use crate::cpu::isa::interface::memory::address::VirtualAddress;
 use crate::cpu::isa::lp::LpId;
 use crate::cpu::isa::lp::ops::set_lp_local_base;
-use crate::cpu::scheduler::threads::ThreadId;
-use crate::memory::{AddressSpaceId, VAddr};
-
-const AS_AFFINITY_COUNT: usize = 4095;
+use crate::cpu::multiprocessor::get_lp_count;
+use crate::memory::VAddr;
 
+/// The per-LP area reached through the LP local base
+///
+/// On x86-64 this is the kernel GS base. The ISRs swap it in with `swapgs` on every entry from
+/// user mode, so the GS base that user mode sees is free for its own use as is the FS base. The
+/// context switch ISRs reach the current thread's context through `gs:[0]` so `thread_context`
+/// must stay the first field.
+#[repr(C)]
 pub struct LogicalProcessor {
+    /// The context of the thread the LP is running or null before the LP has yielded to the
+    /// scheduler
+    pub thread_context: VAddr,
     pub id: LpId,
-    pub as_affinities: [Option<AddressSpaceId>; AS_AFFINITY_COUNT],
-    pub exec_queue_ptr: Arc<Mutex<VecDeque<ThreadId>>>,
 }
 
+const _: () = assert!(offset_of!(LogicalProcessor, thread_context) == 0);
+
+/// The local area of every LP indexed by LP ID
+static LP_LOCAL_AREAS: Once<Box<[AtomicU64]>> = Once::new();
+/// The first entry of `LP_LOCAL_AREAS` or null before it has been allocated. The NMI and machine
+/// check ISRs can interrupt the kernel before its `swapgs` on entry from or after it on the way
+/// back to user mode, so they compare the GS base against this rather than trusting the
+/// interrupted CS.
+#[unsafe(no_mangle)]
+static LP_LOCAL_AREA_TABLE: AtomicPtr<AtomicU64> = AtomicPtr::new(core::ptr::null_mut());
+
 impl LogicalProcessor {
-    pub fn setup(id: LpId, exec_queue_ptr: Arc<Mutex<VecDeque<ThreadId>>>) {
+    /// Allocate the calling LP's local area and make it the LP local base
+    pub fn setup(id: LpId) {
         let lp_struct = Box::try_new(LogicalProcessor {
+            thread_context: VAddr::from(0u64),
             id,
-            as_affinities: [None; AS_AFFINITY_COUNT],
-            exec_queue_ptr,
         })
         .expect(&format!(
             "Failed to allocate an LP struct for LP{}. Main memory is insufficient for core \
@@ -32,6 +50,12 @@ impl LogicalProcessor {
             id
         ));
         let vaddr = VAddr::from_mut(Box::into_raw(lp_struct));
+        let areas = LP_LOCAL_AREAS.call_once(|| {
+            let areas = make_boxed_slice(get_lp_count() as usize, || AtomicU64::new(0));
+            LP_LOCAL_AREA_TABLE.store(areas.as_ptr().cast_mut(), Ordering::Release);
+            areas
+        });
+        areas[id as usize].store(vaddr.into(), Ordering::Release);
         set_lp_local_base(vaddr);
     }
 }
@@ -0,0 +1,102 @@
+//! # Logical Processor Sets
+//!
+//! A bitmap with one bit per LP in the system. LP IDs are assigned consecutively from zero so the
+//! bitmap is sized by the number of LPs.
+
+use alloc::boxed::Box;
+use alloc::vec;
+
+use super::get_lp_count;
+use crate::cpu::isa::lp::LpId;
+
+#[derive(Debug)]
+pub enum Error {
+    /// The LP does not exist
+    InvalidLpId,
+    /// An LP list is not a comma separated list of LP IDs and inclusive ranges of them
+    InvalidLpList,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LpSet {
+    bits: Box<[u64]>,
+}
+
+impl LpSet {
+    /// An empty set
+    pub fn new() -> Self {
+        LpSet {
+            bits: vec![0; (get_lp_count() as usize).div_ceil(64)].into_boxed_slice(),
+        }
+    }
+
+    /// The set of all LPs in the system
+    pub fn all() -> Self {
+        let mut set = Self::new();
+        for lp_id in 0..get_lp_count() {
+            set.bits[lp_id as usize / 64] |= 1 << (lp_id % 64);
+        }
+        set
+    }
+
+    pub fn from_lps(lps: &[LpId]) -> Result<Self, Error> {
+        let mut set = Self::new();
+        for &lp_id in lps {
+            set.insert(lp_id)?;
+        }
+        Ok(set)
+    }
+
+    /// Parse a list such as `1,4-7` of LP IDs and inclusive ranges of them
+    pub fn parse(list: &str) -> Result<Self, Error> {
+        let mut set = Self::new();
+        for item in list.split(',').filter(|item| !item.is_empty()) {
+            let (first, last) = item.split_once('-').unwrap_or((item, item));
+            let first: LpId = first.trim().parse().map_err(|_| Error::InvalidLpList)?;
+            let last: LpId = last.trim().parse().map_err(|_| Error::InvalidLpList)?;
+            if first > last {
+                return Err(Error::InvalidLpList);
+            }
+            for lp_id in first..=last {
+                set.insert(lp_id)?;
+            }
+        }
+        Ok(set)
+    }
+
+    pub fn insert(&mut self, lp_id: LpId) -> Result<(), Error> {
+        let word = self.bits.get_mut(lp_id as usize / 64).ok_or(Error::InvalidLpId)?;
+        if lp_id >= get_lp_count() {
+            return Err(Error::InvalidLpId);
+        }
+        *word |= 1 << (lp_id % 64);
+        Ok(())
+    }
+
+    pub fn remove(&mut self, lp_id: LpId) {
+        if let Some(word) = self.bits.get_mut(lp_id as usize / 64) {
+            *word &= !(1 << (lp_id % 64));
+        }
+    }
+
+    pub fn contains(&self, lp_id: LpId) -> bool {
+        self.bits.get(lp_id as usize / 64).is_some_and(|word| word & (1 << (lp_id % 64)) != 0)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bits.iter().all(|&word| word == 0)
+    }
+
+    pub fn len(&self) -> usize {
+        self.bits.iter().map(|word| word.count_ones() as usize).sum()
+    }
+
+    pub fn intersects(&self, other: &LpSet) -> bool {
+        self.bits.iter().zip(other.bits.iter()).any(|(a, b)| a & b != 0)
+    }
+
+    /// The LPs in the set in ascending order
+    pub fn iter(&self) -> impl Iterator<Item = LpId> + '_ {
+        (0..(self.bits.len() * 64) as LpId).filter(|&lp_id| self.contains(lp_id))
+    }
+}
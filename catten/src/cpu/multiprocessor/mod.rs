@@ -1,6 +1,7 @@
 //! # Multi-Processor Management
 pub mod ipi;
 pub mod lp;
+pub mod lp_set;
 pub mod startup;
 
 #[inline]
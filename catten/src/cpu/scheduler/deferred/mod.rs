@@ -0,0 +1,16 @@
+//! # Deferred Work
+//!
+//! Interrupt handlers acknowledge their interrupt and defer the rest of their work to a softirq
+//! vector that runs on the way out of the interrupt. Softirqs are for short work that has to
+//! happen soon and on the same LP, such as running due timers. Anything that may block or take
+//! longer belongs on a work queue whose workers run it on a thread. Queueing work allocates, so
+//! it is done from thread context, e.g. by a thread that a softirq handler wakes.
+
+pub mod softirq;
+pub mod work_queue;
+
+/// Spawn the softirq threads and create the system work queues
+pub fn start() {
+    softirq::start_threads();
+    work_queue::start_system_work_queues();
+}
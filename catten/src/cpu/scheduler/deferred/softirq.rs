@@ -0,0 +1,186 @@
+//! # Software Interrupts
+//!
+//! An interrupt handler that has more to do than acknowledging its source raises a softirq vector
+//! on its LP and leaves the rest to the vector's handler, which runs when the LP drains its
+//! pending vectors on the way out of the interrupt. The hardware interrupt has been acknowledged
+//! by then and the handlers run with interrupts unmasked, so they must mask interrupts themselves
+//! around locks that interrupt handlers take. Each vector is run at most once per drain no matter
+//! how often it was raised.
+//!
+//! An interrupt that arrives during a drain only raises its vectors, which the drain then runs
+//! before it returns. It does not switch threads either, since the LP is still on the stack of the
+//! context the drained interrupt arrived in. A switch it asks for is performed by the drained
+//! interrupt's handler once the drain is over.
+//!
+//! A drain restarts while vectors are raised again during it, but only a few times. Vectors that
+//! are still pending after that are left to the LP's softirq thread, which can be preempted
+//! between rounds so that a flood of softirqs can not lock the LP up.
+
+use alloc::boxed::Box;
+use core::arch::asm;
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+use spin::Once;
+
+use crate::common::collections::boxed_slice::make_boxed_slice;
+use crate::cpu::isa::lp::ops::{
+    are_interrupts_enabled,
+    get_lp_id,
+    mask_interrupts,
+    unmask_interrupts,
+    without_interrupts,
+};
+use crate::cpu::multiprocessor::lp_set::LpSet;
+use crate::cpu::multiprocessor::{get_lp_count, ipi};
+use crate::cpu::scheduler::preemption::PreemptGuard;
+use crate::cpu::scheduler::sync::lockdep::SoftIrqContext;
+use crate::cpu::scheduler::sync::wait_queue::WaitQueue;
+use crate::cpu::scheduler::system_scheduler::SYSTEM_SCHEDULER;
+use crate::cpu::scheduler::threads::spawn::spawn_kernel_thread_on;
+
+/// The number of times a drain at interrupt exit starts over before leaving the remaining vectors
+/// to the softirq thread
+const MAX_DRAIN_ROUNDS: usize = 8;
+
+/// Indexed by LP ID. Raised vectors are only recorded once this is initialized.
+static SOFTIRQ_STATES: Once<Box<[SoftIrqState]>> = Once::new();
+
+/// The softirq vectors in the order they are run in when several are pending
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u32)]
+pub enum SoftIrq {
+    /// Run the LP's high-resolution timers that are due
+    Timer,
+    /// Run the RPC waiting in the LP's IPI mailbox
+    IpiRpc,
+}
+
+impl SoftIrq {
+    const ALL: [SoftIrq; 2] = [SoftIrq::Timer, SoftIrq::IpiRpc];
+
+    fn get_mask(self) -> u32 {
+        1 << self as u32
+    }
+
+    fn run(self) {
+        match self {
+            SoftIrq::Timer => {
+                SYSTEM_SCHEDULER.expire_timers();
+                without_interrupts(|| SYSTEM_SCHEDULER.get_local_scheduler().lock().rearm_timer());
+            }
+            SoftIrq::IpiRpc => ipi::service_own_mailbox(),
+        }
+    }
+}
+
+struct SoftIrqState {
+    /// One bit per raised vector
+    pending: AtomicU32,
+    /// Whether the LP is running its vectors. Only the LP itself accesses this, with interrupts
+    /// masked.
+    is_draining: AtomicBool,
+    /// Woken when vectors are left to the LP's softirq thread
+    thread_wakeup: WaitQueue,
+}
+
+impl SoftIrqState {
+    fn new() -> Self {
+        SoftIrqState {
+            pending: AtomicU32::new(0),
+            is_draining: AtomicBool::new(false),
+            thread_wakeup: WaitQueue::new(),
+        }
+    }
+}
+
+/// Allocate the per-LP softirq state. Requires the kernel heap.
+pub fn init() {
+    SOFTIRQ_STATES.call_once(|| make_boxed_slice(get_lp_count() as usize, SoftIrqState::new));
+}
+
+/// Mark the vector as pending on the calling LP
+///
+/// Vectors raised in an interrupt handler or by another vector run when the LP drains them.
+/// Otherwise the LP's softirq thread is woken to run them, unless interrupts are masked, in which
+/// case they run at the next drain.
+pub fn raise(vector: SoftIrq) {
+    let Some(states) = SOFTIRQ_STATES.get() else {
+        return;
+    };
+    let state = &states[get_lp_id() as usize];
+    state.pending.fetch_or(vector.get_mask(), Ordering::AcqRel);
+    if are_interrupts_enabled() && !state.is_draining.load(Ordering::Relaxed) {
+        state.thread_wakeup.wake_all();
+    }
+}
+
+/// Run the calling LP's pending vectors with interrupts unmasked. Returns whether an interrupt
+/// that arrived in the meantime asked the LP to switch threads, in which case the caller has to
+/// switch.
+///
+/// Must be called with interrupts masked at the end of interrupt handlers, after the interrupt has
+/// been acknowledged and before the LP switches threads, if it does. Returns with interrupts
+/// masked.
+pub fn drain() -> bool {
+    let Some(states) = SOFTIRQ_STATES.get() else {
+        return false;
+    };
+    let state = &states[get_lp_id() as usize];
+    // an interrupt that arrived during a drain leaves its vectors to that drain
+    if state.is_draining.load(Ordering::Relaxed) || state.pending.load(Ordering::Acquire) == 0 {
+        return false;
+    }
+    let preempt_guard = PreemptGuard::new();
+    {
+        let _softirq_context = SoftIrqContext::enter();
+        run_rounds(state, MAX_DRAIN_ROUNDS);
+    }
+    if state.pending.load(Ordering::Acquire) != 0 {
+        state.thread_wakeup.wake_all();
+    }
+    preempt_guard.release()
+}
+
+/// Run up to `max_rounds` rounds of pending vectors with interrupts unmasked. Must be called with
+/// interrupts masked and preemption disabled, and returns with interrupts masked.
+fn run_rounds(state: &SoftIrqState, max_rounds: usize) {
+    state.is_draining.store(true, Ordering::Relaxed);
+    unmask_interrupts!();
+    for _ in 0..max_rounds {
+        if !run_pending(state) {
+            break;
+        }
+    }
+    mask_interrupts!();
+    state.is_draining.store(false, Ordering::Relaxed);
+}
+
+/// Run each pending vector once. Returns whether any were pending.
+fn run_pending(state: &SoftIrqState) -> bool {
+    let pending = state.pending.swap(0, Ordering::AcqRel);
+    for vector in SoftIrq::ALL {
+        if pending & vector.get_mask() != 0 {
+            vector.run();
+        }
+    }
+    pending != 0
+}
+
+/// Spawn a softirq thread pinned to each LP
+pub fn start_threads() {
+    for lp_id in 0..get_lp_count() {
+        let affinity = LpSet::from_lps(&[lp_id]).expect("A softirq thread's LP does not exist");
+        // the vectors act on the LP they run on, so the thread never runs anywhere else
+        spawn_kernel_thread_on("softirq", affinity, move || thread_main(lp_id as usize)).detach();
+    }
+}
+
+fn thread_main(lp_id: usize) {
+    let state = &SOFTIRQ_STATES.get().expect("Softirqs are not initialized")[lp_id];
+    loop {
+        state.thread_wakeup.wait_until(|| state.pending.load(Ordering::Acquire) != 0);
+        // the thread can be preempted between rounds
+        let _preempt_guard = PreemptGuard::new();
+        without_interrupts(|| run_rounds(state, 1));
+    }
+}
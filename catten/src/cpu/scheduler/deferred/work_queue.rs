@@ -0,0 +1,296 @@
+//! # Work Queues
+//!
+//! A work queue runs closures on kernel worker threads, where they can block and take as long as
+//! they need. Queueing work allocates, so it must be done from thread context and not from
+//! interrupt or softirq handlers.
+//!
+//! An unbound queue has one pool of work shared by all of its workers, which run wherever the
+//! scheduler places them. A per-LP queue has a pool and a worker pinned to it for each LP, and
+//! work is run on the LP it was queued for.
+
+use alloc::boxed::Box;
+use alloc::collections::vec_deque::VecDeque;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use spin::Once;
+
+use crate::cpu::isa::lp::LpId;
+use crate::cpu::isa::lp::ops::get_lp_id;
+use crate::cpu::multiprocessor::get_lp_count;
+use crate::cpu::multiprocessor::lp_set::LpSet;
+use crate::cpu::scheduler::sync::spinlock::IrqSpinLock;
+use crate::cpu::scheduler::sync::wait_queue::WaitQueue;
+use crate::cpu::scheduler::system_scheduler;
+use crate::cpu::scheduler::threads::spawn::{self, try_spawn_kernel_thread_on};
+
+pub type Work = Box<dyn FnOnce() + Send>;
+
+/// The queue for work that may run anywhere
+static SYSTEM_WORK_QUEUE: Once<Arc<WorkQueue>> = Once::new();
+/// The queue for work that must run on a given LP
+static SYSTEM_PER_LP_WORK_QUEUE: Once<Arc<WorkQueue>> = Once::new();
+
+#[derive(Debug)]
+pub enum Error {
+    /// The system work queues have not been started yet
+    NotStarted,
+    /// The queue no longer accepts work
+    Destroyed,
+    /// Work can only be queued for a given LP on a per-LP queue that has a pool for that LP
+    InvalidLpId,
+    SpawnError(spawn::Error),
+    SchedulerError(system_scheduler::Error),
+}
+
+impl From<spawn::Error> for Error {
+    fn from(err: spawn::Error) -> Self {
+        Error::SpawnError(err)
+    }
+}
+
+impl From<system_scheduler::Error> for Error {
+    fn from(err: system_scheduler::Error) -> Self {
+        Error::SchedulerError(err)
+    }
+}
+
+/// Refers to a queued piece of work so that it can be cancelled or waited for
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WorkId {
+    pool: usize,
+    seq:  u64,
+}
+
+/// The work of one pool and the workers that run it
+struct Pool {
+    state: IrqSpinLock<PoolState>,
+    /// Woken each time work is queued and when the queue is destroyed
+    has_work: WaitQueue,
+}
+
+struct PoolState {
+    /// Waiting work in the order it was queued
+    queued:  VecDeque<(u64, Work)>,
+    /// The work the pool's workers are running
+    running: Vec<u64>,
+}
+
+impl PoolState {
+    /// Whether any of the work before `seq`, or exactly `seq` if `is_exact`, is waiting or running
+    fn has_pending(&self, seq: u64, is_exact: bool) -> bool {
+        let is_pending = |other: u64| {
+            if is_exact {
+                other == seq
+            } else {
+                other < seq
+            }
+        };
+        self.queued.iter().any(|&(other, _)| is_pending(other))
+            || self.running.iter().any(|&other| is_pending(other))
+    }
+}
+
+/// A named queue of work run by kernel worker threads
+pub struct WorkQueue {
+    name: &'static str,
+    pools: Box<[Pool]>,
+    is_per_lp: bool,
+    next_seq: AtomicU64,
+    is_destroyed: AtomicBool,
+    /// Woken each time a piece of work has been run
+    finished: WaitQueue,
+}
+
+impl WorkQueue {
+    /// Create an unbound queue with the given number of workers, at least one
+    pub fn new(name: &'static str, worker_count: usize) -> Result<Arc<WorkQueue>, Error> {
+        let queue = Arc::new(Self::with_pools(name, 1, false));
+        for _ in 0..worker_count.max(1) {
+            queue.spawn_worker(0, None)?;
+        }
+        Ok(queue)
+    }
+
+    /// Create a queue with a worker pinned to each LP
+    pub fn new_per_lp(name: &'static str) -> Result<Arc<WorkQueue>, Error> {
+        let lp_count = get_lp_count();
+        let queue = Arc::new(Self::with_pools(name, lp_count as usize, true));
+        for lp_id in 0..lp_count {
+            queue.spawn_worker(lp_id as usize, Some(lp_id))?;
+        }
+        Ok(queue)
+    }
+
+    fn with_pools(name: &'static str, pool_count: usize, is_per_lp: bool) -> Self {
+        WorkQueue {
+            name,
+            pools: (0..pool_count)
+                .map(|_| Pool {
+                    state: IrqSpinLock::new(PoolState {
+                        queued:  VecDeque::new(),
+                        running: Vec::new(),
+                    }),
+                    has_work: WaitQueue::new(),
+                })
+                .collect(),
+            is_per_lp,
+            next_seq: AtomicU64::new(0),
+            is_destroyed: AtomicBool::new(false),
+            finished: WaitQueue::new(),
+        }
+    }
+
+    fn spawn_worker(self: &Arc<Self>, pool: usize, lp_id: Option<LpId>) -> Result<(), Error> {
+        let queue = self.clone();
+        let affinity = lp_id
+            .map(|lp_id| LpSet::from_lps(&[lp_id]).map_err(|_| Error::InvalidLpId))
+            .transpose()?;
+        try_spawn_kernel_thread_on(self.name, affinity, move || queue.run_worker(pool))?.detach();
+        Ok(())
+    }
+
+    pub fn get_name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Queue work. A per-LP queue runs it on the calling LP.
+    ///
+    /// The work is boxed, so this must not be called from interrupt or softirq handlers.
+    pub fn queue(&self, work: impl FnOnce() + Send + 'static) -> Result<WorkId, Error> {
+        let pool = if self.is_per_lp {
+            get_lp_id() as usize
+        } else {
+            0
+        };
+        self.queue_in_pool(pool, Box::new(work))
+    }
+
+    /// Queue work to run on the given LP. Only per-LP queues can do this.
+    ///
+    /// Like `queue` this must not be called from interrupt or softirq handlers.
+    pub fn queue_on(
+        &self,
+        lp_id: LpId,
+        work: impl FnOnce() + Send + 'static,
+    ) -> Result<WorkId, Error> {
+        if !self.is_per_lp || lp_id as usize >= self.pools.len() {
+            return Err(Error::InvalidLpId);
+        }
+        self.queue_in_pool(lp_id as usize, Box::new(work))
+    }
+
+    fn queue_in_pool(&self, pool: usize, work: Work) -> Result<WorkId, Error> {
+        if self.is_destroyed.load(Ordering::Acquire) {
+            return Err(Error::Destroyed);
+        }
+        let seq = self.next_seq.fetch_add(1, Ordering::Relaxed);
+        self.pools[pool].state.lock().queued.push_back((seq, work));
+        self.pools[pool].has_work.wake_all();
+        Ok(WorkId {
+            pool,
+            seq,
+        })
+    }
+
+    /// Remove work that has not started running yet. Returns whether it was removed, in which
+    /// case it never runs. Work that is already running can be waited for with `flush_work`.
+    pub fn cancel(&self, id: WorkId) -> bool {
+        let Some(pool) = self.pools.get(id.pool) else {
+            return false;
+        };
+        let work = {
+            let mut state = pool.state.lock();
+            state
+                .queued
+                .iter()
+                .position(|&(seq, _)| seq == id.seq)
+                .and_then(|index| state.queued.remove(index))
+        };
+        // the closure may own resources that must not be freed with interrupts masked
+        let is_cancelled = work.is_some();
+        drop(work);
+        if is_cancelled {
+            self.finished.wake_all();
+        }
+        is_cancelled
+    }
+
+    /// Wait until the given work has run or been cancelled
+    pub fn flush_work(&self, id: WorkId) {
+        let Some(pool) = self.pools.get(id.pool) else {
+            return;
+        };
+        self.finished.wait_until(|| !pool.state.lock().has_pending(id.seq, true));
+    }
+
+    /// Wait until all work queued before the call has run or been cancelled
+    pub fn flush(&self) {
+        let end = self.next_seq.load(Ordering::Relaxed);
+        self.finished.wait_until(|| {
+            !self.pools.iter().any(|pool| pool.state.lock().has_pending(end, false))
+        });
+    }
+
+    /// Stop accepting work, run the work that is already queued and stop the workers
+    pub fn destroy(&self) {
+        self.is_destroyed.store(true, Ordering::Release);
+        self.flush();
+        for pool in self.pools.iter() {
+            pool.has_work.wake_all();
+        }
+    }
+
+    fn run_worker(&self, pool: usize) {
+        let pool = &self.pools[pool];
+        loop {
+            let work = pool.has_work.wait_for(|| {
+                let mut state = pool.state.lock();
+                if let Some((seq, work)) = state.queued.pop_front() {
+                    state.running.push(seq);
+                    return Some(Some((seq, work)));
+                }
+                self.is_destroyed.load(Ordering::Acquire).then_some(None)
+            });
+            let Some((seq, work)) = work else {
+                return;
+            };
+            work();
+            pool.state.lock().running.retain(|&other| other != seq);
+            self.finished.wake_all();
+        }
+    }
+}
+
+/// Create the system work queues
+pub fn start_system_work_queues() {
+    SYSTEM_WORK_QUEUE.call_once(|| {
+        WorkQueue::new("work", get_lp_count() as usize)
+            .unwrap_or_else(|err| panic!("Failed to create the system work queue: {:?}", err))
+    });
+    SYSTEM_PER_LP_WORK_QUEUE.call_once(|| {
+        WorkQueue::new_per_lp("work-lp")
+            .unwrap_or_else(|err| panic!("Failed to create the per-LP work queue: {:?}", err))
+    });
+}
+
+/// The unbound system work queue
+pub fn get_system_work_queue() -> Result<&'static Arc<WorkQueue>, Error> {
+    SYSTEM_WORK_QUEUE.get().ok_or(Error::NotStarted)
+}
+
+/// The per-LP system work queue
+pub fn get_per_lp_work_queue() -> Result<&'static Arc<WorkQueue>, Error> {
+    SYSTEM_PER_LP_WORK_QUEUE.get().ok_or(Error::NotStarted)
+}
+
+/// Queue work on the unbound system work queue
+pub fn queue_work(work: impl FnOnce() + Send + 'static) -> Result<WorkId, Error> {
+    get_system_work_queue()?.queue(work)
+}
+
+/// Queue work on the per-LP system work queue to run on the given LP
+pub fn queue_work_on(lp_id: LpId, work: impl FnOnce() + Send + 'static) -> Result<WorkId, Error> {
+    get_per_lp_work_queue()?.queue_on(lp_id, work)
+}
@@ -0,0 +1,194 @@
+//! # Futures for Kernel Tasks
+//!
+//! Futures that resolve when an event source is raised, e.g. by a device's interrupt handler, or
+//! when the monotonic clock reaches a deadline. They wake their task from an observer, so they
+//! work with the executors as well as with any other executor.
+
+use alloc::sync::Arc;
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::task::{Context, Poll, Waker};
+
+use spin::Mutex;
+
+use crate::common::time::duration::ExtDuration;
+use crate::cpu::isa::lp::ops::without_interrupts;
+use crate::cpu::isa::timers::get_monotonic_time;
+use crate::cpu::scheduler::hrtimer::{self, TimerHandle};
+use crate::event::{EventSource, Observer};
+
+/// Wakes the task that last polled a future once it is notified
+struct WakerSlot {
+    is_notified: AtomicBool,
+    /// Only ever locked with interrupts masked since it is notified from interrupt handlers
+    waker: Mutex<Option<Waker>>,
+}
+
+impl WakerSlot {
+    fn new() -> Self {
+        WakerSlot {
+            is_notified: AtomicBool::new(false),
+            waker: Mutex::new(None),
+        }
+    }
+
+    fn is_notified(&self) -> bool {
+        self.is_notified.load(Ordering::Acquire)
+    }
+
+    /// Store the waker of the polling task. Returns whether the slot has been notified, in which
+    /// case the future is ready.
+    fn poll(&self, cx: &Context<'_>) -> bool {
+        if self.is_notified() {
+            return true;
+        }
+        without_interrupts(|| {
+            let mut waker = self.waker.lock();
+            match &mut *waker {
+                Some(waker) => waker.clone_from(cx.waker()),
+                None => *waker = Some(cx.waker().clone()),
+            }
+        });
+        // a notification while the waker was stored may have found the previous one
+        self.is_notified()
+    }
+}
+
+impl Observer for WakerSlot {
+    fn notify(&self) {
+        self.is_notified.store(true, Ordering::Release);
+        if let Some(waker) = without_interrupts(|| self.waker.lock().take()) {
+            waker.wake();
+        }
+    }
+}
+
+/// Resolves once its event source has been raised after it was created
+///
+/// It registers with the source when it is created rather than when it is first polled, so an
+/// interrupt that arrives after a driver created it but before the task awaited it is not missed.
+pub struct Raised<'a> {
+    source: &'a EventSource,
+    slot: Arc<WakerSlot>,
+}
+
+impl<'a> Raised<'a> {
+    pub fn new(source: &'a EventSource) -> Self {
+        let slot = Arc::new(WakerSlot::new());
+        source.register(slot.clone());
+        Raised {
+            source,
+            slot,
+        }
+    }
+}
+
+impl Future for Raised<'_> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if self.slot.poll(cx) {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+impl Drop for Raised<'_> {
+    fn drop(&mut self) {
+        if !self.slot.is_notified() {
+            let observer: Arc<dyn Observer> = self.slot.clone();
+            self.source.unregister(&observer);
+        }
+    }
+}
+
+/// Wait for the next time `source` is raised
+pub fn raised(source: &EventSource) -> Raised<'_> {
+    Raised::new(source)
+}
+
+/// Resolves once the monotonic clock has reached its deadline
+///
+/// A high-resolution timer is armed on the LP that first polls the future and cancelled if the
+/// future is dropped before it resolves.
+pub struct Timer {
+    deadline: ExtDuration,
+    /// Created when the timer is armed on the first poll
+    slot: Option<(Arc<WakerSlot>, TimerHandle)>,
+}
+
+impl Timer {
+    pub fn at(deadline: ExtDuration) -> Self {
+        Timer {
+            deadline,
+            slot: None,
+        }
+    }
+
+    pub fn after(delay: ExtDuration) -> Self {
+        Self::at(get_monotonic_time() + delay)
+    }
+
+    pub fn get_deadline(&self) -> ExtDuration {
+        self.deadline
+    }
+}
+
+impl Future for Timer {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if get_monotonic_time() >= self.deadline {
+            return Poll::Ready(());
+        }
+        let deadline = self.deadline;
+        let (slot, _) = self.slot.get_or_insert_with(|| {
+            let slot = Arc::new(WakerSlot::new());
+            let notified = slot.clone();
+            (slot, hrtimer::arm_at(deadline, move || notified.notify()))
+        });
+        if slot.poll(cx) {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+impl Drop for Timer {
+    fn drop(&mut self) {
+        if let Some((slot, timer)) = &self.slot
+            && !slot.is_notified()
+        {
+            hrtimer::cancel(timer);
+        }
+    }
+}
+
+/// Let the executor poll the other ready tasks before polling this one again
+pub async fn yield_now() {
+    struct YieldNow {
+        is_yielded: bool,
+    }
+
+    impl Future for YieldNow {
+        type Output = ();
+
+        fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+            if self.is_yielded {
+                return Poll::Ready(());
+            }
+            self.is_yielded = true;
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        }
+    }
+
+    YieldNow {
+        is_yielded: false,
+    }
+    .await
+}
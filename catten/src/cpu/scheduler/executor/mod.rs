@@ -0,0 +1,214 @@
+//! # Async Executor
+//!
+//! Each LP has an executor that polls kernel tasks, i.e. futures that run to completion, on a
+//! dedicated kernel thread pinned to that LP. The executor thread blocks while none of its tasks
+//! are ready, so it is scheduled like any other thread and only takes LP time when there is work.
+//!
+//! A task is made ready again by waking its `Waker`. Waking only masks interrupts, queues the task
+//! and notifies the executor thread, so futures can be completed from interrupt handlers and from
+//! the observers of events. Each executor's ready queue is a ring with room for as many tasks as it
+//! may have, and a task is in it at most once, so waking never allocates. See `futures` for
+//! futures that resolve on events and timeouts.
+
+pub mod futures;
+
+use alloc::boxed::Box;
+use alloc::collections::btree_map::BTreeMap;
+use alloc::collections::vec_deque::VecDeque;
+use alloc::sync::Arc;
+use alloc::task::Wake;
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use core::task::{Context, Waker};
+
+use spin::Once;
+
+use crate::cpu::isa::lp::LpId;
+use crate::cpu::isa::lp::ops::get_lp_id;
+use crate::cpu::multiprocessor::get_lp_count;
+use crate::cpu::multiprocessor::lp_set::LpSet;
+use crate::cpu::scheduler::sync::spinlock::IrqSpinLock;
+use crate::cpu::scheduler::sync::wait_queue::WaitQueue;
+use crate::cpu::scheduler::system_scheduler::SYSTEM_SCHEDULER;
+use crate::cpu::scheduler::threads::spawn::spawn_kernel_thread;
+
+pub type TaskId = u64;
+/// The number of tasks an executor can have that have not run to completion yet
+pub const MAX_TASK_COUNT: usize = 1 << 12;
+
+static EXECUTORS: Once<Box<[Executor]>> = Once::new();
+
+#[derive(Debug)]
+pub enum Error {
+    /// The executors have not been started yet
+    NotStarted,
+    InvalidLpId,
+    /// The executor already has `MAX_TASK_COUNT` tasks
+    TooManyTasks,
+}
+
+struct Task {
+    future: Pin<Box<dyn Future<Output = ()> + Send>>,
+    waker:  Arc<TaskWaker>,
+}
+
+/// Polls the tasks spawned on one LP
+pub struct Executor {
+    lp_id: LpId,
+    /// The tasks that are not being polled right now
+    tasks: IrqSpinLock<BTreeMap<TaskId, Task>>,
+    /// The number of tasks that have not run to completion yet, including the one being polled
+    task_count: AtomicUsize,
+    /// The IDs of the tasks that are ready to be polled. It is allocated with room for
+    /// `MAX_TASK_COUNT` tasks when the executor is created and never grown.
+    ready: IrqSpinLock<VecDeque<TaskId>>,
+    /// Woken each time a task is queued
+    has_ready: WaitQueue,
+    next_task_id: AtomicU64,
+}
+
+/// Queues its task on the executor it was spawned on
+struct TaskWaker {
+    executor: &'static Executor,
+    task_id: TaskId,
+    /// Whether the task is in the ready queue. It is cleared right before the task is polled, so
+    /// a wake during the poll queues it again, and stays set once the task has completed.
+    is_queued: AtomicBool,
+}
+
+impl Wake for TaskWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        if !self.is_queued.swap(true, Ordering::AcqRel) {
+            self.executor.queue(self.task_id);
+        }
+    }
+}
+
+impl Executor {
+    fn new(lp_id: LpId) -> Self {
+        Executor {
+            lp_id,
+            tasks: IrqSpinLock::new(BTreeMap::new()),
+            task_count: AtomicUsize::new(0),
+            ready: IrqSpinLock::new(VecDeque::with_capacity(MAX_TASK_COUNT)),
+            has_ready: WaitQueue::new(),
+            next_task_id: AtomicU64::new(0),
+        }
+    }
+
+    pub fn get_lp_id(&self) -> LpId {
+        self.lp_id
+    }
+
+    /// Add a task and queue it to be polled for the first time
+    pub fn spawn(
+        &'static self,
+        future: impl Future<Output = ()> + Send + 'static,
+    ) -> Result<TaskId, Error> {
+        self.task_count
+            .try_update(Ordering::AcqRel, Ordering::Acquire, |count| {
+                (count < MAX_TASK_COUNT).then_some(count + 1)
+            })
+            .map_err(|_| Error::TooManyTasks)?;
+        let task_id = self.next_task_id.fetch_add(1, Ordering::Relaxed);
+        let task = Task {
+            future: Box::pin(future),
+            waker:  Arc::new(TaskWaker {
+                executor: self,
+                task_id,
+                is_queued: AtomicBool::new(false),
+            }),
+        };
+        let waker = task.waker.clone();
+        self.tasks.lock().insert(task_id, task);
+        waker.wake();
+        Ok(task_id)
+    }
+
+    /// The number of tasks that have not run to completion yet
+    pub fn get_task_count(&self) -> usize {
+        self.task_count.load(Ordering::Acquire)
+    }
+
+    /// Queue a task to be polled and wake the executor thread if it is waiting for work
+    ///
+    /// Only called by the task's waker when it was not queued already.
+    fn queue(&self, task_id: TaskId) {
+        {
+            let mut ready = self.ready.lock();
+            // each task that has not completed is queued at most once
+            assert!(
+                ready.len() < ready.capacity(),
+                "LP{} executor: The ready queue is full",
+                self.lp_id
+            );
+            ready.push_back(task_id);
+        }
+        self.has_ready.wake_all();
+    }
+
+    /// Poll a task once, dropping it if it has run to completion
+    fn poll(&self, task_id: TaskId) {
+        // a task that has completed may still be woken
+        let Some(mut task) = self.tasks.lock().remove(&task_id) else {
+            return;
+        };
+        task.waker.is_queued.store(false, Ordering::Release);
+        let waker = Waker::from(task.waker.clone());
+        let mut cx = Context::from_waker(&waker);
+        if task.future.as_mut().poll(&mut cx).is_pending() {
+            // a wake during the poll has queued the task again already
+            self.tasks.lock().insert(task_id, task);
+        } else {
+            // wakes of a completed task are ignored so that it takes no room in the ready queue
+            task.waker.is_queued.store(true, Ordering::Release);
+            self.task_count.fetch_sub(1, Ordering::AcqRel);
+        }
+    }
+
+    fn run(&self) {
+        loop {
+            // tasks are taken one at a time so that the ready queue keeps its allocation
+            let task_id = self.has_ready.wait_for(|| self.ready.lock().pop_front());
+            self.poll(task_id);
+        }
+    }
+}
+
+/// Create an executor for each LP and spawn the threads that run them
+pub fn start_executors() {
+    let executors = EXECUTORS.call_once(|| (0..get_lp_count()).map(Executor::new).collect());
+    for executor in executors.iter() {
+        let lp_id = executor.lp_id;
+        let handle = spawn_kernel_thread("executor", move || executor.run());
+        let affinity = LpSet::from_lps(&[lp_id]).expect("An executor's LP does not exist");
+        // the executor may start on another LP but moves to its own on its next switch
+        SYSTEM_SCHEDULER
+            .set_thread_affinity(handle.get_tid(), Some(affinity))
+            .unwrap_or_else(|err| panic!("Failed to pin the LP{} executor: {:?}", lp_id, err));
+        handle.detach();
+    }
+}
+
+/// The executor of the given LP
+pub fn get_executor(lp_id: LpId) -> Result<&'static Executor, Error> {
+    EXECUTORS.get().ok_or(Error::NotStarted)?.get(lp_id as usize).ok_or(Error::InvalidLpId)
+}
+
+/// Spawn a task on the executor of the calling LP
+pub fn spawn(future: impl Future<Output = ()> + Send + 'static) -> Result<TaskId, Error> {
+    spawn_on(get_lp_id(), future)
+}
+
+/// Spawn a task on the executor of the given LP
+pub fn spawn_on(
+    lp_id: LpId,
+    future: impl Future<Output = ()> + Send + 'static,
+) -> Result<TaskId, Error> {
+    get_executor(lp_id)?.spawn(future)
+}
@@ -0,0 +1,152 @@
+//! # High-Resolution Timers
+//!
+//! Each LP keeps a queue of callbacks ordered by their deadlines on the monotonic clock. The LP's
+//! one-shot timer is shared with its local scheduler and always armed for whichever comes first,
+//! the end of the running thread's time slice or the earliest deadline. When it fires before the
+//! time slice is over only the due callbacks are run and the thread keeps running.
+//!
+//! Callbacks run on the LP that armed them, in its timer softirq or when it switches threads. They
+//! run without any local scheduler locked and can not be preempted, but interrupts are only masked
+//! when they run at a switch. So they must be short, must not block and must mask interrupts
+//! around locks that interrupt handlers take. Waking threads and notifying observers is fine.
+
+use alloc::boxed::Box;
+use alloc::collections::btree_map::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use crate::common::time::duration::ExtDuration;
+use crate::cpu::isa::lp::LpId;
+use crate::cpu::isa::timers::get_monotonic_time;
+use crate::cpu::scheduler::preemption::PreemptGuard;
+use crate::cpu::scheduler::system_scheduler::SYSTEM_SCHEDULER;
+use crate::cpu::scheduler::threads::ThreadId;
+use crate::event::Completion;
+
+pub type TimerCallback = Box<dyn FnOnce() + Send>;
+
+/// Identifies an armed timer within its LP's queue. The sequence number tells apart timers with
+/// the same deadline.
+type TimerKey = (ExtDuration, u64);
+
+/// Refers to an armed timer so that it can be cancelled
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TimerHandle {
+    lp_id: LpId,
+    key: TimerKey,
+}
+
+impl TimerHandle {
+    pub fn get_deadline(&self) -> ExtDuration {
+        self.key.0
+    }
+
+    /// The LP whose queue holds the timer and that runs its callback
+    pub fn get_lp_id(&self) -> LpId {
+        self.lp_id
+    }
+}
+
+/// The timers of one LP, kept by its local scheduler
+pub struct TimerQueue {
+    timers: BTreeMap<TimerKey, TimerCallback>,
+    next_seq: u64,
+}
+
+impl TimerQueue {
+    pub const fn new() -> Self {
+        TimerQueue {
+            timers: BTreeMap::new(),
+            next_seq: 0,
+        }
+    }
+
+    pub fn arm(
+        &mut self,
+        lp_id: LpId,
+        deadline: ExtDuration,
+        callback: TimerCallback,
+    ) -> TimerHandle {
+        let key = (deadline, self.next_seq);
+        self.next_seq += 1;
+        self.timers.insert(key, callback);
+        TimerHandle {
+            lp_id,
+            key,
+        }
+    }
+
+    /// Remove the timer if it has not expired yet. Returns whether it was removed.
+    pub fn cancel(&mut self, handle: &TimerHandle) -> bool {
+        self.timers.remove(&handle.key).is_some()
+    }
+
+    /// Take the callbacks of the timers that are due at `now`
+    pub fn take_expired(&mut self, now: ExtDuration) -> Vec<TimerCallback> {
+        let pending = self.timers.split_off(&(now, u64::MAX));
+        core::mem::replace(&mut self.timers, pending).into_values().collect()
+    }
+
+    pub fn get_next_deadline(&self) -> Option<ExtDuration> {
+        self.timers.keys().next().map(|&(deadline, _)| deadline)
+    }
+}
+
+/// Run `callback` on the calling LP once the monotonic clock has reached `deadline`. A deadline
+/// that has already passed expires right away.
+pub fn arm_at(deadline: ExtDuration, callback: impl FnOnce() + Send + 'static) -> TimerHandle {
+    SYSTEM_SCHEDULER.arm_timer(deadline, Box::new(callback))
+}
+
+/// Run `callback` on the calling LP once `delay` has passed
+pub fn arm_after(delay: ExtDuration, callback: impl FnOnce() + Send + 'static) -> TimerHandle {
+    arm_at(get_monotonic_time() + delay, callback)
+}
+
+/// Cancel a timer. Returns whether it was still pending, in which case its callback never runs.
+/// Otherwise the callback has run or is running on the timer's LP.
+pub fn cancel(handle: &TimerHandle) -> bool {
+    SYSTEM_SCHEDULER.cancel_timer(handle)
+}
+
+/// Complete `wakeup` and unblock the thread at `deadline` unless it has been completed by then
+///
+/// This is how blocking waits time out. The thread must be blocked on `wakeup`.
+pub fn arm_wakeup(deadline: ExtDuration, tid: ThreadId, wakeup: Arc<Completion>) -> TimerHandle {
+    arm_at(deadline, move || {
+        if wakeup.complete() {
+            SYSTEM_SCHEDULER.try_unblock(tid);
+        }
+    })
+}
+
+/// Suspend the calling thread until the monotonic clock has reached `deadline`
+///
+/// Code that is not running on a thread spins instead.
+pub fn sleep_until(deadline: ExtDuration) {
+    if get_monotonic_time() >= deadline {
+        return;
+    }
+    let Some(tid) = SYSTEM_SCHEDULER.get_current_tid() else {
+        while get_monotonic_time() < deadline {
+            core::hint::spin_loop();
+        }
+        return;
+    };
+    let wakeup = Arc::new(Completion::new(None));
+    {
+        // the LP must not switch away before the timer is armed
+        let _preempt_guard = PreemptGuard::new();
+        SYSTEM_SCHEDULER
+            .block_on(tid, alloc::vec![wakeup.clone()])
+            .unwrap_or_else(|err| panic!("Failed to put thread {} to sleep: {:?}", tid, err));
+        arm_wakeup(deadline, tid, wakeup.clone());
+    }
+    // the LP switches away now that preemption is enabled again
+    wakeup.spin_until_complete();
+}
+
+/// Suspend the calling thread for `duration`
+pub fn sleep_for(duration: ExtDuration) {
+    sleep_until(get_monotonic_time() + duration);
+}
@@ -0,0 +1,171 @@
+//! # Idle LPs
+//!
+//! An LP without a thread to run switches to its idle context. Its timer is then only armed for
+//! what the LP has to act on next, its earliest high-resolution timer or a decision its strategy
+//! has to make by a certain time, so an idle LP is not woken by a periodic tick.
+//!
+//! Where the ISA can wait for a memory write, the idle context also waits on a per-LP wake flag.
+//! An LP that queues work for an idle LP sets the flag instead of sending it a wake IPI, and the
+//! idle context raises the switch on its own LP once it sees the flag.
+//!
+//! How deeply the LP sleeps is chosen from the C-states the firmware describes. A C-state is only
+//! entered if the LP is expected to stay idle for long enough to make up for its exit latency.
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use spin::Once;
+
+use crate::common::collections::boxed_slice::make_boxed_slice;
+use crate::common::time::duration::ExtDuration;
+use crate::cpu::isa::interface::interrupts::LocalIntCtlrIfce;
+use crate::cpu::isa::interrupts::LocalIntCtlr;
+use crate::cpu::isa::lp::LpId;
+use crate::cpu::isa::lp::ops::{get_lp_id, without_interrupts};
+use crate::cpu::isa::timers::get_monotonic_time;
+use crate::cpu::multiprocessor::get_lp_count;
+use crate::cpu::scheduler::system_scheduler::SYSTEM_SCHEDULER;
+
+/// How many times its exit latency an LP has to be expected to stay idle for a C-state to be worth
+/// entering
+const RESIDENCY_FACTOR: u128 = 3;
+
+/// Indexed by LP ID
+static IDLE_STATES: Once<Box<[IdleState]>> = Once::new();
+/// The C-states the firmware describes, shallowest first. Until they are loaded LPs only enter the
+/// shallowest state the ISA provides.
+static CSTATES: Once<Box<[CState]>> = Once::new();
+
+/// A processor power state an idle LP can enter
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CState {
+    /// The ACPI C-state type. States deeper than C1 may stop the LP's timer.
+    pub acpi_type: u8,
+    /// The worst case time it takes the LP to resume executing once woken
+    pub exit_latency: ExtDuration,
+    /// The ISA specific hint to enter the state with or `None` to use the ISA's default idle
+    /// instruction
+    pub hint: Option<u32>,
+}
+
+/// Kept on its own cache line so that waking an LP only touches the line that LP waits on
+#[repr(align(64))]
+struct IdleState {
+    /// Set to wake the LP while it is waiting on it
+    wake_flag: AtomicBool,
+    /// Whether the LP's idle context waits on the wake flag
+    is_monitoring: AtomicBool,
+    /// How many times the LP was woken through its wake flag
+    flag_wakes: AtomicU64,
+}
+
+impl IdleState {
+    fn new() -> Self {
+        IdleState {
+            wake_flag: AtomicBool::new(false),
+            is_monitoring: AtomicBool::new(false),
+            flag_wakes: AtomicU64::new(0),
+        }
+    }
+}
+
+/// Allocate the per-LP idle state. Requires the kernel heap.
+pub fn init() {
+    IDLE_STATES.call_once(|| make_boxed_slice(get_lp_count() as usize, IdleState::new));
+}
+
+/// Make the given C-states available to idle LPs. Only the first call has an effect.
+pub fn set_cstates(mut cstates: Vec<CState>) {
+    cstates.sort_by_key(|cstate| (cstate.exit_latency, cstate.acpi_type));
+    CSTATES.call_once(|| cstates.into_boxed_slice());
+}
+
+pub fn get_cstates() -> &'static [CState] {
+    CSTATES.get().map_or(&[], |cstates| &cstates[..])
+}
+
+/// The deepest of the given states, which must be ordered shallowest first, that pays off if the
+/// LP stays idle for `idle_for`, or for an unbounded time if it is `None`. States deeper than C1
+/// are only considered if the LP's timer keeps running in them.
+pub fn select_cstate(
+    cstates: &[CState],
+    idle_for: Option<ExtDuration>,
+    is_timer_always_running: bool,
+) -> Option<&CState> {
+    cstates
+        .iter()
+        .filter(|cstate| is_timer_always_running || cstate.acpi_type <= 1)
+        .take_while(|cstate| {
+            idle_for.is_none_or(|idle_for| cstate.exit_latency * RESIDENCY_FACTOR <= idle_for)
+        })
+        .last()
+}
+
+/// The C-state the calling LP should enter now given when its timer fires next. Must be called
+/// from the LP's idle context.
+pub fn pick_cstate(is_timer_always_running: bool) -> Option<&'static CState> {
+    let deadline =
+        without_interrupts(|| SYSTEM_SCHEDULER.get_local_scheduler().lock().get_timer_deadline());
+    let idle_for = deadline.map(|deadline| deadline.saturating_sub(get_monotonic_time()));
+    select_cstate(get_cstates(), idle_for, is_timer_always_running)
+}
+
+/// Announce that the calling LP's idle context is about to wait on its wake flag and return the
+/// flag. From now on LPs waking it set the flag instead of sending it an IPI, so the flag must be
+/// checked once the wait is armed and before the LP waits on it.
+pub fn begin_monitoring() -> Option<&'static AtomicBool> {
+    let state = &IDLE_STATES.get()?[get_lp_id() as usize];
+    state.is_monitoring.store(true, Ordering::SeqCst);
+    Some(&state.wake_flag)
+}
+
+/// Clear the calling LP's wake flag. Returns whether it was set, in which case the idle context
+/// has to make the LP switch threads.
+pub fn take_wake() -> bool {
+    let Some(states) = IDLE_STATES.get() else {
+        return false;
+    };
+    let state = &states[get_lp_id() as usize];
+    let is_woken = state.wake_flag.swap(false, Ordering::SeqCst);
+    if is_woken {
+        state.flag_wakes.fetch_add(1, Ordering::Relaxed);
+    }
+    is_woken
+}
+
+/// Stop waiting on the wake flag. Must be called by each LP whenever it switches threads and
+/// before it picks the next one, which finds whatever the flag was set for.
+pub fn note_context_switch() {
+    let Some(states) = IDLE_STATES.get() else {
+        return;
+    };
+    let state = &states[get_lp_id() as usize];
+    state.is_monitoring.store(false, Ordering::SeqCst);
+    state.wake_flag.store(false, Ordering::SeqCst);
+}
+
+/// Make an idle LP pick up the threads that were queued on it, through its wake flag if its idle
+/// context waits on it and with a wake IPI otherwise
+///
+/// The caller must hold the LP's local scheduler lock, which orders the check against the LP
+/// switching threads.
+pub fn wake_lp(lp_id: LpId) {
+    if let Some(state) = IDLE_STATES.get().and_then(|states| states.get(lp_id as usize))
+        && state.is_monitoring.load(Ordering::SeqCst)
+    {
+        state.wake_flag.store(true, Ordering::SeqCst);
+        return;
+    }
+    // An LP that cannot be sent IPIs yet has not yielded to the scheduler and will find its
+    // threads in its run queue when it does.
+    let _ = LocalIntCtlr::send_wake_lp_ipi(lp_id);
+}
+
+/// How many times the given LP was woken through its wake flag rather than an IPI
+pub fn get_flag_wake_count(lp_id: LpId) -> u64 {
+    IDLE_STATES
+        .get()
+        .and_then(|states| states.get(lp_id as usize))
+        .map_or(0, |state| state.flag_wakes.load(Ordering::Relaxed))
+}
@@ -0,0 +1,269 @@
+//! # Real-Time Local Scheduling Strategy
+//!
+//! Real-time threads declare a period, a budget and a relative deadline. Each thread is released
+//! at the start of every period and may then run for up to its budget. The strategy always runs
+//! the most urgent released thread that has budget left, which is the one with the earliest
+//! absolute deadline under EDF or the one with the shortest period under rate monotonic
+//! scheduling. Whenever no real-time thread can run, the LP runs the best-effort threads through
+//! the strategy this one is layered over.
+//!
+//! Admission control keeps the sum of the densities of the real-time threads on an LP within a
+//! bound. With deadlines no longer than periods this is sufficient for EDF to meet every deadline
+//! as long as the bound is at most 100%, while rate monotonic scheduling is limited to ln 2 of the
+//! LP regardless of the configured bound.
+//!
+//! A thread that uses up its budget is throttled until its next release, at which point its
+//! budget is replenished. A thread that is taken off the LP, e.g. because it blocks, is charged
+//! for the time it ran and keeps its place in its period, so that being queued again does not give
+//! it a new period with a full budget. The LP timer is always armed to fire when the running
+//! thread's budget runs out or when the next release is due, whichever comes first, so a thread can
+//! never overrun its budget by more than the timer's resolution.
+
+use alloc::boxed::Box;
+
+use hashbrown::HashMap;
+
+use super::RunQueue;
+use super::strategy::{LsStratIfce, QueuedThread};
+use crate::common::time::duration::ExtDuration;
+use crate::cpu::isa::timers::get_monotonic_time;
+use crate::cpu::scheduler::threads::{RtParams, RtPeriod, ThreadId};
+use crate::memory::AddressSpaceId;
+
+/// The default share of an LP that real-time threads may reserve. The rest is left for best-effort
+/// threads.
+pub const DEFAULT_UTILISATION_BOUND_PPM: u64 = 950_000;
+/// The Liu and Layland bound for rate monotonic scheduling of any number of threads, i.e. ln 2
+const RM_UTILISATION_BOUND_PPM: u64 = 693_147;
+
+/// How the most urgent real-time thread is chosen
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RtPolicy {
+    /// The thread with the earliest absolute deadline runs first
+    EarliestDeadlineFirst,
+    /// The thread with the shortest period runs first
+    RateMonotonic,
+}
+
+struct RtThread {
+    asid: AddressSpaceId,
+    params: RtParams,
+    /// The start of the thread's current period
+    release: ExtDuration,
+    /// How much of its budget the thread has left in the current period
+    remaining: ExtDuration,
+}
+
+impl RtThread {
+    fn deadline(&self) -> ExtDuration {
+        self.release + self.params.get_deadline()
+    }
+
+    fn next_release(&self) -> ExtDuration {
+        self.release + self.params.get_period()
+    }
+
+    fn is_throttled(&self) -> bool {
+        self.remaining == ExtDuration::default()
+    }
+
+    /// Move on to the period `now` falls in and replenish the budget if the current period is over
+    fn replenish(&mut self, now: ExtDuration) {
+        if now >= self.next_release() {
+            let period = self.params.get_period();
+            let n_periods = (now - self.release).as_picos() / period.as_picos();
+            self.release = self.release + period * n_periods;
+            self.remaining = self.params.get_budget();
+        }
+    }
+}
+
+/// Real-Time Local Scheduling Strategy
+pub struct Edf {
+    policy: RtPolicy,
+    /// The share of the LP that real-time threads may reserve in parts per million
+    utilisation_bound: u64,
+    /// The sum of the densities of the queued real-time threads in parts per million
+    reserved: u64,
+    rt_threads: HashMap<ThreadId, RtThread>,
+    /// The strategy that schedules the best-effort threads
+    best_effort: Box<dyn LsStratIfce>,
+    /// The part of the LP's run queue that holds the best-effort threads
+    be_run_queue: RunQueue,
+    curr_tid: Option<ThreadId>,
+    curr_asid: AddressSpaceId,
+    /// When the current thread was switched to
+    curr_start: ExtDuration,
+}
+
+impl Edf {
+    pub fn new(policy: RtPolicy, best_effort: Box<dyn LsStratIfce>) -> Edf {
+        Self::with_utilisation_bound(policy, best_effort, DEFAULT_UTILISATION_BOUND_PPM)
+    }
+
+    pub fn with_utilisation_bound(
+        policy: RtPolicy,
+        best_effort: Box<dyn LsStratIfce>,
+        utilisation_bound: u64,
+    ) -> Edf {
+        let utilisation_bound = match policy {
+            RtPolicy::EarliestDeadlineFirst => utilisation_bound.min(1_000_000),
+            RtPolicy::RateMonotonic => utilisation_bound.min(RM_UTILISATION_BOUND_PPM),
+        };
+        Edf {
+            policy,
+            utilisation_bound,
+            reserved: 0,
+            rt_threads: HashMap::new(),
+            best_effort,
+            be_run_queue: RunQueue::new(),
+            curr_tid: None,
+            curr_asid: AddressSpaceId::default(),
+            curr_start: ExtDuration::default(),
+        }
+    }
+
+    /// The share of the LP reserved by real-time threads in parts per million
+    pub fn get_reserved_ppm(&self) -> u64 {
+        self.reserved
+    }
+
+    /// Charge the current real-time thread for the time it ran since it was switched to
+    fn charge_current(&mut self, now: ExtDuration) {
+        if let Some(tid) = self.curr_tid
+            && let Some(thread) = self.rt_threads.get_mut(&tid)
+        {
+            thread.remaining = thread.remaining.saturating_sub(now.saturating_sub(self.curr_start));
+        }
+    }
+
+    fn most_urgent(&self) -> Option<ThreadId> {
+        let released = self.rt_threads.iter().filter(|(_, thread)| !thread.is_throttled());
+        match self.policy {
+            RtPolicy::EarliestDeadlineFirst => {
+                released.min_by_key(|(tid, thread)| (thread.deadline(), **tid))
+            }
+            RtPolicy::RateMonotonic => {
+                released.min_by_key(|(tid, thread)| (thread.params.get_period(), **tid))
+            }
+        }
+        .map(|(tid, _)| *tid)
+    }
+
+    /// The time left until the next real-time thread is released
+    fn until_next_release(&self, now: ExtDuration) -> Option<ExtDuration> {
+        self.rt_threads.values().map(|thread| thread.next_release().saturating_sub(now)).min()
+    }
+}
+
+unsafe impl LsStratIfce for Edf {
+    fn next_thread(&mut self, _run_queue: &mut RunQueue) -> Option<ThreadId> {
+        let now = get_monotonic_time();
+        self.charge_current(now);
+        for thread in self.rt_threads.values_mut() {
+            thread.replenish(now);
+        }
+        self.curr_start = now;
+        if let Some(tid) = self.most_urgent() {
+            self.curr_tid = Some(tid);
+            self.curr_asid = self.rt_threads[&tid].asid;
+            return Some(tid);
+        }
+        self.curr_tid = self.best_effort.next_thread(&mut self.be_run_queue);
+        if self.curr_tid.is_some() {
+            self.curr_asid = self.best_effort.get_curr_as();
+        }
+        self.curr_tid
+    }
+
+    fn next_as(&mut self, _run_queue: &mut RunQueue) -> Option<AddressSpaceId> {
+        let next_asid = self.best_effort.next_as(&mut self.be_run_queue)?;
+        self.curr_asid = next_asid;
+        Some(next_asid)
+    }
+
+    fn get_curr_as(&self) -> AddressSpaceId {
+        self.curr_asid
+    }
+
+    fn get_quantum(&self, tid: ThreadId) -> ExtDuration {
+        let now = get_monotonic_time();
+        let quantum = match self.rt_threads.get(&tid) {
+            Some(thread) => thread.remaining,
+            None => self.best_effort.get_quantum(tid),
+        };
+        // a release may make another thread more urgent than this one
+        self.until_next_release(now).map_or(quantum, |until_release| quantum.min(until_release))
+    }
+
+    fn thread_added(&mut self, tid: ThreadId, thread: &QueuedThread) {
+        let rt_period = self.thread_removed(tid).or(thread.rt_period);
+        if let Some(params) = thread.rt_params {
+            self.reserved += params.density_ppm();
+            let rt_period = rt_period.unwrap_or(RtPeriod {
+                release: get_monotonic_time(),
+                remaining: params.get_budget(),
+            });
+            self.rt_threads.insert(
+                tid,
+                RtThread {
+                    asid: thread.asid,
+                    params,
+                    release: rt_period.release,
+                    remaining: rt_period.remaining,
+                },
+            );
+        } else {
+            self.be_run_queue.entry(thread.asid).or_default().push(tid);
+            self.best_effort.thread_added(tid, thread);
+        }
+    }
+
+    fn thread_removed(&mut self, tid: ThreadId) -> Option<RtPeriod> {
+        if self.curr_tid == Some(tid) {
+            // A running thread that is queued again right away, e.g. to lend it a priority, keeps
+            // running, so it stays current and is charged from now on.
+            let now = get_monotonic_time();
+            self.charge_current(now);
+            self.curr_start = now;
+        }
+        if let Some(thread) = self.rt_threads.remove(&tid) {
+            self.reserved -= thread.params.density_ppm();
+            return Some(RtPeriod {
+                release: thread.release,
+                remaining: thread.remaining,
+            });
+        }
+        let mut is_queued = false;
+        self.be_run_queue.retain(|_, as_threads| {
+            as_threads.retain(|&queued_tid| {
+                is_queued |= queued_tid == tid;
+                queued_tid != tid
+            });
+            !as_threads.is_empty()
+        });
+        if is_queued {
+            self.best_effort.thread_removed(tid);
+        }
+        None
+    }
+
+    fn quantum_expired(&mut self, tid: ThreadId) {
+        // Real-time threads are charged for the time they ran when the next thread is picked.
+        // Best-effort threads that were only cut short by a release did not use up their slice.
+        if !self.rt_threads.contains_key(&tid)
+            && get_monotonic_time().saturating_sub(self.curr_start)
+                >= self.best_effort.get_quantum(tid)
+        {
+            self.best_effort.quantum_expired(tid);
+        }
+    }
+
+    fn can_admit(&self, rt_params: &RtParams) -> bool {
+        self.reserved + rt_params.density_ppm() <= self.utilisation_bound
+    }
+
+    fn get_idle_timeout(&self) -> Option<ExtDuration> {
+        self.until_next_release(get_monotonic_time())
+    }
+}
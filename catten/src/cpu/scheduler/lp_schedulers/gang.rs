@@ -0,0 +1,107 @@
+//! # Gang Local Scheduling Strategy
+//!
+//! During the gang schedule window of an address space whose gang includes this LP, the threads
+//! of that address space are run round robin in preference to all others. Outside of such windows
+//! or when none of the gang's threads are queued here, the decision is left to the strategy this
+//! one is layered over. Time slices are cut short at the end of each window so that every LP of a
+//! gang switches to and away from its threads at about the same time.
+
+use alloc::boxed::Box;
+
+use super::RunQueue;
+use super::strategy::{LsStratIfce, QueuedThread};
+use crate::common::time::duration::ExtDuration;
+use crate::cpu::isa::timers::get_monotonic_time;
+use crate::cpu::scheduler::system_scheduler::SYSTEM_SCHEDULER;
+use crate::cpu::scheduler::threads::{RtParams, RtPeriod, ThreadId};
+use crate::memory::AddressSpaceId;
+
+/// Gang Local Scheduling Strategy
+pub struct GangScheduling {
+    inner: Box<dyn LsStratIfce>,
+    /// The address space of the gang thread that was picked last, if it was picked for its gang
+    gang_asid: Option<AddressSpaceId>,
+    /// Index of the gang thread that was picked last in its address space's thread vector
+    gang_thread_idx: usize,
+    /// The end of the gang window the current thread was picked in if there are any gangs
+    window_end: Option<ExtDuration>,
+    /// When the current thread was switched to
+    curr_start: ExtDuration,
+}
+
+impl GangScheduling {
+    pub fn new(inner: Box<dyn LsStratIfce>) -> GangScheduling {
+        GangScheduling {
+            inner,
+            gang_asid: None,
+            gang_thread_idx: 0,
+            window_end: None,
+            curr_start: ExtDuration::default(),
+        }
+    }
+}
+
+unsafe impl LsStratIfce for GangScheduling {
+    fn next_thread(&mut self, run_queue: &mut RunQueue) -> Option<ThreadId> {
+        let now = get_monotonic_time();
+        let window = SYSTEM_SCHEDULER.get_gang_window(now);
+        self.window_end = window.map(|window| window.end);
+        self.curr_start = now;
+        if let Some(asid) = window.and_then(|window| window.asid)
+            && let Some(as_threads) = run_queue.get(&asid)
+        {
+            self.gang_thread_idx = match self.gang_asid {
+                Some(gang_asid) if gang_asid == asid => {
+                    (self.gang_thread_idx + 1) % as_threads.len()
+                }
+                _ => 0,
+            };
+            self.gang_asid = Some(asid);
+            return as_threads.get(self.gang_thread_idx).copied();
+        }
+        self.gang_asid = None;
+        self.inner.next_thread(run_queue)
+    }
+
+    fn next_as(&mut self, run_queue: &mut RunQueue) -> Option<AddressSpaceId> {
+        self.gang_asid = None;
+        self.inner.next_as(run_queue)
+    }
+
+    fn get_curr_as(&self) -> AddressSpaceId {
+        self.gang_asid.unwrap_or_else(|| self.inner.get_curr_as())
+    }
+
+    fn get_quantum(&self, tid: ThreadId) -> ExtDuration {
+        let quantum = self.inner.get_quantum(tid);
+        match self.window_end {
+            Some(window_end) => quantum.min(window_end.saturating_sub(get_monotonic_time())),
+            None => quantum,
+        }
+    }
+
+    fn thread_added(&mut self, tid: ThreadId, thread: &QueuedThread) {
+        self.inner.thread_added(tid, thread);
+    }
+
+    fn thread_removed(&mut self, tid: ThreadId) -> Option<RtPeriod> {
+        self.inner.thread_removed(tid)
+    }
+
+    fn quantum_expired(&mut self, tid: ThreadId) {
+        // time slices cut short at the end of a window were not used up
+        if self.gang_asid.is_none()
+            && get_monotonic_time().saturating_sub(self.curr_start) >= self.inner.get_quantum(tid)
+        {
+            self.inner.quantum_expired(tid);
+        }
+    }
+
+    fn can_admit(&self, rt_params: &RtParams) -> bool {
+        self.inner.can_admit(rt_params)
+    }
+
+    fn get_idle_timeout(&self) -> Option<ExtDuration> {
+        self.inner.get_idle_timeout()
+    }
+}
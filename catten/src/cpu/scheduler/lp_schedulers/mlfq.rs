@@ -0,0 +1,165 @@
+//! # Multilevel Feedback Queue Local Scheduling Strategy
+//!
+//! Threads are queued on one of several priority levels and the LP always runs the first thread of
+//! the most important non-empty level. A thread that uses up its whole time slice moves down a
+//! level, so CPU bound threads sink while interactive ones stay near the top. Lower levels get
+//! longer time slices to make up for running less often. A thread that is queued again after being
+//! blocked, typically on I/O, is boosted one level above its base level. To keep the threads on the
+//! lower levels from starving, every queued thread below its base level moves up one level each
+//! aging period.
+
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+
+use hashbrown::HashMap;
+
+use super::RunQueue;
+use super::strategy::{DEFAULT_QUANTUM_MILLIS, LsStratIfce, QueuedThread};
+use crate::common::time::duration::ExtDuration;
+use crate::cpu::scheduler::threads::{RtPeriod, ThreadId};
+use crate::memory::AddressSpaceId;
+
+pub const DEFAULT_N_LEVELS: usize = 8;
+/// The default number of scheduling decisions between aging passes
+pub const DEFAULT_AGING_PERIOD: usize = 64;
+
+struct MlfqThread {
+    /// The level the thread is currently queued on
+    level: usize,
+    /// The level the thread's base priority maps to. Aging never moves a thread above it.
+    base_level: usize,
+}
+
+/// Multilevel Feedback Queue Local Scheduling Strategy
+pub struct Mlfq {
+    /// Level 0 is the most important one
+    levels: Vec<VecDeque<ThreadId>>,
+    /// Every thread queued on the LP, including the running one
+    threads: HashMap<ThreadId, MlfqThread>,
+    /// The running thread. It is not in any level queue until it is switched away from.
+    curr_tid: Option<ThreadId>,
+    curr_asid: AddressSpaceId,
+    /// Time slice length on level 0. Level `n` gets `n + 1` times as much.
+    base_quantum: ExtDuration,
+    aging_period: usize,
+    decisions_since_aging: usize,
+}
+
+impl Mlfq {
+    pub fn new(n_levels: usize) -> Mlfq {
+        Self::with_params(
+            n_levels,
+            ExtDuration::from_millis(DEFAULT_QUANTUM_MILLIS),
+            DEFAULT_AGING_PERIOD,
+        )
+    }
+
+    pub fn with_params(n_levels: usize, base_quantum: ExtDuration, aging_period: usize) -> Mlfq {
+        Mlfq {
+            levels: (0..n_levels.max(1)).map(|_| VecDeque::new()).collect(),
+            threads: HashMap::new(),
+            curr_tid: None,
+            curr_asid: AddressSpaceId::default(),
+            base_quantum,
+            aging_period: aging_period.max(1),
+            decisions_since_aging: 0,
+        }
+    }
+
+    fn lowest_level(&self) -> usize {
+        self.levels.len() - 1
+    }
+
+    /// Move every queued thread that is below its base level up by one level
+    fn age(&mut self) {
+        for level in 1..self.levels.len() {
+            for tid in core::mem::take(&mut self.levels[level]) {
+                if let Some(thread) = self.threads.get_mut(&tid) {
+                    if thread.level > thread.base_level {
+                        thread.level -= 1;
+                    }
+                    self.levels[thread.level].push_back(tid);
+                }
+            }
+        }
+    }
+
+    fn find_asid(run_queue: &RunQueue, tid: ThreadId) -> Option<AddressSpaceId> {
+        run_queue.iter().find(|(_, as_threads)| as_threads.contains(&tid)).map(|(asid, _)| *asid)
+    }
+}
+
+unsafe impl LsStratIfce for Mlfq {
+    fn next_thread(&mut self, run_queue: &mut RunQueue) -> Option<ThreadId> {
+        // the previous thread goes to the back of its level unless it was taken off the LP
+        if let Some(prev_tid) = self.curr_tid.take()
+            && let Some(thread) = self.threads.get(&prev_tid)
+        {
+            self.levels[thread.level].push_back(prev_tid);
+        }
+        self.decisions_since_aging += 1;
+        if self.decisions_since_aging >= self.aging_period {
+            self.age();
+            self.decisions_since_aging = 0;
+        }
+        let tid = self.levels.iter_mut().find_map(VecDeque::pop_front)?;
+        self.curr_tid = Some(tid);
+        if let Some(asid) = Self::find_asid(run_queue, tid) {
+            self.curr_asid = asid;
+        }
+        Some(tid)
+    }
+
+    fn next_as(&mut self, run_queue: &mut RunQueue) -> Option<AddressSpaceId> {
+        // the address space of the most important thread outside of the current one
+        let next_asid = self.levels.iter().flatten().find_map(|&tid| {
+            Self::find_asid(run_queue, tid).filter(|&asid| asid != self.curr_asid)
+        })?;
+        self.curr_asid = next_asid;
+        Some(next_asid)
+    }
+
+    fn get_curr_as(&self) -> AddressSpaceId {
+        self.curr_asid
+    }
+
+    fn get_quantum(&self, tid: ThreadId) -> ExtDuration {
+        let level = self.threads.get(&tid).map_or(0, |thread| thread.level);
+        self.base_quantum * (level as u128 + 1)
+    }
+
+    fn thread_added(&mut self, tid: ThreadId, thread: &QueuedThread) {
+        self.thread_removed(tid);
+        let base_level = (thread.priority as usize).min(self.lowest_level());
+        let level = if thread.is_woken {
+            base_level.saturating_sub(1)
+        } else {
+            base_level
+        };
+        self.threads.insert(
+            tid,
+            MlfqThread {
+                level,
+                base_level,
+            },
+        );
+        self.levels[level].push_back(tid);
+    }
+
+    fn thread_removed(&mut self, tid: ThreadId) -> Option<RtPeriod> {
+        if let Some(thread) = self.threads.remove(&tid) {
+            self.levels[thread.level].retain(|&queued_tid| queued_tid != tid);
+        }
+        if self.curr_tid == Some(tid) {
+            self.curr_tid = None;
+        }
+        None
+    }
+
+    fn quantum_expired(&mut self, tid: ThreadId) {
+        let lowest_level = self.lowest_level();
+        if let Some(thread) = self.threads.get_mut(&tid) {
+            thread.level = (thread.level + 1).min(lowest_level);
+        }
+    }
+}
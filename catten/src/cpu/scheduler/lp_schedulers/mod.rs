@@ -1,4 +1,7 @@
 //! # Logical Processor Local Schedulers
+pub mod edf;
+pub mod gang;
+pub mod mlfq;
 pub mod strategy;
 
 use alloc::boxed::Box;
@@ -7,18 +10,106 @@ use alloc::vec::Vec;
 
 use hashbrown::HashMap;
 
-use crate::cpu::isa::lp::ops::halt;
+use crate::common::time::duration::ExtDuration;
+use crate::cpu::isa::interface::memory::address::VirtualAddress;
+use crate::cpu::isa::interface::system_info::{CpuInfoIfce, LpTopology};
+use crate::cpu::isa::interface::timers::LpTimerIfce;
+use crate::cpu::isa::lp::LpId;
+use crate::cpu::isa::lp::ops::idle_lp;
+use crate::cpu::isa::lp::thread_context::{self, ThreadContext};
 use crate::cpu::isa::memory::paging::HwAsid;
-use crate::cpu::scheduler::lp_schedulers::strategy::LsStratIfce;
-use crate::cpu::scheduler::threads::{MASTER_THREAD_TABLE, ThreadId};
-use crate::memory::AddressSpaceId;
+use crate::cpu::isa::system_info::CpuInfo;
+use crate::cpu::isa::timers::{LpTimer, get_monotonic_time};
+use crate::cpu::scheduler::hrtimer::{TimerCallback, TimerHandle, TimerQueue};
+use crate::cpu::scheduler::lp_schedulers::strategy::{LsStratIfce, QueuedThread};
+use crate::cpu::scheduler::threads::{
+    MASTER_THREAD_TABLE,
+    RtParams,
+    Thread,
+    ThreadId,
+    ThreadState,
+};
+use crate::memory::{AddressSpaceId, KERNEL_ASID, VAddr};
 
 type RunQueue = BTreeMap<AddressSpaceId, Vec<ThreadId>>;
 
+/// The number of address spaces an LP remembers having run recently
+const AS_AFFINITY_COUNT: usize = 8;
+/// How long an LP that went idle right after switching away from a thread waits before switching
+/// again to release the thread
+const PREV_RELEASE_DELAY_MILLIS: u128 = 1;
+/// The shortest delay the timer is armed with, which also covers deadlines that are already due.
+/// A time slice with less than this left is treated as over.
+const MIN_TIMER_DELAY_MICROS: u128 = 1;
+
+/// The address spaces an LP ran most recently, most recent first. The page tables and data of
+/// these address spaces are the most likely to still be cached near the LP.
+struct AsAffinities([Option<AddressSpaceId>; AS_AFFINITY_COUNT]);
+
+impl AsAffinities {
+    const fn new() -> Self {
+        AsAffinities([None; AS_AFFINITY_COUNT])
+    }
+
+    /// Move the address space to the front, forgetting the least recent one if it is new
+    fn touch(&mut self, asid: AddressSpaceId) {
+        let idx = self.rank(asid).unwrap_or(AS_AFFINITY_COUNT - 1);
+        self.0[..=idx].rotate_right(1);
+        self.0[0] = Some(asid);
+    }
+
+    /// How many other address spaces the LP ran since it last ran the given one
+    fn rank(&self, asid: AddressSpaceId) -> Option<usize> {
+        self.0.iter().position(|&recent| recent == Some(asid))
+    }
+}
+
 pub struct LocalScheduler {
+    lp_id: LpId,
     run_queue: RunQueue,
     strategy: Box<dyn LsStratIfce>,
     asid_mapping: HashMap<AddressSpaceId, HwAsid>,
+    /// The thread the LP is running or `None` if it is running its idle context
+    current: Option<ThreadId>,
+    /// The thread the LP switched away from last. The LP was still on the thread's kernel stack
+    /// while doing so, so the thread is only released at the LP's next switch.
+    prev: Option<ThreadId>,
+    /// Whether the LP has yielded to the scheduler and runs its queued threads
+    is_started: bool,
+    /// Whether the LP is halted in its idle context and needs to be woken to pick up newly added
+    /// threads
+    is_halted: bool,
+    /// The context the LP runs while it has no threads
+    idle_context: ThreadContext,
+    /// The context switch that enters the first thread saves the bootstrap state here. It is never
+    /// switched back to.
+    boot_context: ThreadContext,
+    /// Fires into the context switch path when the running thread's time slice is over or the
+    /// earliest high-resolution timer is due
+    timer: LpTimer,
+    /// When the running thread's time slice ends or the idle LP has to make its next decision
+    slice_end: Option<ExtDuration>,
+    /// When the timer is armed to fire
+    timer_deadline: Option<ExtDuration>,
+    as_affinities: AsAffinities,
+    topology: LpTopology,
+    /// When the LP last balanced its load against the other LPs
+    last_balance: ExtDuration,
+    /// When the LP last looked for a thread to steal after running out of threads
+    last_steal: ExtDuration,
+    /// The system scheduler's placement epoch the last time the LP checked that its threads may
+    /// still run on it
+    placement_epoch: u64,
+    timers: TimerQueue,
+}
+
+/// Why the LP is switching threads
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SwitchReason {
+    /// The running thread used up its time slice
+    QuantumExpired,
+    /// The LP was woken or the running thread was blocked or terminated
+    Rescheduled,
 }
 
 #[repr(u8)]
@@ -27,59 +118,303 @@ pub enum Status {
     QueueFull,
     ThreadNotFound,
     AsNotFound,
+    /// The strategy can not guarantee the thread's real-time parameters on this LP
+    AdmissionRefused,
 }
 
 impl LocalScheduler {
-    pub fn new(strategy: Box<dyn LsStratIfce>) -> LocalScheduler {
-        LocalScheduler {
+    /// Create the local scheduler of the calling LP
+    pub fn new(
+        lp_id: LpId,
+        strategy: Box<dyn LsStratIfce>,
+    ) -> Result<LocalScheduler, thread_context::Error> {
+        Ok(LocalScheduler {
+            lp_id,
             run_queue: RunQueue::new(),
             strategy,
             asid_mapping: HashMap::new(),
+            current: None,
+            prev: None,
+            is_started: false,
+            // the LP picks up queued threads by itself when it first yields
+            is_halted: false,
+            idle_context: ThreadContext::new(KERNEL_ASID, VAddr::from_ptr(idle_lp as *const ()))?,
+            boot_context: ThreadContext::default(),
+            timer: LpTimer::new_preemption_timer(),
+            slice_end: None,
+            timer_deadline: None,
+            as_affinities: AsAffinities::new(),
+            topology: CpuInfo::get_local_topology(),
+            last_balance: ExtDuration::default(),
+            last_steal: ExtDuration::default(),
+            placement_epoch: 0,
+            timers: TimerQueue::new(),
+        })
+    }
+
+    /// Pick the thread to run next and update the states of the outgoing and incoming threads.
+    /// Returns `None` if the LP should run its idle context.
+    pub fn next(&mut self, reason: SwitchReason) -> Option<ThreadId> {
+        if let Some(prev_tid) = self.current.take() {
+            if let SwitchReason::QuantumExpired = reason {
+                self.strategy.quantum_expired(prev_tid);
+            }
+            // a thread that was blocked or terminated while running keeps its new state
+            if let Some(thread) = MASTER_THREAD_TABLE.get(prev_tid) {
+                let mut thread = thread.write();
+                if let ThreadState::Running(_) = thread.state {
+                    thread.state = ThreadState::Ready(self.lp_id);
+                }
+            }
+            self.prev = Some(prev_tid);
         }
+        let next_tid = self.strategy.next_thread(&mut self.run_queue);
+        if let Some(tid) = next_tid {
+            if let Some(thread) = MASTER_THREAD_TABLE.get(tid) {
+                let mut thread = thread.write();
+                thread.state = ThreadState::Running(self.lp_id);
+                thread.on_lp = Some(self.lp_id);
+                self.as_affinities.touch(thread.asid);
+            }
+        }
+        self.current = next_tid;
+        self.is_halted = next_tid.is_none();
+        self.update_timer();
+        next_tid
+    }
+
+    /// Start a new time slice if a thread is about to run. An idle LP is only woken by its timer
+    /// if the strategy needs to make a decision by a certain time, e.g. to release a throttled
+    /// real-time thread, or if it has a thread to release. Either way the timer fires no later
+    /// than the earliest high-resolution timer.
+    fn update_timer(&mut self) {
+        let duration = match self.current {
+            Some(tid) => Some(self.strategy.get_quantum(tid)),
+            None if self.prev.is_some() => {
+                let delay = ExtDuration::from_millis(PREV_RELEASE_DELAY_MILLIS);
+                Some(self.strategy.get_idle_timeout().map_or(delay, |timeout| timeout.min(delay)))
+            }
+            None => self.strategy.get_idle_timeout(),
+        };
+        let now = get_monotonic_time();
+        self.slice_end = duration.map(|duration| now + duration);
+        self.program_timer(now);
     }
 
-    pub fn next(&mut self) -> ThreadId {
-        if let Some(tid) = self.strategy.next_thread(&mut self.run_queue) {
-            tid
+    /// Arm the timer for the end of the time slice or the earliest high-resolution timer,
+    /// whichever comes first
+    fn program_timer(&mut self, now: ExtDuration) {
+        let deadline = match (self.slice_end, self.timers.get_next_deadline()) {
+            (Some(slice_end), Some(timer)) => Some(slice_end.min(timer)),
+            (slice_end, timer) => slice_end.or(timer),
+        };
+        self.timer_deadline = deadline;
+        if let Some(deadline) = deadline {
+            let duration =
+                deadline.saturating_sub(now).max(ExtDuration::from_micros(MIN_TIMER_DELAY_MICROS));
+            if self.timer.set_duration(duration).is_ok() {
+                let _ = self.timer.reset();
+            }
         } else {
-            // The calling LP is halted and will continue execution when it recieves an interrupt
-            // Threads are expected to be added to its local scheduler by the global scheduler
-            // before sending it a unicast IPI with the `Wakeup` command.
-            halt!()
+            let _ = self.timer.stop();
         }
     }
 
-    pub fn add_thread(&mut self, tid: ThreadId) -> Status {
-        if let Some(thread_ptr) = unsafe { MASTER_THREAD_TABLE.try_get_element_arc(tid) } {
-            let asid = thread_ptr.read().asid;
-            if let Some(as_threads) = self.run_queue.get_mut(&asid) {
-                as_threads.push(tid);
-                Status::Success
-            } else {
-                self.run_queue.insert(asid, alloc::vec![tid]);
-                Status::Success
-            }
+    /// Whether the timer fired because the time slice is over rather than only for
+    /// high-resolution timers. An LP without a time slice always makes a new decision.
+    pub fn is_slice_over(&self, now: ExtDuration) -> bool {
+        self.slice_end.is_none_or(|slice_end| {
+            slice_end.saturating_sub(now) < ExtDuration::from_micros(MIN_TIMER_DELAY_MICROS)
+        })
+    }
+
+    /// When the timer fires next or `None` if it is stopped
+    pub fn get_timer_deadline(&self) -> Option<ExtDuration> {
+        self.timer_deadline
+    }
+
+    /// Arm the timer for what is due next without starting a new time slice
+    pub fn rearm_timer(&mut self) {
+        self.program_timer(get_monotonic_time());
+    }
+
+    /// Queue a thread on this LP. The caller must hold the thread's lock, which is always taken
+    /// after the local scheduler's.
+    pub fn add_thread(&mut self, tid: ThreadId, thread: &mut Thread) -> Status {
+        if let Some(rt_params) = &thread.rt_params
+            && !self.strategy.can_admit(rt_params)
+        {
+            return Status::AdmissionRefused;
+        }
+        let queued_thread = QueuedThread {
+            asid: thread.asid,
+            priority: thread.get_effective_priority(),
+            is_woken: core::mem::take(&mut thread.is_woken),
+            rt_params: thread.rt_params,
+            rt_period: thread.rt_period.take(),
+        };
+        self.strategy.thread_added(tid, &queued_thread);
+        if let Some(as_threads) = self.run_queue.get_mut(&thread.asid) {
+            as_threads.push(tid);
         } else {
-            Status::ThreadNotFound
+            self.run_queue.insert(thread.asid, alloc::vec![tid]);
         }
+        Status::Success
     }
 
-    pub fn remove_threads(&mut self, tids: Vec<ThreadId>) {
-        todo!()
+    /// Whether a thread with the given real-time parameters could be queued on this LP right now
+    pub fn can_admit(&self, rt_params: &RtParams) -> bool {
+        self.strategy.can_admit(rt_params)
+    }
+
+    /// Remove the thread from the run queue. A real-time thread remembers where it was in its
+    /// period. A thread that is not queued on this LP is ignored.
+    pub fn remove_thread(&mut self, tid: ThreadId, thread: &mut Thread) {
+        let Some(as_threads) = self.run_queue.get_mut(&thread.asid) else {
+            return;
+        };
+        let Some(idx) = as_threads.iter().position(|&queued_tid| queued_tid == tid) else {
+            return;
+        };
+        as_threads.remove(idx);
+        if as_threads.is_empty() {
+            self.run_queue.remove(&thread.asid);
+        }
+        thread.rt_period = self.strategy.thread_removed(tid);
     }
 
     pub fn remove_as(&mut self, asid: AddressSpaceId) {
         if self.strategy.get_curr_as() == asid {
             self.strategy.next_as(&mut self.run_queue);
         }
-        self.run_queue.remove(&asid);
+        if let Some(as_threads) = self.run_queue.remove(&asid) {
+            for tid in as_threads {
+                self.strategy.thread_removed(tid);
+            }
+        }
     }
 
     pub fn is_idle(&self) -> bool {
         self.run_queue.is_empty()
     }
 
+    /// The number of threads queued on this LP
+    pub fn load(&self) -> usize {
+        self.run_queue.values().map(Vec::len).sum()
+    }
+
+    /// The queued threads and their address spaces
+    pub fn queued_threads(&self) -> impl Iterator<Item = (AddressSpaceId, ThreadId)> + '_ {
+        self.run_queue
+            .iter()
+            .flat_map(|(&asid, as_threads)| as_threads.iter().map(move |&tid| (asid, tid)))
+    }
+
+    /// The queued threads other than the one the LP is running and their address spaces
+    pub fn waiting_threads(&self) -> impl Iterator<Item = (AddressSpaceId, ThreadId)> + '_ {
+        self.queued_threads().filter(|&(_, tid)| Some(tid) != self.current)
+    }
+
+    /// Forget which thread the LP is running so that the thread can be placed on another LP.
+    /// Must only be called on the LP itself once the thread's context has been saved.
+    pub fn forget_current(&mut self) {
+        self.prev = self.current.take();
+    }
+
+    /// Take the thread the LP switched away from last. Must only be called on the LP itself while
+    /// it is switching threads, at which point it is no longer on that thread's kernel stack.
+    pub fn take_prev(&mut self) -> Option<ThreadId> {
+        self.prev.take()
+    }
+
+    /// Run `callback` once the monotonic clock has reached `deadline`. Must only be called on the
+    /// LP itself. The timer is armed right away if the deadline comes before everything else the
+    /// LP is waiting for, unless the LP has not yielded to the scheduler yet, in which case it
+    /// takes effect at the LP's first switch.
+    pub fn arm_timer(&mut self, deadline: ExtDuration, callback: TimerCallback) -> TimerHandle {
+        let handle = self.timers.arm(self.lp_id, deadline, callback);
+        if self.is_started && self.timer_deadline.is_none_or(|armed| deadline < armed) {
+            self.program_timer(get_monotonic_time());
+        }
+        handle
+    }
+
+    /// Remove a timer of this LP that has not expired yet. The LP's timer may still fire for it
+    /// but then finds nothing due. Returns whether it was removed.
+    pub fn cancel_timer(&mut self, handle: &TimerHandle) -> bool {
+        self.timers.cancel(handle)
+    }
+
+    /// Take the callbacks of the timers that are due at `now`
+    pub fn take_expired_timers(&mut self, now: ExtDuration) -> Vec<TimerCallback> {
+        self.timers.take_expired(now)
+    }
+
+    pub fn get_topology(&self) -> LpTopology {
+        self.topology
+    }
+
+    /// Record that the LP has seen the given placement epoch. Returns whether it is a new one.
+    pub fn update_placement_epoch(&mut self, epoch: u64) -> bool {
+        let is_new = self.placement_epoch != epoch;
+        self.placement_epoch = epoch;
+        is_new
+    }
+
+    /// Record a balancing pass at `now` if the last one was at least `period` ago. Returns whether
+    /// a pass is due.
+    pub fn take_balance_due(&mut self, now: ExtDuration, period: ExtDuration) -> bool {
+        take_due(&mut self.last_balance, now, period)
+    }
+
+    /// Like `take_balance_due` but for the attempts of an LP without threads to steal one
+    pub fn take_steal_due(&mut self, now: ExtDuration, period: ExtDuration) -> bool {
+        take_due(&mut self.last_steal, now, period)
+    }
+
+    /// Whether the LP has run a thread of the given address space recently
+    pub fn has_as_affinity(&self, asid: AddressSpaceId) -> bool {
+        self.as_affinities.rank(asid).is_some()
+    }
+
+    pub fn get_current(&self) -> Option<ThreadId> {
+        self.current
+    }
+
+    pub fn is_started(&self) -> bool {
+        self.is_started
+    }
+
+    pub fn set_started(&mut self) {
+        self.is_started = true;
+    }
+
+    pub fn is_halted(&self) -> bool {
+        self.is_halted
+    }
+
+    pub fn set_halted(&mut self, is_halted: bool) {
+        self.is_halted = is_halted;
+    }
+
+    pub fn get_idle_context_vaddr(&self) -> VAddr {
+        VAddr::from_ptr(&self.idle_context)
+    }
+
+    pub fn get_boot_context_vaddr(&self) -> VAddr {
+        VAddr::from_ptr(&self.boot_context)
+    }
+
     pub fn asid_to_hwasid(&self, asid: AddressSpaceId) -> Option<HwAsid> {
         self.asid_mapping.get(&asid).cloned()
     }
 }
+
+/// Set `last` to `now` if it is at least `period` before it. Returns whether it was.
+fn take_due(last: &mut ExtDuration, now: ExtDuration, period: ExtDuration) -> bool {
+    let is_due = now.saturating_sub(*last) >= period;
+    if is_due {
+        *last = now;
+    }
+    is_due
+}
@@ -1,11 +1,54 @@
+use core::ops::Bound::{Excluded, Unbounded};
+
 use super::*;
+use crate::common::time::duration::ExtDuration;
+use crate::cpu::scheduler::threads::{Priority, RtParams, RtPeriod};
 use crate::memory::AddressSpaceId;
 
+pub(super) const DEFAULT_QUANTUM_MILLIS: u128 = 10;
+
+/// What a strategy is told about a thread when it is queued on its LP
+#[derive(Clone, Copy, Debug)]
+pub struct QueuedThread {
+    pub asid: AddressSpaceId,
+    /// The thread's effective priority, including any priority lent to it
+    pub priority: Priority,
+    /// Set if the thread is queued again after having been blocked
+    pub is_woken: bool,
+    pub rt_params: Option<RtParams>,
+    /// Where a real-time thread was in its period when it was last taken off an LP or `None` to
+    /// release it now
+    pub rt_period: Option<RtPeriod>,
+}
+
 /// Local Scheduling Strategy Interface
 pub unsafe trait LsStratIfce {
     fn next_thread(&mut self, run_queue: &mut RunQueue) -> Option<ThreadId>;
     fn next_as(&mut self, run_queue: &mut RunQueue) -> Option<AddressSpaceId>;
     fn get_curr_as(&self) -> AddressSpaceId;
+    /// How long the given thread may run before it is preempted
+    fn get_quantum(&self, tid: ThreadId) -> ExtDuration;
+    /// Called when a thread is queued on the LP. Threads with real-time parameters are only queued
+    /// after `can_admit` has accepted them.
+    fn thread_added(&mut self, _tid: ThreadId, _thread: &QueuedThread) {}
+    /// Called when a thread is taken off the LP. Returns where a real-time thread is in its period
+    /// so that it resumes there when it is queued again.
+    fn thread_removed(&mut self, _tid: ThreadId) -> Option<RtPeriod> {
+        None
+    }
+    /// Called when the running thread is preempted because it used up its whole time slice
+    fn quantum_expired(&mut self, _tid: ThreadId) {}
+    /// Whether a thread with the given real-time parameters can be queued on the LP without
+    /// endangering the guarantees given to the real-time threads already queued on it. Strategies
+    /// without a real-time class refuse every such thread.
+    fn can_admit(&self, _rt_params: &RtParams) -> bool {
+        false
+    }
+    /// How long an LP without runnable threads may stay idle before it has to make another
+    /// scheduling decision, or `None` if it only needs to do so once a thread is queued on it
+    fn get_idle_timeout(&self) -> Option<ExtDuration> {
+        None
+    }
 }
 /// Simple Round Robin Local Scheduling Strategy
 pub struct RoundRobin {
@@ -13,57 +56,53 @@ pub struct RoundRobin {
     curr_asid: AddressSpaceId,
     /// Index of the current thread in the Thread Vector
     as_thread_idx: usize,
+    /// Time slice length
+    quantum: ExtDuration,
 }
 
 impl RoundRobin {
     /// Constructor
     pub fn new() -> RoundRobin {
+        Self::with_quantum(ExtDuration::from_millis(DEFAULT_QUANTUM_MILLIS))
+    }
+
+    pub fn with_quantum(quantum: ExtDuration) -> RoundRobin {
         RoundRobin {
             curr_asid: AddressSpaceId::default(),
             as_thread_idx: usize::default(),
+            quantum,
         }
     }
 }
 
 unsafe impl LsStratIfce for RoundRobin {
     fn next_thread(&mut self, run_queue: &mut RunQueue) -> Option<ThreadId> {
-        if run_queue.is_empty() {
-            None
-        } else {
-            if let Some(as_vec) = run_queue.get(&self.curr_asid) {
-                if as_vec.len() == (*self).as_thread_idx + 1 {
-                    let mut key_iter = run_queue.keys();
-                    while key_iter.next() != Some(&self.curr_asid) {}
-                    self.curr_asid = match key_iter.next() {
-                        Some(asid) => *asid,
-                        None => *run_queue.keys().nth(0).unwrap(),
-                    };
-                    self.as_thread_idx = 0;
-                } else {
-                    self.as_thread_idx += 1;
-                }
+        match run_queue.get(&self.curr_asid) {
+            Some(as_vec) if self.as_thread_idx + 1 < as_vec.len() => self.as_thread_idx += 1,
+            // the current address space has run out of threads or was removed from the queue
+            _ => {
+                self.next_as(run_queue)?;
             }
-
-            Some(run_queue.get(&self.curr_asid).unwrap()[self.as_thread_idx])
         }
+        run_queue.get(&self.curr_asid).and_then(|as_vec| as_vec.get(self.as_thread_idx)).copied()
     }
 
     fn next_as(&mut self, run_queue: &mut RunQueue) -> Option<AddressSpaceId> {
-        if run_queue.is_empty() {
-            None
-        } else {
-            let mut key_iter = run_queue.keys();
-            while key_iter.next() != Some(&self.curr_asid) {}
-            self.curr_asid = match key_iter.next() {
-                Some(asid) => *asid,
-                None => *run_queue.keys().nth(0).unwrap(),
-            };
-            self.as_thread_idx = 0;
-            Some(self.curr_asid)
-        }
+        let next_asid = run_queue
+            .range((Excluded(self.curr_asid), Unbounded))
+            .next()
+            .or_else(|| run_queue.iter().next())
+            .map(|(asid, _)| *asid)?;
+        self.curr_asid = next_asid;
+        self.as_thread_idx = 0;
+        Some(next_asid)
     }
 
     fn get_curr_as(&self) -> AddressSpaceId {
         self.curr_asid
     }
+
+    fn get_quantum(&self, _tid: ThreadId) -> ExtDuration {
+        self.quantum
+    }
 }
@@ -1,4 +1,9 @@
+pub mod deferred;
+pub mod executor;
+pub mod hrtimer;
+pub mod idle;
 pub mod lp_schedulers;
+pub mod preemption;
 pub mod sync;
 pub mod system_scheduler;
 pub mod threads;
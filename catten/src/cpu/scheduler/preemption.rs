@@ -0,0 +1,116 @@
+//! # Preemption Control
+//!
+//! Threads are preempted when the time slice timer of their LP expires. Code that must not lose
+//! the LP in the middle of what it is doing, such as a region holding a spinlock that other
+//! threads on the same LP would otherwise spin on for a whole time slice, disables preemption on
+//! its LP for its duration. Context switches requested in the meantime are deferred until
+//! preemption is enabled again.
+
+use alloc::boxed::Box;
+use core::marker::PhantomData;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use spin::Once;
+
+use crate::common::collections::boxed_slice::make_boxed_slice;
+use crate::cpu::isa::interface::interrupts::LocalIntCtlrIfce;
+use crate::cpu::isa::interrupts::LocalIntCtlr;
+use crate::cpu::isa::lp::ops::{get_lp_id, without_interrupts};
+use crate::cpu::multiprocessor::get_lp_count;
+
+/// Indexed by LP ID. Preemption can not happen before this is initialized since no time slice
+/// timer is armed until then.
+static PREEMPT_STATES: Once<Box<[PreemptState]>> = Once::new();
+
+struct PreemptState {
+    /// The number of regions that currently have preemption disabled on the LP
+    count: AtomicUsize,
+    /// Whether a context switch was deferred while preemption was disabled
+    is_switch_pending: AtomicBool,
+}
+
+impl PreemptState {
+    fn new() -> Self {
+        PreemptState {
+            count: AtomicUsize::new(0),
+            is_switch_pending: AtomicBool::new(false),
+        }
+    }
+}
+
+/// Allocate the per-LP preemption state. Requires the kernel heap.
+pub fn init() {
+    PREEMPT_STATES.call_once(|| make_boxed_slice(get_lp_count() as usize, PreemptState::new));
+}
+
+/// Keeps preemption disabled on the calling LP for as long as it is alive
+pub struct PreemptGuard {
+    is_counted: bool,
+    // the guard must be dropped on the LP it was created on
+    _not_send:  PhantomData<*const ()>,
+}
+
+impl PreemptGuard {
+    pub fn new() -> Self {
+        let is_counted = PREEMPT_STATES.get().is_some_and(|states| {
+            without_interrupts(|| {
+                states[get_lp_id() as usize].count.fetch_add(1, Ordering::Relaxed)
+            });
+            true
+        });
+        PreemptGuard {
+            is_counted,
+            _not_send: PhantomData,
+        }
+    }
+
+    /// Enable preemption again without performing a switch that was deferred in the meantime.
+    /// Returns whether there was one, in which case the caller has to perform it. This is for
+    /// interrupt handlers, which switch threads on their way out anyway.
+    pub fn release(mut self) -> bool {
+        let is_switch_due = self.take_count();
+        // the count is already given back
+        self.is_counted = false;
+        is_switch_due
+    }
+
+    /// Give back the count taken by the guard. Returns whether a switch is due now.
+    fn take_count(&self) -> bool {
+        if !self.is_counted {
+            return false;
+        }
+        let states = PREEMPT_STATES.get().unwrap();
+        without_interrupts(|| {
+            let state = &states[get_lp_id() as usize];
+            state.count.fetch_sub(1, Ordering::Relaxed) == 1
+                && state.is_switch_pending.swap(false, Ordering::Relaxed)
+        })
+    }
+}
+
+impl Drop for PreemptGuard {
+    fn drop(&mut self) {
+        if self.take_count() {
+            // the wake ISR performs the deferred context switch
+            let _ = LocalIntCtlr::send_wake_lp_ipi(get_lp_id());
+        }
+    }
+}
+
+/// Whether the calling LP may switch away from the thread it is running. If it may not the switch
+/// is remembered and performed once preemption is enabled again.
+///
+/// Must be called with interrupts masked.
+pub fn try_begin_switch() -> bool {
+    let Some(states) = PREEMPT_STATES.get() else {
+        return true;
+    };
+    let state = &states[get_lp_id() as usize];
+    if state.count.load(Ordering::Relaxed) == 0 {
+        state.is_switch_pending.store(false, Ordering::Relaxed);
+        true
+    } else {
+        state.is_switch_pending.store(true, Ordering::Relaxed);
+        false
+    }
+}
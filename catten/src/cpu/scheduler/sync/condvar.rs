@@ -0,0 +1,109 @@
+//! # Condition Variable
+//!
+//! Waiters are parked before the mutex they hold is released so that a notification sent after
+//! the mutex was released can not be missed. A woken thread locks the mutex again before
+//! returning and may wake spuriously, so it must check its condition again.
+
+use super::mutex::MutexGuard;
+use super::spinlock::SpinLock;
+use super::wait_queue::{Waiter, Waiters};
+use crate::common::time::duration::ExtDuration;
+use crate::cpu::isa::timers::get_monotonic_time;
+use crate::cpu::scheduler::hrtimer;
+use crate::cpu::scheduler::system_scheduler::SYSTEM_SCHEDULER;
+
+/// A condition variable used together with a sleeping `Mutex`
+pub struct Condvar {
+    waiters: SpinLock<Waiters>,
+}
+
+impl Condvar {
+    pub const fn new() -> Self {
+        Condvar {
+            waiters: SpinLock::new(Waiters::new()),
+        }
+    }
+
+    /// Release the mutex, wait to be notified and lock the mutex again
+    pub fn wait<'a, T: ?Sized>(&self, guard: MutexGuard<'a, T>) -> MutexGuard<'a, T> {
+        let mutex = guard.get_mutex();
+        let mut waiters = self.waiters.lock();
+        match SYSTEM_SCHEDULER.get_current_tid() {
+            Some(tid) => {
+                let wakeup = waiters.park(tid, ());
+                drop(guard);
+                // the LP switches away once the waiters are unlocked
+                drop(waiters);
+                wakeup.spin_until_complete();
+            }
+            // code that is not running on a thread can only poll
+            None => {
+                drop(guard);
+                drop(waiters);
+                core::hint::spin_loop();
+            }
+        }
+        mutex.lock()
+    }
+
+    /// Like `wait` but stop waiting once `timeout` has passed. Also returns whether it timed out.
+    pub fn wait_timeout<'a, T: ?Sized>(
+        &self,
+        guard: MutexGuard<'a, T>,
+        timeout: ExtDuration,
+    ) -> (MutexGuard<'a, T>, bool) {
+        let deadline = get_monotonic_time() + timeout;
+        let mutex = guard.get_mutex();
+        let mut waiters = self.waiters.lock();
+        let is_timed_out = match SYSTEM_SCHEDULER.get_current_tid() {
+            Some(tid) => {
+                let wakeup = waiters.park(tid, ());
+                // the timer is armed before the LP can switch away
+                let timer = hrtimer::arm_wakeup(deadline, tid, wakeup.clone());
+                drop(guard);
+                drop(waiters);
+                wakeup.spin_until_complete();
+                hrtimer::cancel(&timer);
+                // a thread that timed out is still queued
+                self.waiters.lock().remove(tid).is_some()
+            }
+            None => {
+                drop(guard);
+                drop(waiters);
+                core::hint::spin_loop();
+                get_monotonic_time() >= deadline
+            }
+        };
+        (mutex.lock(), is_timed_out)
+    }
+
+    /// Wait until `condition` holds, releasing the mutex while waiting
+    pub fn wait_while<'a, T: ?Sized>(
+        &self,
+        mut guard: MutexGuard<'a, T>,
+        mut condition: impl FnMut(&mut T) -> bool,
+    ) -> MutexGuard<'a, T> {
+        while condition(&mut *guard) {
+            guard = self.wait(guard);
+        }
+        guard
+    }
+
+    /// Wake the thread that has waited the longest. Returns whether there was one.
+    pub fn notify_one(&self) -> bool {
+        let waiter = self.waiters.lock().pop_front();
+        let is_woken = waiter.is_some();
+        if let Some(waiter) = waiter {
+            waiter.wake();
+        }
+        is_woken
+    }
+
+    /// Wake all waiting threads. Returns how many there were.
+    pub fn notify_all(&self) -> usize {
+        let waiters = self.waiters.lock().take_all();
+        let count = waiters.len();
+        waiters.into_iter().for_each(Waiter::wake);
+        count
+    }
+}
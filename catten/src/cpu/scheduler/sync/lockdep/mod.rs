@@ -0,0 +1,399 @@
+//! # Lock Dependency Validator
+//!
+//! `Mutex` and `RwLock` wrap their `spin` counterparts and belong to a lock class, which is the
+//! place they were created at unless they are given a named class. With the `lockdep` feature
+//! enabled every acquisition is checked before the lock is spun on:
+//!
+//! - Each LP records the locks it holds. Taking a lock while holding another one records that the
+//!   class of the held lock is taken before the class of the new one. An order that closes a cycle
+//!   with the orders recorded before can deadlock and is reported.
+//! - Taking a lock that the LP already holds is reported as recursion, except for nested reads.
+//! - A class that is taken in an interrupt handler and also taken with interrupts unmasked is
+//!   reported since the handler can interrupt the holder on the same LP and spin forever.
+//!
+//! Each problem is reported once when it first occurs, together with the stack traces of both
+//! acquisitions involved. Stack traces follow the frame pointer chain, so the kernel should be
+//! built with `-C force-frame-pointers=yes` to get complete ones. Without the feature the
+//! wrappers add nothing to the locks they wrap.
+
+#[cfg(feature = "lockdep")]
+mod validator;
+
+use core::marker::PhantomData;
+use core::ops::{Deref, DerefMut};
+use core::panic::Location;
+
+/// The class that the validator tracks a lock as
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct LockClass {
+    /// The name of a named class or the file a lock was created in
+    name: &'static str,
+    line: u32,
+    column: u32,
+}
+
+impl LockClass {
+    /// A class shared by all locks created with it, wherever they were created
+    pub const fn named(name: &'static str) -> Self {
+        LockClass {
+            name,
+            line: 0,
+            column: 0,
+        }
+    }
+
+    /// The class of the locks created at the caller's location
+    #[track_caller]
+    pub const fn here() -> Self {
+        let location = Location::caller();
+        LockClass {
+            name: location.file(),
+            line: location.line(),
+            column: location.column(),
+        }
+    }
+}
+
+impl core::fmt::Display for LockClass {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        if self.line == 0 {
+            write!(f, "{}", self.name)
+        } else {
+            write!(f, "{}:{}:{}", self.name, self.line, self.column)
+        }
+    }
+}
+
+/// Marks the calling LP as running an interrupt handler for as long as it is alive
+pub struct IrqContext {
+    _not_send: PhantomData<*const ()>,
+}
+
+impl IrqContext {
+    #[inline]
+    pub fn enter() -> Self {
+        #[cfg(feature = "lockdep")]
+        validator::enter_irq();
+        IrqContext {
+            _not_send: PhantomData,
+        }
+    }
+}
+
+impl Drop for IrqContext {
+    #[inline]
+    fn drop(&mut self) {
+        #[cfg(feature = "lockdep")]
+        validator::exit_irq();
+    }
+}
+
+/// Marks the calling LP as running softirqs that an interrupt handler drains with interrupts
+/// unmasked for as long as it is alive. Their locks are validated like those taken by threads
+/// since interrupts can arrive while they are held.
+pub struct SoftIrqContext {
+    _not_send: PhantomData<*const ()>,
+}
+
+impl SoftIrqContext {
+    /// Must be called within an `IrqContext`
+    #[inline]
+    pub fn enter() -> Self {
+        #[cfg(feature = "lockdep")]
+        validator::exit_irq();
+        SoftIrqContext {
+            _not_send: PhantomData,
+        }
+    }
+}
+
+impl Drop for SoftIrqContext {
+    #[inline]
+    fn drop(&mut self) {
+        #[cfg(feature = "lockdep")]
+        validator::enter_irq();
+    }
+}
+
+/// Allocate the validator's per-LP state. Requires the kernel heap. Locks are not validated
+/// before this.
+pub fn init() {
+    #[cfg(feature = "lockdep")]
+    validator::init();
+}
+
+/// The number of problems that have been reported. Always 0 without the `lockdep` feature.
+pub fn get_report_count() -> usize {
+    #[cfg(feature = "lockdep")]
+    return validator::get_report_count();
+    #[cfg(not(feature = "lockdep"))]
+    0
+}
+
+/// Tracks a lock as held by the calling LP until it is dropped
+struct Held {
+    #[cfg(feature = "lockdep")]
+    addr: usize,
+    #[cfg(feature = "lockdep")]
+    is_tracked: bool,
+    _not_send: PhantomData<*const ()>,
+}
+
+impl Held {
+    #[inline]
+    #[allow(unused_variables)]
+    fn acquire(class: LockClass, addr: usize, is_shared: bool) -> Self {
+        Held {
+            #[cfg(feature = "lockdep")]
+            addr,
+            #[cfg(feature = "lockdep")]
+            is_tracked: validator::acquire(class, addr, is_shared, false),
+            _not_send: PhantomData,
+        }
+    }
+
+    /// Track a lock that was taken without spinning, which can not deadlock
+    #[inline]
+    #[allow(unused_variables)]
+    fn try_acquired(class: LockClass, addr: usize, is_shared: bool) -> Self {
+        Held {
+            #[cfg(feature = "lockdep")]
+            addr,
+            #[cfg(feature = "lockdep")]
+            is_tracked: validator::acquire(class, addr, is_shared, true),
+            _not_send: PhantomData,
+        }
+    }
+}
+
+impl Drop for Held {
+    #[inline]
+    fn drop(&mut self) {
+        #[cfg(feature = "lockdep")]
+        if self.is_tracked {
+            validator::release(self.addr);
+        }
+    }
+}
+
+/// A `spin::Mutex` whose acquisitions are validated
+pub struct Mutex<T: ?Sized> {
+    #[cfg(feature = "lockdep")]
+    class: LockClass,
+    inner: spin::Mutex<T>,
+}
+
+pub struct MutexGuard<'a, T: ?Sized + 'a> {
+    // the lock is released before the validator stops tracking it
+    guard: spin::MutexGuard<'a, T>,
+    _held: Held,
+}
+
+impl<T> Mutex<T> {
+    #[track_caller]
+    pub const fn new(data: T) -> Self {
+        Self::with_class(data, LockClass::here())
+    }
+
+    #[allow(unused_variables)]
+    pub const fn with_class(data: T, class: LockClass) -> Self {
+        Mutex {
+            #[cfg(feature = "lockdep")]
+            class,
+            inner: spin::Mutex::new(data),
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.inner.into_inner()
+    }
+}
+
+impl<T: ?Sized> Mutex<T> {
+    fn get_class(&self) -> LockClass {
+        #[cfg(feature = "lockdep")]
+        return self.class;
+        #[cfg(not(feature = "lockdep"))]
+        LockClass::named("")
+    }
+
+    fn get_addr(&self) -> usize {
+        self as *const Self as *const () as usize
+    }
+
+    pub fn lock(&self) -> MutexGuard<'_, T> {
+        let held = Held::acquire(self.get_class(), self.get_addr(), false);
+        MutexGuard {
+            guard: self.inner.lock(),
+            _held: held,
+        }
+    }
+
+    /// Lock the mutex and call `relax` each time it finds the mutex held while spinning on it
+    pub fn lock_with_relax(&self, mut relax: impl FnMut()) -> MutexGuard<'_, T> {
+        let held = Held::acquire(self.get_class(), self.get_addr(), false);
+        let guard = loop {
+            if let Some(guard) = self.inner.try_lock() {
+                break guard;
+            }
+            relax();
+            core::hint::spin_loop();
+        };
+        MutexGuard {
+            guard,
+            _held: held,
+        }
+    }
+
+    pub fn try_lock(&self) -> Option<MutexGuard<'_, T>> {
+        let guard = self.inner.try_lock()?;
+        Some(MutexGuard {
+            guard,
+            _held: Held::try_acquired(self.get_class(), self.get_addr(), false),
+        })
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.inner.is_locked()
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.inner.get_mut()
+    }
+}
+
+impl<T: Default> Default for Mutex<T> {
+    #[track_caller]
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+impl<T: ?Sized> Deref for MutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.guard
+    }
+}
+
+impl<T: ?Sized> DerefMut for MutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.guard
+    }
+}
+
+/// A `spin::RwLock` whose acquisitions are validated
+pub struct RwLock<T: ?Sized> {
+    #[cfg(feature = "lockdep")]
+    class: LockClass,
+    inner: spin::RwLock<T>,
+}
+
+pub struct RwLockReadGuard<'a, T: ?Sized + 'a> {
+    guard: spin::RwLockReadGuard<'a, T>,
+    _held: Held,
+}
+
+pub struct RwLockWriteGuard<'a, T: ?Sized + 'a> {
+    guard: spin::RwLockWriteGuard<'a, T>,
+    _held: Held,
+}
+
+impl<T> RwLock<T> {
+    #[track_caller]
+    pub const fn new(data: T) -> Self {
+        Self::with_class(data, LockClass::here())
+    }
+
+    #[allow(unused_variables)]
+    pub const fn with_class(data: T, class: LockClass) -> Self {
+        RwLock {
+            #[cfg(feature = "lockdep")]
+            class,
+            inner: spin::RwLock::new(data),
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.inner.into_inner()
+    }
+}
+
+impl<T: ?Sized> RwLock<T> {
+    fn get_class(&self) -> LockClass {
+        #[cfg(feature = "lockdep")]
+        return self.class;
+        #[cfg(not(feature = "lockdep"))]
+        LockClass::named("")
+    }
+
+    fn get_addr(&self) -> usize {
+        self as *const Self as *const () as usize
+    }
+
+    pub fn read(&self) -> RwLockReadGuard<'_, T> {
+        let held = Held::acquire(self.get_class(), self.get_addr(), true);
+        RwLockReadGuard {
+            guard: self.inner.read(),
+            _held: held,
+        }
+    }
+
+    pub fn write(&self) -> RwLockWriteGuard<'_, T> {
+        let held = Held::acquire(self.get_class(), self.get_addr(), false);
+        RwLockWriteGuard {
+            guard: self.inner.write(),
+            _held: held,
+        }
+    }
+
+    pub fn try_read(&self) -> Option<RwLockReadGuard<'_, T>> {
+        let guard = self.inner.try_read()?;
+        Some(RwLockReadGuard {
+            guard,
+            _held: Held::try_acquired(self.get_class(), self.get_addr(), true),
+        })
+    }
+
+    pub fn try_write(&self) -> Option<RwLockWriteGuard<'_, T>> {
+        let guard = self.inner.try_write()?;
+        Some(RwLockWriteGuard {
+            guard,
+            _held: Held::try_acquired(self.get_class(), self.get_addr(), false),
+        })
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.inner.get_mut()
+    }
+}
+
+impl<T: Default> Default for RwLock<T> {
+    #[track_caller]
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+impl<T: ?Sized> Deref for RwLockReadGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.guard
+    }
+}
+
+impl<T: ?Sized> Deref for RwLockWriteGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.guard
+    }
+}
+
+impl<T: ?Sized> DerefMut for RwLockWriteGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.guard
+    }
+}
@@ -0,0 +1,404 @@
+//! # Lock Dependency Validation
+//!
+//! The validator keeps its own state behind raw `spin` locks that are only taken with interrupts
+//! masked. Locks that are taken while the calling LP is validating, e.g. by the allocator or the
+//! logger while a report is printed, are neither validated nor tracked.
+
+use alloc::boxed::Box;
+use alloc::collections::btree_map::BTreeMap;
+use alloc::collections::btree_set::BTreeSet;
+use alloc::vec::Vec;
+
+use spin::{Mutex, Once};
+
+use super::LockClass;
+use crate::common::collections::boxed_slice::make_boxed_slice;
+use crate::cpu::isa::lp::ops::{
+    are_interrupts_enabled,
+    get_frame_pointer,
+    get_lp_id,
+    without_interrupts,
+};
+use crate::cpu::multiprocessor::get_lp_count;
+use crate::logln;
+use crate::memory::allocators::global_allocator::is_heap_locked_by_current_lp;
+
+/// The number of return addresses kept per stack trace
+const MAX_FRAMES: usize = 16;
+/// Frame pointers further than this from the first one are not followed
+const MAX_STACK_SPAN: usize = 1 << 20;
+
+/// Indexed by LP ID
+static LP_STATES: Once<Box<[Mutex<LpState>]>> = Once::new();
+static GRAPH: Mutex<Graph> = Mutex::new(Graph {
+    dependencies: BTreeMap::new(),
+    usages: BTreeMap::new(),
+    reported: BTreeSet::new(),
+});
+
+#[derive(Clone, Copy)]
+struct StackTrace {
+    return_addrs: [usize; MAX_FRAMES],
+    len: usize,
+}
+
+impl StackTrace {
+    /// Follow the frame pointer chain of the calling LP's current stack
+    #[inline(always)]
+    fn capture() -> Self {
+        let mut trace = StackTrace {
+            return_addrs: [0; MAX_FRAMES],
+            len: 0,
+        };
+        let first = get_frame_pointer();
+        let mut fp = first;
+        // kernel stacks are in the higher half and grow down, so callers' frames are above
+        while trace.len < MAX_FRAMES
+            && fp & (size_of::<usize>() - 1) == 0
+            && (fp as isize) < 0
+            && fp - first < MAX_STACK_SPAN
+        {
+            // SAFETY: The frame pointer points into the current stack as checked above.
+            let (prev_fp, return_addr) =
+                unsafe { (*(fp as *const usize), *((fp as *const usize).add(1))) };
+            if return_addr == 0 {
+                break;
+            }
+            trace.return_addrs[trace.len] = return_addr;
+            trace.len += 1;
+            if prev_fp <= fp {
+                break;
+            }
+            fp = prev_fp;
+        }
+        trace
+    }
+
+    fn log(&self, title: &str) {
+        logln!("  {}:", title);
+        for return_addr in &self.return_addrs[..self.len] {
+            logln!("    {:#018x}", return_addr);
+        }
+    }
+}
+
+struct HeldLock {
+    class: LockClass,
+    addr: usize,
+    is_shared: bool,
+    trace: StackTrace,
+}
+
+struct LpState {
+    /// The locks the LP holds in the order it took them
+    held: Vec<HeldLock>,
+    /// The number of interrupt handlers the LP is running
+    irq_depth: usize,
+    is_validating: bool,
+}
+
+/// The class on the left was held while the one on the right was taken
+type Dependency = (LockClass, LockClass);
+
+/// The acquisitions a dependency was first recorded at
+#[derive(Clone, Copy)]
+struct DependencyTraces {
+    held:  StackTrace,
+    taken: StackTrace,
+}
+
+/// The first acquisition of a class in each context
+#[derive(Default)]
+struct Usage {
+    irq_shared: Option<StackTrace>,
+    irq_exclusive: Option<StackTrace>,
+    irqs_on_shared: Option<StackTrace>,
+    irqs_on_exclusive: Option<StackTrace>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Problem {
+    Cycle(Dependency),
+    Recursion(LockClass),
+    IrqUnsafe(LockClass),
+}
+
+struct Graph {
+    dependencies: BTreeMap<Dependency, DependencyTraces>,
+    usages: BTreeMap<LockClass, Usage>,
+    reported: BTreeSet<Problem>,
+}
+
+impl Graph {
+    /// The dependencies leading from `from` to `to` if there is such a path
+    fn find_path(&self, from: LockClass, to: LockClass) -> Option<Vec<Dependency>> {
+        let mut visited = BTreeSet::new();
+        let mut stack = alloc::vec![(from, Vec::new())];
+        while let Some((class, path)) = stack.pop() {
+            if class == to {
+                return Some(path);
+            }
+            if !visited.insert(class) {
+                continue;
+            }
+            let successors = self
+                .dependencies
+                .range((class, LockClass::named(""))..)
+                .take_while(|((before, _), _)| *before == class);
+            for (&dependency, _) in successors {
+                let mut path = path.clone();
+                path.push(dependency);
+                stack.push((dependency.1, path));
+            }
+        }
+        None
+    }
+}
+
+/// A problem to log once the graph is unlocked
+enum Report {
+    Cycle {
+        held: LockClass,
+        taken: LockClass,
+        path: Vec<Dependency>,
+        current: StackTrace,
+        previous: DependencyTraces,
+    },
+    Recursion {
+        class: LockClass,
+        current: StackTrace,
+        previous: StackTrace,
+    },
+    IrqUnsafe {
+        class: LockClass,
+        irq: StackTrace,
+        irqs_on: StackTrace,
+    },
+}
+
+impl Report {
+    fn log(&self) {
+        logln!("***\nLOCKDEP: LP{} found a possible deadlock", (get_lp_id()));
+        match self {
+            Report::Cycle {
+                held,
+                taken,
+                path,
+                current,
+                previous,
+            } => {
+                logln!("Taking {} while holding {} inverts the order:", taken, held);
+                for (before, after) in path {
+                    logln!("  {} -> {}", before, after);
+                }
+                current.log("This acquisition");
+                previous.held.log("The first lock of the inverted order was held at");
+                previous.taken.log("when the second one was taken at");
+            }
+            Report::Recursion {
+                class,
+                current,
+                previous,
+            } => {
+                logln!("Taking {} which this LP already holds", class);
+                current.log("This acquisition");
+                previous.log("The lock was taken before at");
+            }
+            Report::IrqUnsafe {
+                class,
+                irq,
+                irqs_on,
+            } => {
+                logln!(
+                    "{} is taken both by interrupt handlers and with interrupts unmasked",
+                    class
+                );
+                irq.log("Taken in an interrupt handler at");
+                irqs_on.log("Taken with interrupts unmasked at");
+            }
+        }
+        logln!("***");
+    }
+}
+
+pub(super) fn init() {
+    LP_STATES.call_once(|| {
+        make_boxed_slice(get_lp_count() as usize, || {
+            Mutex::new(LpState {
+                held: Vec::new(),
+                irq_depth: 0,
+                is_validating: false,
+            })
+        })
+    });
+}
+
+pub(super) fn get_report_count() -> usize {
+    without_interrupts(|| GRAPH.lock().reported.len())
+}
+
+pub(super) fn enter_irq() {
+    if let Some(states) = LP_STATES.get() {
+        without_interrupts(|| states[get_lp_id() as usize].lock().irq_depth += 1);
+    }
+}
+
+pub(super) fn exit_irq() {
+    if let Some(states) = LP_STATES.get() {
+        without_interrupts(|| {
+            let mut state = states[get_lp_id() as usize].lock();
+            state.irq_depth = state.irq_depth.saturating_sub(1);
+        });
+    }
+}
+
+/// Validate an acquisition and track the lock as held by the calling LP. Returns whether it is
+/// tracked.
+pub(super) fn acquire(class: LockClass, addr: usize, is_shared: bool, is_try: bool) -> bool {
+    let Some(states) = LP_STATES.get() else {
+        return false;
+    };
+    // validating allocates, which would spin forever on the heap lock this LP holds
+    if is_heap_locked_by_current_lp() {
+        return false;
+    }
+    let trace = StackTrace::capture();
+    let reports = without_interrupts(|| {
+        let mut state = states[get_lp_id() as usize].lock();
+        if state.is_validating {
+            return None;
+        }
+        state.is_validating = true;
+        let mut reports = Vec::new();
+        {
+            let mut graph = GRAPH.lock();
+            check_usage(&mut graph, &state, class, is_shared, trace, &mut reports);
+            // taking a lock without spinning can not deadlock
+            if !is_try {
+                check_held(&mut graph, &state, class, addr, is_shared, trace, &mut reports);
+            }
+        }
+        state.held.push(HeldLock {
+            class,
+            addr,
+            is_shared,
+            trace,
+        });
+        Some(reports)
+    });
+    let Some(reports) = reports else {
+        return false;
+    };
+    for report in &reports {
+        report.log();
+    }
+    without_interrupts(|| states[get_lp_id() as usize].lock().is_validating = false);
+    true
+}
+
+/// Stop tracking a lock. A lock that a thread took on another LP before it was moved is looked
+/// for on the other LPs.
+pub(super) fn release(addr: usize) {
+    let Some(states) = LP_STATES.get() else {
+        return;
+    };
+    let lp_id = get_lp_id() as usize;
+    let lp_ids = core::iter::once(lp_id).chain((0..states.len()).filter(|&id| id != lp_id));
+    without_interrupts(|| {
+        for id in lp_ids {
+            let mut state = states[id].lock();
+            if let Some(index) = state.held.iter().rposition(|held| held.addr == addr) {
+                state.held.remove(index);
+                return;
+            }
+        }
+    });
+}
+
+/// Record the context the class is taken in and report using it both with interrupts unmasked
+/// and in interrupt handlers
+fn check_usage(
+    graph: &mut Graph,
+    state: &LpState,
+    class: LockClass,
+    is_shared: bool,
+    trace: StackTrace,
+    reports: &mut Vec<Report>,
+) {
+    let usage = graph.usages.entry(class).or_default();
+    let slot = match (state.irq_depth > 0, is_shared) {
+        (true, true) => &mut usage.irq_shared,
+        (true, false) => &mut usage.irq_exclusive,
+        (false, _) if !are_interrupts_enabled() => return,
+        (false, true) => &mut usage.irqs_on_shared,
+        (false, false) => &mut usage.irqs_on_exclusive,
+    };
+    slot.get_or_insert(trace);
+    // readers only block each other out if one of them is a writer
+    let conflict = usage
+        .irq_exclusive
+        .zip(usage.irqs_on_exclusive.or(usage.irqs_on_shared))
+        .or(usage.irq_shared.zip(usage.irqs_on_exclusive));
+    if let Some((irq, irqs_on)) = conflict
+        && graph.reported.insert(Problem::IrqUnsafe(class))
+    {
+        reports.push(Report::IrqUnsafe {
+            class,
+            irq,
+            irqs_on,
+        });
+    }
+}
+
+/// Report recursion and order inversions against the locks the LP holds and record the new
+/// dependencies
+fn check_held(
+    graph: &mut Graph,
+    state: &LpState,
+    class: LockClass,
+    addr: usize,
+    is_shared: bool,
+    trace: StackTrace,
+    reports: &mut Vec<Report>,
+) {
+    for held in &state.held {
+        if held.addr == addr {
+            // nested reads do not block each other
+            if !(held.is_shared && is_shared) && graph.reported.insert(Problem::Recursion(class)) {
+                reports.push(Report::Recursion {
+                    class,
+                    current: trace,
+                    previous: held.trace,
+                });
+            }
+            continue;
+        }
+        // locks of the same class are ordered by their callers, e.g. by address
+        if held.class == class {
+            continue;
+        }
+        let dependency = (held.class, class);
+        if graph.dependencies.contains_key(&dependency) {
+            continue;
+        }
+        if let Some(path) = graph.find_path(class, held.class) {
+            if graph.reported.insert(Problem::Cycle(dependency)) {
+                reports.push(Report::Cycle {
+                    held: held.class,
+                    taken: class,
+                    current: trace,
+                    previous: graph.dependencies[&path[0]],
+                    path,
+                });
+            }
+            continue;
+        }
+        graph.dependencies.insert(
+            dependency,
+            DependencyTraces {
+                held:  held.trace,
+                taken: trace,
+            },
+        );
+    }
+}
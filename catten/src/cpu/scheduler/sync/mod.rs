@@ -1 +1,14 @@
+//! # Scheduler Aware Synchronization Primitives
+//!
+//! Spinlocks are meant for short critical sections that are never held across blocking. The
+//! other primitives park the threads that wait on them so that their LPs can run other threads in
+//! the meantime. Read-mostly data can be protected with RCU, whose readers never wait.
 
+pub mod condvar;
+pub mod lockdep;
+pub mod mutex;
+pub mod rcu;
+pub mod rwlock;
+pub mod semaphore;
+pub mod spinlock;
+pub mod wait_queue;
@@ -0,0 +1,269 @@
+//! # Sleeping Mutex
+//!
+//! A thread that finds the mutex locked first spins for a while if the owner is running on
+//! another LP, since the owner is then likely to release it soon, and otherwise parks until the
+//! mutex is handed over to it. Ownership is handed directly to the thread that has waited the
+//! longest so that waiters are served in FIFO order and can not be overtaken by newcomers.
+//!
+//! Waiters lend their priority to the owner until it releases the mutex. If the owner is itself
+//! parked on another mutex, the priority is passed along to that mutex's owner and so on for up to
+//! `MAX_INHERITANCE_DEPTH` mutexes, which also bounds the walk when the chain is a deadlock cycle.
+
+use alloc::collections::BTreeMap;
+use core::cell::UnsafeCell;
+use core::marker::PhantomData;
+use core::ops::{Deref, DerefMut};
+
+use super::spinlock::SpinLock;
+use super::wait_queue::Waiters;
+use crate::cpu::isa::lp::ops::get_lp_id;
+use crate::cpu::scheduler::system_scheduler::SYSTEM_SCHEDULER;
+use crate::cpu::scheduler::threads::ThreadId;
+
+/// How many times a thread polls a mutex whose owner is running on another LP before parking
+const ADAPTIVE_SPIN_LIMIT: usize = 1000;
+/// How many mutexes a lent priority is passed along at most
+const MAX_INHERITANCE_DEPTH: usize = 8;
+
+/// The state of the mutex each parked thread waits on. A thread stays in here until it has
+/// returned from parking, so the mutex it refers to is still borrowed by that thread's `lock`
+/// call. This is locked before the state of any mutex.
+static BLOCKED_ON: SpinLock<BTreeMap<ThreadId, BlockedOn>> = SpinLock::new(BTreeMap::new());
+
+struct BlockedOn(*const SpinLock<MutexState>);
+
+// the state is only reached through `BLOCKED_ON` while its waiter keeps the mutex alive
+unsafe impl Send for BlockedOn {}
+
+struct MutexState {
+    is_locked: bool,
+    /// The thread holding the mutex or `None` if it is unlocked or held by code that does not
+    /// run on a thread
+    owner: Option<ThreadId>,
+    waiters: Waiters,
+}
+
+/// A mutual exclusion lock that parks the threads waiting for it
+pub struct Mutex<T: ?Sized> {
+    state: SpinLock<MutexState>,
+    data:  UnsafeCell<T>,
+}
+
+unsafe impl<T: ?Sized + Send> Send for Mutex<T> {}
+unsafe impl<T: ?Sized + Send> Sync for Mutex<T> {}
+
+pub struct MutexGuard<'a, T: ?Sized + 'a> {
+    mutex: &'a Mutex<T>,
+    // the mutex lends priorities to the thread that locked it so it must be unlocked there
+    _not_send: PhantomData<*const ()>,
+}
+
+unsafe impl<T: ?Sized + Sync> Sync for MutexGuard<'_, T> {}
+
+impl<T> Mutex<T> {
+    pub const fn new(data: T) -> Self {
+        Mutex {
+            state: SpinLock::new(MutexState {
+                is_locked: false,
+                owner: None,
+                waiters: Waiters::new(),
+            }),
+            data:  UnsafeCell::new(data),
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.data.into_inner()
+    }
+}
+
+impl<T: ?Sized> Mutex<T> {
+    pub fn lock(&self) -> MutexGuard<'_, T> {
+        let tid = SYSTEM_SCHEDULER.get_current_tid();
+        for _ in 0..ADAPTIVE_SPIN_LIMIT {
+            if let Some(guard) = self.try_lock_as(tid) {
+                return guard;
+            }
+            if !self.is_owner_running_elsewhere() {
+                break;
+            }
+            core::hint::spin_loop();
+        }
+        loop {
+            let mut blocked_on = BLOCKED_ON.lock();
+            let mut state = self.state.lock();
+            if !state.is_locked {
+                state.is_locked = true;
+                state.owner = tid;
+                return MutexGuard::new(self);
+            }
+            match tid {
+                Some(tid) => {
+                    if state.owner == Some(tid) {
+                        panic!("Thread {} tried to lock a mutex it already holds", tid);
+                    }
+                    let wakeup = state.waiters.park(tid, ());
+                    blocked_on.insert(tid, BlockedOn(&self.state));
+                    self.lend_top_priority(&state);
+                    let owner = state.owner;
+                    drop(state);
+                    pass_on_priority(&blocked_on, owner);
+                    // the LP switches away once the spinlocks are unlocked
+                    drop(blocked_on);
+                    wakeup.spin_until_complete();
+                    BLOCKED_ON.lock().remove(&tid);
+                    // the mutex is handed over before its waiters are woken
+                    if self.state.lock().owner == Some(tid) {
+                        return MutexGuard::new(self);
+                    }
+                }
+                None => {
+                    drop(state);
+                    drop(blocked_on);
+                    core::hint::spin_loop();
+                }
+            }
+        }
+    }
+
+    pub fn try_lock(&self) -> Option<MutexGuard<'_, T>> {
+        self.try_lock_as(SYSTEM_SCHEDULER.get_current_tid())
+    }
+
+    fn try_lock_as(&self, tid: Option<ThreadId>) -> Option<MutexGuard<'_, T>> {
+        let mut state = self.state.lock();
+        if state.is_locked {
+            return None;
+        }
+        state.is_locked = true;
+        state.owner = tid;
+        Some(MutexGuard::new(self))
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.state.lock().is_locked
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.data.get_mut()
+    }
+
+    /// Whether the mutex is held by a thread that is running on another LP
+    fn is_owner_running_elsewhere(&self) -> bool {
+        let owner = self.state.lock().owner;
+        owner
+            .and_then(|owner| SYSTEM_SCHEDULER.get_running_lp(owner))
+            .is_some_and(|lp_id| lp_id != get_lp_id())
+    }
+
+    /// The key the mutex lends priorities under
+    fn key(&self) -> usize {
+        get_key(&self.state)
+    }
+
+    /// Lend the owner the priority of the most important waiter
+    fn lend_top_priority(&self, state: &MutexState) {
+        lend_top_priority(self.key(), state);
+    }
+
+    fn unlock(&self) {
+        let waiter = {
+            let mut state = self.state.lock();
+            if let Some(owner) = state.owner {
+                let _ = SYSTEM_SCHEDULER.lend_priority(owner, self.key(), None);
+            }
+            let waiter = state.waiters.pop_front();
+            match &waiter {
+                Some(waiter) => {
+                    state.owner = Some(waiter.tid);
+                    self.lend_top_priority(&state);
+                }
+                None => {
+                    state.is_locked = false;
+                    state.owner = None;
+                }
+            }
+            waiter
+        };
+        if let Some(waiter) = waiter {
+            waiter.wake();
+        }
+    }
+}
+
+/// The key a mutex lends priorities under, which is the address of its state so that it can also
+/// be derived from the states in `BLOCKED_ON`
+fn get_key(state: *const SpinLock<MutexState>) -> usize {
+    state as usize
+}
+
+fn lend_top_priority(key: usize, state: &MutexState) {
+    if let Some(owner) = state.owner {
+        // the owner may have been terminated while holding the mutex
+        let _ = SYSTEM_SCHEDULER.lend_priority(owner, key, state.waiters.top_priority());
+    }
+}
+
+/// Pass the effective priority of `owner` along the chain of mutexes that it and the owners after
+/// it are parked on
+fn pass_on_priority(blocked_on: &BTreeMap<ThreadId, BlockedOn>, mut owner: Option<ThreadId>) {
+    for _ in 0..MAX_INHERITANCE_DEPTH {
+        let Some(tid) = owner else {
+            return;
+        };
+        let Some(BlockedOn(next)) = blocked_on.get(&tid) else {
+            return;
+        };
+        let Some(priority) = SYSTEM_SCHEDULER.get_effective_priority(tid) else {
+            return;
+        };
+        // `tid` is in `BLOCKED_ON` so it is still inside the `lock` call that borrows the mutex
+        let mut state = unsafe { &**next }.lock();
+        // the thread may have been handed the mutex and not have removed itself yet
+        if !state.waiters.set_priority(tid, priority) {
+            return;
+        }
+        lend_top_priority(get_key(*next), &state);
+        owner = state.owner;
+    }
+}
+
+impl<T: Default> Default for Mutex<T> {
+    fn default() -> Self {
+        Mutex::new(T::default())
+    }
+}
+
+impl<'a, T: ?Sized> MutexGuard<'a, T> {
+    fn new(mutex: &'a Mutex<T>) -> Self {
+        MutexGuard {
+            mutex,
+            _not_send: PhantomData,
+        }
+    }
+
+    /// The mutex the guard locks
+    pub(super) fn get_mutex(&self) -> &'a Mutex<T> {
+        self.mutex
+    }
+}
+
+impl<T: ?Sized> Deref for MutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.mutex.data.get() }
+    }
+}
+
+impl<T: ?Sized> DerefMut for MutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.mutex.data.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for MutexGuard<'_, T> {
+    fn drop(&mut self) {
+        self.mutex.unlock();
+    }
+}
@@ -0,0 +1,123 @@
+//! # RCU-Protected Hash Map
+//!
+//! Readers look entries up without taking any lock. Writers are serialized, copy the whole map,
+//! change the copy and publish it, and the previous copy is freed once a grace period has passed.
+//! Updates are therefore expensive, which suits tables that are read far more often than they
+//! change.
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::hash::Hash;
+use core::sync::atomic::{AtomicPtr, Ordering};
+
+use hashbrown::HashMap;
+
+use super::{RcuReadGuard, call_rcu, rcu_read_lock};
+use crate::cpu::scheduler::sync::spinlock::SpinLock;
+
+pub struct RcuHashMap<K, V> {
+    /// The published copy of the map or null while it is empty and has never been written
+    map: AtomicPtr<HashMap<K, V>>,
+    /// Serializes writers
+    write_lock: SpinLock<()>,
+}
+
+// SAFETY: Readers on any LP get shared references to the entries and writers move entries in and
+// out of the map and drop the previous copy on the RCU callback thread.
+unsafe impl<K: Send + Sync, V: Send + Sync> Sync for RcuHashMap<K, V> {}
+unsafe impl<K: Send, V: Send> Send for RcuHashMap<K, V> {}
+
+/// The previous copy of a map waiting to be freed
+struct Retired<K, V>(*mut HashMap<K, V>);
+
+// SAFETY: No reader can still be using the copy once it is freed.
+unsafe impl<K: Send, V: Send> Send for Retired<K, V> {}
+
+impl<K, V> RcuHashMap<K, V>
+where
+    K: Clone + Eq + Hash + Send + Sync + 'static,
+    V: Clone + Send + Sync + 'static,
+{
+    pub const fn new() -> Self {
+        RcuHashMap {
+            map: AtomicPtr::new(core::ptr::null_mut()),
+            write_lock: SpinLock::new(()),
+        }
+    }
+
+    /// The published copy of the map, which stays valid for as long as the guard is alive
+    fn read<'g>(&self, _guard: &'g RcuReadGuard) -> Option<&'g HashMap<K, V>> {
+        // SAFETY: The copy is only freed after a grace period and the guard delays the end of any
+        // grace period that starts while it is alive.
+        unsafe { self.map.load(Ordering::SeqCst).as_ref() }
+    }
+
+    /// Look up an entry. The reference is valid for as long as the guard is alive.
+    pub fn get<'g>(&self, key: &K, guard: &'g RcuReadGuard) -> Option<&'g V> {
+        self.read(guard)?.get(key)
+    }
+
+    /// Look up an entry and clone it
+    pub fn get_cloned(&self, key: &K) -> Option<V> {
+        let guard = rcu_read_lock();
+        self.get(key, &guard).cloned()
+    }
+
+    pub fn contains_key(&self, key: &K) -> bool {
+        let guard = rcu_read_lock();
+        self.read(&guard).is_some_and(|map| map.contains_key(key))
+    }
+
+    pub fn len(&self) -> usize {
+        let guard = rcu_read_lock();
+        self.read(&guard).map_or(0, HashMap::len)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The keys of all entries in the published copy
+    pub fn keys(&self) -> Vec<K> {
+        let guard = rcu_read_lock();
+        self.read(&guard).map_or(Vec::new(), |map| map.keys().cloned().collect())
+    }
+
+    /// Run `f` on a copy of the map and publish the copy afterwards
+    pub fn update<R>(&self, f: impl FnOnce(&mut HashMap<K, V>) -> R) -> R {
+        let _write_lock = self.write_lock.lock();
+        let prev = self.map.load(Ordering::SeqCst);
+        // SAFETY: Copies are only freed by writers, which are serialized by the write lock.
+        let mut map = unsafe { prev.as_ref() }.cloned().unwrap_or_default();
+        let result = f(&mut map);
+        self.map.store(Box::into_raw(Box::new(map)), Ordering::SeqCst);
+        if !prev.is_null() {
+            let retired = Retired(prev);
+            call_rcu(Box::new(move || {
+                let retired = retired;
+                // SAFETY: The copy was unpublished before the grace period started.
+                drop(unsafe { Box::from_raw(retired.0) });
+            }));
+        }
+        result
+    }
+
+    /// Insert an entry, returning the one it replaced
+    pub fn insert(&self, key: K, value: V) -> Option<V> {
+        self.update(|map| map.insert(key, value))
+    }
+
+    pub fn remove(&self, key: &K) -> Option<V> {
+        self.update(|map| map.remove(key))
+    }
+}
+
+impl<K, V> Drop for RcuHashMap<K, V> {
+    fn drop(&mut self) {
+        let map = *self.map.get_mut();
+        if !map.is_null() {
+            // SAFETY: A map that is being dropped can not have any readers left.
+            drop(unsafe { Box::from_raw(map) });
+        }
+    }
+}
@@ -0,0 +1,172 @@
+//! # Read-Copy-Update
+//!
+//! Readers of RCU-protected data only disable preemption on their LP. Writers publish a new copy
+//! of the data and wait for a grace period before freeing the old one, i.e. until every LP has
+//! passed through a quiescent state in which it can not be reading anything published before.
+//!
+//! Since readers can not be preempted, each context switch is a quiescent state of its LP. An LP
+//! that runs its idle or boot context is in an extended quiescent state whenever it is not
+//! inside a read-side critical section, e.g. in an interrupt handler. Each LP counts its switches
+//! and its read-side critical sections so that grace periods can be observed from other LPs.
+
+pub mod hash_map;
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::marker::PhantomData;
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+
+use spin::Once;
+
+use crate::common::collections::boxed_slice::make_boxed_slice;
+use crate::cpu::isa::lp::ops::get_lp_id;
+use crate::cpu::multiprocessor::get_lp_count;
+use crate::cpu::scheduler::preemption::PreemptGuard;
+use crate::cpu::scheduler::sync::spinlock::IrqSpinLock;
+use crate::cpu::scheduler::sync::wait_queue::WaitQueue;
+use crate::cpu::scheduler::threads::spawn::spawn_kernel_thread;
+
+/// Indexed by LP ID. Grace periods end right away until this is initialized since no LP can have
+/// switched to a thread before then.
+static RCU_STATES: Once<Box<[RcuState]>> = Once::new();
+
+/// Callbacks waiting for a grace period to pass
+static CALLBACK_QUEUE: IrqSpinLock<Vec<Box<dyn FnOnce() + Send>>> = IrqSpinLock::new(Vec::new());
+/// Woken each time a callback is queued
+static CALLBACKS_QUEUED: WaitQueue = WaitQueue::new();
+
+struct RcuState {
+    /// The number of context switches the LP has made
+    switch_count: AtomicU64,
+    /// Whether the LP is running its idle or boot context rather than a thread
+    is_idle: AtomicBool,
+    /// The number of read-side critical sections the LP is currently in
+    read_depth: AtomicUsize,
+}
+
+impl RcuState {
+    fn new() -> Self {
+        RcuState {
+            switch_count: AtomicU64::new(0),
+            is_idle: AtomicBool::new(true),
+            read_depth: AtomicUsize::new(0),
+        }
+    }
+
+    /// Whether the LP is in an extended quiescent state right now
+    fn is_quiescent(&self) -> bool {
+        self.is_idle.load(Ordering::SeqCst) && self.read_depth.load(Ordering::SeqCst) == 0
+    }
+}
+
+/// Allocate the per-LP RCU state. Requires the kernel heap.
+pub fn init() {
+    RCU_STATES.call_once(|| make_boxed_slice(get_lp_count() as usize, RcuState::new));
+}
+
+/// Record a context switch of the calling LP as a quiescent state
+///
+/// Must be called on each switch once the LP has picked the context it switches to.
+pub fn note_context_switch(is_to_idle: bool) {
+    let Some(states) = RCU_STATES.get() else {
+        return;
+    };
+    let state = &states[get_lp_id() as usize];
+    state.is_idle.store(is_to_idle, Ordering::SeqCst);
+    state.switch_count.fetch_add(1, Ordering::SeqCst);
+}
+
+/// Keeps the calling LP inside a read-side critical section for as long as it is alive
+///
+/// Data read from an RCU-protected structure may be used until the guard is dropped.
+pub struct RcuReadGuard {
+    is_counted: bool,
+    _preempt_guard: PreemptGuard,
+    // the guard must be dropped on the LP it was created on
+    _not_send: PhantomData<*const ()>,
+}
+
+/// Enter a read-side critical section. Sections can be nested and may be entered from
+/// interrupt handlers.
+pub fn rcu_read_lock() -> RcuReadGuard {
+    let preempt_guard = PreemptGuard::new();
+    let is_counted = RCU_STATES.get().is_some_and(|states| {
+        states[get_lp_id() as usize].read_depth.fetch_add(1, Ordering::SeqCst);
+        true
+    });
+    RcuReadGuard {
+        is_counted,
+        _preempt_guard: preempt_guard,
+        _not_send: PhantomData,
+    }
+}
+
+impl Drop for RcuReadGuard {
+    fn drop(&mut self) {
+        if self.is_counted {
+            RCU_STATES.get().unwrap()[get_lp_id() as usize]
+                .read_depth
+                .fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+}
+
+/// Wait until every read-side critical section that was entered before the call has been left
+///
+/// # Panics
+/// If called from inside a read-side critical section, which would never end
+pub fn synchronize_rcu() {
+    let Some(states) = RCU_STATES.get() else {
+        return;
+    };
+    let lp_id = get_lp_id() as usize;
+    assert_eq!(
+        states[lp_id].read_depth.load(Ordering::SeqCst),
+        0,
+        "synchronize_rcu was called inside an RCU read-side critical section"
+    );
+    let snapshot: Vec<u64> =
+        states.iter().map(|state| state.switch_count.load(Ordering::SeqCst)).collect();
+    // the calling LP is not reading anything and can not have been preempted by a reader
+    let mut is_pending: Vec<bool> = (0..states.len()).map(|id| id != lp_id).collect();
+    while is_pending.contains(&true) {
+        for (id, state) in states.iter().enumerate() {
+            if is_pending[id]
+                && (state.switch_count.load(Ordering::SeqCst) != snapshot[id]
+                    || state.is_quiescent())
+            {
+                is_pending[id] = false;
+            }
+        }
+        core::hint::spin_loop();
+    }
+}
+
+/// Run `callback` once a grace period has passed
+///
+/// Callbacks are run in batches by the RCU callback thread, so this does not wait and can be
+/// called before that thread has been started. It can not be called from interrupt or softirq
+/// handlers since queueing the callback may allocate.
+pub fn call_rcu(callback: Box<dyn FnOnce() + Send>) {
+    CALLBACK_QUEUE.lock().push(callback);
+    CALLBACKS_QUEUED.wake_all();
+}
+
+/// Spawn the thread that runs the callbacks passed to `call_rcu`
+pub fn start_callback_thread() {
+    spawn_kernel_thread("rcu", callback_thread_main).detach();
+}
+
+fn callback_thread_main() {
+    loop {
+        let callbacks = CALLBACKS_QUEUED.wait_for(|| {
+            let mut queue = CALLBACK_QUEUE.lock();
+            (!queue.is_empty()).then(|| core::mem::take(&mut *queue))
+        });
+        // one grace period covers the whole batch
+        synchronize_rcu();
+        for callback in callbacks {
+            callback();
+        }
+    }
+}
@@ -0,0 +1,206 @@
+//! # Sleeping Reader-Writer Lock
+//!
+//! Readers and writers are served in the order they arrive. A reader only joins the readers
+//! holding the lock if nobody is waiting so that a steady stream of readers can not starve a
+//! writer. When the lock is released it is handed directly to the writer at the front of the
+//! queue or to all readers at the front of the queue up to the next writer.
+
+use alloc::vec::Vec;
+use core::cell::UnsafeCell;
+use core::ops::{Deref, DerefMut};
+
+use super::spinlock::SpinLock;
+use super::wait_queue::{Waiter, Waiters};
+use crate::cpu::scheduler::system_scheduler::SYSTEM_SCHEDULER;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Access {
+    Read,
+    Write,
+}
+
+struct RwLockState {
+    readers: usize,
+    is_write_locked: bool,
+    waiters: Waiters<Access>,
+}
+
+impl RwLockState {
+    fn try_acquire(&mut self, access: Access) -> bool {
+        let is_free = match access {
+            Access::Read => !self.is_write_locked && self.waiters.is_empty(),
+            Access::Write => !self.is_write_locked && self.readers == 0,
+        };
+        if is_free {
+            self.grant(access);
+        }
+        is_free
+    }
+
+    fn grant(&mut self, access: Access) {
+        match access {
+            Access::Read => self.readers += 1,
+            Access::Write => self.is_write_locked = true,
+        }
+    }
+
+    /// Hand the lock to as many waiters at the front of the queue as it can be held by
+    fn hand_over(&mut self) -> Vec<Waiter<Access>> {
+        let mut woken = Vec::new();
+        while let Some(access) = self.waiters.front().map(|waiter| waiter.kind) {
+            let is_free = match access {
+                Access::Read => !self.is_write_locked,
+                Access::Write => !self.is_write_locked && self.readers == 0,
+            };
+            if !is_free {
+                break;
+            }
+            self.grant(access);
+            woken.extend(self.waiters.pop_front());
+        }
+        woken
+    }
+}
+
+/// A reader-writer lock that parks the threads waiting for it
+pub struct RwLock<T: ?Sized> {
+    state: SpinLock<RwLockState>,
+    data:  UnsafeCell<T>,
+}
+
+unsafe impl<T: ?Sized + Send> Send for RwLock<T> {}
+unsafe impl<T: ?Sized + Send + Sync> Sync for RwLock<T> {}
+
+pub struct RwLockReadGuard<'a, T: ?Sized + 'a> {
+    lock: &'a RwLock<T>,
+}
+
+pub struct RwLockWriteGuard<'a, T: ?Sized + 'a> {
+    lock: &'a RwLock<T>,
+}
+
+impl<T> RwLock<T> {
+    pub const fn new(data: T) -> Self {
+        RwLock {
+            state: SpinLock::new(RwLockState {
+                readers: 0,
+                is_write_locked: false,
+                waiters: Waiters::new(),
+            }),
+            data:  UnsafeCell::new(data),
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.data.into_inner()
+    }
+}
+
+impl<T: ?Sized> RwLock<T> {
+    pub fn read(&self) -> RwLockReadGuard<'_, T> {
+        self.acquire(Access::Read);
+        RwLockReadGuard {
+            lock: self,
+        }
+    }
+
+    pub fn write(&self) -> RwLockWriteGuard<'_, T> {
+        self.acquire(Access::Write);
+        RwLockWriteGuard {
+            lock: self,
+        }
+    }
+
+    pub fn try_read(&self) -> Option<RwLockReadGuard<'_, T>> {
+        // the guard must only be created once the lock is held since dropping it releases it
+        let is_acquired = self.state.lock().try_acquire(Access::Read);
+        is_acquired.then(|| RwLockReadGuard {
+            lock: self,
+        })
+    }
+
+    pub fn try_write(&self) -> Option<RwLockWriteGuard<'_, T>> {
+        let is_acquired = self.state.lock().try_acquire(Access::Write);
+        is_acquired.then(|| RwLockWriteGuard {
+            lock: self,
+        })
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.data.get_mut()
+    }
+
+    fn acquire(&self, access: Access) {
+        let mut state = self.state.lock();
+        if state.try_acquire(access) {
+            return;
+        }
+        match SYSTEM_SCHEDULER.get_current_tid() {
+            Some(tid) => {
+                let wakeup = state.waiters.park(tid, access);
+                // The LP switches away once the state is unlocked and the lock is handed over
+                // before the thread is woken.
+                drop(state);
+                wakeup.spin_until_complete();
+            }
+            None => {
+                drop(state);
+                while !self.state.lock().try_acquire(access) {
+                    core::hint::spin_loop();
+                }
+            }
+        }
+    }
+
+    fn release(&self, access: Access) {
+        let woken = {
+            let mut state = self.state.lock();
+            match access {
+                Access::Read => state.readers -= 1,
+                Access::Write => state.is_write_locked = false,
+            }
+            state.hand_over()
+        };
+        woken.into_iter().for_each(Waiter::wake);
+    }
+}
+
+impl<T: Default> Default for RwLock<T> {
+    fn default() -> Self {
+        RwLock::new(T::default())
+    }
+}
+
+impl<T: ?Sized> Deref for RwLockReadGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for RwLockReadGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.release(Access::Read);
+    }
+}
+
+impl<T: ?Sized> Deref for RwLockWriteGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> DerefMut for RwLockWriteGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for RwLockWriteGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.release(Access::Write);
+    }
+}
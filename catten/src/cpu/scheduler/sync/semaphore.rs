@@ -0,0 +1,121 @@
+//! # Counting Semaphore
+//!
+//! A released permit is handed directly to the thread that has waited the longest for one, so
+//! waiters are served in FIFO order.
+
+use super::spinlock::SpinLock;
+use super::wait_queue::Waiters;
+use crate::common::time::duration::ExtDuration;
+use crate::cpu::isa::timers::get_monotonic_time;
+use crate::cpu::scheduler::hrtimer;
+use crate::cpu::scheduler::system_scheduler::SYSTEM_SCHEDULER;
+
+struct SemaphoreState {
+    permits: usize,
+    waiters: Waiters,
+}
+
+/// A counting semaphore that parks the threads waiting for a permit
+pub struct Semaphore {
+    state: SpinLock<SemaphoreState>,
+}
+
+impl Semaphore {
+    pub const fn new(permits: usize) -> Self {
+        Semaphore {
+            state: SpinLock::new(SemaphoreState {
+                permits,
+                waiters: Waiters::new(),
+            }),
+        }
+    }
+
+    /// Take a permit, waiting for one to be released if there is none
+    pub fn acquire(&self) {
+        let mut state = self.state.lock();
+        if state.permits > 0 && state.waiters.is_empty() {
+            state.permits -= 1;
+            return;
+        }
+        match SYSTEM_SCHEDULER.get_current_tid() {
+            Some(tid) => {
+                let wakeup = state.waiters.park(tid, ());
+                // The LP switches away once the state is unlocked and a permit is handed over
+                // before the thread is woken.
+                drop(state);
+                wakeup.spin_until_complete();
+            }
+            None => {
+                drop(state);
+                while !self.try_acquire() {
+                    core::hint::spin_loop();
+                }
+            }
+        }
+    }
+
+    /// Take a permit, waiting at most `timeout` for one to be released. Returns whether a permit
+    /// was taken.
+    pub fn acquire_timeout(&self, timeout: ExtDuration) -> bool {
+        let deadline = get_monotonic_time() + timeout;
+        let mut state = self.state.lock();
+        if state.permits > 0 && state.waiters.is_empty() {
+            state.permits -= 1;
+            return true;
+        }
+        match SYSTEM_SCHEDULER.get_current_tid() {
+            Some(tid) => {
+                let wakeup = state.waiters.park(tid, ());
+                // the timer is armed before the LP can switch away
+                let timer = hrtimer::arm_wakeup(deadline, tid, wakeup.clone());
+                drop(state);
+                wakeup.spin_until_complete();
+                hrtimer::cancel(&timer);
+                // a waiter that is no longer queued has been handed a permit
+                self.state.lock().waiters.remove(tid).is_none()
+            }
+            None => {
+                drop(state);
+                loop {
+                    if self.try_acquire() {
+                        return true;
+                    }
+                    if get_monotonic_time() >= deadline {
+                        return false;
+                    }
+                    core::hint::spin_loop();
+                }
+            }
+        }
+    }
+
+    /// Take a permit if one is available right away
+    pub fn try_acquire(&self) -> bool {
+        let mut state = self.state.lock();
+        let is_acquired = state.permits > 0 && state.waiters.is_empty();
+        if is_acquired {
+            state.permits -= 1;
+        }
+        is_acquired
+    }
+
+    /// Give back a permit
+    pub fn release(&self) {
+        let waiter = {
+            let mut state = self.state.lock();
+            let waiter = state.waiters.pop_front();
+            if waiter.is_none() {
+                state.permits += 1;
+            }
+            waiter
+        };
+        if let Some(waiter) = waiter {
+            waiter.wake();
+        }
+    }
+
+    /// The number of permits that are available right now
+    pub fn available_permits(&self) -> usize {
+        self.state.lock().permits
+    }
+}
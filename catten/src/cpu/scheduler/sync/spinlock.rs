@@ -0,0 +1,236 @@
+//! # Preemption and Interrupt Safe Spinlocks
+
+use core::arch::asm;
+use core::marker::PhantomData;
+use core::ops::{Deref, DerefMut};
+
+use super::lockdep::{Mutex, MutexGuard, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use crate::cpu::isa::lp::ops::{are_interrupts_enabled, mask_interrupts, unmask_interrupts};
+use crate::cpu::multiprocessor::ipi::service_own_mailbox;
+use crate::cpu::scheduler::preemption::PreemptGuard;
+
+/// A spinlock that disables preemption on the holder's LP while it is held so that threads on the
+/// same LP never spin on it for a whole time slice.
+///
+/// It does not mask interrupts so it must not be taken by interrupt handlers.
+pub struct SpinLock<T: ?Sized> {
+    inner: Mutex<T>,
+}
+
+pub struct SpinLockGuard<'a, T: ?Sized + 'a> {
+    // the lock is released before preemption is enabled again
+    guard: MutexGuard<'a, T>,
+    _preempt_guard: PreemptGuard,
+}
+
+impl<T> SpinLock<T> {
+    #[track_caller]
+    pub const fn new(data: T) -> Self {
+        SpinLock {
+            inner: Mutex::new(data),
+        }
+    }
+}
+
+impl<T: ?Sized> SpinLock<T> {
+    pub fn lock(&self) -> SpinLockGuard<'_, T> {
+        let preempt_guard = PreemptGuard::new();
+        SpinLockGuard {
+            guard: self.inner.lock(),
+            _preempt_guard: preempt_guard,
+        }
+    }
+
+    pub fn try_lock(&self) -> Option<SpinLockGuard<'_, T>> {
+        let preempt_guard = PreemptGuard::new();
+        self.inner.try_lock().map(|guard| SpinLockGuard {
+            guard,
+            _preempt_guard: preempt_guard,
+        })
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.inner.is_locked()
+    }
+}
+
+impl<T: ?Sized + core::fmt::Debug> core::fmt::Debug for SpinLockGuard<'_, T> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        core::fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<T: ?Sized> Deref for SpinLockGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.guard
+    }
+}
+
+impl<T: ?Sized> DerefMut for SpinLockGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.guard
+    }
+}
+
+/// A reader-writer spinlock that disables preemption on the holder's LP while it is held
+///
+/// Like `SpinLock` it must not be taken by interrupt handlers.
+pub struct SpinRwLock<T: ?Sized> {
+    inner: RwLock<T>,
+}
+
+pub struct SpinRwLockReadGuard<'a, T: ?Sized + 'a> {
+    guard: RwLockReadGuard<'a, T>,
+    _preempt_guard: PreemptGuard,
+}
+
+pub struct SpinRwLockWriteGuard<'a, T: ?Sized + 'a> {
+    guard: RwLockWriteGuard<'a, T>,
+    _preempt_guard: PreemptGuard,
+}
+
+impl<T> SpinRwLock<T> {
+    #[track_caller]
+    pub const fn new(data: T) -> Self {
+        SpinRwLock {
+            inner: RwLock::new(data),
+        }
+    }
+}
+
+impl<T: ?Sized> SpinRwLock<T> {
+    pub fn read(&self) -> SpinRwLockReadGuard<'_, T> {
+        let preempt_guard = PreemptGuard::new();
+        SpinRwLockReadGuard {
+            guard: self.inner.read(),
+            _preempt_guard: preempt_guard,
+        }
+    }
+
+    pub fn write(&self) -> SpinRwLockWriteGuard<'_, T> {
+        let preempt_guard = PreemptGuard::new();
+        SpinRwLockWriteGuard {
+            guard: self.inner.write(),
+            _preempt_guard: preempt_guard,
+        }
+    }
+}
+
+impl<T: ?Sized> Deref for SpinRwLockReadGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.guard
+    }
+}
+
+impl<T: ?Sized> Deref for SpinRwLockWriteGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.guard
+    }
+}
+
+impl<T: ?Sized> DerefMut for SpinRwLockWriteGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.guard
+    }
+}
+
+/// A spinlock that masks interrupts on the holder's LP while it is held, which also keeps the
+/// holder from being preempted. It can be taken by interrupt handlers and by code that interrupt
+/// handlers call into, such as the memory allocators.
+///
+/// LPs spinning on it keep running the RPCs other LPs send them so that the holder can wait for
+/// RPCs to complete.
+pub struct IrqSpinLock<T: ?Sized> {
+    inner: Mutex<T>,
+}
+
+pub struct IrqSpinLockGuard<'a, T: ?Sized + 'a> {
+    // the lock is released before interrupts are unmasked again
+    guard: MutexGuard<'a, T>,
+    _interrupts_masked: InterruptsMasked,
+}
+
+/// Keeps interrupts masked on the calling LP until it is dropped and then restores their previous
+/// state
+struct InterruptsMasked {
+    were_enabled: bool,
+    _not_send: PhantomData<*const ()>,
+}
+
+impl InterruptsMasked {
+    fn new() -> Self {
+        let were_enabled = are_interrupts_enabled();
+        mask_interrupts!();
+        InterruptsMasked {
+            were_enabled,
+            _not_send: PhantomData,
+        }
+    }
+}
+
+impl Drop for InterruptsMasked {
+    fn drop(&mut self) {
+        if self.were_enabled {
+            unmask_interrupts!();
+        }
+    }
+}
+
+impl<T> IrqSpinLock<T> {
+    #[track_caller]
+    pub const fn new(data: T) -> Self {
+        IrqSpinLock {
+            inner: Mutex::new(data),
+        }
+    }
+}
+
+impl<T: ?Sized> IrqSpinLock<T> {
+    pub fn lock(&self) -> IrqSpinLockGuard<'_, T> {
+        let interrupts_masked = InterruptsMasked::new();
+        IrqSpinLockGuard {
+            // the holder may be waiting for this LP to run an RPC such as a TLB shootdown, which
+            // can not arrive as an interrupt while it spins
+            guard: self.inner.lock_with_relax(service_own_mailbox),
+            _interrupts_masked: interrupts_masked,
+        }
+    }
+
+    pub fn try_lock(&self) -> Option<IrqSpinLockGuard<'_, T>> {
+        let interrupts_masked = InterruptsMasked::new();
+        self.inner.try_lock().map(|guard| IrqSpinLockGuard {
+            guard,
+            _interrupts_masked: interrupts_masked,
+        })
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.inner.is_locked()
+    }
+}
+
+impl<T: ?Sized + core::fmt::Debug> core::fmt::Debug for IrqSpinLockGuard<'_, T> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        core::fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<T: ?Sized> Deref for IrqSpinLockGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.guard
+    }
+}
+
+impl<T: ?Sized> DerefMut for IrqSpinLockGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.guard
+    }
+}
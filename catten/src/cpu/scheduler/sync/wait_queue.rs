@@ -0,0 +1,220 @@
+//! # Wait Queues
+//!
+//! Threads that have to wait for a condition are parked in a FIFO queue with
+//! `SystemScheduler::block_tid` and woken in the order they arrived. The blocking primitives in
+//! this module are built on `Waiters`, which they keep under the same spinlock as the state their
+//! waiters wait on so that a wakeup can never slip in between checking the state and parking.
+
+use alloc::collections::vec_deque::VecDeque;
+use alloc::sync::Arc;
+
+use super::spinlock::IrqSpinLock;
+use crate::common::time::duration::ExtDuration;
+use crate::cpu::isa::timers::get_monotonic_time;
+use crate::cpu::scheduler::hrtimer;
+use crate::cpu::scheduler::system_scheduler::SYSTEM_SCHEDULER;
+use crate::cpu::scheduler::threads::{DEFAULT_PRIORITY, Priority, ThreadId};
+use crate::event::{Completion, Event, Observer};
+
+/// A thread parked on a primitive
+pub(super) struct Waiter<K> {
+    pub tid: ThreadId,
+    /// The thread's effective priority when it was parked or when a priority was last passed on
+    /// to it while parked
+    pub priority: Priority,
+    /// What the thread is waiting for, for primitives with more than one kind of waiter
+    pub kind: K,
+    observer: Arc<dyn Observer>,
+}
+
+impl<K> Waiter<K> {
+    /// Make the thread runnable again
+    pub fn wake(self) {
+        self.observer.notify();
+    }
+}
+
+/// The threads parked on a primitive in the order they arrived
+pub(super) struct Waiters<K = ()> {
+    queue: VecDeque<Waiter<K>>,
+}
+
+impl<K> Waiters<K> {
+    pub const fn new() -> Self {
+        Waiters {
+            queue: VecDeque::new(),
+        }
+    }
+
+    /// Block the given thread, which must be the calling one, and queue it. The LP switches away
+    /// from it as soon as preemption is enabled again, i.e. once the caller has released the
+    /// spinlock that protects the queue. Returns the completion that is completed when the thread
+    /// is woken, which the caller must wait for after releasing the spinlock.
+    pub fn park(&mut self, tid: ThreadId, kind: K) -> Arc<Completion> {
+        let priority = SYSTEM_SCHEDULER.get_effective_priority(tid).unwrap_or(DEFAULT_PRIORITY);
+        let mut parking = Parking {
+            observer: None,
+        };
+        let wakeup = SYSTEM_SCHEDULER
+            .block_tid(tid, &mut parking)
+            .unwrap_or_else(|err| panic!("Failed to park thread {}: {:?}", tid, err));
+        self.queue.push_back(Waiter {
+            tid,
+            priority,
+            kind,
+            observer: parking.observer.expect("block_tid registered no observer"),
+        });
+        wakeup
+    }
+
+    pub fn pop_front(&mut self) -> Option<Waiter<K>> {
+        self.queue.pop_front()
+    }
+
+    pub fn front(&self) -> Option<&Waiter<K>> {
+        self.queue.front()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+
+    /// The most important priority among the waiters
+    pub fn top_priority(&self) -> Option<Priority> {
+        self.queue.iter().map(|waiter| waiter.priority).min()
+    }
+
+    /// Update the priority of a parked thread. Returns `false` if it is not parked here.
+    pub fn set_priority(&mut self, tid: ThreadId, priority: Priority) -> bool {
+        match self.queue.iter_mut().find(|waiter| waiter.tid == tid) {
+            Some(waiter) => {
+                waiter.priority = priority;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn take_all(&mut self) -> VecDeque<Waiter<K>> {
+        core::mem::take(&mut self.queue)
+    }
+
+    /// Take the thread out of the queue, e.g. after its wait timed out. Returns `None` if it has
+    /// already been woken.
+    pub fn remove(&mut self, tid: ThreadId) -> Option<Waiter<K>> {
+        let index = self.queue.iter().position(|waiter| waiter.tid == tid)?;
+        self.queue.remove(index)
+    }
+}
+
+/// Captures the observer that `SystemScheduler::block_tid` registers for a parked thread
+struct Parking {
+    observer: Option<Arc<dyn Observer>>,
+}
+
+impl Event for Parking {
+    fn register_observer(&mut self, observer: Arc<dyn Observer>) {
+        self.observer = Some(observer);
+    }
+}
+
+/// A queue of threads waiting for a condition that other threads or interrupt handlers signal
+/// after changing it
+///
+/// The queue masks interrupts while it is locked, so it can be woken from interrupt handlers, and
+/// conditions are checked with interrupts masked. Code that does not run on a thread, such as an
+/// LP that has not yielded to the scheduler yet, spins instead of being parked.
+pub struct WaitQueue {
+    waiters: IrqSpinLock<Waiters>,
+}
+
+impl WaitQueue {
+    #[track_caller]
+    pub const fn new() -> Self {
+        WaitQueue {
+            waiters: IrqSpinLock::new(Waiters::new()),
+        }
+    }
+
+    /// Wait until `condition` holds. It is checked before parking and each time the calling thread
+    /// is woken, always with the queue locked, so it must not block.
+    pub fn wait_until(&self, mut condition: impl FnMut() -> bool) {
+        self.wait_for(|| condition().then_some(()))
+    }
+
+    /// Wait until `poll` returns a value and return it, e.g. to take an item out of a queue that is
+    /// filled before the wait queue is woken. Like a condition it is only run with the queue
+    /// locked.
+    pub fn wait_for<T>(&self, mut poll: impl FnMut() -> Option<T>) -> T {
+        loop {
+            let mut waiters = self.waiters.lock();
+            if let Some(value) = poll() {
+                return value;
+            }
+            match SYSTEM_SCHEDULER.get_current_tid() {
+                Some(tid) => {
+                    let wakeup = waiters.park(tid, ());
+                    // the LP switches away once the queue is unlocked
+                    drop(waiters);
+                    wakeup.spin_until_complete();
+                }
+                None => {
+                    drop(waiters);
+                    core::hint::spin_loop();
+                }
+            }
+        }
+    }
+
+    /// Wait until `condition` holds or `timeout` has passed. Returns whether the condition holds.
+    pub fn wait_until_timeout(
+        &self,
+        mut condition: impl FnMut() -> bool,
+        timeout: ExtDuration,
+    ) -> bool {
+        let deadline = get_monotonic_time() + timeout;
+        loop {
+            let mut waiters = self.waiters.lock();
+            if condition() {
+                return true;
+            }
+            if get_monotonic_time() >= deadline {
+                return false;
+            }
+            match SYSTEM_SCHEDULER.get_current_tid() {
+                Some(tid) => {
+                    let wakeup = waiters.park(tid, ());
+                    // the timer is armed before the LP can switch away
+                    let timer = hrtimer::arm_wakeup(deadline, tid, wakeup.clone());
+                    drop(waiters);
+                    wakeup.spin_until_complete();
+                    hrtimer::cancel(&timer);
+                    // a thread that timed out is still queued
+                    self.waiters.lock().remove(tid);
+                }
+                None => {
+                    drop(waiters);
+                    core::hint::spin_loop();
+                }
+            }
+        }
+    }
+
+    /// Wake the thread that has waited the longest. Returns whether there was one.
+    pub fn wake_one(&self) -> bool {
+        let waiter = self.waiters.lock().pop_front();
+        let is_woken = waiter.is_some();
+        if let Some(waiter) = waiter {
+            waiter.wake();
+        }
+        is_woken
+    }
+
+    /// Wake all waiting threads. Returns how many there were.
+    pub fn wake_all(&self) -> usize {
+        let waiters = self.waiters.lock().take_all();
+        let count = waiters.len();
+        waiters.into_iter().for_each(Waiter::wake);
+        count
+    }
+}
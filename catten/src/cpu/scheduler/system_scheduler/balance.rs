@@ -0,0 +1,219 @@
+//! # Load Balancing
+//!
+//! Each time an LP switches threads it first moves any of its threads that it may no longer run to
+//! other LPs and then balances its own load against the other LPs:
+//! - An LP that has run out of threads steals a waiting thread from the nearest LP that has one. It
+//!   looks at most once per steal period, since an idle LP may switch often, e.g. to run softirqs,
+//!   and each look locks every other LP's scheduler.
+//! - Once per balancing period an LP pulls threads from the nearest LP that has noticeably more
+//!   threads queued than itself. If there is no such LP it pushes a waiting thread to the nearest
+//!   halted LP instead, since a halted LP does not look for work until something wakes it.
+//!
+//! Nearby LPs are preferred because a thread that moves to an SMT sibling or to an LP that shares
+//! the last level cache finds its working set still cached, and one that stays on its NUMA node
+//! keeps its memory local. Threads of address spaces the receiving LP has run recently are moved
+//! first.
+
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::cmp::Reverse;
+use core::sync::atomic::Ordering;
+
+use super::SystemScheduler;
+use crate::common::time::duration::ExtDuration;
+use crate::cpu::isa::interface::system_info::LpTopology;
+use crate::cpu::isa::lp::LpId;
+use crate::cpu::isa::lp::ops::get_lp_id;
+use crate::cpu::isa::timers::get_monotonic_time;
+use crate::cpu::scheduler::idle;
+use crate::cpu::scheduler::lp_schedulers::{LocalScheduler, Status};
+use crate::cpu::scheduler::sync::lockdep::Mutex;
+use crate::cpu::scheduler::threads::{MASTER_THREAD_TABLE, ThreadId, ThreadState};
+use crate::logln;
+use crate::memory::AddressSpaceId;
+
+pub const BALANCE_PERIOD_MILLIS: u128 = 50;
+pub const STEAL_PERIOD_MILLIS: u128 = 1;
+/// How many more threads another LP must have queued for the balancing LP to pull threads from it
+const IMBALANCE_THRESHOLD: usize = 2;
+
+/// A snapshot of the load of another LP
+struct LpLoad {
+    lp_id: LpId,
+    local_scheduler: Arc<Mutex<LocalScheduler>>,
+    load: usize,
+    n_waiting: usize,
+    is_halted: bool,
+    distance: u8,
+}
+
+/// How far apart two LPs are in the processor topology
+pub fn distance(a: &LpTopology, b: &LpTopology) -> u8 {
+    if a.core_id == b.core_id {
+        0
+    } else if a.llc_id == b.llc_id {
+        1
+    } else if a.numa_node == b.numa_node {
+        2
+    } else {
+        3
+    }
+}
+
+impl SystemScheduler {
+    /// Balance the calling LP's load against the other LPs
+    ///
+    /// Must be called with interrupts masked and without any local scheduler locked.
+    pub fn balance_lp(&self) {
+        let lp_id = get_lp_id();
+        let Some(local_scheduler) = self.get_lp_scheduler(lp_id) else {
+            return;
+        };
+        self.evict_disallowed_threads(lp_id, &local_scheduler);
+        let (load, n_waiting, topology, is_due) = {
+            let mut local_scheduler = local_scheduler.lock();
+            let now = get_monotonic_time();
+            let load = local_scheduler.load();
+            let is_due = if load == 0 {
+                local_scheduler.take_steal_due(now, ExtDuration::from_millis(STEAL_PERIOD_MILLIS))
+            } else {
+                local_scheduler
+                    .take_balance_due(now, ExtDuration::from_millis(BALANCE_PERIOD_MILLIS))
+            };
+            (
+                load,
+                local_scheduler.waiting_threads().count(),
+                local_scheduler.get_topology(),
+                is_due,
+            )
+        };
+        if !is_due {
+            return;
+        }
+        let mut others = self.get_lp_loads(lp_id, &topology);
+        others.sort_by_key(|other| other.distance);
+        let this_lp = (lp_id, &local_scheduler);
+        if load == 0 {
+            for other in others.iter().filter(|other| other.n_waiting > 0) {
+                if self.migrate_threads((other.lp_id, &other.local_scheduler), this_lp, 1) > 0 {
+                    return;
+                }
+            }
+        } else if let Some(busiest) = others
+            .iter()
+            .filter(|other| other.n_waiting > 0 && other.load >= load + IMBALANCE_THRESHOLD)
+            .min_by_key(|other| (other.distance, Reverse(other.load)))
+        {
+            let n = (busiest.load - load) / 2;
+            self.migrate_threads((busiest.lp_id, &busiest.local_scheduler), this_lp, n);
+        } else if n_waiting > 0
+            && let Some(halted) = others.iter().find(|other| other.is_halted && other.load == 0)
+        {
+            self.migrate_threads(this_lp, (halted.lp_id, &halted.local_scheduler), 1);
+        }
+    }
+
+    /// Take the threads that may no longer run on the calling LP off it and place them elsewhere
+    /// after a change to affinity masks or gangs. This includes the thread the LP was running
+    /// since its context has been saved by the time the LP makes a scheduling decision.
+    fn evict_disallowed_threads(&self, lp_id: LpId, local_scheduler: &Arc<Mutex<LocalScheduler>>) {
+        let epoch = self.placement_epoch.load(Ordering::Acquire);
+        let evicted = {
+            let mut local_scheduler = local_scheduler.lock();
+            if !local_scheduler.update_placement_epoch(epoch) {
+                return;
+            }
+            let mut evicted = Vec::new();
+            let queued: Vec<ThreadId> =
+                local_scheduler.queued_threads().map(|(_, tid)| tid).collect();
+            for tid in queued {
+                if let Some(thread) = MASTER_THREAD_TABLE.get(tid) {
+                    let mut thread = thread.write();
+                    if !self.is_placement_allowed(thread.asid, thread.affinity.as_ref(), lp_id) {
+                        local_scheduler.remove_thread(tid, &mut thread);
+                        thread.state = ThreadState::NeedsLpAssignment;
+                        evicted.push(tid);
+                    }
+                }
+            }
+            if local_scheduler.get_current().is_some_and(|tid| evicted.contains(&tid)) {
+                local_scheduler.forget_current();
+            }
+            evicted
+        };
+        for tid in evicted {
+            if let Err(err) = self.submit_ready_thread(tid) {
+                logln!("LP{}: Failed to move thread {} off the LP: {:?}", lp_id, tid, err);
+            }
+        }
+    }
+
+    fn get_lp_loads(&self, lp_id: LpId, topology: &LpTopology) -> Vec<LpLoad> {
+        self.lp_schedulers
+            .read()
+            .iter()
+            .filter(|&(&other_lp_id, _)| other_lp_id != lp_id)
+            .map(|(&other_lp_id, other)| {
+                let local_scheduler = other.lock();
+                LpLoad {
+                    lp_id: other_lp_id,
+                    local_scheduler: other.clone(),
+                    load: local_scheduler.load(),
+                    n_waiting: local_scheduler.waiting_threads().count(),
+                    is_halted: local_scheduler.is_halted(),
+                    distance: distance(topology, &local_scheduler.get_topology()),
+                }
+            })
+            .collect()
+    }
+
+    /// Move up to `n` waiting threads from one LP to another and wake the receiving LP if it is
+    /// halted. Returns the number of threads that were moved.
+    ///
+    /// When two local schedulers are locked at once, the one of the lower LP is locked first.
+    fn migrate_threads(
+        &self,
+        (src_lp_id, src): (LpId, &Arc<Mutex<LocalScheduler>>),
+        (dst_lp_id, dst): (LpId, &Arc<Mutex<LocalScheduler>>),
+        n: usize,
+    ) -> usize {
+        let (mut src, mut dst) = if src_lp_id < dst_lp_id {
+            let src = src.lock();
+            (src, dst.lock())
+        } else {
+            let dst = dst.lock();
+            (src.lock(), dst)
+        };
+        let mut candidates: Vec<(AddressSpaceId, ThreadId)> = src.waiting_threads().collect();
+        candidates.sort_by_key(|&(asid, _)| !dst.has_as_affinity(asid));
+        let mut n_moved = 0;
+        for (_, tid) in candidates {
+            if n_moved == n {
+                break;
+            }
+            let Some(thread) = MASTER_THREAD_TABLE.get(tid) else {
+                continue;
+            };
+            let mut thread = thread.write();
+            if !matches!(thread.state, ThreadState::Ready(lp_id) if lp_id == src_lp_id)
+                || !self.is_placement_allowed(thread.asid, thread.affinity.as_ref(), dst_lp_id)
+                || thread.rt_params.is_some_and(|rt_params| !dst.can_admit(&rt_params))
+            {
+                continue;
+            }
+            src.remove_thread(tid, &mut thread);
+            if let Status::Success = dst.add_thread(tid, &mut thread) {
+                thread.state = ThreadState::Ready(dst_lp_id);
+                n_moved += 1;
+            } else {
+                src.add_thread(tid, &mut thread);
+            }
+        }
+        // the calling LP picks up its new threads when it makes its next scheduling decision
+        if n_moved > 0 && dst.is_halted() && dst_lp_id != get_lp_id() {
+            dst.set_halted(false);
+            idle::wake_lp(dst_lp_id);
+        }
+        n_moved
+    }
+}
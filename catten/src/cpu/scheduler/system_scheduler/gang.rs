@@ -0,0 +1,46 @@
+//! # Address Space Gangs
+//!
+//! Threads of the same address space that interact closely, e.g. through spin locks in shared
+//! memory, make the most progress when they run at the same time. A gang binds an address space to
+//! a set of LPs on which its threads are co-scheduled.
+//!
+//! Time is divided into windows of equal length that are aligned to the monotonic clock so that
+//! all LPs agree on the current window without having to communicate. The windows cycle through
+//! the registered gangs followed by one window that belongs to no gang so that other threads are
+//! never starved. During a gang's window the gang's LPs run its threads in preference to all
+//! others.
+
+use crate::common::time::duration::ExtDuration;
+use crate::cpu::isa::lp::LpId;
+use crate::cpu::multiprocessor::lp_set::LpSet;
+use crate::memory::AddressSpaceId;
+
+pub const GANG_WINDOW_MILLIS: u128 = 20;
+
+pub struct Gang {
+    pub asid: AddressSpaceId,
+    /// The LPs the gang's threads are placed on
+    pub lps:  LpSet,
+}
+
+/// A window of the gang schedule as seen from one LP
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct GangWindow {
+    /// The address space whose threads the LP should prefer until the end of the window, if any
+    pub asid: Option<AddressSpaceId>,
+    pub end:  ExtDuration,
+}
+
+/// Find the window that `now` falls in on the given LP. There is no schedule without gangs.
+pub(super) fn get_window(gangs: &[Gang], lp_id: LpId, now: ExtDuration) -> Option<GangWindow> {
+    if gangs.is_empty() {
+        return None;
+    }
+    let window_len = ExtDuration::from_millis(GANG_WINDOW_MILLIS);
+    let window_idx = (now / window_len).as_picos();
+    let slot = (window_idx % (gangs.len() as u128 + 1)) as usize;
+    Some(GangWindow {
+        asid: gangs.get(slot).filter(|gang| gang.lps.contains(lp_id)).map(|gang| gang.asid),
+        end:  window_len * (window_idx + 1),
+    })
+}
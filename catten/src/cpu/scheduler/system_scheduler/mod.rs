@@ -1,68 +1,595 @@
+pub mod balance;
+pub mod gang;
+pub mod priority;
+pub mod reaper;
+
+use alloc::boxed::Box;
 use alloc::collections::btree_map::BTreeMap;
 use alloc::sync::Arc;
 use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU64, Ordering};
 
-use spin::Mutex;
+use spin::Lazy;
 
-use super::lp_schedulers::LocalScheduler;
-use crate::cpu::isa::lp::LpId;
-use crate::cpu::isa::lp::ops::get_lp_id;
-use crate::cpu::scheduler::threads::ThreadId;
-use crate::event::Event;
+use self::gang::{Gang, GangWindow};
+use super::hrtimer::{TimerCallback, TimerHandle};
+use super::idle;
+use super::lp_schedulers::strategy::LsStratIfce;
+use super::lp_schedulers::{LocalScheduler, Status};
+use super::sync::lockdep::{Mutex, RwLock};
+use crate::common::time::duration::ExtDuration;
+use crate::cpu::isa::interface::interrupts::LocalIntCtlrIfce;
+use crate::cpu::isa::interrupts::LocalIntCtlr;
+use crate::cpu::isa::lp::ops::{get_lp_id, set_thread_context_ptr, without_interrupts};
+use crate::cpu::isa::lp::{LpId, thread_context};
+use crate::cpu::isa::timers::get_monotonic_time;
+use crate::cpu::multiprocessor::get_lp_count;
+use crate::cpu::multiprocessor::ipi::{self, IpiRpc};
+use crate::cpu::multiprocessor::lp_set::LpSet;
+use crate::cpu::scheduler::threads::{
+    MASTER_THREAD_TABLE,
+    RtParams,
+    Thread,
+    ThreadId,
+    ThreadState,
+};
+use crate::environment::boot_protocol::cmdline;
+use crate::event::{Completion, Event, Observer};
+use crate::logln;
 use crate::memory::AddressSpaceId;
 
 pub static SYSTEM_SCHEDULER: SystemScheduler = SystemScheduler::new();
+/// LPs reserved for threads pinned to them with an affinity mask, from the `isolate_lps` boot
+/// option, e.g. `isolate_lps=2,4-7`
+pub static ISOLATED_LPS: Lazy<LpSet> = Lazy::new(|| {
+    let Some(list) = cmdline::get_option("isolate_lps") else {
+        return LpSet::new();
+    };
+    match LpSet::parse(list) {
+        // at least one LP has to be left for everything else
+        Ok(isolated) if isolated.len() < get_lp_count() as usize => isolated,
+        result => {
+            logln!("Ignoring the invalid isolate_lps boot option {}: {:?}", list, (result.err()));
+            LpSet::new()
+        }
+    }
+});
+/// How many more queued threads an LP that recently ran a thread's address space may have than
+/// the least loaded LP and still be picked for the thread
+const AS_AFFINITY_LOAD_BONUS: usize = 1;
 
+#[derive(Debug)]
 pub enum Error {
     InvalidThread,
+    /// The thread is not in a state that allows the requested operation
+    InvalidThreadState,
+    /// No LP has registered a local scheduler yet
+    NoLocalSchedulers,
+    /// No LP can guarantee the thread's real-time parameters
+    AdmissionRefused,
+    /// No LP may run the thread
+    InvalidAffinity,
+    /// The address space already has a gang
+    GangExists,
+    /// None of the LPs of the gang have registered a local scheduler
+    InvalidGang,
+    ThreadContextError(thread_context::Error),
+}
+
+impl From<thread_context::Error> for Error {
+    fn from(err: thread_context::Error) -> Self {
+        Error::ThreadContextError(err)
+    }
 }
 
 /// The system-wide thread scheduler
+///
+/// Local schedulers are locked from both thread and interrupt context on their own LP, so they are
+/// only ever locked with interrupts masked. When a thread and a local scheduler are both locked,
+/// the local scheduler is locked first.
 pub struct SystemScheduler {
-    lp_schedulers: BTreeMap<LpId, Arc<Mutex<LocalScheduler>>>,
+    lp_schedulers: RwLock<BTreeMap<LpId, Arc<Mutex<LocalScheduler>>>>,
+    /// Read by local scheduling strategies in interrupt context so it is only ever written with
+    /// interrupts masked
+    gangs: RwLock<Vec<Gang>>,
+    /// Incremented whenever a change to affinity masks or gangs may have left threads on LPs
+    /// they may no longer run on
+    placement_epoch: AtomicU64,
 }
 
 impl SystemScheduler {
     pub const fn new() -> Self {
         Self {
-            lp_schedulers: BTreeMap::new(),
+            lp_schedulers: RwLock::new(BTreeMap::new()),
+            gangs: RwLock::new(Vec::new()),
+            placement_epoch: AtomicU64::new(0),
         }
     }
 
+    /// Create the local scheduler of the calling LP with the given scheduling strategy. Each LP
+    /// calls this once during initialization after the kernel heap is available and the BSP has
+    /// initialized the per-LP state of the scheduling subsystems.
+    pub fn register_lp(&self, strategy: Box<dyn LsStratIfce>) -> Result<(), Error> {
+        let lp_id = get_lp_id();
+        // the boot option is parsed now rather than in the middle of the first context switch
+        Lazy::force(&ISOLATED_LPS);
+        let local_scheduler = LocalScheduler::new(lp_id, strategy)?;
+        self.lp_schedulers.write().insert(lp_id, Arc::new(Mutex::new(local_scheduler)));
+        Ok(())
+    }
+
     pub fn get_local_scheduler(&self) -> Arc<Mutex<LocalScheduler>> {
-        self.lp_schedulers[&get_lp_id()].clone()
+        self.lp_schedulers.read()[&get_lp_id()].clone()
     }
 
+    /// The thread running on the calling LP or `None` if it is not running a thread yet
+    pub fn get_current_tid(&self) -> Option<ThreadId> {
+        without_interrupts(|| {
+            self.get_lp_scheduler(get_lp_id())
+                .and_then(|local_scheduler| local_scheduler.lock().get_current())
+        })
+    }
+
+    /// The local scheduler of the given LP or `None` if the LP has not registered yet
+    pub fn get_lp_scheduler(&self, lp_id: LpId) -> Option<Arc<Mutex<LocalScheduler>>> {
+        self.lp_schedulers.read().get(&lp_id).cloned()
+    }
+
+    /// Queue a thread that needs an LP assignment on the least loaded LP and wake that LP if it is
+    /// halted. Returns the LP the thread was placed on.
+    ///
+    /// A real-time thread is only placed on an LP that admits it and that LP is always made to
+    /// reschedule since the thread may be more urgent than the one it is running.
     pub fn submit_ready_thread(&self, tid: ThreadId) -> Result<LpId, Error> {
-        todo!()
+        let thread = MASTER_THREAD_TABLE.get(tid).ok_or(Error::InvalidThread)?;
+        let (asid, rt_params, affinity) = {
+            let thread = thread.read();
+            (thread.asid, thread.rt_params, thread.affinity.clone())
+        };
+        without_interrupts(|| {
+            loop {
+                let (lp_id, local_scheduler) =
+                    self.pick_lp(tid, asid, rt_params.as_ref(), affinity.as_ref())?;
+                let mut local_scheduler = local_scheduler.lock();
+                {
+                    let mut thread = thread.write();
+                    if !matches!(thread.state, ThreadState::NeedsLpAssignment) {
+                        return Err(Error::InvalidThreadState);
+                    }
+                    // another thread may have been admitted since the LP was picked
+                    if let Status::AdmissionRefused = local_scheduler.add_thread(tid, &mut thread) {
+                        continue;
+                    }
+                    thread.state = ThreadState::Ready(lp_id);
+                }
+                if local_scheduler.is_halted() || rt_params.is_some() {
+                    local_scheduler.set_halted(false);
+                    idle::wake_lp(lp_id);
+                }
+                return Ok(lp_id);
+            }
+        })
+    }
+
+    /// Co-schedule the threads of an address space on the given LPs. Threads of the address space
+    /// that are submitted from now on are only placed on those LPs.
+    pub fn register_gang(&self, asid: AddressSpaceId, mut lps: LpSet) -> Result<(), Error> {
+        {
+            let lp_schedulers = self.lp_schedulers.read();
+            for lp_id in lps.clone().iter() {
+                if !lp_schedulers.contains_key(&lp_id) {
+                    lps.remove(lp_id);
+                }
+            }
+        }
+        if lps.is_empty() {
+            return Err(Error::InvalidGang);
+        }
+        without_interrupts(|| {
+            let mut gangs = self.gangs.write();
+            if gangs.iter().any(|gang| gang.asid == asid) {
+                return Err(Error::GangExists);
+            }
+            gangs.push(Gang {
+                asid,
+                lps,
+            });
+            self.placement_epoch.fetch_add(1, Ordering::Release);
+            Ok(())
+        })
+    }
+
+    pub fn unregister_gang(&self, asid: AddressSpaceId) {
+        without_interrupts(|| self.gangs.write().retain(|gang| gang.asid != asid));
+        self.placement_epoch.fetch_add(1, Ordering::Release);
+    }
+
+    /// Restrict the LPs a thread may run on or lift the restriction with `None`. A thread that is
+    /// queued on an LP it may no longer run on is moved the next time that LP switches threads.
+    pub fn set_thread_affinity(&self, tid: ThreadId, affinity: Option<LpSet>) -> Result<(), Error> {
+        if affinity.as_ref().is_some_and(LpSet::is_empty) {
+            return Err(Error::InvalidAffinity);
+        }
+        without_interrupts(|| {
+            let lp_id = self.with_queued_thread(tid, |_, thread| {
+                thread.affinity = affinity;
+                thread.state.get_lp()
+            })?;
+            self.placement_epoch.fetch_add(1, Ordering::Release);
+            if let Some(lp_id) = lp_id
+                && lp_id != get_lp_id()
+            {
+                let _ = LocalIntCtlr::send_wake_lp_ipi(lp_id);
+            }
+            Ok(())
+        })
+    }
+
+    /// The gang schedule window that `now` falls in on the calling LP or `None` if there are no
+    /// gangs
+    pub fn get_gang_window(&self, now: ExtDuration) -> Option<GangWindow> {
+        gang::get_window(&self.gangs.read(), get_lp_id(), now)
     }
 
     /// Yield the current LP's execution to the scheduler
     /// This differs from blocking in that the processor state on entry is discarded
     pub unsafe fn yield_lp(&self) -> ! {
-        todo!()
+        without_interrupts(|| {
+            let local_scheduler = self.get_local_scheduler();
+            let mut local_scheduler = local_scheduler.lock();
+            local_scheduler.set_started();
+            // the first context switch saves the bootstrap stack pointer here
+            unsafe {
+                set_thread_context_ptr(local_scheduler.get_boot_context_vaddr());
+            }
+            // the wake ISR switches to the first thread or to the idle context if there is none
+            if LocalIntCtlr::send_wake_lp_ipi(get_lp_id()).is_err() {
+                panic!("LP{}: Failed to send a wake IPI to itself", (get_lp_id()));
+            }
+        });
+        // the pending wake IPI is taken as soon as interrupts are unmasked
+        crate::cpu::isa::lp::ops::halt!()
     }
 
     /// Block the specified thread at least until the given event notifies its observers
-    pub fn block_tid(&self, tid: ThreadId, event: &dyn Event) -> Result<(), Error> {
-        /* Crate a completion object registered with event and push it to the back of the blocker
-        queue for the specified thread. If the tid doesn't point to any thread structure then
-        return Error::InvalidThread. If the thread is not already blocked then send a broadcast
-        over the kernel IPI-RPC protocol with the EvictThread command. */
-        todo!()
+    ///
+    /// A thread that is blocked on several events becomes ready again once all of them have
+    /// notified their observers. Returns the completion the event completes. A thread that blocks
+    /// itself keeps running until its LP switches away, so it must wait for the completion before
+    /// acting on having been woken.
+    pub fn block_tid(
+        &self,
+        tid: ThreadId,
+        event: &mut dyn Event,
+    ) -> Result<Arc<Completion>, Error> {
+        let completion = Arc::new(Completion::new(None));
+        without_interrupts(|| {
+            self.block_on(tid, alloc::vec![completion.clone()])?;
+            event.register_observer(Arc::new(Unblocker {
+                tid,
+                completion: completion.clone(),
+            }));
+            Ok(completion)
+        })
+    }
+
+    /// Block the specified thread at least until all of the given completions are complete. Whoever
+    /// completes them must call `try_unblock` afterwards.
+    ///
+    /// If the thread is running the LP is made to switch away from it. If that is this LP the
+    /// switch happens as soon as interrupts are unmasked and preemption is enabled again.
+    pub fn block_on(
+        &self,
+        tid: ThreadId,
+        new_completions: Vec<Arc<Completion>>,
+    ) -> Result<(), Error> {
+        without_interrupts(|| {
+            let running_lp = self.with_queued_thread(tid, |local_scheduler, thread| {
+                let running_lp = match &mut thread.state {
+                    ThreadState::Terminated => return Err(Error::InvalidThreadState),
+                    ThreadState::Blocked(completions) => {
+                        completions.extend(new_completions);
+                        return Ok(None);
+                    }
+                    ThreadState::Running(lp_id) => Some(*lp_id),
+                    _ => None,
+                };
+                if let Some(local_scheduler) = local_scheduler {
+                    local_scheduler.remove_thread(tid, thread);
+                }
+                thread.state = ThreadState::Blocked(new_completions);
+                Ok(running_lp)
+            })??;
+            if let Some(lp_id) = running_lp {
+                let _ = LocalIntCtlr::send_wake_lp_ipi(lp_id);
+            }
+            Ok(())
+        })
+    }
+
+    /// Run `callback` on the calling LP once the monotonic clock has reached `deadline`
+    pub fn arm_timer(&self, deadline: ExtDuration, callback: TimerCallback) -> TimerHandle {
+        without_interrupts(|| self.get_local_scheduler().lock().arm_timer(deadline, callback))
+    }
+
+    /// Cancel a timer on whichever LP it was armed on. Returns whether it was still pending.
+    pub fn cancel_timer(&self, handle: &TimerHandle) -> bool {
+        self.get_lp_scheduler(handle.get_lp_id()).is_some_and(|local_scheduler| {
+            without_interrupts(|| local_scheduler.lock().cancel_timer(handle))
+        })
     }
 
+    /// Run the callbacks of the calling LP's timers that are due
+    ///
+    /// Must be called with preemption disabled whenever the LP's timer fires and without any local
+    /// scheduler locked.
+    pub fn expire_timers(&self) {
+        let Some(local_scheduler) = self.get_lp_scheduler(get_lp_id()) else {
+            return;
+        };
+        let expired =
+            without_interrupts(|| local_scheduler.lock().take_expired_timers(get_monotonic_time()));
+        for callback in expired {
+            callback();
+        }
+    }
+
+    /// Stop the given threads so that they are never scheduled again
     pub fn terminate_threads(&self, tids: Vec<ThreadId>) {
-        todo!()
+        self.stop_threads(&tids, false);
     }
 
+    /// Stop the given threads immediately because they can not be allowed to continue
     pub fn abort_threads(&self, tids: Vec<ThreadId>) {
-        todo!()
+        self.stop_threads(&tids, true);
     }
 
+    /// Abort every thread of the given address space
     pub fn abort_as_threads(&self, asid: AddressSpaceId) {
-        todo!()
+        let tids = MASTER_THREAD_TABLE
+            .iter()
+            .filter(|(_, thread)| thread.read().asid == asid)
+            .map(|(tid, _)| tid)
+            .collect();
+        self.abort_threads(tids);
+    }
+
+    /// Take the given threads off their LPs and mark them as terminated, then notify their exit
+    /// observers. Threads that are running on other LPs are stopped by those LPs, which are sent
+    /// the corresponding IPI-RPC so that they switch away from them right away.
+    fn stop_threads(&self, tids: &[ThreadId], is_abort: bool) {
+        let lp_id = get_lp_id();
+        let mut remote_tids: BTreeMap<LpId, Vec<ThreadId>> = BTreeMap::new();
+        for &tid in tids {
+            let stop = without_interrupts(|| {
+                self.with_queued_thread(tid, |local_scheduler, thread| match thread.state {
+                    ThreadState::Terminated => Stop::AlreadyStopped,
+                    ThreadState::Running(running_lp) if running_lp != lp_id => {
+                        Stop::Remote(running_lp)
+                    }
+                    _ => {
+                        if let Some(local_scheduler) = local_scheduler {
+                            local_scheduler.remove_thread(tid, thread);
+                        }
+                        let is_running = matches!(thread.state, ThreadState::Running(_));
+                        thread.state = ThreadState::Terminated;
+                        if is_abort {
+                            logln!(
+                                "LP{}: Aborting thread {} ({})",
+                                lp_id,
+                                tid,
+                                (thread.name.as_deref().unwrap_or("unnamed"))
+                            );
+                        }
+                        Stop::Stopped {
+                            is_running,
+                            // otherwise the LP that is on its stack hands it over once it is not
+                            is_reapable: thread.on_lp.is_none(),
+                            exit_observers: thread.take_exit_observers(),
+                        }
+                    }
+                })
+            });
+            match stop {
+                Ok(Stop::Stopped {
+                    is_running,
+                    is_reapable,
+                    exit_observers,
+                }) => {
+                    if is_running {
+                        // the switch happens as soon as interrupts are unmasked
+                        let _ = LocalIntCtlr::send_wake_lp_ipi(lp_id);
+                    }
+                    for observer in exit_observers {
+                        observer.notify();
+                    }
+                    if is_reapable {
+                        self.queue_for_reaping(tid);
+                    }
+                }
+                Ok(Stop::Remote(running_lp)) => {
+                    remote_tids.entry(running_lp).or_default().push(tid);
+                }
+                Ok(Stop::AlreadyStopped) | Err(_) => {}
+            }
+        }
+        for (running_lp, tids) in remote_tids {
+            let rpc = if is_abort {
+                IpiRpc::AbortThreads(tids)
+            } else {
+                IpiRpc::TerminateThreads(tids)
+            };
+            if let Err(err) = ipi::send_unicast_rpc(running_lp, rpc) {
+                logln!("LP{}: Failed to stop threads on LP{}: {:?}", lp_id, running_lp, err);
+            }
+        }
+    }
+
+    /// Make a thread ready again if all of the completions it is blocked on are complete
+    pub fn try_unblock(&self, tid: ThreadId) {
+        let Some(thread) = MASTER_THREAD_TABLE.get(tid) else {
+            return;
+        };
+        let is_unblocked = without_interrupts(|| {
+            let mut thread = thread.write();
+            if let ThreadState::Blocked(completions) = &thread.state
+                && completions.iter().all(|completion| completion.poll())
+            {
+                thread.state = ThreadState::NeedsLpAssignment;
+                thread.is_woken = true;
+                true
+            } else {
+                false
+            }
+        });
+        if is_unblocked {
+            // the thread may have been submitted by someone else in the meantime
+            let _ = self.submit_ready_thread(tid);
+        }
+    }
+
+    /// Pick the LP to place a thread on. A thread that an LP has not switched away from yet must
+    /// go back to that LP since its context may not have been saved yet. Otherwise the thread is
+    /// only placed on LPs that its placement constraints allow and real-time threads only on LPs
+    /// that admit them.
+    ///
+    /// Among the remaining LPs the one with the fewest queued threads is chosen. LPs that have
+    /// recently run the thread's address space count as slightly less loaded than they are and
+    /// halted LPs are preferred on ties. LPs that have not yielded to the scheduler yet are only
+    /// chosen if no other LP can take the thread since the code they run may be waiting for it.
+    fn pick_lp(
+        &self,
+        tid: ThreadId,
+        asid: AddressSpaceId,
+        rt_params: Option<&RtParams>,
+        affinity: Option<&LpSet>,
+    ) -> Result<(LpId, Arc<Mutex<LocalScheduler>>), Error> {
+        let lp_schedulers = self.lp_schedulers.read();
+        if lp_schedulers.is_empty() {
+            return Err(Error::NoLocalSchedulers);
+        }
+        let mut least_loaded: Option<(
+            (bool, usize, bool, bool),
+            LpId,
+            &Arc<Mutex<LocalScheduler>>,
+        )> = None;
+        let mut is_any_allowed = false;
+        for (&lp_id, local_scheduler) in lp_schedulers.iter() {
+            let (is_started, current, load, has_affinity, is_halted, is_admitted) = {
+                let local_scheduler = local_scheduler.lock();
+                (
+                    local_scheduler.is_started(),
+                    local_scheduler.get_current(),
+                    local_scheduler.load(),
+                    local_scheduler.has_as_affinity(asid),
+                    local_scheduler.is_halted(),
+                    rt_params.is_none_or(|rt_params| local_scheduler.can_admit(rt_params)),
+                )
+            };
+            if current == Some(tid) {
+                return if is_admitted {
+                    Ok((lp_id, local_scheduler.clone()))
+                } else {
+                    Err(Error::AdmissionRefused)
+                };
+            }
+            if !self.is_placement_allowed(asid, affinity, lp_id) {
+                continue;
+            }
+            is_any_allowed = true;
+            if !is_admitted {
+                continue;
+            }
+            let load = if has_affinity {
+                load.saturating_sub(AS_AFFINITY_LOAD_BONUS)
+            } else {
+                load
+            };
+            let key = (!is_started, load, !has_affinity, !is_halted);
+            if least_loaded.is_none_or(|(min_key, _, _)| key < min_key) {
+                least_loaded = Some((key, lp_id, local_scheduler));
+            }
+        }
+        match least_loaded {
+            Some((_, lp_id, local_scheduler)) => Ok((lp_id, local_scheduler.clone())),
+            None if is_any_allowed => Err(Error::AdmissionRefused),
+            None => Err(Error::InvalidAffinity),
+        }
+    }
+
+    /// Whether a thread of the given address space and with the given affinity mask may be placed
+    /// on the LP. Isolated LPs only run threads whose affinity mask includes them.
+    fn is_placement_allowed(
+        &self,
+        asid: AddressSpaceId,
+        affinity: Option<&LpSet>,
+        lp_id: LpId,
+    ) -> bool {
+        let is_affine = match affinity {
+            Some(affinity) => affinity.contains(lp_id),
+            None => !ISOLATED_LPS.contains(lp_id),
+        };
+        is_affine
+            && self
+                .gangs
+                .read()
+                .iter()
+                .find(|gang| gang.asid == asid)
+                .is_none_or(|gang| gang.lps.contains(lp_id))
+    }
+
+    /// Run `f` with the thread and the local scheduler of the LP it is queued on locked. The local
+    /// scheduler is `None` if the thread is not queued on any LP.
+    ///
+    /// Must be called with interrupts masked.
+    fn with_queued_thread<R>(
+        &self,
+        tid: ThreadId,
+        f: impl FnOnce(Option<&mut LocalScheduler>, &mut Thread) -> R,
+    ) -> Result<R, Error> {
+        let thread = MASTER_THREAD_TABLE.get(tid).ok_or(Error::InvalidThread)?;
+        let mut f = Some(f);
+        loop {
+            let lp_id = thread.read().state.get_lp();
+            let local_scheduler = lp_id.and_then(|lp_id| self.get_lp_scheduler(lp_id));
+            let mut local_scheduler = local_scheduler.as_ref().map(|ls| ls.lock());
+            let mut thread = thread.write();
+            // the thread may have moved while the local scheduler was being locked
+            if thread.state.get_lp() == lp_id {
+                let f = f.take().unwrap();
+                return Ok(f(local_scheduler.as_deref_mut(), &mut thread));
+            }
+        }
     }
 }
 
 unsafe impl Sync for SystemScheduler {}
+
+/// What `SystemScheduler::stop_threads` did with a thread
+enum Stop {
+    Stopped {
+        /// Whether the calling LP is running the thread
+        is_running: bool,
+        /// Whether no LP is on the thread's stack anymore
+        is_reapable: bool,
+        exit_observers: Vec<Arc<dyn Observer>>,
+    },
+    /// The thread is running on another LP
+    Remote(LpId),
+    AlreadyStopped,
+}
+
+/// Observer registered by `SystemScheduler::block_tid` that completes the thread's completion the
+/// first time the event it is blocked on is raised
+struct Unblocker {
+    tid: ThreadId,
+    completion: Arc<Completion>,
+}
+
+impl Observer for Unblocker {
+    fn notify(&self) {
+        if self.completion.complete() {
+            SYSTEM_SCHEDULER.try_unblock(self.tid);
+        }
+    }
+}
@@ -0,0 +1,51 @@
+//! # Priority Inheritance
+//!
+//! A thread that blocks on a mutex lends its priority to the mutex's owner so that a less
+//! important thread holding the mutex can not keep it from running for longer than the critical
+//! section takes. The owner is scheduled with the most important of its base priority and the
+//! priorities lent to it until it releases the mutexes it was lent them for. An owner that is
+//! itself waiting on a mutex passes what it was lent on to that mutex's owner.
+
+use super::{Error, SystemScheduler};
+use crate::cpu::isa::lp::LpId;
+use crate::cpu::isa::lp::ops::without_interrupts;
+use crate::cpu::scheduler::threads::{MASTER_THREAD_TABLE, Priority, ThreadId, ThreadState};
+
+impl SystemScheduler {
+    /// The priority the thread is currently scheduled with
+    pub fn get_effective_priority(&self, tid: ThreadId) -> Option<Priority> {
+        let thread = MASTER_THREAD_TABLE.get(tid)?;
+        Some(without_interrupts(|| thread.read().get_effective_priority()))
+    }
+
+    /// The LP the thread is running on, if any
+    pub fn get_running_lp(&self, tid: ThreadId) -> Option<LpId> {
+        let thread = MASTER_THREAD_TABLE.get(tid)?;
+        without_interrupts(|| match thread.read().state {
+            ThreadState::Running(lp_id) => Some(lp_id),
+            _ => None,
+        })
+    }
+
+    /// Lend a priority to a thread on behalf of the mutex with the given key or take it back with
+    /// `None`. A queued thread whose effective priority changes is queued again so that its local
+    /// scheduler's strategy sees the new priority.
+    pub fn lend_priority(
+        &self,
+        tid: ThreadId,
+        key: usize,
+        priority: Option<Priority>,
+    ) -> Result<(), Error> {
+        without_interrupts(|| {
+            self.with_queued_thread(tid, |local_scheduler, thread| {
+                if thread.lend_priority(key, priority)
+                    && let Some(local_scheduler) = local_scheduler
+                {
+                    local_scheduler.remove_thread(tid, thread);
+                    // the thread's real-time parameters were admitted before so they still are
+                    let _ = local_scheduler.add_thread(tid, thread);
+                }
+            })
+        })
+    }
+}
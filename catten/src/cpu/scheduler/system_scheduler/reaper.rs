@@ -0,0 +1,96 @@
+//! # Thread Reaper
+//!
+//! A terminated thread's kernel stack can not be freed by the LP that terminated it while that LP
+//! may still be running on it, so terminated threads are handed to a reaper thread instead. A
+//! thread is handed over as soon as no LP is on its stack anymore: right away if it was not
+//! running anywhere when it was terminated, and otherwise by the LP that ran it when that LP next
+//! switches threads. The reaper frees the thread's stacks and its slot in the thread table.
+
+use alloc::vec::Vec;
+
+use super::{SYSTEM_SCHEDULER, SystemScheduler};
+use crate::cpu::isa::lp::ops::get_lp_id;
+use crate::cpu::scheduler::sync::spinlock::IrqSpinLock;
+use crate::cpu::scheduler::sync::wait_queue::WaitQueue;
+use crate::cpu::scheduler::threads::spawn::spawn_kernel_thread;
+use crate::cpu::scheduler::threads::{MASTER_THREAD_TABLE, ThreadId, ThreadState, remove_thread};
+use crate::logln;
+
+/// Terminated threads waiting to be reaped. Threads are handed over from the context switch path.
+static REAP_QUEUE: IrqSpinLock<Vec<ThreadId>> = IrqSpinLock::new(Vec::new());
+/// Woken each time a thread is queued
+static THREADS_QUEUED: WaitQueue = WaitQueue::new();
+
+impl SystemScheduler {
+    /// Spawn the reaper thread. Threads that terminate before then are reaped once it runs.
+    pub fn start_reaper(&self) {
+        spawn_kernel_thread("reaper", reaper_main).detach();
+    }
+
+    /// Release the thread the calling LP switched away from last now that the LP is no longer on
+    /// its kernel stack, handing it to the reaper if it has terminated
+    ///
+    /// Must be called on each switch before the LP picks the next thread and without any local
+    /// scheduler locked.
+    pub fn release_prev_thread(&self) {
+        let lp_id = get_lp_id();
+        let Some(local_scheduler) = self.get_lp_scheduler(lp_id) else {
+            return;
+        };
+        let Some(tid) = local_scheduler.lock().take_prev() else {
+            return;
+        };
+        let Some(thread) = MASTER_THREAD_TABLE.get(tid) else {
+            return;
+        };
+        let is_terminated = {
+            let mut thread = thread.write();
+            // another LP may have switched to the thread since
+            if thread.on_lp == Some(lp_id) {
+                thread.on_lp = None;
+                matches!(thread.state, ThreadState::Terminated)
+            } else {
+                false
+            }
+        };
+        if is_terminated {
+            self.queue_for_reaping(tid);
+        }
+    }
+
+    /// Hand a terminated thread that no LP is on the stack of anymore to the reaper
+    pub(super) fn queue_for_reaping(&self, tid: ThreadId) {
+        REAP_QUEUE.lock().push(tid);
+        THREADS_QUEUED.wake_all();
+    }
+
+    /// Free the resources of a terminated thread
+    fn reap(&self, tid: ThreadId) {
+        let Some(thread) = MASTER_THREAD_TABLE.get(tid) else {
+            return;
+        };
+        {
+            let mut thread = thread.write();
+            if !matches!(thread.state, ThreadState::Terminated) || thread.on_lp.is_some() {
+                return;
+            }
+            // SAFETY: The thread has terminated and no LP is on its stack anymore.
+            if let Err(err) = unsafe { thread.context.deallocate_stacks() } {
+                logln!("Reaper: Failed to deallocate the stacks of thread {}: {:?}", tid, err);
+            }
+        }
+        remove_thread(tid);
+    }
+}
+
+fn reaper_main() {
+    loop {
+        let tids = THREADS_QUEUED.wait_for(|| {
+            let mut reap_queue = REAP_QUEUE.lock();
+            (!reap_queue.is_empty()).then(|| core::mem::take(&mut *reap_queue))
+        });
+        for tid in tids {
+            SYSTEM_SCHEDULER.reap(tid);
+        }
+    }
+}
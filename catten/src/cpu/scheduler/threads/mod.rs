@@ -1,41 +1,193 @@
+pub mod spawn;
+
+use alloc::string::String;
+use alloc::sync::Arc;
 use alloc::vec::Vec;
 
 use spin::Lazy;
 
-use crate::common::collections::id_table::IdTable;
+use crate::common::collections::handle_table::{Full, Handle, HandleTable};
+use crate::common::time::duration::ExtDuration;
 use crate::cpu::isa::interface::memory::address::VirtualAddress;
 use crate::cpu::isa::lp::LpId;
-use crate::cpu::isa::lp::thread_context::ThreadContext;
-use crate::event::Completion;
+use crate::cpu::isa::lp::thread_context::{self, ThreadContext};
+use crate::cpu::multiprocessor::lp_set::LpSet;
+use crate::event::{Completion, Event, Observer};
 use crate::memory::{AddressSpaceId, VAddr};
 
-pub static mut MASTER_THREAD_TABLE: Lazy<ThreadTable> = Lazy::new(ThreadTable::new);
-pub type ThreadTable = IdTable<ThreadId, Thread>;
-pub type ThreadId = usize;
+pub static MASTER_THREAD_TABLE: Lazy<ThreadTable> =
+    Lazy::new(|| ThreadTable::new(MAX_THREAD_COUNT));
+pub type ThreadTable = HandleTable<Thread>;
+/// A handle into the master thread table, so a stale ID never refers to a newer thread
+pub type ThreadId = Handle;
+/// Scheduling priority of a thread. Lower values are more important.
+pub type Priority = u8;
+pub const DEFAULT_PRIORITY: Priority = 0;
+/// The capacity of the master thread table
+pub const MAX_THREAD_COUNT: usize = 1 << 16;
+
+/// Add a thread to the master thread table and return its ID. Hands the thread back if the table
+/// is full.
+pub fn add_thread(thread: Thread) -> Result<ThreadId, Full<Thread>> {
+    MASTER_THREAD_TABLE.insert(thread)
+}
+
+/// Remove a thread from the master thread table. Its ID never refers to a thread again.
+pub fn remove_thread(tid: ThreadId) {
+    MASTER_THREAD_TABLE.remove(tid);
+}
+
+/// Real-time scheduling parameters
+///
+/// The thread is released once every period. After each release it may run for up to its budget
+/// and should have done so by the time its relative deadline has passed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RtParams {
+    period: ExtDuration,
+    budget: ExtDuration,
+    deadline: ExtDuration,
+}
+
+impl RtParams {
+    /// Returns `None` unless `0 < budget <= deadline <= period`
+    pub fn new(period: ExtDuration, budget: ExtDuration, deadline: ExtDuration) -> Option<Self> {
+        (ExtDuration::default() < budget && budget <= deadline && deadline <= period).then_some(
+            RtParams {
+                period,
+                budget,
+                deadline,
+            },
+        )
+    }
+
+    pub fn get_period(&self) -> ExtDuration {
+        self.period
+    }
+
+    pub fn get_budget(&self) -> ExtDuration {
+        self.budget
+    }
+
+    pub fn get_deadline(&self) -> ExtDuration {
+        self.deadline
+    }
+
+    /// The share of an LP the thread needs to meet its deadlines in parts per million. This is the
+    /// density rather than the utilisation since the deadline may be shorter than the period.
+    pub fn density_ppm(&self) -> u64 {
+        (self.budget.as_picos() * 1_000_000).div_ceil(self.deadline.as_picos()) as u64
+    }
+}
+
+/// Where a real-time thread is in its current period
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RtPeriod {
+    /// The start of the period
+    pub release: ExtDuration,
+    /// How much of its budget the thread has left in the period
+    pub remaining: ExtDuration,
+}
 
 pub enum ThreadState {
     Running(LpId),
     Ready(LpId),
     NeedsLpAssignment,
-    Blocked(Vec<Completion>),
-    Terminated, //Used while the thread is being cleaned up
+    Blocked(Vec<Arc<Completion>>),
+    /// The thread never runs again and its resources are reclaimed once no LP is using its stack
+    Terminated,
+}
+
+impl ThreadState {
+    /// The LP whose local scheduler the thread is queued on, if any
+    pub fn get_lp(&self) -> Option<LpId> {
+        match self {
+            ThreadState::Running(lp_id) | ThreadState::Ready(lp_id) => Some(*lp_id),
+            _ => None,
+        }
+    }
 }
 
 pub struct Thread {
+    /// Shown in diagnostics
+    pub name: Option<String>,
     pub is_user: bool,
     pub context: ThreadContext,
     pub asid: AddressSpaceId,
     pub state: ThreadState,
+    pub base_priority: Priority,
+    /// Set when the thread is unblocked so that the local scheduler strategy it is queued on next
+    /// can favour it
+    pub is_woken: bool,
+    /// Threads with real-time parameters are only admitted to LPs whose scheduling strategy has a
+    /// real-time class with enough capacity left
+    pub rt_params: Option<RtParams>,
+    /// Where a real-time thread was in its period when it was last taken off an LP, e.g. to block
+    /// or to move to another LP. It resumes there when it is queued again rather than getting a
+    /// new period with a full budget.
+    pub rt_period: Option<RtPeriod>,
+    /// The LPs the thread may run on. Threads without an affinity mask run on any LP that is not
+    /// isolated.
+    pub affinity: Option<LpSet>,
+    /// The LP that last switched to the thread until it has switched away from it again. It may
+    /// still be on the thread's kernel stack even when the thread's state says otherwise.
+    pub on_lp: Option<LpId>,
+    /// Notified once the thread has terminated
+    exit_observers: Vec<Arc<dyn Observer>>,
+    /// The priorities lent to the thread by threads waiting on the mutexes it holds, keyed by the
+    /// address of the mutex
+    lent_priorities: Vec<(usize, Priority)>,
 }
 
 impl Thread {
     pub fn new(is_user: bool, asid: AddressSpaceId, entry_point: VAddr) -> Self {
-        Thread {
+        Self::try_new(is_user, asid, entry_point).expect("Error creating thread context")
+    }
+
+    pub fn try_new(
+        is_user: bool,
+        asid: AddressSpaceId,
+        entry_point: VAddr,
+    ) -> Result<Self, thread_context::Error> {
+        Ok(Thread {
+            name: None,
             is_user,
-            context: ThreadContext::new(asid, entry_point).expect("Error creating thread context"),
+            context: ThreadContext::new(asid, entry_point)?,
             asid,
             state: ThreadState::NeedsLpAssignment,
+            base_priority: DEFAULT_PRIORITY,
+            is_woken: false,
+            rt_params: None,
+            rt_period: None,
+            affinity: None,
+            on_lp: None,
+            exit_observers: Vec::new(),
+            lent_priorities: Vec::new(),
+        })
+    }
+
+    /// Take the observers to notify now that the thread has terminated
+    pub fn take_exit_observers(&mut self) -> Vec<Arc<dyn Observer>> {
+        core::mem::take(&mut self.exit_observers)
+    }
+
+    /// The priority the thread is scheduled with, which is the most important of its base
+    /// priority and the priorities lent to it
+    pub fn get_effective_priority(&self) -> Priority {
+        self.lent_priorities
+            .iter()
+            .map(|&(_, priority)| priority)
+            .fold(self.base_priority, Priority::min)
+    }
+
+    /// Lend the thread a priority on behalf of the mutex with the given key or take back the one
+    /// lent for it with `None`. Returns whether the effective priority changed.
+    pub fn lend_priority(&mut self, key: usize, priority: Option<Priority>) -> bool {
+        let prev = self.get_effective_priority();
+        self.lent_priorities.retain(|&(lender, _)| lender != key);
+        if let Some(priority) = priority {
+            self.lent_priorities.push((key, priority));
         }
+        self.get_effective_priority() != prev
     }
 
     pub unsafe fn get_context_vaddr(&self) -> VAddr {
@@ -44,3 +196,15 @@ impl Thread {
         VAddr::from_ptr(&self.context)
     }
 }
+
+impl Event for Thread {
+    /// The observer is notified once the thread has terminated. If it already has, the observer is
+    /// notified right away while the caller still holds the thread's lock.
+    fn register_observer(&mut self, observer: Arc<dyn Observer>) {
+        if let ThreadState::Terminated = self.state {
+            observer.notify();
+        } else {
+            self.exit_observers.push(observer);
+        }
+    }
+}
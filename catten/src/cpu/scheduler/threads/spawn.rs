@@ -0,0 +1,230 @@
+//! # Kernel Thread Spawning
+//!
+//! A kernel thread runs a closure to completion and then terminates itself. The closure is boxed
+//! and handed to a trampoline that the thread enters with a pointer to the box as its only
+//! argument. Its result is passed back through the thread's `JoinHandle`, which is also told when
+//! the thread is terminated or aborted before the closure returns.
+
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use super::{Thread, ThreadId, add_thread};
+use crate::common::collections::handle_table::Full;
+use crate::cpu::isa::interface::memory::address::VirtualAddress;
+use crate::cpu::isa::lp::ops::halt;
+use crate::cpu::isa::lp::thread_context;
+use crate::cpu::multiprocessor::lp_set::LpSet;
+use crate::cpu::scheduler::sync::spinlock::SpinLock;
+use crate::cpu::scheduler::system_scheduler::{self, SYSTEM_SCHEDULER};
+use crate::event::{Event, Observer};
+use crate::memory::{KERNEL_ASID, VAddr};
+
+#[derive(Debug)]
+pub enum Error {
+    ThreadTableFull,
+    ThreadContextError(thread_context::Error),
+    SchedulerError(system_scheduler::Error),
+}
+
+impl From<thread_context::Error> for Error {
+    fn from(err: thread_context::Error) -> Self {
+        Error::ThreadContextError(err)
+    }
+}
+
+impl From<system_scheduler::Error> for Error {
+    fn from(err: system_scheduler::Error) -> Self {
+        Error::SchedulerError(err)
+    }
+}
+
+/// The state shared by a spawned thread and its join handle. It is raised as an event once the
+/// thread has terminated.
+struct Packet<T> {
+    result: Option<T>,
+    is_finished: bool,
+    observers: Vec<Arc<dyn Observer>>,
+}
+
+impl<T> Event for Packet<T> {
+    fn register_observer(&mut self, observer: Arc<dyn Observer>) {
+        // a joiner that blocks after the thread finished must not wait forever
+        if self.is_finished {
+            observer.notify();
+        } else {
+            self.observers.push(observer);
+        }
+    }
+}
+
+/// Observer registered with a spawned thread that raises its packet once it has terminated
+struct Finisher<T> {
+    packet: Arc<SpinLock<Packet<T>>>,
+}
+
+impl<T: Send> Observer for Finisher<T> {
+    fn notify(&self) {
+        let observers = {
+            let mut packet = self.packet.lock();
+            packet.is_finished = true;
+            core::mem::take(&mut packet.observers)
+        };
+        for observer in observers {
+            observer.notify();
+        }
+    }
+}
+
+/// An owned permission to join a kernel thread
+///
+/// Dropping the handle detaches the thread.
+pub struct JoinHandle<T> {
+    tid: ThreadId,
+    packet: Arc<SpinLock<Packet<T>>>,
+}
+
+impl<T> JoinHandle<T> {
+    pub fn get_tid(&self) -> ThreadId {
+        self.tid
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.packet.lock().is_finished
+    }
+
+    /// Wait for the thread to terminate and return the result of its closure or `None` if the
+    /// thread was terminated before the closure returned
+    ///
+    /// A calling thread is blocked until then. Code that does not run on a thread, such as an LP
+    /// that has not yielded to the scheduler yet, spins instead.
+    pub fn join(self) -> Option<T> {
+        loop {
+            let mut packet = self.packet.lock();
+            if packet.is_finished {
+                return packet.result.take();
+            }
+            match SYSTEM_SCHEDULER.get_current_tid() {
+                Some(tid) => {
+                    let wakeup =
+                        SYSTEM_SCHEDULER.block_tid(tid, &mut *packet).unwrap_or_else(|err| {
+                            panic!(
+                                "Failed to block thread {} to join thread {}: {:?}",
+                                tid, self.tid, err
+                            )
+                        });
+                    // the LP switches away once the packet is unlocked
+                    drop(packet);
+                    wakeup.spin_until_complete();
+                }
+                None => {
+                    drop(packet);
+                    core::hint::spin_loop();
+                }
+            }
+        }
+    }
+
+    /// Let the thread run to completion without waiting for it. Its result is dropped.
+    pub fn detach(self) {}
+}
+
+/// Spawn a kernel thread that runs `f`. See `try_spawn_kernel_thread`.
+///
+/// # Panics
+/// If the thread could not be created or scheduled
+pub fn spawn_kernel_thread<F, T>(name: &str, f: F) -> JoinHandle<T>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    try_spawn_kernel_thread(name, f)
+        .unwrap_or_else(|err| panic!("Failed to spawn kernel thread {}: {:?}", name, err))
+}
+
+/// Create a kernel thread that runs `f` and submit it to the scheduler
+pub fn try_spawn_kernel_thread<F, T>(name: &str, f: F) -> Result<JoinHandle<T>, Error>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    try_spawn_kernel_thread_on(name, None, f)
+}
+
+/// Spawn a kernel thread that runs `f` only on the given LPs. See `try_spawn_kernel_thread_on`.
+///
+/// # Panics
+/// If the thread could not be created or scheduled
+pub fn spawn_kernel_thread_on<F, T>(name: &str, affinity: LpSet, f: F) -> JoinHandle<T>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    try_spawn_kernel_thread_on(name, Some(affinity), f)
+        .unwrap_or_else(|err| panic!("Failed to spawn kernel thread {}: {:?}", name, err))
+}
+
+/// Create a kernel thread that runs `f` and submit it to the scheduler. A thread with an affinity
+/// mask is placed on one of its LPs from the start, so unlike one that is pinned after it was
+/// spawned it never runs anywhere else.
+pub fn try_spawn_kernel_thread_on<F, T>(
+    name: &str,
+    affinity: Option<LpSet>,
+    f: F,
+) -> Result<JoinHandle<T>, Error>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    let packet = Arc::new(SpinLock::new(Packet {
+        result: None,
+        is_finished: false,
+        observers: Vec::new(),
+    }));
+    let thread_packet = packet.clone();
+    let start: Box<dyn FnOnce() + Send> = Box::new(move || {
+        let result = f();
+        thread_packet.lock().result = Some(result);
+    });
+    // the trampoline takes a thin pointer
+    let start = Box::into_raw(Box::new(start));
+    let mut thread =
+        match Thread::try_new(false, KERNEL_ASID, VAddr::from_ptr(trampoline as *const ())) {
+            Ok(thread) => thread,
+            Err(err) => {
+                drop(unsafe { Box::from_raw(start) });
+                return Err(err.into());
+            }
+        };
+    thread.name = Some(String::from(name));
+    thread.affinity = affinity;
+    thread.context.set_entry_arg(start as u64);
+    thread.register_observer(Arc::new(Finisher {
+        packet: packet.clone(),
+    }));
+    let tid = match add_thread(thread) {
+        Ok(tid) => tid,
+        Err(Full(mut thread)) => {
+            // SAFETY: The thread has never run.
+            let _ = unsafe { thread.context.deallocate_stacks() };
+            drop(unsafe { Box::from_raw(start) });
+            return Err(Error::ThreadTableFull);
+        }
+    };
+    SYSTEM_SCHEDULER.submit_ready_thread(tid)?;
+    Ok(JoinHandle {
+        tid,
+        packet,
+    })
+}
+
+extern "C" fn trampoline(start: *mut Box<dyn FnOnce() + Send>) -> ! {
+    let start = unsafe { Box::from_raw(start) };
+    start();
+    if let Some(tid) = SYSTEM_SCHEDULER.get_current_tid() {
+        SYSTEM_SCHEDULER.terminate_threads(alloc::vec![tid]);
+    }
+    // the LP switches away as soon as the terminate IPI arrives
+    halt!()
+}
@@ -20,3 +20,7 @@ pub static COM6: u16 = 0x4f8;
 pub static COM7: u16 = 0x5e8;
 #[allow(unused)]
 pub static COM8: u16 = 0x4e8;
+
+/// The ISA IRQ that COM1 and COM3 interrupt on
+#[allow(unused)]
+pub static COM1_IRQ: u8 = 4;
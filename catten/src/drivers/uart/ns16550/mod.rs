@@ -4,26 +4,46 @@ mod legacy_ports;
 use core::fmt::{self, Write};
 use core::result::Result;
 
+#[cfg(target_arch = "x86_64")]
+use spin::Once;
 #[cfg(target_arch = "x86_64")]
 use spin::lazy::Lazy;
 #[cfg(target_arch = "x86_64")]
 use spin::mutex::Mutex;
 
 use crate::common::io::Read;
+use crate::common::time::duration::ExtDuration;
 use crate::cpu::isa::interface::io::{IReg8Ifce, OReg8Ifce};
 #[cfg(target_arch = "x86_64")]
+use crate::cpu::isa::interrupts::irq::{self, IsaIrq};
+#[cfg(target_arch = "x86_64")]
 use crate::cpu::isa::io;
 use crate::cpu::isa::io::IoReg8;
+#[cfg(target_arch = "x86_64")]
+use crate::cpu::isa::lp::ops::get_lp_id;
 use crate::drivers::uart::Uart;
+use crate::event::EventSource;
+use crate::event::wait::wait_any;
+
+/// How many milliseconds a read waits for the receive interrupt before it checks the UART again. A
+/// character can arrive after the check but before the wait has started listening for its
+/// interrupt.
+const RECEIVE_WAIT_TIMEOUT_MS: u128 = 10;
+/// The interrupt enable register bit for received data
+const IER_RECEIVED_DATA: u8 = 1;
 
 #[cfg(target_arch = "x86_64")]
 pub static LOG_PORT: Lazy<Mutex<Uart16550>> =
     Lazy::new(|| Mutex::new(Uart16550::try_new(io::IoReg8::IoPort(legacy_ports::COM1)).unwrap()));
+/// The IRQ of the log port once it has been routed
+#[cfg(target_arch = "x86_64")]
+static LOG_PORT_IRQ: Once<IsaIrq> = Once::new();
 
 #[derive(Copy, Clone, Debug)]
-#[repr(transparent)]
 pub struct Uart16550 {
     base: IoReg8,
+    /// Raised by the UART's interrupt once its receive interrupt is enabled
+    receive_event: Option<&'static EventSource>,
 }
 #[derive(Debug, Clone, Copy)]
 pub enum Error {
@@ -39,10 +59,36 @@ impl Uart16550 {
         (unsafe { (self.base + 5).read() } & 1) != 0
     }
 
+    /// Wait for a character. Threads wait for the receive interrupt if it is enabled and
+    /// everything else polls the UART.
     fn read_char(&self) -> char {
-        while !self.received() {}
+        while !self.received() {
+            let is_waiting = self.receive_event.is_some_and(|event| {
+                wait_any(&[event], Some(ExtDuration::from_millis(RECEIVE_WAIT_TIMEOUT_MS))).is_ok()
+            });
+            if !is_waiting {
+                core::hint::spin_loop();
+            }
+        }
         unsafe { (self.base).read() as char }
     }
+
+    /// Have the UART interrupt when it receives data so that reads wait for the interrupt. `event`
+    /// must be raised by the IRQ that the UART is connected to.
+    pub fn enable_receive_irq(&mut self, event: &'static EventSource) {
+        self.receive_event = Some(event);
+        unsafe {
+            (self.base + 1).write(IER_RECEIVED_DATA);
+        }
+    }
+}
+
+/// Route the log port's IRQ to the calling LP and have reads from the log port wait for it
+#[cfg(target_arch = "x86_64")]
+pub fn enable_log_port_irq() -> Result<(), irq::Error> {
+    let irq = LOG_PORT_IRQ.try_call_once(|| IsaIrq::route(legacy_ports::COM1_IRQ, get_lp_id()))?;
+    LOG_PORT.lock().enable_receive_irq(irq.get_event());
+    Ok(())
 }
 
 impl Uart for Uart16550 {
@@ -51,6 +97,7 @@ impl Uart for Uart16550 {
     fn try_new(base: IoReg8) -> Result<Self, Error> {
         let port = Uart16550 {
             base: base,
+            receive_event: None,
         };
         unsafe {
             (port.base + 1).write(0x00); // Disable all interrupts
@@ -0,0 +1,194 @@
+//! # Processor Power States
+//!
+//! The C-states an LP can enter while idle are described by the `_CST` object of its processor
+//! device, or on firmware that predates processor devices, of its legacy `Processor()` object
+//! under `\_PR` or `\_SB`. LPs are assumed to share the same C-states, so the states of the first
+//! processor that describes any are used for all of them.
+//!
+//! Only states that are entered with the ISA's native idle instruction are returned. On x86_64
+//! these are the functional fixed hardware states that give an `mwait` hint, and C1 which can
+//! always be entered with `hlt`.
+
+use alloc::vec::Vec;
+use core::ffi::*;
+
+use uacpi_raw::*;
+
+use crate::common::time::duration::ExtDuration;
+use crate::cpu::scheduler::idle::CState;
+
+/// The hardware ID of processor devices
+const PROCESSOR_HID: &CStr = c"ACPI0007";
+/// The namespaces that legacy `Processor()` objects are declared in, searched in this order
+const LEGACY_PROCESSOR_NAMESPACES: [uacpi_predefined_namespace; 2] = [
+    uacpi_predefined_namespace_UACPI_PREDEFINED_NAMESPACE_PR,
+    uacpi_predefined_namespace_UACPI_PREDEFINED_NAMESPACE_SB,
+];
+/// The tag of the Generic Register Descriptor resource that gives a C-state's entry register
+const GENERIC_REGISTER_DESCRIPTOR: u8 = 0x82;
+/// The length of a Generic Register Descriptor including its tag and length fields
+const GENERIC_REGISTER_DESCRIPTOR_LEN: usize = 15;
+/// The address space of registers that are accessed in a processor specific way
+const ADDRESS_SPACE_FFH: u8 = 0x7f;
+/// The functional fixed hardware register class of C-states that are entered with `mwait`
+const FFH_CLASS_NATIVE_CSTATE: u8 = 2;
+
+#[derive(Debug)]
+pub enum Error {
+    /// uACPI failed to search the namespace
+    Uacpi(uacpi_status),
+    /// No processor device or `Processor()` object has a `_CST` object
+    NotFound,
+    /// A `_CST` package does not match the layout given by the ACPI specification
+    Malformed,
+}
+
+/// Read the C-states of the first processor that describes any
+pub fn read_cstates() -> Result<Vec<CState>, Error> {
+    let mut found: Option<Result<Vec<CState>, Error>> = None;
+    let status = unsafe {
+        uacpi_find_devices(
+            PROCESSOR_HID.as_ptr(),
+            Some(visit_processor),
+            (&raw mut found).cast::<c_void>(),
+        )
+    };
+    if status != uacpi_status_UACPI_STATUS_OK {
+        return Err(Error::Uacpi(status));
+    }
+    for namespace in LEGACY_PROCESSOR_NAMESPACES {
+        if found.is_some() {
+            break;
+        }
+        let parent = unsafe { uacpi_namespace_get_predefined(namespace) };
+        if parent.is_null() {
+            continue;
+        }
+        let status = unsafe {
+            uacpi_namespace_for_each_child(
+                parent,
+                Some(visit_processor),
+                None,
+                uacpi_object_type_bits_UACPI_OBJECT_PROCESSOR_BIT,
+                UACPI_MAX_DEPTH_ANY,
+                (&raw mut found).cast::<c_void>(),
+            )
+        };
+        if status != uacpi_status_UACPI_STATUS_OK {
+            return Err(Error::Uacpi(status));
+        }
+    }
+    found.unwrap_or(Err(Error::NotFound))
+}
+
+unsafe extern "C" fn visit_processor(
+    user: *mut c_void,
+    node: *mut uacpi_namespace_node,
+    _node_depth: uacpi_u32,
+) -> uacpi_iteration_decision {
+    let found = unsafe { &mut *user.cast::<Option<Result<Vec<CState>, Error>>>() };
+    let mut cst: *mut uacpi_object = core::ptr::null_mut();
+    if unsafe { uacpi_eval_simple_package(node, c"_CST".as_ptr(), &raw mut cst) }
+        != uacpi_status_UACPI_STATUS_OK
+    {
+        return uacpi_iteration_decision_UACPI_ITERATION_DECISION_CONTINUE;
+    }
+    let cstates = unsafe { parse_cst(cst) };
+    unsafe { uacpi_object_unref(cst) };
+    *found = Some(cstates);
+    uacpi_iteration_decision_UACPI_ITERATION_DECISION_BREAK
+}
+
+/// Parse a `_CST` package, which holds the number of states followed by a package for each state
+/// that holds its entry register, type, worst case latency in microseconds and power draw
+///
+/// # Safety
+/// `cst` must be a valid package object.
+unsafe fn parse_cst(cst: *mut uacpi_object) -> Result<Vec<CState>, Error> {
+    let entries = unsafe { get_package(cst) }?;
+    let (&count, entries) = entries.split_first().ok_or(Error::Malformed)?;
+    let count = unsafe { get_integer(count) }?;
+    let mut cstates = Vec::new();
+    for &entry in entries.iter().take(count as usize) {
+        let fields = unsafe { get_package(entry) }?;
+        let [register, acpi_type, latency, _power] = fields else {
+            return Err(Error::Malformed);
+        };
+        let register = unsafe { get_buffer(*register) }?;
+        let acpi_type = unsafe { get_integer(*acpi_type) }? as u8;
+        let latency = unsafe { get_integer(*latency) }?;
+        let hint = match parse_mwait_hint(register)? {
+            Some(hint) => Some(hint),
+            None if acpi_type == 1 => None,
+            // states entered through I/O port reads are not supported
+            None => continue,
+        };
+        cstates.push(CState {
+            acpi_type,
+            exit_latency: ExtDuration::from_micros(latency as u128),
+            hint,
+        });
+    }
+    Ok(cstates)
+}
+
+/// The `mwait` hint of a C-state's entry register or `None` if it is not entered with `mwait`
+fn parse_mwait_hint(register: &[u8]) -> Result<Option<u32>, Error> {
+    if register.len() < GENERIC_REGISTER_DESCRIPTOR_LEN
+        || register[0] != GENERIC_REGISTER_DESCRIPTOR
+    {
+        return Err(Error::Malformed);
+    }
+    let address_space = register[3];
+    // the bit offset field holds the register class for functional fixed hardware
+    let class = register[5];
+    if address_space != ADDRESS_SPACE_FFH || class != FFH_CLASS_NATIVE_CSTATE {
+        return Ok(None);
+    }
+    let address = u64::from_le_bytes(register[7..15].try_into().unwrap());
+    Ok(Some(address as u32))
+}
+
+/// # Safety
+/// `object` must be a valid object that outlives the returned slice.
+unsafe fn get_package<'a>(object: *mut uacpi_object) -> Result<&'a [*mut uacpi_object], Error> {
+    let mut array = uacpi_object_array {
+        objects: core::ptr::null_mut(),
+        count: 0,
+    };
+    if unsafe { uacpi_object_get_package(object, &raw mut array) } != uacpi_status_UACPI_STATUS_OK {
+        return Err(Error::Malformed);
+    }
+    if array.count == 0 {
+        return Ok(&[]);
+    }
+    Ok(unsafe { core::slice::from_raw_parts(array.objects, array.count) })
+}
+
+/// # Safety
+/// `object` must be a valid object.
+unsafe fn get_integer(object: *mut uacpi_object) -> Result<u64, Error> {
+    let mut value: uacpi_u64 = 0;
+    if unsafe { uacpi_object_get_integer(object, &raw mut value) } != uacpi_status_UACPI_STATUS_OK {
+        return Err(Error::Malformed);
+    }
+    Ok(value)
+}
+
+/// # Safety
+/// `object` must be a valid object that outlives the returned slice.
+unsafe fn get_buffer<'a>(object: *mut uacpi_object) -> Result<&'a [u8], Error> {
+    let mut view = uacpi_data_view {
+        __bindgen_anon_1: uacpi_data_view__bindgen_ty_1 {
+            const_bytes: core::ptr::null(),
+        },
+        length: 0,
+    };
+    if unsafe { uacpi_object_get_buffer(object, &raw mut view) } != uacpi_status_UACPI_STATUS_OK {
+        return Err(Error::Malformed);
+    }
+    if view.length == 0 {
+        return Ok(&[]);
+    }
+    Ok(unsafe { core::slice::from_raw_parts(view.__bindgen_anon_1.const_bytes, view.length) })
+}
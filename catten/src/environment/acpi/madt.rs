@@ -0,0 +1,112 @@
+//! # Multiple APIC Description Table
+//!
+//! The MADT lists the I/O APICs and how the legacy ISA IRQs are connected to their inputs. LPs are
+//! enumerated through the bootloader instead, so their entries are not read. This is specific to
+//! x86_64.
+
+use alloc::vec::Vec;
+
+use spin::Lazy;
+
+use super::sdt::{SDT_HEADER_LEN, find_table};
+
+const MADT_SIGNATURE: &[u8; 4] = b"APIC";
+/// The MADT header is followed by the local APIC address and flags before its first entry
+const MADT_ENTRIES_OFFSET: usize = SDT_HEADER_LEN + 8;
+/// The MADT flag that marks the system as also having dual 8259 PICs
+const MADT_FLAG_PCAT_COMPAT: u32 = 1;
+const ENTRY_TYPE_IO_APIC: u8 = 1;
+const ENTRY_TYPE_INTERRUPT_SOURCE_OVERRIDE: u8 = 2;
+const MPS_POLARITY_MASK: u16 = 0b11;
+const MPS_POLARITY_ACTIVE_LOW: u16 = 0b11;
+const MPS_TRIGGER_MASK: u16 = 0b11 << 2;
+const MPS_TRIGGER_LEVEL: u16 = 0b11 << 2;
+
+#[derive(Clone, Copy, Debug)]
+pub struct IoApicEntry {
+    pub id: u8,
+    pub paddr: u32,
+    /// The first global system interrupt connected to the I/O APIC's inputs
+    pub gsi_base: u32,
+}
+
+/// How a legacy ISA IRQ is connected to an I/O APIC input
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IsaIrqRoute {
+    pub gsi: u32,
+    pub is_active_low: bool,
+    pub is_level_triggered: bool,
+}
+
+struct Madt {
+    io_apics: Vec<IoApicEntry>,
+    /// ISA IRQs that are not connected to the I/O APIC input with their own number
+    overrides: Vec<(u8, IsaIrqRoute)>,
+    has_legacy_pics: bool,
+}
+
+static MADT: Lazy<Madt> = Lazy::new(read_madt);
+
+/// The I/O APICs of the system or an empty slice if there is no MADT
+pub fn get_io_apics() -> &'static [IoApicEntry] {
+    &MADT.io_apics
+}
+
+/// How the given ISA IRQ is connected. IRQs without an override are edge triggered and active
+/// high on the input with their own number.
+pub fn get_isa_irq_route(irq: u8) -> IsaIrqRoute {
+    MADT.overrides.iter().find(|(source, _)| *source == irq).map(|(_, route)| *route).unwrap_or(
+        IsaIrqRoute {
+            gsi: irq as u32,
+            is_active_low: false,
+            is_level_triggered: false,
+        },
+    )
+}
+
+/// Whether the 8259 PICs are present and have to be masked before the I/O APICs are used
+pub fn has_legacy_pics() -> bool {
+    MADT.has_legacy_pics
+}
+
+fn read_madt() -> Madt {
+    let mut madt = Madt {
+        io_apics: Vec::new(),
+        overrides: Vec::new(),
+        has_legacy_pics: false,
+    };
+    let Some(table) = find_table(MADT_SIGNATURE) else {
+        return madt;
+    };
+    let table_len = table.read::<u32>(4) as usize;
+    madt.has_legacy_pics = table.read::<u32>(SDT_HEADER_LEN + 4) & MADT_FLAG_PCAT_COMPAT != 0;
+    let mut offset = MADT_ENTRIES_OFFSET;
+    while offset + 2 <= table_len {
+        let entry_type = table.read::<u8>(offset);
+        let entry_len = table.read::<u8>(offset + 1) as usize;
+        if entry_len < 2 || offset + entry_len > table_len {
+            break;
+        }
+        match entry_type {
+            ENTRY_TYPE_IO_APIC if entry_len >= 12 => madt.io_apics.push(IoApicEntry {
+                id: table.read::<u8>(offset + 2),
+                paddr: table.read::<u32>(offset + 4),
+                gsi_base: table.read::<u32>(offset + 8),
+            }),
+            ENTRY_TYPE_INTERRUPT_SOURCE_OVERRIDE if entry_len >= 10 => {
+                let flags = table.read::<u16>(offset + 8);
+                madt.overrides.push((
+                    table.read::<u8>(offset + 3),
+                    IsaIrqRoute {
+                        gsi: table.read::<u32>(offset + 4),
+                        is_active_low: flags & MPS_POLARITY_MASK == MPS_POLARITY_ACTIVE_LOW,
+                        is_level_triggered: flags & MPS_TRIGGER_MASK == MPS_TRIGGER_LEVEL,
+                    },
+                ));
+            }
+            _ => {}
+        }
+        offset += entry_len;
+    }
+    madt
+}
@@ -1 +1,5 @@
+pub mod cst;
+pub mod madt;
+pub mod sdt;
+pub mod srat;
 pub mod uacpi_kernel;
@@ -0,0 +1,83 @@
+//! # System Description Tables
+//!
+//! Finds static ACPI tables by walking the root system description table from the RSDP that the
+//! bootloader provides. This works before uACPI is initialized, so the tables that the kernel
+//! needs while it brings up its LPs and interrupt controllers are read with it.
+
+use core::ffi::c_void;
+
+use crate::environment::acpi::uacpi_kernel::{uacpi_kernel_map, uacpi_kernel_unmap};
+use crate::environment::boot_protocol::limine::RSDP_REQUEST;
+
+/// The length of the RSDP up to and including the XSDT address
+const RSDP_LEN: usize = 32;
+/// The length of the header that all system description tables start with
+pub const SDT_HEADER_LEN: usize = 36;
+
+/// A physical memory range mapped into the kernel address space for as long as it lives
+pub struct PhysMapping {
+    ptr: *const u8,
+    len: usize,
+}
+
+impl PhysMapping {
+    pub fn new(paddr: u64, len: usize) -> Option<Self> {
+        let ptr = uacpi_kernel_map(paddr, len) as *const u8;
+        (!ptr.is_null()).then_some(PhysMapping {
+            ptr,
+            len,
+        })
+    }
+
+    pub fn read<T: Copy>(&self, offset: usize) -> T {
+        assert!(offset + size_of::<T>() <= self.len);
+        unsafe { self.ptr.add(offset).cast::<T>().read_unaligned() }
+    }
+}
+
+impl Drop for PhysMapping {
+    fn drop(&mut self) {
+        uacpi_kernel_unmap(self.ptr as *mut c_void, self.len);
+    }
+}
+
+/// Map the whole system description table at the given physical address
+fn map_table(paddr: u64) -> Option<PhysMapping> {
+    let len = PhysMapping::new(paddr, SDT_HEADER_LEN)?.read::<u32>(4) as usize;
+    PhysMapping::new(paddr, len.max(SDT_HEADER_LEN))
+}
+
+/// Find the first table with the given signature among the tables listed by the XSDT or, on
+/// ACPI 1.0 systems, the RSDT
+pub fn find_table(signature: &[u8; 4]) -> Option<PhysMapping> {
+    let rsdp_paddr = RSDP_REQUEST.get_response()?.address() as u64;
+    let (root_paddr, entry_len) = {
+        let rsdp = PhysMapping::new(rsdp_paddr, RSDP_LEN)?;
+        let revision = rsdp.read::<u8>(15);
+        if revision >= 2 && rsdp.read::<u64>(24) != 0 {
+            (rsdp.read::<u64>(24), size_of::<u64>())
+        } else {
+            (rsdp.read::<u32>(16) as u64, size_of::<u32>())
+        }
+    };
+    let root = map_table(root_paddr)?;
+    let root_len = root.read::<u32>(4) as usize;
+    (SDT_HEADER_LEN..root_len)
+        .step_by(entry_len)
+        .take_while(|offset| offset + entry_len <= root_len)
+        .map(|offset| {
+            if entry_len == size_of::<u64>() {
+                root.read::<u64>(offset)
+            } else {
+                root.read::<u32>(offset) as u64
+            }
+        })
+        .find_map(|table_paddr| {
+            let header = PhysMapping::new(table_paddr, SDT_HEADER_LEN)?;
+            if header.read::<[u8; 4]>(0) != *signature {
+                return None;
+            }
+            drop(header);
+            map_table(table_paddr)
+        })
+}
@@ -0,0 +1,69 @@
+//! # System Resource Affinity Table
+//!
+//! The SRAT assigns each LP to a proximity domain, i.e. a NUMA node. It is found by walking the
+//! root system description table, so it can be read before uACPI is initialized. Domains are only
+//! read for the local APIC and local x2APIC entries, so this is specific to x86_64.
+
+use alloc::collections::btree_map::BTreeMap;
+
+use spin::Lazy;
+
+use super::sdt::find_table;
+
+/// The SRAT header is followed by reserved fields before its first entry
+const SRAT_ENTRIES_OFFSET: usize = 48;
+const SRAT_SIGNATURE: &[u8; 4] = b"SRAT";
+const ENTRY_TYPE_LOCAL_APIC: u8 = 0;
+const ENTRY_TYPE_LOCAL_X2APIC: u8 = 2;
+/// The entry flag that marks the LP as present
+const ENTRY_FLAG_ENABLED: u32 = 1;
+
+/// The proximity domain of each LP that the SRAT describes, by x2APIC ID. This is first read while
+/// the BSP registers with the scheduler, before any other LP is started.
+static PROXIMITY_DOMAINS: Lazy<BTreeMap<u32, u32>> = Lazy::new(read_proximity_domains);
+
+/// The proximity domain of the LP with the given x2APIC ID or `None` if there is no SRAT or it
+/// does not describe the LP
+pub fn get_proximity_domain(x2apic_id: u32) -> Option<u32> {
+    PROXIMITY_DOMAINS.get(&x2apic_id).copied()
+}
+
+fn read_proximity_domains() -> BTreeMap<u32, u32> {
+    let mut domains = BTreeMap::new();
+    if let Some(srat) = find_table(SRAT_SIGNATURE) {
+        let srat_len = srat.read::<u32>(4) as usize;
+        let mut offset = SRAT_ENTRIES_OFFSET;
+        while offset + 2 <= srat_len {
+            let entry_type = srat.read::<u8>(offset);
+            let entry_len = srat.read::<u8>(offset + 1) as usize;
+            if entry_len < 2 || offset + entry_len > srat_len {
+                break;
+            }
+            match entry_type {
+                ENTRY_TYPE_LOCAL_APIC if entry_len >= 16 => {
+                    let flags = srat.read::<u32>(offset + 4);
+                    if flags & ENTRY_FLAG_ENABLED != 0 {
+                        // the low byte of the domain is separate from its upper three bytes
+                        let high = srat.read::<[u8; 3]>(offset + 9);
+                        let domain = u32::from_le_bytes([
+                            srat.read::<u8>(offset + 2),
+                            high[0],
+                            high[1],
+                            high[2],
+                        ]);
+                        domains.insert(srat.read::<u8>(offset + 3) as u32, domain);
+                    }
+                }
+                ENTRY_TYPE_LOCAL_X2APIC if entry_len >= 24 => {
+                    let flags = srat.read::<u32>(offset + 12);
+                    if flags & ENTRY_FLAG_ENABLED != 0 {
+                        domains.insert(srat.read::<u32>(offset + 8), srat.read::<u32>(offset + 4));
+                    }
+                }
+                _ => {}
+            }
+            offset += entry_len;
+        }
+    }
+    domains
+}
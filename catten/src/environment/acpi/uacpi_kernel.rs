@@ -11,11 +11,13 @@ use uacpi_raw::*;
 
 use crate::cpu::isa::interface::memory::address::VirtualAddress;
 use crate::cpu::isa::memory::paging::PAGE_SIZE;
+use crate::cpu::multiprocessor::ipi;
+use crate::cpu::scheduler::deferred::work_queue;
 use crate::environment::boot_protocol::limine::RSDP_REQUEST;
-use crate::log;
 use crate::memory::linear::address_map::{LA_MAP, RegionType};
 use crate::memory::linear::{MemoryMapping, PageType};
-use crate::memory::{AddressSpaceInterface, KERNEL_AS, PAddr, VAddr};
+use crate::memory::{AddressSpaceInterface, KERNEL_AS, KERNEL_ASID, PAddr, VAddr};
+use crate::{log, logln};
 
 #[allow(unused)]
 #[unsafe(no_mangle)]
@@ -68,6 +70,7 @@ pub extern "C" fn uacpi_kernel_unmap(mapped_addr: *mut c_void, len: uacpi_size)
     for va in (corrected_lin_addr..corrected_lin_addr + corrected_len).step_by(PAGE_SIZE) {
         kas.unmap_page(va);
     }
+    ipi::flush_tlb(KERNEL_ASID, corrected_lin_addr, corrected_len as usize / PAGE_SIZE);
 }
 
 #[allow(unused)]
@@ -85,3 +88,56 @@ pub extern "C" fn uacpi_kernel_log(ll: uacpi_log_level, cstr: *const uacpi_char)
     };
     log!("{} UACPI: {}", prefix, rust_str);
 }
+
+/// Work scheduled by uACPI together with its context
+struct AcpiWork {
+    handler: unsafe extern "C" fn(uacpi_handle),
+    ctx: uacpi_handle,
+}
+
+// SAFETY: uACPI expects its work to be run on another thread than the one that scheduled it.
+unsafe impl Send for AcpiWork {}
+
+#[allow(unused)]
+#[unsafe(no_mangle)]
+pub extern "C" fn uacpi_kernel_schedule_work(
+    work_type: uacpi_work_type,
+    handler: uacpi_work_handler,
+    ctx: uacpi_handle,
+) -> uacpi_status {
+    let Some(handler) = handler else {
+        return uacpi_status_UACPI_STATUS_INVALID_ARGUMENT;
+    };
+    let acpi_work = AcpiWork {
+        handler,
+        ctx,
+    };
+    let work = move || {
+        let acpi_work = acpi_work;
+        unsafe { (acpi_work.handler)(acpi_work.ctx) }
+    };
+    // uACPI only schedules work from thread context since no SCI handler is installed, which
+    // queueing work requires
+    let result = match work_type {
+        // GPE methods are run on the BSP, which is always LP 0, since firmware may rely on it
+        uacpi_work_type_UACPI_WORK_GPE_EXECUTION => work_queue::queue_work_on(0, work),
+        _ => work_queue::queue_work(work),
+    };
+    match result {
+        Ok(_) => uacpi_status_UACPI_STATUS_OK,
+        Err(err) => {
+            logln!("UACPI: Failed to schedule work: {:?}", err);
+            uacpi_status_UACPI_STATUS_INTERNAL_ERROR
+        }
+    }
+}
+
+#[allow(unused)]
+#[unsafe(no_mangle)]
+pub extern "C" fn uacpi_kernel_wait_for_work_completion() -> uacpi_status {
+    let queues = [work_queue::get_per_lp_work_queue(), work_queue::get_system_work_queue()];
+    for queue in queues.into_iter().flatten() {
+        queue.flush();
+    }
+    uacpi_status_UACPI_STATUS_OK
+}
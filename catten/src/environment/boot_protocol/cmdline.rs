@@ -0,0 +1,22 @@
+//! # Kernel Command Line
+//!
+//! Boot options are passed on the kernel command line as whitespace separated `name=value` pairs or
+//! as bare flags.
+
+use crate::environment::boot_protocol::limine::EXECUTABLE_CMDLINE_REQUEST;
+
+/// The command line the kernel was booted with or an empty one if the bootloader provided none
+pub fn get_cmdline() -> &'static str {
+    EXECUTABLE_CMDLINE_REQUEST
+        .get_response()
+        .and_then(|response| response.cmdline().to_str().ok())
+        .unwrap_or("")
+}
+
+/// The value of the boot option with the given name. Flags without a value have an empty one.
+pub fn get_option(name: &str) -> Option<&'static str> {
+    get_cmdline().split_whitespace().find_map(|option| {
+        let (option_name, value) = option.split_once('=').unwrap_or((option, ""));
+        (option_name == name).then_some(value)
+    })
+}
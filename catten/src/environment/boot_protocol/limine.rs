@@ -1,6 +1,7 @@
 use limine::BaseRevision;
 use limine::request::{
     ExecutableAddressRequest,
+    ExecutableCmdlineRequest,
     FramebufferRequest,
     HhdmRequest,
     MemoryMapRequest,
@@ -16,6 +17,7 @@ pub static BASE_REVISION: BaseRevision = BaseRevision::new();
 pub static MEMORY_MAP_REQUEST: MemoryMapRequest = MemoryMapRequest::new();
 pub static HHDM_REQUEST: HhdmRequest = HhdmRequest::new();
 pub static EXECUTABLE_ADDRESS_REQUEST: ExecutableAddressRequest = ExecutableAddressRequest::new();
+pub static EXECUTABLE_CMDLINE_REQUEST: ExecutableCmdlineRequest = ExecutableCmdlineRequest::new();
 pub static FRAMEBUFFER_REQUEST: FramebufferRequest = FramebufferRequest::new();
 pub static SMP_REQUEST: MpRequest = MpRequest::new();
 pub static RSDP_REQUEST: RsdpRequest = RsdpRequest::new();
@@ -12,4 +12,5 @@
 //! support on a given device please contact the manufacturer and request that they provide support
 //! for ARM SystemReady compliant firmware.
 
+pub mod cmdline;
 pub mod limine;
@@ -22,7 +22,7 @@
 //!   As such we do not provide a separate module for SMM calls.
 
 // Advanced Configuration and Power Interface (ACPI)
-mod acpi;
+pub mod acpi;
 pub mod boot_protocol;
 // Device Tree
 #[cfg(not(target_arch = "x86_64"))]
@@ -1,28 +1,146 @@
 //! # Event Subsystem
+//!
+//! Events notify the observers registered with them when they are raised. Observers are shared
+//! through `Arc`s and only take `&self` when notified, so they keep their state in atomics and can
+//! be notified on any LP, including from interrupt handlers.
+
+pub mod wait;
+
+use alloc::collections::vec_deque::VecDeque;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use spin::Mutex;
+
+use crate::cpu::isa::lp::ops::without_interrupts;
 
 pub trait Event {
-    fn register_observer(&mut self, observer: &dyn Observer);
+    /// Register an observer to be notified each time the event is raised. The event keeps its own
+    /// reference to the observer.
+    fn register_observer(&mut self, observer: Arc<dyn Observer>);
+}
+
+pub trait Observer: Send + Sync {
+    fn notify(&self);
 }
 
-pub trait Observer {
-    fn notify(&mut self);
+/// An event that can be raised from any context, including interrupt handlers
+///
+/// Its observers form a wait queue and are notified in the order they were registered. Each
+/// observer is only notified once and has to be registered again to be notified the next time.
+/// Persistent observers, e.g. a `Sentinel` counting the edges of an interrupt, stay registered
+/// instead and are notified each time the event is raised. Both are only ever locked with
+/// interrupts masked.
+pub struct EventSource {
+    observers: Mutex<VecDeque<Arc<dyn Observer>>>,
+    persistent_observers: Mutex<Vec<Arc<dyn Observer>>>,
 }
 
+impl EventSource {
+    pub const fn new() -> Self {
+        EventSource {
+            observers: Mutex::new(VecDeque::new()),
+            persistent_observers: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn register(&self, observer: Arc<dyn Observer>) {
+        without_interrupts(|| self.observers.lock().push_back(observer));
+    }
+
+    /// Register an observer that stays registered until it is unregistered. It is notified while
+    /// the persistent observers are locked, so it must not register with or unregister from this
+    /// source while being notified.
+    pub fn register_persistent(&self, observer: Arc<dyn Observer>) {
+        without_interrupts(|| self.persistent_observers.lock().push(observer));
+    }
+
+    /// Remove an observer that has not been notified yet or a persistent observer
+    pub fn unregister(&self, observer: &Arc<dyn Observer>) {
+        without_interrupts(|| {
+            self.observers.lock().retain(|registered| !Arc::ptr_eq(registered, observer));
+            self.persistent_observers
+                .lock()
+                .retain(|registered| !Arc::ptr_eq(registered, observer));
+        });
+    }
+
+    /// Notify all registered observers. Returns how many there were.
+    pub fn raise(&self) -> usize {
+        let persistent_count = without_interrupts(|| {
+            let persistent_observers = self.persistent_observers.lock();
+            for observer in persistent_observers.iter() {
+                observer.notify();
+            }
+            persistent_observers.len()
+        });
+        let observers = without_interrupts(|| core::mem::take(&mut *self.observers.lock()));
+        let count = observers.len();
+        for observer in observers {
+            observer.notify();
+        }
+        persistent_count + count
+    }
+
+    /// Notify the observer that was registered first, leaving out the persistent ones. Returns
+    /// whether there was one.
+    pub fn raise_one(&self) -> bool {
+        let observer = without_interrupts(|| self.observers.lock().pop_front());
+        let is_raised = observer.is_some();
+        if let Some(observer) = observer {
+            observer.notify();
+        }
+        is_raised
+    }
+}
+
+impl core::fmt::Debug for EventSource {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("EventSource").finish_non_exhaustive()
+    }
+}
+
+impl Event for EventSource {
+    fn register_observer(&mut self, observer: Arc<dyn Observer>) {
+        self.register(observer);
+    }
+}
+
+/// Completed by the first notification and never reset
 pub struct Completion {
-    completed: bool,
-    callback:  Option<fn()>,
+    is_completed: AtomicBool,
+    callback: Option<fn()>,
 }
 
 impl Completion {
-    pub fn new(callback: Option<fn()>) -> Self {
+    pub const fn new(callback: Option<fn()>) -> Self {
         Completion {
-            completed: false,
+            is_completed: AtomicBool::new(false),
             callback,
         }
     }
 
     pub fn poll(&self) -> bool {
-        self.completed
+        self.is_completed.load(Ordering::Acquire)
+    }
+
+    /// Complete it and run the callback unless it has been completed already. Returns whether
+    /// this call completed it.
+    pub fn complete(&self) -> bool {
+        let is_completed_now = !self.is_completed.swap(true, Ordering::AcqRel);
+        if is_completed_now && let Some(cb) = self.callback {
+            cb();
+        }
+        is_completed_now
+    }
+
+    /// Spin until it is complete. A thread that is blocked on the completion is switched away from
+    /// while spinning and only returns once it has been woken.
+    pub fn spin_until_complete(&self) {
+        while !self.poll() {
+            core::hint::spin_loop();
+        }
     }
 
     pub fn register_callback(&mut self, callback: fn()) {
@@ -31,29 +149,37 @@ impl Completion {
 }
 
 impl Observer for Completion {
-    fn notify(&mut self) {
-        self.completed = true;
-        if let Some(cb) = self.callback {
-            cb();
-        }
+    fn notify(&self) {
+        self.complete();
     }
 }
 
+/// Counts the edges of an event, e.g. the interrupts of a device. It has to be registered as a
+/// persistent observer to see every edge.
 pub struct Sentinel {
-    times_notified: u64,
+    times_notified: AtomicU64,
+    /// The count up to which `take_new_edges` has reported edges
+    times_taken: AtomicU64,
     callback: Option<fn()>,
 }
 
 impl Sentinel {
-    pub fn new(callback: Option<fn()>) -> Self {
+    pub const fn new(callback: Option<fn()>) -> Self {
         Sentinel {
-            times_notified: 0,
+            times_notified: AtomicU64::new(0),
+            times_taken: AtomicU64::new(0),
             callback,
         }
     }
 
     pub fn get_times_notified(&self) -> u64 {
-        self.times_notified
+        self.times_notified.load(Ordering::Acquire)
+    }
+
+    /// The number of edges since the last call
+    pub fn take_new_edges(&self) -> u64 {
+        let times_notified = self.get_times_notified();
+        times_notified.saturating_sub(self.times_taken.fetch_max(times_notified, Ordering::AcqRel))
     }
 
     pub fn register_callback(&mut self, callback: fn()) {
@@ -62,8 +188,8 @@ impl Sentinel {
 }
 
 impl Observer for Sentinel {
-    fn notify(&mut self) {
-        self.times_notified += 1;
+    fn notify(&self) {
+        self.times_notified.fetch_add(1, Ordering::AcqRel);
         if let Some(cb) = self.callback {
             cb();
         }
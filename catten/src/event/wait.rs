@@ -0,0 +1,166 @@
+//! # Waiting on Several Events
+//!
+//! The calling thread is blocked on completions that observers registered with the event sources
+//! complete when they are notified. Waiting on any of the events shares one completion between
+//! all of them while waiting on all of them gives each its own. A timeout completes all of them.
+
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use super::{Completion, EventSource, Observer};
+use crate::common::time::duration::ExtDuration;
+use crate::cpu::isa::timers::get_monotonic_time;
+use crate::cpu::scheduler::hrtimer;
+use crate::cpu::scheduler::preemption::PreemptGuard;
+use crate::cpu::scheduler::system_scheduler::{self, SYSTEM_SCHEDULER};
+use crate::cpu::scheduler::threads::ThreadId;
+
+/// Marks the first notification of a wait as the timeout rather than one of its events
+const TIMED_OUT: usize = usize::MAX;
+const NONE_RAISED: usize = usize::MAX - 1;
+
+#[derive(Debug)]
+pub enum Error {
+    /// Only threads can wait on events
+    NotOnThread,
+    /// Waiting on any of no sources without a timeout would never return
+    NoSources,
+    SchedulerError(system_scheduler::Error),
+}
+
+impl From<system_scheduler::Error> for Error {
+    fn from(err: system_scheduler::Error) -> Self {
+        Error::SchedulerError(err)
+    }
+}
+
+/// What happened during a wait
+struct Outcome {
+    /// The index of the event that was raised first or one of the markers
+    first:  AtomicUsize,
+    /// The number of events that were raised before the timeout
+    raised: AtomicUsize,
+}
+
+/// Registered with one of the event sources of a wait or as its timeout
+struct WaitObserver {
+    tid: ThreadId,
+    /// The index of the event source or `TIMED_OUT`
+    index: usize,
+    /// The completions this observer completes
+    completions: Vec<Arc<Completion>>,
+    outcome: Arc<Outcome>,
+}
+
+impl Observer for WaitObserver {
+    fn notify(&self) {
+        let mut is_new = false;
+        for completion in &self.completions {
+            is_new |= completion.complete();
+        }
+        if !is_new {
+            return;
+        }
+        if self.index != TIMED_OUT {
+            self.outcome.raised.fetch_add(1, Ordering::AcqRel);
+        }
+        let _ = self.outcome.first.compare_exchange(
+            NONE_RAISED,
+            self.index,
+            Ordering::AcqRel,
+            Ordering::Acquire,
+        );
+        SYSTEM_SCHEDULER.try_unblock(self.tid);
+    }
+}
+
+/// Wait until any of the sources is raised or the timeout has passed. Returns the index of the
+/// source that was raised first or `None` on timeout.
+///
+/// Waiting on no sources at all with a timeout sleeps for that long and without one is an error.
+pub fn wait_any(
+    sources: &[&EventSource],
+    timeout: Option<ExtDuration>,
+) -> Result<Option<usize>, Error> {
+    if sources.is_empty() && timeout.is_none() {
+        return Err(Error::NoSources);
+    }
+    let outcome = wait(sources, timeout, false)?;
+    Ok(match outcome.first.load(Ordering::Acquire) {
+        TIMED_OUT | NONE_RAISED => None,
+        index => Some(index),
+    })
+}
+
+/// Wait until all of the sources have been raised or the timeout has passed. Returns whether all
+/// of them were raised in time.
+pub fn wait_all(sources: &[&EventSource], timeout: Option<ExtDuration>) -> Result<bool, Error> {
+    if sources.is_empty() {
+        return Ok(true);
+    }
+    let outcome = wait(sources, timeout, true)?;
+    Ok(outcome.raised.load(Ordering::Acquire) == sources.len())
+}
+
+fn wait(
+    sources: &[&EventSource],
+    timeout: Option<ExtDuration>,
+    is_all: bool,
+) -> Result<Arc<Outcome>, Error> {
+    let tid = SYSTEM_SCHEDULER.get_current_tid().ok_or(Error::NotOnThread)?;
+    let deadline = timeout.map(|timeout| get_monotonic_time() + timeout);
+    let outcome = Arc::new(Outcome {
+        first:  AtomicUsize::new(NONE_RAISED),
+        raised: AtomicUsize::new(0),
+    });
+    let completion_count = if is_all {
+        sources.len()
+    } else {
+        1
+    };
+    let completions: Vec<_> =
+        (0..completion_count).map(|_| Arc::new(Completion::new(None))).collect();
+    let observers: Vec<Arc<dyn Observer>> = (0..sources.len())
+        .map(|index| {
+            // waiting on any of the sources shares the first completion
+            let completion = completions.get(index).unwrap_or(&completions[0]);
+            Arc::new(WaitObserver {
+                tid,
+                index,
+                completions: alloc::vec![completion.clone()],
+                outcome: outcome.clone(),
+            }) as Arc<dyn Observer>
+        })
+        .collect();
+    let timer;
+    {
+        // The thread must be blocked before the observers can be notified and the LP must not
+        // switch away from it before they are all registered.
+        let _preempt_guard = PreemptGuard::new();
+        SYSTEM_SCHEDULER.block_on(tid, completions.clone())?;
+        for (source, observer) in sources.iter().zip(&observers) {
+            source.register(observer.clone());
+        }
+        timer = deadline.map(|deadline| {
+            let observer = WaitObserver {
+                tid,
+                index: TIMED_OUT,
+                completions: completions.clone(),
+                outcome: outcome.clone(),
+            };
+            hrtimer::arm_at(deadline, move || observer.notify())
+        });
+    }
+    // the LP switches away now that preemption is enabled again
+    for completion in &completions {
+        completion.spin_until_complete();
+    }
+    if let Some(timer) = timer {
+        hrtimer::cancel(&timer);
+    }
+    for (source, observer) in sources.iter().zip(&observers) {
+        source.unregister(observer);
+    }
+    Ok(outcome)
+}
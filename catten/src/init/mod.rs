@@ -1,8 +1,20 @@
 //! # Initialization Module
 
+use alloc::boxed::Box;
+
 use crate::cpu::isa::init::IsaInitializer;
 use crate::cpu::isa::interface::init::InitInterface;
+use crate::cpu::isa::interface::interrupts::LocalIntCtlrIfce;
+use crate::cpu::isa::interrupts::LocalIntCtlr;
 use crate::cpu::isa::lp;
+use crate::cpu::multiprocessor::ipi;
+use crate::cpu::multiprocessor::lp::LogicalProcessor;
+use crate::cpu::scheduler::deferred::softirq;
+use crate::cpu::scheduler::lp_schedulers::gang::GangScheduling;
+use crate::cpu::scheduler::lp_schedulers::strategy::RoundRobin;
+use crate::cpu::scheduler::sync::{lockdep, rcu};
+use crate::cpu::scheduler::system_scheduler::SYSTEM_SCHEDULER;
+use crate::cpu::scheduler::{idle, preemption};
 use crate::logln;
 use crate::memory::PHYSICAL_FRAME_ALLOCATOR;
 use crate::memory::allocators::global_allocator::init_primary_allocator;
@@ -29,6 +41,15 @@ pub fn bsp_init() {
     logln!("Initializing kernel allocator...");
     init_primary_allocator();
     logln!("Intialized kernel allocator.");
+    logln!("Initializing the per-LP scheduling state...");
+    // every LP uses these as soon as it registers, so they are set up once before any does
+    preemption::init();
+    ipi::init();
+    rcu::init();
+    softirq::init();
+    idle::init();
+    lockdep::init();
+    init_lp_scheduling();
     logln!("ISA independent initialization complete.");
     logln!("BSP initialization complete.");
 }
@@ -44,5 +65,20 @@ pub fn ap_init() {
             panic!("LP {}: ISA specific initialization failed: {:?}", lp_id, e);
         }
     }
+    init_lp_scheduling();
     logln!("LP{}: ISA independent initialization complete.", lp_id);
 }
+
+/// Set up the calling LP's local area, make the LP reachable by IPIs and give it a local scheduler
+fn init_lp_scheduling() {
+    let lp_id = lp::ops::get_lp_id();
+    LogicalProcessor::setup(lp_id);
+    LocalIntCtlr::init_local();
+    if let Err(e) =
+        SYSTEM_SCHEDULER.register_lp(Box::new(GangScheduling::new(Box::new(RoundRobin::new()))))
+    {
+        // an LP without a local scheduler has nothing to run
+        panic!("LP {}: Failed to register the local scheduler: {:?}", lp_id, e);
+    }
+    logln!("LP {}: Registered the local scheduler.", lp_id);
+}
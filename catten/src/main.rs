@@ -44,13 +44,23 @@ use limine::mp::Cpu;
 use spin::{Barrier, Lazy};
 
 use crate::cpu::isa::interface::system_info::CpuInfoIfce;
+#[cfg(target_arch = "x86_64")]
+use crate::cpu::isa::interrupts::ioapic;
 use crate::cpu::isa::lp::ops::get_lp_id;
 use crate::cpu::isa::system_info::CpuInfo;
 #[cfg(target_arch = "x86_64")]
 use crate::cpu::isa::timers::tsc::{IS_TSC_INVARIANT, TSC_CYCLE_PERIOD, TSC_FREQUENCY_HZ};
 use crate::cpu::multiprocessor::get_lp_count;
 use crate::cpu::multiprocessor::startup::{assign_id, start_secondary_lps};
+#[cfg(target_arch = "x86_64")]
+use crate::cpu::scheduler::idle;
+use crate::cpu::scheduler::sync::rcu;
 use crate::cpu::scheduler::system_scheduler::SYSTEM_SCHEDULER;
+use crate::cpu::scheduler::{deferred, executor};
+#[cfg(target_arch = "x86_64")]
+use crate::drivers::uart::ns16550;
+#[cfg(target_arch = "x86_64")]
+use crate::environment::acpi::cst;
 
 const KERNEL_VERSION: (u64, u64, u64) = (0, 3, 5);
 static INIT_BARRIER: Lazy<Barrier> = Lazy::new(|| Barrier::new(get_lp_count() as usize));
@@ -76,6 +86,33 @@ pub extern "C" fn bsp_main() -> ! {
     logln!("Starting secondary LPs...");
     start_secondary_lps().expect("Failed to start secondary LPs");
     INIT_BARRIER.wait();
+    logln!("Starting the thread reaper...");
+    SYSTEM_SCHEDULER.start_reaper();
+    logln!("Starting the RCU callback thread...");
+    rcu::start_callback_thread();
+    #[cfg(target_arch = "x86_64")]
+    {
+        logln!("Masking the legacy PICs and the I/O APIC inputs...");
+        ioapic::init();
+        logln!("Routing the log port's interrupt...");
+        if let Err(err) = ns16550::enable_log_port_irq() {
+            logln!("Reads from the log port poll it since its IRQ could not be routed: {:?}", err);
+        }
+        logln!("Reading the processor power states...");
+        match cst::read_cstates() {
+            Ok(cstates) => {
+                logln!("Found {} usable processor power states.", (cstates.len()));
+                idle::set_cstates(cstates);
+            }
+            Err(err) => {
+                logln!("Idle LPs only enter C1 since the power states are unknown: {:?}", err)
+            }
+        }
+    }
+    logln!("Starting the softirq threads and work queues...");
+    deferred::start();
+    logln!("Starting the async executors...");
+    executor::start_executors();
     self_test::run_self_tests();
     #[cfg(target_arch = "x86_64")]
     {
@@ -1,21 +1,99 @@
+use alloc::sync::Arc;
+use core::arch::asm;
 use core::mem::MaybeUninit;
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
 
-use spin::Mutex;
 use talc::*;
 
 use crate::common::size::mebibytes;
-use crate::cpu::isa::interface::memory::address::VirtualAddress;
+use crate::cpu::isa::interface::memory::address::{Address, VirtualAddress};
+use crate::cpu::isa::lp::LpId;
+use crate::cpu::isa::lp::ops::{
+    are_interrupts_enabled,
+    get_lp_id,
+    mask_interrupts,
+    unmask_interrupts,
+};
 use crate::cpu::isa::memory::paging::PAGE_SIZE;
-use crate::memory::allocators::memory::try_allocate_and_map_range;
+use crate::cpu::multiprocessor::ipi::service_own_mailbox;
+use crate::memory::KERNEL_AS;
+use crate::memory::allocators::memory::{
+    try_allocate_and_map_range,
+    unmap_and_deallocate_range_in,
+};
 use crate::memory::linear::VAddr;
 use crate::memory::linear::address_map::LA_MAP;
 use crate::memory::linear::address_map::RegionType::KernelStackArena;
+use crate::memory::reclaim::{self, Shrinker};
 
 const INITIAL_HEAP_SIZE: usize = mebibytes(2);
 #[global_allocator]
-pub static PRIMARY_ALLOCATOR: Talck<Mutex<()>, ExtendOnOom> =
+pub static PRIMARY_ALLOCATOR: Talck<HeapLock, ExtendOnOom> =
     Talck::new(Talc::new(ExtendOnOom::new()));
 
+/// The LP that holds the heap lock or `NO_OWNER`. There is only one heap, so this is kept outside
+/// of the lock, which talc does not give access to.
+static HEAP_OWNER: AtomicU32 = AtomicU32::new(NO_OWNER);
+/// Whether interrupts were enabled on the owner of the heap lock when it took the lock
+static WERE_INTERRUPTS_ENABLED: AtomicBool = AtomicBool::new(false);
+const NO_OWNER: LpId = LpId::MAX;
+
+/// The lock of the kernel heap. It masks interrupts while it is held so that interrupt handlers,
+/// including the context switch path, can allocate without spinning on a lock that the code they
+/// interrupted holds.
+pub struct HeapLock(spin::Mutex<()>);
+
+unsafe impl lock_api::RawMutex for HeapLock {
+    type GuardMarker = lock_api::GuardNoSend;
+
+    #[allow(clippy::declare_interior_mutable_const)]
+    const INIT: Self = HeapLock(spin::Mutex::new(()));
+
+    fn lock(&self) {
+        let were_interrupts_enabled = are_interrupts_enabled();
+        mask_interrupts!();
+        while !<spin::Mutex<()> as lock_api::RawMutex>::try_lock(&self.0) {
+            // the holder may be waiting for this LP to run an RPC such as a TLB shootdown
+            service_own_mailbox();
+            core::hint::spin_loop();
+        }
+        set_owner(were_interrupts_enabled);
+    }
+
+    fn try_lock(&self) -> bool {
+        let were_interrupts_enabled = are_interrupts_enabled();
+        mask_interrupts!();
+        if <spin::Mutex<()> as lock_api::RawMutex>::try_lock(&self.0) {
+            set_owner(were_interrupts_enabled);
+            return true;
+        }
+        if were_interrupts_enabled {
+            unmask_interrupts!();
+        }
+        false
+    }
+
+    unsafe fn unlock(&self) {
+        let were_interrupts_enabled = WERE_INTERRUPTS_ENABLED.load(Ordering::Relaxed);
+        HEAP_OWNER.store(NO_OWNER, Ordering::Relaxed);
+        unsafe { <spin::Mutex<()> as lock_api::RawMutex>::unlock(&self.0) };
+        if were_interrupts_enabled {
+            unmask_interrupts!();
+        }
+    }
+}
+
+fn set_owner(were_interrupts_enabled: bool) {
+    WERE_INTERRUPTS_ENABLED.store(were_interrupts_enabled, Ordering::Relaxed);
+    HEAP_OWNER.store(get_lp_id(), Ordering::Relaxed);
+}
+
+/// Whether the calling LP holds the heap lock, in which case it must not allocate
+pub fn is_heap_locked_by_current_lp() -> bool {
+    // the owner can not change under the LP holding the lock since it has interrupts masked
+    HEAP_OWNER.load(Ordering::Relaxed) == get_lp_id()
+}
+
 pub fn init_primary_allocator() {
     let base = LA_MAP.get_region(KernelStackArena).base;
     try_allocate_and_map_range(base, INITIAL_HEAP_SIZE / PAGE_SIZE)
@@ -27,6 +105,7 @@ pub fn init_primary_allocator() {
             pa_lock.claim(span).expect("Talc failed to claim the initial kernel heap");
         pa_lock.oom_handler.heap_span.write(returned_span);
     }
+    reclaim::register_shrinker(Arc::new(HeapTailShrinker));
 }
 
 pub struct ExtendOnOom {
@@ -63,3 +142,62 @@ impl OomHandler for ExtendOnOom {
         }
     }
 }
+
+/// Gives whole pages at the end of the kernel heap that hold no allocations back to the frame
+/// allocator. The initial heap is never shrunk.
+struct HeapTailShrinker;
+
+impl HeapTailShrinker {
+    /// Returns the current end of the heap and the lowest end it could be truncated to
+    fn truncation_bounds(talc: &Talc<ExtendOnOom>) -> (VAddr, VAddr, VAddr) {
+        let heap_span = unsafe { *talc.oom_handler.heap_span.assume_init_ref() };
+        let raw_span = heap_span.get_base_acme().unwrap();
+        let (base, acme) = (VAddr::from_ptr(raw_span.0), VAddr::from_ptr(raw_span.1));
+        let allocated_acme = match talc.get_allocated_span(heap_span).get_base_acme() {
+            Some((_, allocated_acme)) => VAddr::from_ptr(allocated_acme).next_aligned_to(PAGE_SIZE),
+            None => base,
+        };
+        (base, acme, core::cmp::max(allocated_acme, base + INITIAL_HEAP_SIZE))
+    }
+}
+
+impl Shrinker for HeapTailShrinker {
+    fn count_reclaimable(&self) -> usize {
+        // the heap is locked if it is the one asking for memory
+        let Some(talc) = PRIMARY_ALLOCATOR.try_lock() else {
+            return 0;
+        };
+        let (_, acme, min_acme) = Self::truncation_bounds(&talc);
+        (acme - min_acme).max(0) as usize / PAGE_SIZE
+    }
+
+    fn shrink(&self, n_frames: usize) -> usize {
+        // the allocation that triggered reclaim may be mapping into the kernel address space, so
+        // its lock is taken before the heap is truncated and the shrink is skipped if it is held
+        let Some(mut kas) = KERNEL_AS.try_lock() else {
+            return 0;
+        };
+        let Some(mut talc) = PRIMARY_ALLOCATOR.try_lock() else {
+            return 0;
+        };
+        let (base, acme, min_acme) = Self::truncation_bounds(&talc);
+        let requested_acme = core::cmp::max(min_acme, acme - n_frames * PAGE_SIZE);
+        if requested_acme >= acme {
+            return 0;
+        }
+        let old_span = Span::new(base.into_mut(), acme.into_mut());
+        let new_span = unsafe {
+            talc.truncate(old_span, Span::new(base.into_mut(), requested_acme.into_mut()))
+        };
+        unsafe {
+            *(talc.oom_handler.heap_span.assume_init_mut()) = new_span;
+        }
+        drop(talc);
+        // talc may keep a little more than requested
+        let new_acme =
+            VAddr::from_ptr(new_span.get_base_acme().unwrap().1).next_aligned_to(PAGE_SIZE);
+        let n_freed = (acme - new_acme).max(0) as usize / PAGE_SIZE;
+        unmap_and_deallocate_range_in(&mut kas, new_acme, n_freed);
+        n_freed
+    }
+}
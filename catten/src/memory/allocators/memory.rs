@@ -1,9 +1,17 @@
 use crate::cpu::isa::interface::memory::AddressSpaceInterface;
 use crate::cpu::isa::memory::paging::PAGE_SIZE;
+use crate::cpu::multiprocessor::ipi;
 use crate::logln;
 use crate::memory::linear::{MemoryMapping, PageType, VAddr};
 use crate::memory::physical::*;
-use crate::memory::{KERNEL_AS, PHYSICAL_FRAME_ALLOCATOR, physical};
+use crate::memory::{
+    AddressSpace,
+    KERNEL_AS,
+    KERNEL_ASID,
+    PHYSICAL_FRAME_ALLOCATOR,
+    physical,
+    reclaim,
+};
 
 #[derive(Debug)]
 pub enum Error {
@@ -24,8 +32,6 @@ impl From<crate::cpu::isa::memory::Error> for Error {
 }
 
 pub fn try_allocate_and_map_range(base: VAddr, num_pages: usize) -> Result<(), Error> {
-    // lock the kernel address space for writing
-    let mut kas = KERNEL_AS.lock();
     let mut mapping = MemoryMapping {
         vaddr: VAddr::default(),
         paddr: PAddr::default(),
@@ -34,11 +40,11 @@ pub fn try_allocate_and_map_range(base: VAddr, num_pages: usize) -> Result<(), E
     // allocate and map the pages
     // if mapping fails, deallocate and unmap the frames that were allocated
     for page_idx in 0..num_pages {
-        let frame = match PHYSICAL_FRAME_ALLOCATOR.lock().allocate_frame() {
+        // the kernel address space must not be locked while allocating since shrinkers may need
+        // to lock it in order to give memory back
+        let frame = match reclaim::allocate_zeroed_frame() {
             Ok(f) => f,
             Err(err) => {
-                // release the lock so the unmap_and_deallocate_range function can acquire it
-                drop(kas);
                 unmap_and_deallocate_range(base, page_idx);
                 return Err(Error::PfaError(err));
             }
@@ -46,11 +52,10 @@ pub fn try_allocate_and_map_range(base: VAddr, num_pages: usize) -> Result<(), E
         let vaddr = base + (page_idx * PAGE_SIZE) as isize;
         mapping.vaddr = vaddr;
         mapping.paddr = frame;
-        if let Err(err) = kas.map_page(mapping.clone()) {
-            // release the lock so the unmap_and_deallocate_range function can acquire it
-            drop(kas);
+        let map_result = KERNEL_AS.lock().map_page(mapping.clone());
+        if let Err(err) = map_result {
             // deallocate and unmap the frames that were allocated
-            unmap_and_deallocate_range(base, page_idx + 1);
+            unmap_and_deallocate_range(base, page_idx);
             // deallocate the frame that was just allocated
             if let Err(err) = PHYSICAL_FRAME_ALLOCATOR.lock().deallocate_frame(frame) {
                 logln!("Error deallocating frame at {frame:?} during cleanup: {err:?}");
@@ -62,15 +67,33 @@ pub fn try_allocate_and_map_range(base: VAddr, num_pages: usize) -> Result<(), E
 }
 
 pub fn unmap_and_deallocate_range(base: VAddr, num_pages: usize) {
-    let mut kas = KERNEL_AS.lock();
-    for page_idx in 0..num_pages {
-        let vaddr = base + (page_idx * PAGE_SIZE) as isize;
-        if let Ok(paddr) = kas.translate_address(vaddr) {
-            if let Err(err) = PHYSICAL_FRAME_ALLOCATOR.lock().deallocate_frame(paddr) {
-                logln!("Error deallocating frame at {paddr:?} during cleanup: {err:?}");
+    unmap_and_deallocate_range_in(&mut KERNEL_AS.lock(), base, num_pages);
+}
+
+/// Like `unmap_and_deallocate_range` for callers that already hold the kernel address space lock
+pub fn unmap_and_deallocate_range_in(kas: &mut AddressSpace, base: VAddr, num_pages: usize) {
+    // Frames are only freed once no LP can reach them through a stale translation. They are
+    // collected in fixed size batches since this also runs with the kernel heap locked.
+    const BATCH_SIZE: usize = 64;
+    for batch_start in (0..num_pages).step_by(BATCH_SIZE) {
+        let batch_base = base + (batch_start * PAGE_SIZE) as isize;
+        let batch_len = BATCH_SIZE.min(num_pages - batch_start);
+        let mut frames = [None; BATCH_SIZE];
+        for (page_idx, frame) in frames[..batch_len].iter_mut().enumerate() {
+            let vaddr = batch_base + (page_idx * PAGE_SIZE) as isize;
+            if !kas.is_mapped(vaddr).unwrap_or(false) {
+                continue;
+            }
+            match kas.unmap_page(vaddr) {
+                Ok(paddr) => *frame = Some(paddr),
+                Err(err) => logln!("Error unmapping vaddr {vaddr:?} during cleanup: {err:?}"),
             }
-            if let Err(err) = kas.unmap_page(vaddr) {
-                logln!("Error unmapping vaddr {vaddr:?} during cleanup: {err:?}");
+        }
+        ipi::flush_tlb(KERNEL_ASID, batch_base, batch_len);
+        let mut pfa = PHYSICAL_FRAME_ALLOCATOR.lock();
+        for paddr in frames.into_iter().flatten() {
+            if let Err(err) = pfa.deallocate_frame(paddr) {
+                logln!("Error deallocating frame at {paddr:?} during cleanup: {err:?}");
             }
         }
     }
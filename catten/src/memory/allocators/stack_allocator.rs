@@ -13,11 +13,12 @@
 use alloc::collections::BTreeSet;
 use core::ops::Bound::{Excluded, Unbounded};
 
-use spin::{Lazy, RwLock};
+use spin::Lazy;
 
 use super::memory;
 use crate::cpu::isa::memory::paging::PAGE_SIZE;
 use crate::cpu::isa::memory::{MemoryInterface, MemoryInterfaceImpl};
+use crate::cpu::scheduler::sync::lockdep::RwLock;
 use crate::memory::linear::VAddr;
 use crate::memory::linear::address_map::LA_MAP;
 use crate::memory::{AddressSpaceInterface, KERNEL_AS};
@@ -58,28 +59,38 @@ pub fn allocate_stack(n_pages: usize) -> Result<VAddr, Error> {
             .into(),
     )?;
     memory::try_allocate_and_map_range(stack_buf_base + PAGE_SIZE, n_pages)?;
-    Ok(stack_buf_base + PAGE_SIZE * (n_pages + 1))
+    let stack_end = stack_buf_base + PAGE_SIZE * (n_pages + 1);
+    // the guard pages delimit the stack when it is deallocated
+    let mut guard_set = KERNEL_GUARD_PAGE_SET.write();
+    guard_set.insert(stack_buf_base);
+    guard_set.insert(stack_end);
+    Ok(stack_end)
 }
 
 /// Deallocate a kernel stack previously allocated by `allocate_stack`.
+///
+/// The stack must not be in use by any LP.
 pub fn deallocate_stack(stack_end: VAddr) -> Result<(), Error> {
-    let n_pages = validate_stack(stack_end)?;
-    memory::unmap_and_deallocate_range(stack_end - PAGE_SIZE * (n_pages + 1), n_pages);
+    let mut guard_set = KERNEL_GUARD_PAGE_SET.write();
+    let n_pages = validate_stack(&guard_set, stack_end)?;
+    let stack_buf_base = stack_end - PAGE_SIZE * (n_pages + 1);
+    guard_set.remove(&stack_buf_base);
+    guard_set.remove(&stack_end);
+    drop(guard_set);
+    memory::unmap_and_deallocate_range(stack_buf_base + PAGE_SIZE, n_pages);
     Ok(())
 }
 
-fn validate_stack(stack_end: VAddr) -> Result<usize, Error> {
-    let stack_buf_base = stack_end - PAGE_SIZE;
-    let guard_set = KERNEL_GUARD_PAGE_SET.read();
-    if guard_set.contains(&stack_buf_base) {
-        let next_guard = guard_set
-            .range((Excluded(&stack_buf_base), Unbounded))
-            .next()
-            .copied()
-            .ok_or(Error::InvalidStack)?;
-
-        Ok((next_guard - stack_buf_base) as usize / PAGE_SIZE)
-    } else {
-        Err(Error::InvalidStack)
+/// Find the number of usable pages of the stack that ends at the upper guard page `stack_end`
+fn validate_stack(guard_set: &BTreeSet<VAddr>, stack_end: VAddr) -> Result<usize, Error> {
+    if !guard_set.contains(&stack_end) {
+        return Err(Error::InvalidStack);
     }
+    // the closest guard page below is the stack's lower guard page
+    let stack_buf_base = guard_set
+        .range((Unbounded, Excluded(&stack_end)))
+        .next_back()
+        .copied()
+        .ok_or(Error::InvalidStack)?;
+    Ok((stack_end - stack_buf_base) as usize / PAGE_SIZE - 1)
 }
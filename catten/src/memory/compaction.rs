@@ -0,0 +1,325 @@
+//! # Physical Memory Compaction
+//!
+//! Once physical memory is fragmented, contiguous allocations such as DMA buffers and huge pages
+//! can fail even though plenty of frames are free. Compaction opens up a contiguous range by moving
+//! the contents of the allocated frames inside it elsewhere and pointing their mappings at the new
+//! frames.
+//!
+//! Only frames whose owner can find and update every mapping of them are movable: resident pages
+//! of anonymous regions registered with the swap subsystem and the frames of mapped memory
+//! objects. Kernel heap memory is referenced by address and cannot be moved, so the free part of
+//! the heap is handed back through the shrinkers before compacting instead.
+//!
+//! A frame is moved by revoking write access to all of its mappings, shooting down the stale
+//! translations, copying the frame and finally remapping the pages to the copy. Readers are never
+//! held up and writers that fault on one of the write protected pages in the meantime wait for the
+//! move to finish and then retry. The owner's locks are dropped for the shootdowns, so the owner
+//! checks that the mappings are unchanged before the frame is copied.
+//!
+//! The frame's alias in the higher half direct mapping stays writable throughout. The kernel only
+//! writes the contents of movable frames through that alias while populating or swapping them in,
+//! which it does with the owner's locks held, so no such write can overlap the copy.
+
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use super::object::{self, MemoryObject};
+use super::{
+    AddressSpaceId,
+    AddressSpaceInterface,
+    PAddr,
+    PHYSICAL_FRAME_ALLOCATOR,
+    VAddr,
+    physical,
+    reclaim,
+    swap,
+    with_address_space,
+};
+use crate::cpu::isa::memory::paging::PAGE_SIZE;
+use crate::cpu::isa::memory::{MemoryInterface, MemoryInterfaceImpl};
+use crate::cpu::multiprocessor::ipi;
+use crate::cpu::scheduler::sync::lockdep::Mutex;
+use crate::logln;
+
+/// Only one compaction pass runs at a time
+static COMPACTION: Mutex<()> = Mutex::new(());
+/// Set while the mappings of a frame being moved are write protected
+static IS_MOVING_FRAME: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug)]
+pub enum Error {
+    /// No range of the requested size and alignment consists of only free and movable frames
+    NoMovableRange,
+    PfaError(physical::Error),
+    IsaMemoryError(<MemoryInterfaceImpl as MemoryInterface>::Error),
+    Swap(swap::Error),
+    MemoryObject(object::Error),
+}
+
+impl From<physical::Error> for Error {
+    fn from(err: physical::Error) -> Self {
+        Error::PfaError(err)
+    }
+}
+
+impl From<<MemoryInterfaceImpl as MemoryInterface>::Error> for Error {
+    fn from(err: <MemoryInterfaceImpl as MemoryInterface>::Error) -> Self {
+        Error::IsaMemoryError(err)
+    }
+}
+
+impl From<swap::Error> for Error {
+    fn from(err: swap::Error) -> Self {
+        Error::Swap(err)
+    }
+}
+
+impl From<object::Error> for Error {
+    fn from(err: object::Error) -> Self {
+        Error::MemoryObject(err)
+    }
+}
+
+/// Who to ask to move a frame
+pub(super) enum FrameOwner {
+    /// A resident page of an anonymous region
+    Anonymous(AddressSpaceId, VAddr),
+    /// A page of a mapped memory object, identified by its index into the object
+    Object(Arc<MemoryObject>, usize),
+}
+
+/// Allocate `n_frames` physically contiguous frames aligned to `alignment` bytes by moving movable
+/// frames out of the way.
+///
+/// The range chosen is the one that requires the fewest frames to be moved.
+pub fn compact(n_frames: usize, alignment: usize) -> Result<PAddr, Error> {
+    if n_frames == 0 {
+        return Err(physical::Error::NoOp.into());
+    }
+    if alignment == 0 || alignment % PAGE_SIZE != 0 {
+        return Err(physical::Error::InvalidPhysAlignment.into());
+    }
+    let _compaction = COMPACTION.lock();
+    let mut movable = BTreeMap::new();
+    swap::collect_movable_frames(&mut movable);
+    object::collect_movable_frames(&mut movable);
+    let base = find_range(&movable, n_frames, alignment)?;
+    logln!("Compacting {} frames starting at {:?}...", n_frames, base);
+    let mut claimed = Vec::with_capacity(n_frames);
+    let result = claim_range(&mut movable, base, n_frames, &mut claimed);
+    if result.is_err() {
+        let mut pfa = PHYSICAL_FRAME_ALLOCATOR.lock();
+        for frame in claimed {
+            if let Err(err) = pfa.deallocate_frame(frame) {
+                logln!("Error deallocating frame at {frame:?}: {err:?}");
+            }
+        }
+    }
+    result.map(|_| base)
+}
+
+/// A frame whose contents are being moved to another one
+///
+/// The owner of the frame drives the move with its own locks held or dropped as noted on each
+/// step:
+/// 1. `begin` with the locks held revokes write access to every mapping of the frame
+/// 2. `flush` with the locks dropped shoots down the writable translations
+/// 3. `finish` with the locks held again copies the frame and points the mappings at the copy, or
+///    `abort` restores write access if the mappings changed in the meantime
+/// 4. `end` with the locks dropped shoots down the translations of the old frame and lets the
+///    writers that faulted in the meantime retry
+#[must_use]
+pub(super) struct FrameMove {
+    old: PAddr,
+    new: PAddr,
+    mappings: Vec<(AddressSpaceId, VAddr)>,
+    was_writable: Vec<bool>,
+}
+
+impl FrameMove {
+    pub(super) fn begin(
+        old: PAddr,
+        new: PAddr,
+        mappings: Vec<(AddressSpaceId, VAddr)>,
+    ) -> Result<Self, <MemoryInterfaceImpl as MemoryInterface>::Error> {
+        IS_MOVING_FRAME.store(true, Ordering::Release);
+        let mut frame_move = FrameMove {
+            old,
+            new,
+            mappings,
+            was_writable: Vec::new(),
+        };
+        for &(asid, page) in frame_move.mappings.iter() {
+            match with_address_space(asid, |aspace| aspace.set_page_writable(page, false))
+                .transpose()
+            {
+                Ok(writable) => frame_move.was_writable.push(writable.unwrap_or(false)),
+                Err(err) => {
+                    frame_move.abort();
+                    frame_move.end();
+                    return Err(err);
+                }
+            }
+        }
+        Ok(frame_move)
+    }
+
+    /// The mappings the move was started with, which the owner compares against the current ones
+    /// before finishing the move
+    pub(super) fn mappings(&self) -> &[(AddressSpaceId, VAddr)] {
+        &self.mappings
+    }
+
+    pub(super) fn flush(&self) {
+        // writes through stale translations could otherwise be lost in the copy
+        self.flush_mappings();
+    }
+
+    pub(super) fn finish(&mut self) -> Result<(), <MemoryInterfaceImpl as MemoryInterface>::Error> {
+        unsafe {
+            core::ptr::copy_nonoverlapping(
+                <PAddr as Into<*mut u8>>::into(self.old),
+                <PAddr as Into<*mut u8>>::into(self.new),
+                PAGE_SIZE,
+            );
+        }
+        for (&(asid, page), &writable) in self.mappings.iter().zip(self.was_writable.iter()) {
+            with_address_space(asid, |aspace| {
+                aspace.remap_page(page, self.new)?;
+                aspace.set_page_writable(page, writable)
+            })
+            .transpose()?;
+        }
+        Ok(())
+    }
+
+    pub(super) fn abort(&mut self) {
+        for (&(asid, page), &writable) in self.mappings.iter().zip(self.was_writable.iter()) {
+            if writable {
+                with_address_space(asid, |aspace| aspace.set_page_writable(page, true));
+            }
+        }
+    }
+
+    pub(super) fn end(self) {
+        // stale translations would still point at the old frame
+        self.flush_mappings();
+        IS_MOVING_FRAME.store(false, Ordering::Release);
+    }
+
+    fn flush_mappings(&self) {
+        for &(asid, page) in self.mappings.iter() {
+            ipi::flush_tlb(asid, page, 1);
+        }
+    }
+}
+
+/// Wait for a frame move that may have write protected the page behind a write fault. Returns
+/// whether the fault should be retried.
+pub fn wait_for_frame_move() -> bool {
+    if !IS_MOVING_FRAME.load(Ordering::Acquire) {
+        return false;
+    }
+    while IS_MOVING_FRAME.load(Ordering::Acquire) {
+        // the LP moving the frame may be waiting for this one to flush its TLB
+        ipi::service_own_mailbox();
+        core::hint::spin_loop();
+    }
+    true
+}
+
+/// The range with the fewest allocated frames that consists only of free and movable frames
+fn find_range(
+    movable: &BTreeMap<PAddr, FrameOwner>,
+    n_frames: usize,
+    alignment: usize,
+) -> Result<PAddr, Error> {
+    let pfa = PHYSICAL_FRAME_ALLOCATOR.lock();
+    let end = pfa.n_frames() * PAGE_SIZE;
+    let mut best: Option<(usize, usize)> = None;
+    let mut base = alignment;
+    'candidates: while base + n_frames * PAGE_SIZE <= end {
+        let mut n_allocated = 0;
+        for frame_idx in 0..n_frames {
+            let frame = frame_at(base + frame_idx * PAGE_SIZE)?;
+            if pfa.is_frame_free(frame)? {
+                continue;
+            }
+            if !movable.contains_key(&frame) {
+                // no range containing this frame can be used so skip past it
+                base = ((base + frame_idx * PAGE_SIZE) / alignment + 1) * alignment;
+                continue 'candidates;
+            }
+            n_allocated += 1;
+        }
+        if best.is_none_or(|(_, best_n_allocated)| n_allocated < best_n_allocated) {
+            best = Some((base, n_allocated));
+            if n_allocated == 0 {
+                break;
+            }
+        }
+        base += alignment;
+    }
+    best.ok_or(Error::NoMovableRange).and_then(|(base, _)| frame_at(base))
+}
+
+/// Take ownership of every frame in the range by reserving the free ones and moving the contents
+/// of the allocated ones elsewhere. Frames taken so far are recorded in `claimed`.
+fn claim_range(
+    movable: &mut BTreeMap<PAddr, FrameOwner>,
+    base: PAddr,
+    n_frames: usize,
+    claimed: &mut Vec<PAddr>,
+) -> Result<(), Error> {
+    let frames = (0..n_frames).map(|frame_idx| base + (frame_idx * PAGE_SIZE) as isize);
+    // nothing may allocate from the kernel heap while the frame allocator is locked
+    let mut occupied = Vec::with_capacity(n_frames);
+    // reserve the free frames first so that none of them can be picked as a move destination
+    {
+        let mut pfa = PHYSICAL_FRAME_ALLOCATOR.lock();
+        for frame in frames {
+            match pfa.mark_frame_unavailable(frame) {
+                Ok(()) => claimed.push(frame),
+                Err(physical::Error::FrameAlreadyInUse) => occupied.push(frame),
+                Err(err) => return Err(err.into()),
+            }
+        }
+    }
+    for frame in occupied {
+        let owner = movable.remove(&frame).ok_or(Error::NoMovableRange)?;
+        let new = reclaim::allocate_frame()?;
+        let moved = match &owner {
+            FrameOwner::Anonymous(asid, page) => {
+                swap::migrate_page(*asid, *page, frame, new).map_err(Error::from)
+            }
+            FrameOwner::Object(object, page_idx) => {
+                object::migrate_page(object, *page_idx, frame, new).map_err(Error::from)
+            }
+        };
+        match moved {
+            // the old frame stays allocated and now belongs to the range
+            Ok(true) => claimed.push(frame),
+            // the owner let go of the frame since the range was chosen
+            Ok(false) => {
+                let mut pfa = PHYSICAL_FRAME_ALLOCATOR.lock();
+                pfa.deallocate_frame(new)?;
+                match pfa.mark_frame_unavailable(frame) {
+                    Ok(()) => claimed.push(frame),
+                    Err(physical::Error::FrameAlreadyInUse) => return Err(Error::NoMovableRange),
+                    Err(err) => return Err(err.into()),
+                }
+            }
+            Err(err) => {
+                PHYSICAL_FRAME_ALLOCATOR.lock().deallocate_frame(new)?;
+                return Err(err);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn frame_at(raw_addr: usize) -> Result<PAddr, Error> {
+    PAddr::try_from(raw_addr).map_err(|err| Error::PfaError(err.into()))
+}
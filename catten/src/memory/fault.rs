@@ -0,0 +1,61 @@
+//! # Page Fault Resolution
+//!
+//! This module contains the architecture independent part of page fault handling. ISA specific
+//! page fault handlers decode the fault into a `PageFault` and pass it to `resolve_page_fault`,
+//! which tries to satisfy it from the demand paging sources known to the memory subsystem. Only if
+//! none of them can resolve the fault is it treated as a genuine access violation.
+
+use super::{VAddr, compaction, object, swap};
+
+/// A decoded page fault
+#[derive(Debug, Clone, Copy)]
+pub struct PageFault {
+    /// The linear address whose access caused the fault
+    pub vaddr: VAddr,
+    /// The fault was caused by a permission violation on a present page rather than a missing one
+    pub is_present: bool,
+    pub is_write: bool,
+    pub is_user: bool,
+    pub is_instruction_fetch: bool,
+}
+
+#[derive(Debug)]
+pub enum Error {
+    /// No demand paging source is responsible for the faulting address
+    Unresolvable,
+    MemoryObject(object::Error),
+    Swap(swap::Error),
+}
+
+impl From<object::Error> for Error {
+    fn from(err: object::Error) -> Self {
+        Error::MemoryObject(err)
+    }
+}
+
+impl From<swap::Error> for Error {
+    fn from(err: swap::Error) -> Self {
+        Error::Swap(err)
+    }
+}
+
+/// Try to resolve a page fault by populating the faulting page.
+///
+/// Returns `Ok(())` if the faulting access can be retried.
+pub fn resolve_page_fault(fault: &PageFault) -> Result<(), Error> {
+    if fault.is_present {
+        // the page may have been write protected while its frame is being moved
+        if fault.is_write && compaction::wait_for_frame_move() {
+            return Ok(());
+        }
+        // Demand paging only ever fills in missing pages
+        return Err(Error::Unresolvable);
+    }
+    if object::try_populate(fault)? {
+        return Ok(());
+    }
+    if swap::try_swap_in(fault)? {
+        return Ok(());
+    }
+    Err(Error::Unresolvable)
+}
@@ -130,25 +130,33 @@ pub struct LinearAddressMap {
 
 impl LinearAddressMap {
     pub fn region_type(&self, addr: VAddr) -> RegionType {
+        self.try_region_type(addr).unwrap_or_else(|| {
+            unreachable!(
+                "This should be unreachable because the entire address space is tightly mapped \
+                 and the VAddr type guarantees a valid address."
+            )
+        })
+    }
+
+    /// Like `region_type` but returns `None` instead of panicking for addresses outside of every
+    /// region, which is useful for diagnostics that inspect arbitrary page table contents.
+    pub fn try_region_type(&self, addr: VAddr) -> Option<RegionType> {
         if self.null_page.contains(addr) {
-            RegionType::NullPage
+            Some(RegionType::NullPage)
         } else if self.application.contains(addr) {
-            RegionType::Application
+            Some(RegionType::Application)
         } else if self.direct_mapping.contains(addr) {
-            RegionType::DirectMapping
+            Some(RegionType::DirectMapping)
         } else if self.kernel_stack_arena.contains(addr) {
-            RegionType::KernelStackArena
+            Some(RegionType::KernelStackArena)
         } else if self.kernel_mmio.contains(addr) {
-            RegionType::KernelMmio
+            Some(RegionType::KernelMmio)
         } else if self.kenrnel_allocator_arena.contains(addr) {
-            RegionType::KernelAllocatorArena
+            Some(RegionType::KernelAllocatorArena)
         } else if self.kernel_image.contains(addr) {
-            RegionType::KernelImage
+            Some(RegionType::KernelImage)
         } else {
-            unreachable!(
-                "This should be unreachable because the entire address space is tightly mapped \
-                 and the VAddr type guarantees a valid address."
-            );
+            None
         }
     }
 
@@ -1,7 +1,13 @@
 pub mod address_map;
 
+use core::fmt;
+
+use address_map::LA_MAP;
+
+use crate::cpu::isa::interface::memory::AddressSpaceInterface;
 pub use crate::cpu::isa::memory::address::paddr::PAddr;
 pub use crate::cpu::isa::memory::address::vaddr::VAddr;
+use crate::logln;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Error {
@@ -69,3 +75,146 @@ pub struct MemoryMapping {
     pub paddr: PAddr,
     pub page_type: PageType,
 }
+
+/// The effective access attributes of a present mapping after combining the permissions granted by
+/// every level of the translation hierarchy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PageFlags {
+    pub writable: bool,
+    pub user_accessible: bool,
+    pub no_execute: bool,
+    pub global: bool,
+}
+
+impl PageFlags {
+    /// The closest `PageType` with the same access permissions. Caching attributes are not
+    /// considered so MMIO, DMA and framebuffer mappings are reported as ordinary data pages.
+    pub fn page_type(&self) -> PageType {
+        match (self.user_accessible, self.writable, self.no_execute) {
+            (true, _, false) => PageType::UserCode,
+            (true, true, true) => PageType::UserData,
+            (true, false, true) => PageType::UserRoData,
+            (false, _, false) => PageType::KernelCode,
+            (false, true, true) => PageType::KernelData,
+            (false, false, true) => PageType::KernelRoData,
+        }
+    }
+}
+
+impl From<PageType> for PageFlags {
+    fn from(page_type: PageType) -> Self {
+        PageFlags {
+            writable: page_type.is_writable(),
+            user_accessible: page_type.is_user_accessible(),
+            no_execute: page_type.is_no_execute(),
+            global: false,
+        }
+    }
+}
+
+impl fmt::Display for PageFlags {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let privilege = if self.user_accessible {
+            'u'
+        } else {
+            'k'
+        };
+        let write = if self.writable {
+            'w'
+        } else {
+            '-'
+        };
+        let execute = if self.no_execute {
+            '-'
+        } else {
+            'x'
+        };
+        let global = if self.global {
+            'g'
+        } else {
+            '-'
+        };
+        write!(f, "{privilege}{write}{execute}{global}")
+    }
+}
+
+/// A run of contiguous virtual pages backed by contiguous physical frames that all share the same
+/// page size and effective attributes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MappedRange {
+    pub vaddr: VAddr,
+    pub paddr: PAddr,
+    /// Length of the range in bytes
+    pub length: usize,
+    pub page_size: usize,
+    pub flags: PageFlags,
+}
+
+impl MappedRange {
+    /// The first virtual address past the end of the range
+    pub fn end(&self) -> VAddr {
+        self.vaddr + self.length
+    }
+
+    pub fn contains(&self, vaddr: VAddr) -> bool {
+        vaddr >= self.vaddr && vaddr < self.end()
+    }
+
+    pub fn n_pages(&self) -> usize {
+        self.length / self.page_size
+    }
+
+    /// Extend this range with `next` if it continues it both virtually and physically with the
+    /// same page size and attributes. Returns whether `next` was absorbed.
+    pub fn try_coalesce(&mut self, next: &MappedRange) -> bool {
+        if next.vaddr == self.end()
+            && next.paddr == self.paddr + self.length
+            && next.page_size == self.page_size
+            && next.flags == self.flags
+        {
+            self.length += next.length;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// A frame that holds a page table of an address space
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PageTableFrame {
+    /// The first virtual address translated through the table
+    pub vaddr: VAddr,
+    pub paddr: PAddr,
+    /// The number of tables above this one, 0 for the top level table
+    pub level: usize,
+}
+
+impl fmt::Display for MappedRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:?}..{:?} -> {:?} {} x {:#x} {} {:?}",
+            self.vaddr,
+            self.end(),
+            self.paddr,
+            self.n_pages(),
+            self.page_size,
+            self.flags,
+            self.flags.page_type()
+        )?;
+        match LA_MAP.try_region_type(self.vaddr) {
+            Some(region) => write!(f, " in {:?}", region),
+            None => write!(f, " outside of the linear address map"),
+        }
+    }
+}
+
+/// Log every present mapping in an address space, one coalesced range per line, labelled with the
+/// linear address map region it falls in.
+pub fn dump_mappings<A: AddressSpaceInterface>(address_space: &A) {
+    logln!("Address space mappings:");
+    for range in address_space.mappings() {
+        logln!("    {}", range);
+    }
+}
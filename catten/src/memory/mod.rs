@@ -1,19 +1,27 @@
 //! # Memory Management Subsystem
 
 pub mod allocators;
+pub mod compaction;
+pub mod fault;
 pub mod linear;
+pub mod object;
 pub mod physical;
+pub mod reclaim;
+pub mod swap;
 
 pub use linear::VAddr;
 pub use physical::{MemoryInterface, PAddr, PhysicalFrameAllocator};
-pub use spin::{Lazy, Mutex, RwLock};
+pub use spin::Lazy;
 
-pub use crate::common::collections::id_table::IdTable;
+use crate::common::collections::handle_table::{Handle, HandleTable};
 pub use crate::cpu::isa::interface::memory::AddressSpaceInterface;
 pub use crate::cpu::isa::memory::paging::AddressSpace;
+pub use crate::cpu::scheduler::sync::lockdep::{Mutex, RwLock};
+use crate::cpu::scheduler::sync::spinlock::IrqSpinLock;
 use crate::environment::boot_protocol::limine::{HHDM_REQUEST, MEMORY_MAP_REQUEST};
 
-pub type AddressSpaceId = usize;
+/// A handle into the address space table or `KERNEL_ASID`, which no handle is equal to
+pub type AddressSpaceId = Handle;
 
 /*The kernel address space is always ASID 0 and it is handled differently from userspace address
  * spaces because it needs to be initialized and accessible before the kernel allocator is
@@ -23,11 +31,14 @@ pub type AddressSpaceId = usize;
 pub const KERNEL_ASID: AddressSpaceId = 0;
 /// The kernel address space. It is initialized to the current address space when this static is
 /// first accessed. Which should happen during the BSP init process.
-pub static KERNEL_AS: Lazy<Mutex<AddressSpace>> =
-    Lazy::new(|| Mutex::new(AddressSpace::get_current()));
+pub static KERNEL_AS: Lazy<IrqSpinLock<AddressSpace>> =
+    Lazy::new(|| IrqSpinLock::new(AddressSpace::get_current()));
 /// Holds all userspace address spaces, indexed by their kernel assigned AddressSpaceId.
-type AddressSpaceTable = IdTable<AddressSpaceId, AddressSpace>;
-pub static ADDRESS_SPACE_TABLE: Lazy<AddressSpaceTable> = Lazy::new(|| AddressSpaceTable::new());
+type AddressSpaceTable = HandleTable<AddressSpace>;
+pub static ADDRESS_SPACE_TABLE: Lazy<AddressSpaceTable> =
+    Lazy::new(|| AddressSpaceTable::new(MAX_ADDRESS_SPACE_COUNT));
+/// The capacity of the address space table
+pub const MAX_ADDRESS_SPACE_COUNT: usize = 1 << 12;
 /// The starting virtual address of the higher half direct mapping region created by the bootloader.
 /// This should be remapped by the VMM during BSP init to be placed at the address specified by the
 /// kernel virtual memory map at which point this address should be updated to reflect the new
@@ -41,8 +52,25 @@ pub static HHDM_BASE: Lazy<VAddr> = Lazy::new(|| {
     )
 });
 /// The physical frame allocator instance used by the kernel.
-pub static PHYSICAL_FRAME_ALLOCATOR: Lazy<Mutex<PhysicalFrameAllocator>> = Lazy::new(|| {
-    Mutex::new(PhysicalFrameAllocator::from(
+pub static PHYSICAL_FRAME_ALLOCATOR: Lazy<IrqSpinLock<PhysicalFrameAllocator>> = Lazy::new(|| {
+    IrqSpinLock::new(PhysicalFrameAllocator::from(
         MEMORY_MAP_REQUEST.get_response().expect("Limine failed to provide a memory map."),
     ))
 });
+
+/// Run `f` with exclusive access to the address space identified by `asid`.
+///
+/// This hides the fact that the kernel address space lives outside of the address space table.
+/// Returns `None` if there is no address space with the given ID.
+pub fn with_address_space<R>(
+    asid: AddressSpaceId,
+    f: impl FnOnce(&mut AddressSpace) -> R,
+) -> Option<R> {
+    if asid == KERNEL_ASID {
+        Some(f(&mut KERNEL_AS.lock()))
+    } else {
+        let aspace = ADDRESS_SPACE_TABLE.get(asid)?;
+        let result = f(&mut aspace.write());
+        Some(result)
+    }
+}
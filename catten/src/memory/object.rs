@@ -0,0 +1,541 @@
+//! # Memory Objects
+//!
+//! A memory object is a kernel owned set of page frames that can be mapped into any number of
+//! address spaces at the same time, with each mapping having its own permissions. This is the
+//! building block for zero-copy IPC and for sharing buffers such as framebuffers between address
+//! spaces.
+//!
+//! Objects are reference counted: every live mapping holds a reference to its object and the
+//! object's frames are returned to the physical frame allocator once the last mapping has been
+//! removed and the last handle to the object has been dropped.
+//!
+//! Lazily populated objects only allocate a frame for a page when it is first touched through a
+//! mapping. Mappings into the kernel address space are always fully populated because the kernel
+//! address space is shared with allocators that know nothing about unpopulated object pages.
+//!
+//! Mappings keep the length they were created with when their object grows, so pages added by a
+//! resize are only reachable through mappings made afterwards.
+
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use spin::Lazy;
+
+use super::compaction::{FrameMove, FrameOwner};
+use super::fault::PageFault;
+use super::linear::address_map::{LA_MAP, RegionType};
+use super::linear::{MemoryMapping, PageType};
+use super::{
+    ADDRESS_SPACE_TABLE,
+    AddressSpace,
+    AddressSpaceId,
+    AddressSpaceInterface,
+    KERNEL_ASID,
+    PAddr,
+    PHYSICAL_FRAME_ALLOCATOR,
+    VAddr,
+    physical,
+    reclaim,
+    with_address_space,
+};
+use crate::cpu::isa::interface::memory::address::Address;
+use crate::cpu::isa::memory::paging::PAGE_SIZE;
+use crate::cpu::isa::memory::{MemoryInterface, MemoryInterfaceImpl};
+use crate::cpu::multiprocessor::ipi;
+use crate::cpu::scheduler::sync::lockdep::Mutex;
+use crate::cpu::scheduler::sync::spinlock::SpinRwLock;
+use crate::logln;
+
+/// Every object mapping in the system indexed by address space and then by base address
+static OBJECT_REGIONS: Lazy<SpinRwLock<BTreeMap<AddressSpaceId, BTreeMap<VAddr, ObjectRegion>>>> =
+    Lazy::new(|| SpinRwLock::new(BTreeMap::new()));
+
+#[derive(Debug)]
+pub enum Error {
+    ZeroSized,
+    /// Object pages can only be mapped as ordinary kernel or user memory matching the privilege
+    /// level of the target address space.
+    InvalidPageType,
+    MisalignedBase,
+    /// The requested range is outside of the region of the linear address map used for objects in
+    /// the target address space.
+    OutOfRegion,
+    RegionInUse,
+    NoSuchAddressSpace,
+    AccessViolation,
+    PfaError(physical::Error),
+    IsaMemoryError(<MemoryInterfaceImpl as MemoryInterface>::Error),
+}
+
+impl From<physical::Error> for Error {
+    fn from(err: physical::Error) -> Self {
+        Error::PfaError(err)
+    }
+}
+
+impl From<<MemoryInterfaceImpl as MemoryInterface>::Error> for Error {
+    fn from(err: <MemoryInterfaceImpl as MemoryInterface>::Error) -> Self {
+        Error::IsaMemoryError(err)
+    }
+}
+
+/// When the frames backing a memory object are allocated
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Population {
+    /// All frames are allocated when the object is created or grown
+    Eager,
+    /// Frames are allocated on the first access to each page
+    Lazy,
+}
+
+pub struct MemoryObject {
+    population: Population,
+    /// Backing frames indexed by page offset into the object; `None` for unpopulated pages
+    frames: Mutex<Vec<Option<PAddr>>>,
+}
+
+/// A mapping of a memory object into an address space
+struct ObjectRegion {
+    object: Arc<MemoryObject>,
+    n_pages: usize,
+    page_type: PageType,
+}
+
+/// Handle to a live mapping of a memory object. The mapping is removed when this is dropped.
+pub struct ObjectMapping {
+    asid: AddressSpaceId,
+    base: VAddr,
+}
+
+impl MemoryObject {
+    pub fn new(n_pages: usize, population: Population) -> Result<Arc<Self>, Error> {
+        if n_pages == 0 {
+            return Err(Error::ZeroSized);
+        }
+        let object = Arc::new(MemoryObject {
+            population,
+            frames: Mutex::new(Vec::with_capacity(n_pages)),
+        });
+        // on failure dropping the object releases any frames allocated so far
+        object.grow(&mut object.frames.lock(), n_pages)?;
+        Ok(object)
+    }
+
+    pub fn population(&self) -> Population {
+        self.population
+    }
+
+    pub fn n_pages(&self) -> usize {
+        self.frames.lock().len()
+    }
+
+    /// Map the whole object at a free location in the address space identified by `asid`.
+    pub fn map(
+        self: &Arc<Self>,
+        asid: AddressSpaceId,
+        page_type: PageType,
+    ) -> Result<ObjectMapping, Error> {
+        validate_page_type(asid, page_type)?;
+        let mut regions = OBJECT_REGIONS.write();
+        let mut frames = self.frames.lock();
+        if asid == KERNEL_ASID {
+            self.populate_all(&mut frames)?;
+        }
+        let n_pages = frames.len();
+        let as_regions = regions.entry(asid).or_default();
+        let (mut search_base, search_end): (VAddr, VAddr) =
+            LA_MAP.get_region(object_region_type(asid)).clone().into();
+        let base = with_address_space(asid, |aspace| -> Result<VAddr, Error> {
+            loop {
+                let base = aspace.find_free_region(n_pages, (search_base, search_end))?;
+                // unpopulated pages of other objects are not visible in the page tables
+                match overlapping_region_end(as_regions, base, n_pages) {
+                    Some(conflict_end) => search_base = conflict_end,
+                    None => {
+                        map_frames(aspace, base, &frames, page_type)?;
+                        return Ok(base);
+                    }
+                }
+            }
+        })
+        .ok_or(Error::NoSuchAddressSpace)??;
+        as_regions.insert(
+            base,
+            ObjectRegion {
+                object: self.clone(),
+                n_pages,
+                page_type,
+            },
+        );
+        Ok(ObjectMapping {
+            asid,
+            base,
+        })
+    }
+
+    /// Map the whole object at `base` in the address space identified by `asid`.
+    pub fn map_at(
+        self: &Arc<Self>,
+        asid: AddressSpaceId,
+        base: VAddr,
+        page_type: PageType,
+    ) -> Result<ObjectMapping, Error> {
+        validate_page_type(asid, page_type)?;
+        if !base.is_aligned_to(PAGE_SIZE) {
+            return Err(Error::MisalignedBase);
+        }
+        let mut regions = OBJECT_REGIONS.write();
+        let mut frames = self.frames.lock();
+        if asid == KERNEL_ASID {
+            self.populate_all(&mut frames)?;
+        }
+        let n_pages = frames.len();
+        let last_page = base + (n_pages - 1) * PAGE_SIZE;
+        let region_type = object_region_type(asid);
+        if LA_MAP.try_region_type(base) != Some(region_type)
+            || LA_MAP.try_region_type(last_page) != Some(region_type)
+        {
+            return Err(Error::OutOfRegion);
+        }
+        let as_regions = regions.entry(asid).or_default();
+        if overlapping_region_end(as_regions, base, n_pages).is_some() {
+            return Err(Error::RegionInUse);
+        }
+        with_address_space(asid, |aspace| -> Result<(), Error> {
+            for page_idx in 0..n_pages {
+                if aspace.is_mapped(base + page_idx * PAGE_SIZE)? {
+                    return Err(Error::RegionInUse);
+                }
+            }
+            map_frames(aspace, base, &frames, page_type)
+        })
+        .ok_or(Error::NoSuchAddressSpace)??;
+        as_regions.insert(
+            base,
+            ObjectRegion {
+                object: self.clone(),
+                n_pages,
+                page_type,
+            },
+        );
+        Ok(ObjectMapping {
+            asid,
+            base,
+        })
+    }
+
+    /// Change the size of the object to `n_pages`.
+    ///
+    /// Growing the object does not extend existing mappings since the linear addresses after them
+    /// may already be in use, so the new pages are only reachable through mappings created after
+    /// the resize. Callers that need them through an existing mapping have to drop it and map the
+    /// object again. Shrinking the object unmaps the truncated pages from every mapping and frees
+    /// their frames once no LP can reach them through a stale translation anymore.
+    pub fn resize(&self, n_pages: usize) -> Result<(), Error> {
+        if n_pages == 0 {
+            return Err(Error::ZeroSized);
+        }
+        let mut regions = OBJECT_REGIONS.write();
+        let mut frames = self.frames.lock();
+        let old_n_pages = frames.len();
+        if n_pages > old_n_pages {
+            if let Err(err) = self.grow(&mut frames, n_pages) {
+                release_frames(&mut frames, old_n_pages);
+                return Err(err);
+            }
+            return Ok(());
+        }
+        let mut truncated_ranges = Vec::new();
+        for (asid, as_regions) in regions.iter_mut() {
+            for (base, region) in as_regions.iter_mut() {
+                if !core::ptr::eq(Arc::as_ptr(&region.object), self) || region.n_pages <= n_pages {
+                    continue;
+                }
+                let truncated_base = *base + n_pages * PAGE_SIZE;
+                let n_truncated = region.n_pages - n_pages;
+                with_address_space(*asid, |aspace| {
+                    unmap_pages(aspace, truncated_base, n_truncated)
+                });
+                truncated_ranges.push((*asid, truncated_base, n_truncated));
+                region.n_pages = n_pages;
+            }
+        }
+        let mut truncated_frames = frames.split_off(n_pages);
+        // waiting for the shootdowns with the registry locked would keep the other LPs from
+        // servicing them if they are waiting for it too
+        drop(frames);
+        drop(regions);
+        for (asid, base, n_truncated) in truncated_ranges {
+            ipi::flush_tlb(asid, base, n_truncated);
+        }
+        release_frames(&mut truncated_frames, 0);
+        Ok(())
+    }
+
+    fn grow(&self, frames: &mut Vec<Option<PAddr>>, n_pages: usize) -> Result<(), Error> {
+        while frames.len() < n_pages {
+            let frame = match self.population {
+                Population::Eager => Some(reclaim::allocate_zeroed_frame()?),
+                Population::Lazy => None,
+            };
+            frames.push(frame);
+        }
+        Ok(())
+    }
+
+    fn populate_all(&self, frames: &mut Vec<Option<PAddr>>) -> Result<(), Error> {
+        for frame in frames.iter_mut().filter(|frame| frame.is_none()) {
+            *frame = Some(reclaim::allocate_zeroed_frame()?);
+        }
+        Ok(())
+    }
+}
+
+impl Drop for MemoryObject {
+    fn drop(&mut self) {
+        release_frames(self.frames.get_mut(), 0);
+    }
+}
+
+impl ObjectMapping {
+    pub fn asid(&self) -> AddressSpaceId {
+        self.asid
+    }
+
+    pub fn base(&self) -> VAddr {
+        self.base
+    }
+}
+
+impl Drop for ObjectMapping {
+    fn drop(&mut self) {
+        let mut regions = OBJECT_REGIONS.write();
+        let Some(as_regions) = regions.get_mut(&self.asid) else {
+            return;
+        };
+        let Some(region) = as_regions.remove(&self.base) else {
+            return;
+        };
+        if as_regions.is_empty() {
+            regions.remove(&self.asid);
+        }
+        with_address_space(self.asid, |aspace| unmap_pages(aspace, self.base, region.n_pages));
+        // the object may be freed along with the region so release the registry first
+        drop(regions);
+        // no LP may reach the frames anymore by the time they are freed
+        ipi::flush_tlb(self.asid, self.base, region.n_pages);
+    }
+}
+
+/// Populate the faulting page if it belongs to a lazily populated object mapping in the active
+/// address space.
+///
+/// Returns `Ok(false)` if the faulting address is not covered by any object mapping.
+pub fn try_populate(fault: &PageFault) -> Result<bool, Error> {
+    let page = fault.vaddr.prev_aligned_to(PAGE_SIZE);
+    let regions = OBJECT_REGIONS.read();
+    for (&asid, as_regions) in regions.iter() {
+        // mappings in the kernel address space are always fully populated
+        if asid == KERNEL_ASID {
+            continue;
+        }
+        let Some((&base, region)) = as_regions.range(..=page).next_back() else {
+            continue;
+        };
+        if page >= base + region.n_pages * PAGE_SIZE {
+            continue;
+        }
+        let Some(aspace) = ADDRESS_SPACE_TABLE.get(asid) else {
+            continue;
+        };
+        if !aspace.read().is_active() {
+            continue;
+        }
+        if (fault.is_write && !region.page_type.is_writable())
+            || (fault.is_instruction_fetch && region.page_type.is_no_execute())
+            || (fault.is_user && !region.page_type.is_user_accessible())
+        {
+            return Err(Error::AccessViolation);
+        }
+        let page_idx = (page - base) as usize / PAGE_SIZE;
+        let mut frames = region.object.frames.lock();
+        let frame = match frames[page_idx] {
+            Some(frame) => frame,
+            None => {
+                let frame = reclaim::allocate_zeroed_frame()?;
+                frames[page_idx] = Some(frame);
+                frame
+            }
+        };
+        let mut aspace = aspace.write();
+        // another LP may have resolved a fault on the same page in the meantime
+        if !aspace.is_mapped(page)? {
+            aspace.map_page(MemoryMapping {
+                vaddr: page,
+                paddr: frame,
+                page_type: region.page_type,
+            })?;
+        }
+        return Ok(true);
+    }
+    Ok(false)
+}
+
+/// Record every populated frame of a mapped object in `movable` so that compaction can relocate
+/// it. Objects that are not mapped anywhere are left alone.
+pub(super) fn collect_movable_frames(movable: &mut BTreeMap<PAddr, FrameOwner>) {
+    let regions = OBJECT_REGIONS.read();
+    for region in regions.values().flat_map(|as_regions| as_regions.values()) {
+        let frames = region.object.frames.lock();
+        for (page_idx, frame) in frames.iter().enumerate() {
+            if let Some(frame) = *frame {
+                movable
+                    .entry(frame)
+                    .or_insert_with(|| FrameOwner::Object(region.object.clone(), page_idx));
+            }
+        }
+    }
+}
+
+/// Move page `page_idx` of `object` from `old` to `new`, updating every mapping of it. Returns
+/// `Ok(false)` if the page is no longer backed by `old` or its mappings changed during the move.
+pub(super) fn migrate_page(
+    object: &Arc<MemoryObject>,
+    page_idx: usize,
+    old: PAddr,
+    new: PAddr,
+) -> Result<bool, Error> {
+    let mut frame_move = {
+        let regions = OBJECT_REGIONS.read();
+        let frames = object.frames.lock();
+        if frames.get(page_idx).copied().flatten() != Some(old) {
+            return Ok(false);
+        }
+        FrameMove::begin(old, new, find_mappings(&regions, object, page_idx)?)?
+    };
+    // the locks are dropped while waiting for the other LPs
+    frame_move.flush();
+    let regions = OBJECT_REGIONS.read();
+    let mut frames = object.frames.lock();
+    let is_unchanged = frames.get(page_idx).copied().flatten() == Some(old)
+        && find_mappings(&regions, object, page_idx)
+            .is_ok_and(|mappings| mappings == frame_move.mappings());
+    let result = if is_unchanged {
+        frame_move.finish().map(|_| {
+            frames[page_idx] = Some(new);
+            true
+        })
+    } else {
+        frame_move.abort();
+        Ok(false)
+    };
+    drop(frames);
+    drop(regions);
+    frame_move.end();
+    Ok(result?)
+}
+
+/// Every mapped page through which page `page_idx` of `object` is accessible
+fn find_mappings(
+    regions: &BTreeMap<AddressSpaceId, BTreeMap<VAddr, ObjectRegion>>,
+    object: &Arc<MemoryObject>,
+    page_idx: usize,
+) -> Result<Vec<(AddressSpaceId, VAddr)>, Error> {
+    let mut mappings = Vec::new();
+    for (&asid, as_regions) in regions.iter() {
+        for (&base, region) in as_regions.iter() {
+            if !Arc::ptr_eq(&region.object, object) || page_idx >= region.n_pages {
+                continue;
+            }
+            let page = base + page_idx * PAGE_SIZE;
+            // untouched pages of lazily populated mappings are not mapped
+            let is_mapped = with_address_space(asid, |aspace| aspace.is_mapped(page));
+            if let Some(true) = is_mapped.transpose()? {
+                mappings.push((asid, page));
+            }
+        }
+    }
+    Ok(mappings)
+}
+
+fn object_region_type(asid: AddressSpaceId) -> RegionType {
+    if asid == KERNEL_ASID {
+        RegionType::KernelAllocatorArena
+    } else {
+        RegionType::Application
+    }
+}
+
+fn validate_page_type(asid: AddressSpaceId, page_type: PageType) -> Result<(), Error> {
+    let is_valid = match page_type {
+        PageType::KernelCode | PageType::KernelData | PageType::KernelRoData => asid == KERNEL_ASID,
+        PageType::UserCode | PageType::UserData | PageType::UserRoData => asid != KERNEL_ASID,
+        _ => false,
+    };
+    if is_valid {
+        Ok(())
+    } else {
+        Err(Error::InvalidPageType)
+    }
+}
+
+/// Returns the end of an existing region overlapping `n_pages` pages starting at `base`, if any
+fn overlapping_region_end(
+    as_regions: &BTreeMap<VAddr, ObjectRegion>,
+    base: VAddr,
+    n_pages: usize,
+) -> Option<VAddr> {
+    let end = base + n_pages * PAGE_SIZE;
+    as_regions
+        .range(..end)
+        .next_back()
+        .map(|(&region_base, region)| region_base + region.n_pages * PAGE_SIZE)
+        .filter(|&region_end| region_end > base)
+}
+
+/// Map every populated frame of an object starting at `base`, undoing the partial mapping on error
+fn map_frames(
+    aspace: &mut AddressSpace,
+    base: VAddr,
+    frames: &[Option<PAddr>],
+    page_type: PageType,
+) -> Result<(), Error> {
+    for (page_idx, frame) in frames.iter().enumerate() {
+        let Some(frame) = *frame else {
+            continue;
+        };
+        let mapping = MemoryMapping {
+            vaddr: base + page_idx * PAGE_SIZE,
+            paddr: frame,
+            page_type,
+        };
+        if let Err(err) = aspace.map_page(mapping) {
+            unmap_pages(aspace, base, page_idx);
+            return Err(err.into());
+        }
+    }
+    Ok(())
+}
+
+fn unmap_pages(aspace: &mut AddressSpace, base: VAddr, n_pages: usize) {
+    for page_idx in 0..n_pages {
+        let vaddr = base + page_idx * PAGE_SIZE;
+        // unpopulated pages of lazy objects have nothing to unmap
+        if let Ok(true) = aspace.is_mapped(vaddr) {
+            if let Err(err) = aspace.unmap_page(vaddr) {
+                logln!("Error unmapping memory object page at {vaddr:?}: {err:?}");
+            }
+        }
+    }
+}
+
+/// Return every frame past the first `keep` pages to the physical frame allocator
+fn release_frames(frames: &mut Vec<Option<PAddr>>, keep: usize) {
+    let mut pfa = PHYSICAL_FRAME_ALLOCATOR.lock();
+    for frame in frames.drain(keep..).flatten() {
+        if let Err(err) = pfa.deallocate_frame(frame) {
+            logln!("Error deallocating memory object frame at {frame:?}: {err:?}");
+        }
+    }
+}
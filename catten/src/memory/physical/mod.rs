@@ -41,6 +41,9 @@ impl From<PAddrError> for Error {
 pub struct PhysicalFrameAllocator {
     bitmap_ptr: *mut u8,
     bitmap_len: usize,
+    /// Number of frames that were available after boot
+    n_usable: usize,
+    n_free: usize,
 }
 
 unsafe impl Send for PhysicalFrameAllocator {}
@@ -59,6 +62,7 @@ impl PhysicalFrameAllocator {
             } else {
                 // set the bit corresponding to the frame being marked unavailable
                 *self.bitmap_ptr.offset(byte_idx as isize) |= 1 << bit_idx;
+                self.n_free -= 1;
                 return Ok(());
             }
         }
@@ -70,11 +74,12 @@ impl PhysicalFrameAllocator {
             unsafe {
                 curr_byte_ptr = self.bitmap_ptr.offset(byte_idx as isize);
                 if curr_byte_ptr.read() != 0xff {
-                    for bit_idx in 0..7 {
+                    for bit_idx in 0..BITS_PER_BYTE {
                         if curr_byte_ptr.read() & (1 << bit_idx) == 0u8 {
                             //set the bit corresponding to the allocated frame
                             curr_byte_ptr
                                 .write_volatile(curr_byte_ptr.read_volatile() | (1 << bit_idx));
+                            self.n_free -= 1;
                             let raw_addr = (byte_idx * BITS_PER_BYTE + bit_idx) * PAGE_FRAME_SIZE;
                             return Ok(PAddr::try_from(raw_addr)?);
                         }
@@ -85,6 +90,38 @@ impl PhysicalFrameAllocator {
         Err(Error::OutOfFrames)
     }
 
+    pub fn n_free_frames(&self) -> usize {
+        self.n_free
+    }
+
+    pub fn n_usable_frames(&self) -> usize {
+        self.n_usable
+    }
+
+    /// The number of frames covered by the bitmap, whether usable or not
+    pub fn n_frames(&self) -> usize {
+        self.bitmap_len * BITS_PER_BYTE
+    }
+
+    pub fn is_frame_free(&self, frame_addr: PAddr) -> Result<bool, Error> {
+        if <PAddr as Into<usize>>::into(frame_addr) % PAGE_FRAME_SIZE != 0 {
+            return Err(Error::MisalignedPhysicalAddress);
+        }
+        if <PAddr as Into<usize>>::into(frame_addr) / PAGE_FRAME_SIZE >= self.n_frames() {
+            return Err(Error::InvalidPAddr);
+        }
+        self.is_containing_frame_available(frame_addr)
+    }
+
+    /// Allocate a frame and clear its contents through the higher half direct mapping.
+    pub fn allocate_zeroed_frame(&mut self) -> Result<PAddr, Error> {
+        let frame = self.allocate_frame()?;
+        unsafe {
+            core::ptr::write_bytes(<PAddr as Into<*mut u8>>::into(frame), 0, PAGE_FRAME_SIZE);
+        }
+        Ok(frame)
+    }
+
     #[inline]
     fn is_containing_frame_available(&self, addr: PAddr) -> Result<bool, Error> {
         let (byte_idx, bit_idx) = addr_to_bitmap_index(addr.prev_aligned_to(PAGE_FRAME_SIZE))?;
@@ -109,21 +146,27 @@ impl PhysicalFrameAllocator {
     ) -> Result<PAddr, Error> {
         if nframes == 0 {
             Err(Error::NoOp)
-        } else if nframes / BITS_PER_BYTE > self.bitmap_len {
+        } else if nframes > self.n_frames() {
             Err(Error::RequestLargerThanTotalMemory)
-        } else if alignment % PAGE_FRAME_SIZE != 0
+        } else if alignment == 0
+            || alignment % PAGE_FRAME_SIZE != 0
             || alignment / (PAGE_FRAME_SIZE * BITS_PER_BYTE) > self.bitmap_len
         {
             Err(Error::InvalidPhysAlignment)
         } else {
+            let end = self.n_frames() * PAGE_FRAME_SIZE;
             let mut start_frame_base = alignment;
 
             'outer: loop {
+                if start_frame_base + nframes * PAGE_FRAME_SIZE > end {
+                    return Err(Error::OutOfFrames);
+                }
                 for fb in (start_frame_base..(start_frame_base + nframes * PAGE_FRAME_SIZE))
                     .step_by(PAGE_FRAME_SIZE)
                 {
                     if !self.is_containing_frame_available(PAddr::try_from(fb as usize).unwrap())? {
-                        start_frame_base += alignment;
+                        // no range containing this frame can be used so skip past it
+                        start_frame_base = (fb / alignment + 1) * alignment;
                         break;
                     } else if fb == start_frame_base + (nframes - 1) * PAGE_FRAME_SIZE {
                         // found a suitable range
@@ -133,7 +176,7 @@ impl PhysicalFrameAllocator {
             }
             let start_addr = PAddr::try_from(start_frame_base)?;
             self.mark_frames_unavailable(start_addr, nframes)?;
-            Ok(start_addr) // Placeholder
+            Ok(start_addr)
         }
     }
 
@@ -150,6 +193,7 @@ impl PhysicalFrameAllocator {
                 } else {
                     // clear the bit corresponding to the frame being deallocated
                     *self.bitmap_ptr.offset(byte_idx as isize) &= !(1 << bit_idx);
+                    self.n_free += 1;
                     return Ok(());
                 }
             }
@@ -169,9 +213,11 @@ impl From<&MemoryMapResponse> for PhysicalFrameAllocator {
         logln!("Finding best fit memory location for the PhysicalFrameAllocator bitmap...");
         let bitmap_addr: PAddr = find_mmap_best_fit(response, bitmap_size).unwrap();
         logln!("PhysicalFrameAllocator bitmap addr (physical): {:?}", bitmap_addr);
-        let pfa = PhysicalFrameAllocator {
+        let mut pfa = PhysicalFrameAllocator {
             bitmap_ptr: unsafe { bitmap_addr.into_hhdm_mut::<u8>() },
             bitmap_len: bitmap_size,
+            n_usable: 0,
+            n_free: 0,
         };
         // Initially mark all frames as unavailable.
         logln!("Clearing PhysicalFrameAllocator bitmap...");
@@ -188,6 +234,11 @@ impl From<&MemoryMapResponse> for PhysicalFrameAllocator {
         }
         // Mark the bitmap region as unusable.
         mark_pfa_bitmap_unusable(pfa.bitmap_ptr, bitmap_addr, bitmap_size);
+        pfa.n_usable = (0..bitmap_size)
+            .map(|i| unsafe { pfa.bitmap_ptr.offset(i as isize).read() }.count_zeros() as usize)
+            .sum();
+        pfa.n_free = pfa.n_usable;
+        logln!("PhysicalFrameAllocator tracks {} usable frames.", (pfa.n_usable));
         logln!("PhysicalFrameAllocator bitmap initialized.");
 
         pfa
@@ -0,0 +1,231 @@
+//! # Memory Reclaim
+//!
+//! Subsystems that hold on to memory they could give back, such as caches or the tail of the
+//! kernel heap, register a `Shrinker` here. Frame allocations made through `allocate_frame` and
+//! `allocate_zeroed_frame` watch the number of free frames and ask the registered shrinkers for
+//! memory once it drops below the low watermark and again before reporting that physical memory
+//! has been exhausted.
+//!
+//! Contiguous allocations made through `allocate_contiguous` fall back to compacting physical
+//! memory when no sufficiently large free range is left.
+//!
+//! Every rise in memory pressure is also signalled through the `MEMORY_PRESSURE` event source so
+//! that user space memory managers can trim their own caches. Like with any event source an
+//! observer is notified once and registers again to hear about the next rise.
+//!
+//! Page table frames are allocated through here as well, so mapping memory also reclaims before
+//! failing.
+//!
+//! Shrinkers run in the context of whatever allocation triggered them and must not allocate from
+//! the kernel heap or wait for locks that may be held around frame allocations, such as the lock
+//! of the address space being mapped into. Only one LP runs
+//! them at a time and the others wait for it to finish before running them again themselves.
+
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use core::sync::atomic::{AtomicU8, AtomicU32, AtomicUsize, Ordering};
+
+use spin::Lazy;
+
+use super::{PAddr, PHYSICAL_FRAME_ALLOCATOR, compaction, physical};
+use crate::cpu::isa::lp::LpId;
+use crate::cpu::isa::lp::ops::get_lp_id;
+use crate::cpu::isa::memory::paging::PAGE_SIZE;
+use crate::cpu::multiprocessor::ipi::service_own_mailbox;
+use crate::cpu::scheduler::sync::lockdep::RwLock;
+use crate::cpu::scheduler::sync::spinlock::SpinLock;
+use crate::event::EventSource;
+use crate::logln;
+
+/// Number of frames reclaimed at once when an allocation fails
+const RECLAIM_BATCH: usize = 32;
+
+static SHRINKERS: Lazy<RwLock<BTreeMap<ShrinkerId, Arc<dyn Shrinker>>>> =
+    Lazy::new(|| RwLock::new(BTreeMap::new()));
+static NEXT_SHRINKER_ID: AtomicUsize = AtomicUsize::new(0);
+/// Held while the shrinkers run. It disables preemption, so the reclaimer stays on its LP.
+static RECLAIM_LOCK: SpinLock<()> = SpinLock::new(());
+/// The LP running the shrinkers or `NO_RECLAIMER`, so that allocations made by the shrinkers or by
+/// interrupt handlers on that LP do not recurse into reclaim
+static RECLAIMER_LP: AtomicU32 = AtomicU32::new(NO_RECLAIMER);
+const NO_RECLAIMER: LpId = LpId::MAX;
+static PRESSURE_LEVEL: AtomicU8 = AtomicU8::new(PressureLevel::Normal as u8);
+/// Raised whenever the memory pressure level rises
+pub static MEMORY_PRESSURE: EventSource = EventSource::new();
+
+pub type ShrinkerId = usize;
+
+pub trait Shrinker: Send + Sync {
+    /// An estimate of the number of frames `shrink` could currently free
+    fn count_reclaimable(&self) -> usize;
+    /// Try to free up to `n_frames` frames and return the number of frames actually freed
+    fn shrink(&self, n_frames: usize) -> usize;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(u8)]
+pub enum PressureLevel {
+    Normal,
+    /// The number of free frames has dropped below the low watermark
+    Low,
+    /// An allocation could not be satisfied even after reclaiming
+    Critical,
+}
+
+/// Free frame thresholds derived from the amount of usable physical memory
+pub struct Watermarks {
+    /// Below this many free frames the shrinkers are invoked
+    pub low:  usize,
+    /// The level shrinkers try to restore and above which pressure returns to normal
+    pub high: usize,
+}
+
+impl Watermarks {
+    fn compute() -> Self {
+        let n_usable = PHYSICAL_FRAME_ALLOCATOR.lock().n_usable_frames();
+        Watermarks {
+            low:  n_usable / 64,
+            high: n_usable / 32,
+        }
+    }
+}
+
+static WATERMARKS: Lazy<Watermarks> = Lazy::new(Watermarks::compute);
+
+pub fn register_shrinker(shrinker: Arc<dyn Shrinker>) -> ShrinkerId {
+    let id = NEXT_SHRINKER_ID.fetch_add(1, Ordering::Relaxed);
+    SHRINKERS.write().insert(id, shrinker);
+    id
+}
+
+pub fn unregister_shrinker(id: ShrinkerId) {
+    SHRINKERS.write().remove(&id);
+}
+
+pub fn get_watermarks() -> &'static Watermarks {
+    &WATERMARKS
+}
+
+pub fn pressure_level() -> PressureLevel {
+    match PRESSURE_LEVEL.load(Ordering::Relaxed) {
+        0 => PressureLevel::Normal,
+        1 => PressureLevel::Low,
+        _ => PressureLevel::Critical,
+    }
+}
+
+/// Ask the registered shrinkers to free up to `n_frames` frames. Returns the number of frames
+/// freed.
+///
+/// If another LP is running the shrinkers this waits for it to finish and then runs them again,
+/// since what it freed may already have been taken. Nothing is freed while a shrinker is being
+/// registered or unregistered.
+pub fn shrink(n_frames: usize) -> usize {
+    if RECLAIMER_LP.load(Ordering::Acquire) == get_lp_id() {
+        return 0;
+    }
+    let _reclaiming = loop {
+        if let Some(guard) = RECLAIM_LOCK.try_lock() {
+            break guard;
+        }
+        // the reclaimer may be waiting for this LP to acknowledge a TLB shootdown
+        service_own_mailbox();
+        core::hint::spin_loop();
+    };
+    RECLAIMER_LP.store(get_lp_id(), Ordering::Release);
+    let mut n_freed = 0;
+    // registering a shrinker allocates while the registry is write locked, possibly on this LP,
+    // so a contended registry is skipped rather than waited for
+    if let Some(shrinkers) = SHRINKERS.try_read() {
+        // this may run on behalf of the kernel heap so it must not allocate
+        for shrinker in shrinkers.values() {
+            if n_freed >= n_frames {
+                break;
+            }
+            if shrinker.count_reclaimable() > 0 {
+                n_freed += shrinker.shrink(n_frames - n_freed);
+            }
+        }
+    }
+    RECLAIMER_LP.store(NO_RECLAIMER, Ordering::Release);
+    n_freed
+}
+
+/// Allocate a frame, invoking the shrinkers if physical memory is running low.
+pub fn allocate_frame() -> Result<PAddr, physical::Error> {
+    let (result, n_free) = {
+        let mut pfa = PHYSICAL_FRAME_ALLOCATOR.lock();
+        (pfa.allocate_frame(), pfa.n_free_frames())
+    };
+    match result {
+        Ok(frame) => {
+            update_pressure(n_free);
+            Ok(frame)
+        }
+        Err(physical::Error::OutOfFrames) => {
+            shrink(RECLAIM_BATCH);
+            let retry = PHYSICAL_FRAME_ALLOCATOR.lock().allocate_frame();
+            if retry.is_err() {
+                raise_pressure(PressureLevel::Critical);
+            }
+            retry
+        }
+        Err(err) => Err(err),
+    }
+}
+
+/// Like `allocate_frame` but the contents of the frame are cleared.
+pub fn allocate_zeroed_frame() -> Result<PAddr, physical::Error> {
+    let frame = allocate_frame()?;
+    unsafe {
+        core::ptr::write_bytes(<PAddr as Into<*mut u8>>::into(frame), 0, PAGE_SIZE);
+    }
+    Ok(frame)
+}
+
+/// Allocate `n_frames` physically contiguous frames aligned to `alignment` bytes, e.g. for DMA
+/// buffers or huge pages.
+///
+/// If physical memory is too fragmented the shrinkers are asked to free memory and the remaining
+/// allocations are compacted to open up a suitable range.
+pub fn allocate_contiguous(n_frames: usize, alignment: usize) -> Result<PAddr, physical::Error> {
+    match PHYSICAL_FRAME_ALLOCATOR.lock().allocate_contiguous(n_frames, alignment) {
+        Err(physical::Error::OutOfFrames) => {}
+        result => return result,
+    }
+    shrink(n_frames);
+    if let Ok(frame) = PHYSICAL_FRAME_ALLOCATOR.lock().allocate_contiguous(n_frames, alignment) {
+        return Ok(frame);
+    }
+    compaction::compact(n_frames, alignment).map_err(|err| {
+        logln!("Error compacting physical memory: {err:?}");
+        raise_pressure(PressureLevel::Critical);
+        physical::Error::OutOfFrames
+    })
+}
+
+/// Update the pressure level after an allocation left `n_free` free frames, reclaiming memory if
+/// that crossed the low watermark
+pub fn update_pressure(n_free: usize) {
+    let watermarks = &*WATERMARKS;
+    if n_free < watermarks.low {
+        // reclaim once when crossing the watermark rather than on every allocation below it
+        if raise_pressure(PressureLevel::Low) {
+            shrink(watermarks.high - n_free);
+        }
+    } else if n_free >= watermarks.high {
+        PRESSURE_LEVEL.store(PressureLevel::Normal as u8, Ordering::Relaxed);
+    }
+}
+
+/// Raise the pressure level to `level` and notify observers if it was lower before. Returns
+/// whether the level changed.
+fn raise_pressure(level: PressureLevel) -> bool {
+    let previous = PRESSURE_LEVEL.fetch_max(level as u8, Ordering::Relaxed);
+    if previous < level as u8 {
+        MEMORY_PRESSURE.raise();
+        true
+    } else {
+        false
+    }
+}
@@ -0,0 +1,587 @@
+//! # Swap
+//!
+//! This module evicts anonymous user memory to a swap device when physical memory runs out and
+//! brings it back through the demand paging path when it is accessed again.
+//!
+//! Only pages inside regions registered with `register_anonymous_region` are considered for
+//! eviction. Victims are chosen with the clock algorithm: a hand sweeps over every eligible page
+//! and gives recently accessed pages a second chance by clearing their accessed flag. Pages that
+//! were swapped in and have not been written to since keep their swap slot so they can be evicted
+//! again without writing them back. Address spaces of latency sensitive processes can opt out of
+//! swapping entirely with `set_swap_exempt`.
+//!
+//! Registered regions are also demand-zero: their pages are allocated and cleared on first access.
+
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use spin::Lazy;
+
+use super::compaction::{FrameMove, FrameOwner};
+use super::fault::PageFault;
+use super::linear::{MemoryMapping, PageType};
+use super::{
+    ADDRESS_SPACE_TABLE,
+    AddressSpace,
+    AddressSpaceId,
+    AddressSpaceInterface,
+    KERNEL_ASID,
+    PAddr,
+    PHYSICAL_FRAME_ALLOCATOR,
+    VAddr,
+    physical,
+    reclaim,
+};
+use crate::cpu::isa::interface::memory::address::Address;
+use crate::cpu::isa::memory::paging::PAGE_SIZE;
+use crate::cpu::isa::memory::{MemoryInterface, MemoryInterfaceImpl};
+use crate::cpu::multiprocessor::ipi;
+use crate::cpu::scheduler::sync::lockdep::Mutex;
+use crate::logln;
+
+static SWAPPER: Lazy<Mutex<Swapper>> = Lazy::new(|| {
+    Mutex::new(Swapper {
+        area: None,
+        spaces: BTreeMap::new(),
+        hand: None,
+    })
+});
+
+#[derive(Debug)]
+pub enum Error {
+    NoSwapDevice,
+    SwapDeviceAlreadyRegistered,
+    SwapFull,
+    /// The swap device failed to transfer a page
+    IoError,
+    InvalidPageType,
+    RegionInUse,
+    NoSuchAddressSpace,
+    AccessViolation,
+    PfaError(physical::Error),
+    IsaMemoryError(<MemoryInterfaceImpl as MemoryInterface>::Error),
+}
+
+impl From<physical::Error> for Error {
+    fn from(err: physical::Error) -> Self {
+        Error::PfaError(err)
+    }
+}
+
+impl From<<MemoryInterfaceImpl as MemoryInterface>::Error> for Error {
+    fn from(err: <MemoryInterfaceImpl as MemoryInterface>::Error) -> Self {
+        Error::IsaMemoryError(err)
+    }
+}
+
+/// A block device, or part of one, set aside for holding swapped out pages. The device is divided
+/// into page sized slots.
+pub trait SwapDevice: Send {
+    fn n_slots(&self) -> usize;
+    /// Fill `frame` with the contents of `slot`
+    fn read_slot(&mut self, slot: usize, frame: PAddr) -> Result<(), Error>;
+    /// Store the contents of `frame` in `slot`
+    fn write_slot(&mut self, slot: usize, frame: PAddr) -> Result<(), Error>;
+}
+
+struct SwapArea {
+    device: Box<dyn SwapDevice>,
+    /// One bit per slot, set if the slot is in use
+    slot_bitmap: Vec<u64>,
+}
+
+impl SwapArea {
+    fn allocate_slot(&mut self) -> Option<usize> {
+        let n_slots = self.device.n_slots();
+        for (word_idx, word) in self.slot_bitmap.iter_mut().enumerate() {
+            if *word != u64::MAX {
+                let bit_idx = word.trailing_ones() as usize;
+                let slot = word_idx * u64::BITS as usize + bit_idx;
+                if slot >= n_slots {
+                    return None;
+                }
+                *word |= 1 << bit_idx;
+                return Some(slot);
+            }
+        }
+        None
+    }
+
+    fn free_slot(&mut self, slot: usize) {
+        self.slot_bitmap[slot / u64::BITS as usize] &= !(1 << (slot % u64::BITS as usize));
+    }
+}
+
+struct AnonymousRegion {
+    n_pages: usize,
+    page_type: PageType,
+}
+
+/// Swap state of a single address space
+#[derive(Default)]
+struct AnonymousSpace {
+    exempt: bool,
+    regions: BTreeMap<VAddr, AnonymousRegion>,
+    /// Resident pages whose swap slot still holds an up to date copy of their contents
+    swap_cache: BTreeMap<VAddr, usize>,
+}
+
+impl AnonymousSpace {
+    fn region_containing(&self, page: VAddr) -> Option<(VAddr, &AnonymousRegion)> {
+        self.regions
+            .range(..=page)
+            .next_back()
+            .filter(|(base, region)| page < **base + region.n_pages * PAGE_SIZE)
+            .map(|(base, region)| (*base, region))
+    }
+}
+
+struct Swapper {
+    area: Option<SwapArea>,
+    spaces: BTreeMap<AddressSpaceId, AnonymousSpace>,
+    /// The last page visited by the clock hand
+    hand: Option<(AddressSpaceId, VAddr)>,
+}
+
+impl Swapper {
+    /// The first eligible page after `hand` in clock order, wrapping around at the end
+    fn advance_hand(&self) -> Option<(AddressSpaceId, VAddr)> {
+        let (hand_asid, hand_page) = match self.hand {
+            Some((asid, page)) => (asid, Some(page + PAGE_SIZE)),
+            None => (0, None),
+        };
+        let after_hand =
+            self.spaces.range(hand_asid..).filter(|(_, space)| !space.exempt).find_map(
+                |(&asid, space)| {
+                    let resume_at = hand_page.filter(|_| asid == hand_asid);
+                    space.regions.iter().find_map(|(&base, region)| {
+                        let end = base + region.n_pages * PAGE_SIZE;
+                        match resume_at {
+                            Some(page) if page >= end => None,
+                            Some(page) if page > base => Some((asid, page)),
+                            _ => Some((asid, base)),
+                        }
+                    })
+                },
+            );
+        after_hand.or_else(|| {
+            self.spaces
+                .iter()
+                .filter(|(_, space)| !space.exempt)
+                .find_map(|(&asid, space)| space.regions.keys().next().map(|&base| (asid, base)))
+        })
+    }
+
+    fn n_eligible_pages(&self) -> usize {
+        self.spaces
+            .values()
+            .filter(|space| !space.exempt)
+            .flat_map(|space| space.regions.values())
+            .map(|region| region.n_pages)
+            .sum()
+    }
+}
+
+/// Evicts anonymous memory when the frame allocator runs low
+struct SwapShrinker;
+
+impl reclaim::Shrinker for SwapShrinker {
+    fn count_reclaimable(&self) -> usize {
+        // resident pages are not tracked separately so this is an upper bound
+        SWAPPER.try_lock().map_or(0, |swapper| swapper.n_eligible_pages())
+    }
+
+    fn shrink(&self, n_frames: usize) -> usize {
+        // the swapper lock may be held by whoever is allocating
+        SWAPPER.try_lock().map_or(0, |mut swapper| reclaim_locked(&mut swapper, n_frames))
+    }
+}
+
+/// Make `device` the swap area used for evicting anonymous memory.
+pub fn register_swap_device(device: Box<dyn SwapDevice>) -> Result<(), Error> {
+    let mut swapper = SWAPPER.lock();
+    if swapper.area.is_some() {
+        return Err(Error::SwapDeviceAlreadyRegistered);
+    }
+    let n_words = device.n_slots().div_ceil(u64::BITS as usize);
+    swapper.area = Some(SwapArea {
+        device,
+        slot_bitmap: alloc::vec![0; n_words],
+    });
+    reclaim::register_shrinker(Arc::new(SwapShrinker));
+    Ok(())
+}
+
+/// Register `n_pages` pages starting at `base` in the address space identified by `asid` as
+/// anonymous memory. The pages become demand-zero and are eligible for eviction to swap.
+pub fn register_anonymous_region(
+    asid: AddressSpaceId,
+    base: VAddr,
+    n_pages: usize,
+    page_type: PageType,
+) -> Result<(), Error> {
+    if asid == KERNEL_ASID || !page_type.is_user_accessible() {
+        return Err(Error::InvalidPageType);
+    }
+    if ADDRESS_SPACE_TABLE.get(asid).is_none() {
+        return Err(Error::NoSuchAddressSpace);
+    }
+    let mut swapper = SWAPPER.lock();
+    let space = swapper.spaces.entry(asid).or_default();
+    let end = base + n_pages * PAGE_SIZE;
+    let overlaps = space
+        .regions
+        .range(..end)
+        .next_back()
+        .is_some_and(|(&region_base, region)| region_base + region.n_pages * PAGE_SIZE > base);
+    if overlaps {
+        return Err(Error::RegionInUse);
+    }
+    space.regions.insert(
+        base,
+        AnonymousRegion {
+            n_pages,
+            page_type,
+        },
+    );
+    Ok(())
+}
+
+/// Stop tracking the anonymous region starting at `base` and release the swap slots it occupies.
+///
+/// Resident pages of the region are left mapped for the caller to unmap and free.
+pub fn unregister_anonymous_region(asid: AddressSpaceId, base: VAddr) {
+    let mut swapper = SWAPPER.lock();
+    let Swapper {
+        area,
+        spaces,
+        hand,
+    } = &mut *swapper;
+    let Some(space) = spaces.get_mut(&asid) else {
+        return;
+    };
+    let Some(region) = space.regions.remove(&base) else {
+        return;
+    };
+    let end = base + region.n_pages * PAGE_SIZE;
+    let cached_slots: Vec<VAddr> =
+        space.swap_cache.range(base..end).map(|(&page, _)| page).collect();
+    let mut released_slots: Vec<usize> =
+        cached_slots.iter().filter_map(|page| space.swap_cache.remove(page)).collect();
+    if let Some(aspace) = ADDRESS_SPACE_TABLE.get(asid) {
+        let mut aspace = aspace.write();
+        for page_idx in 0..region.n_pages {
+            let page = base + page_idx * PAGE_SIZE;
+            if let Ok(Some(slot)) = aspace.get_swap_slot(page) {
+                released_slots.push(slot);
+            }
+        }
+    }
+    if let Some(area) = area {
+        for slot in released_slots {
+            area.free_slot(slot);
+        }
+    }
+    if space.regions.is_empty() && !space.exempt {
+        spaces.remove(&asid);
+    }
+    if hand.is_some_and(|(hand_asid, page)| hand_asid == asid && page >= base && page < end) {
+        *hand = None;
+    }
+}
+
+/// Exempt the address space identified by `asid` from swapping or make it eligible again.
+///
+/// Exempting an address space brings all of its swapped out pages back into memory so that none
+/// of its accesses can stall on swap I/O afterwards.
+pub fn set_swap_exempt(asid: AddressSpaceId, exempt: bool) -> Result<(), Error> {
+    let mut swapper = SWAPPER.lock();
+    swapper.spaces.entry(asid).or_default().exempt = exempt;
+    if !exempt {
+        return Ok(());
+    }
+    let aspace = ADDRESS_SPACE_TABLE.get(asid).ok_or(Error::NoSuchAddressSpace)?;
+    let Swapper {
+        area,
+        spaces,
+        ..
+    } = &mut *swapper;
+    let (Some(area), Some(space)) = (area, spaces.get_mut(&asid)) else {
+        return Ok(());
+    };
+    let mut aspace = aspace.write();
+    for (&base, region) in space.regions.iter() {
+        for page_idx in 0..region.n_pages {
+            let page = base + page_idx * PAGE_SIZE;
+            let Some(slot) = aspace.get_swap_slot(page)? else {
+                continue;
+            };
+            // the swap shrinker skips eviction while the swapper lock is held, so reclaiming here
+            // only runs the other shrinkers
+            let frame = reclaim::allocate_frame()?;
+            let mapping = MemoryMapping {
+                vaddr: page,
+                paddr: frame,
+                page_type: region.page_type,
+            };
+            if let Err(err) = area.device.read_slot(slot, frame) {
+                free_frame(frame);
+                return Err(err);
+            }
+            if let Err(err) = aspace.map_page(mapping) {
+                free_frame(frame);
+                return Err(err.into());
+            }
+            area.free_slot(slot);
+        }
+    }
+    // an exempt address space never gets evicted so there is no point in keeping swap copies
+    for (_, slot) in core::mem::take(&mut space.swap_cache) {
+        area.free_slot(slot);
+    }
+    Ok(())
+}
+
+/// Evict up to `n_frames` anonymous pages to swap and return the number of frames freed.
+pub fn reclaim(n_frames: usize) -> usize {
+    reclaim_locked(&mut SWAPPER.lock(), n_frames)
+}
+
+fn reclaim_locked(swapper: &mut Swapper, n_frames: usize) -> usize {
+    if swapper.area.is_none() {
+        return 0;
+    }
+    let mut n_freed = 0;
+    // the first sweep may do nothing but clear accessed flags, the second one then finds victims
+    let max_steps = 2 * swapper.n_eligible_pages();
+    for _ in 0..max_steps {
+        if n_freed == n_frames {
+            break;
+        }
+        let Some((asid, page)) = swapper.advance_hand() else {
+            break;
+        };
+        swapper.hand = Some((asid, page));
+        match evict_page(swapper, asid, page) {
+            Ok(true) => n_freed += 1,
+            Ok(false) => {}
+            Err(Error::SwapFull) => break,
+            Err(err) => logln!("Error evicting page {page:?} of address space {asid}: {err:?}"),
+        }
+    }
+    n_freed
+}
+
+/// Record every resident anonymous page in `movable` so that compaction can relocate it.
+pub(super) fn collect_movable_frames(movable: &mut BTreeMap<PAddr, FrameOwner>) {
+    let swapper = SWAPPER.lock();
+    for (&asid, space) in swapper.spaces.iter() {
+        let Some(aspace) = ADDRESS_SPACE_TABLE.get(asid) else {
+            continue;
+        };
+        for (&base, region) in space.regions.iter() {
+            for page_idx in 0..region.n_pages {
+                let page = base + page_idx * PAGE_SIZE;
+                // the address space lock is not held while inserting since that may allocate
+                let Ok(frame) = aspace.write().translate_address(page) else {
+                    continue;
+                };
+                movable.insert(frame, FrameOwner::Anonymous(asid, page));
+            }
+        }
+    }
+}
+
+/// Move the anonymous page mapped at `page` in the address space identified by `asid` from `old`
+/// to `new`. Returns `Ok(false)` if the page is no longer backed by `old`.
+pub(super) fn migrate_page(
+    asid: AddressSpaceId,
+    page: VAddr,
+    old: PAddr,
+    new: PAddr,
+) -> Result<bool, Error> {
+    let Some(aspace) = ADDRESS_SPACE_TABLE.get(asid) else {
+        return Ok(false);
+    };
+    // holding the swapper lock keeps the page from being evicted or unregistered meanwhile
+    let is_backed_by_old = |swapper: &Swapper| {
+        swapper.spaces.get(&asid).is_some_and(|space| space.region_containing(page).is_some())
+            && aspace.write().translate_address(page).ok() == Some(old)
+    };
+    let mut frame_move = {
+        let swapper = SWAPPER.lock();
+        if !is_backed_by_old(&swapper) {
+            return Ok(false);
+        }
+        FrameMove::begin(old, new, alloc::vec![(asid, page)])?
+    };
+    // the lock is dropped while waiting for the other LPs
+    frame_move.flush();
+    let swapper = SWAPPER.lock();
+    let result = if is_backed_by_old(&swapper) {
+        frame_move.finish().map(|_| true)
+    } else {
+        frame_move.abort();
+        Ok(false)
+    };
+    drop(swapper);
+    frame_move.end();
+    Ok(result?)
+}
+
+/// Resolve a fault on an anonymous page of the active address space by reading it back from swap
+/// or by providing a zeroed page if it has never been populated.
+///
+/// Returns `Ok(false)` if the faulting address is not inside any anonymous region.
+pub fn try_swap_in(fault: &PageFault) -> Result<bool, Error> {
+    let page = fault.vaddr.prev_aligned_to(PAGE_SIZE);
+    let Some((asid, page_type)) = find_active_region(page) else {
+        return Ok(false);
+    };
+    if (fault.is_write && !page_type.is_writable())
+        || (fault.is_instruction_fetch && page_type.is_no_execute())
+        || (fault.is_user && !page_type.is_user_accessible())
+    {
+        return Err(Error::AccessViolation);
+    }
+    let aspace = ADDRESS_SPACE_TABLE.get(asid).ok_or(Error::NoSuchAddressSpace)?;
+    // allocate before taking the swapper lock since allocating may need to reclaim
+    let frame = reclaim::allocate_zeroed_frame()?;
+    let mut swapper = SWAPPER.lock();
+    let mut aspace = aspace.write();
+    match populate_page(&mut swapper, &mut aspace, asid, page, page_type, frame, fault.is_write) {
+        Ok(true) => Ok(true),
+        Ok(false) => {
+            free_frame(frame);
+            Ok(true)
+        }
+        Err(err) => {
+            free_frame(frame);
+            Err(err)
+        }
+    }
+}
+
+/// Fill `frame` with the contents of a not present anonymous page and map it. Returns whether
+/// `frame` was used.
+fn populate_page(
+    swapper: &mut Swapper,
+    aspace: &mut AddressSpace,
+    asid: AddressSpaceId,
+    page: VAddr,
+    page_type: PageType,
+    frame: PAddr,
+    is_write: bool,
+) -> Result<bool, Error> {
+    // another LP may have resolved a fault on the same page in the meantime
+    if aspace.is_mapped(page)? {
+        return Ok(false);
+    }
+    let mapping = MemoryMapping {
+        vaddr: page,
+        paddr: frame,
+        page_type,
+    };
+    let Some(slot) = aspace.get_swap_slot(page)? else {
+        // never populated, the frame is already zeroed
+        aspace.map_page(mapping)?;
+        return Ok(true);
+    };
+    let area = swapper.area.as_mut().ok_or(Error::NoSwapDevice)?;
+    area.device.read_slot(slot, frame)?;
+    aspace.map_page(mapping)?;
+    match swapper.spaces.get_mut(&asid) {
+        // the page is mapped clean so its slot can be reused as long as it stays that way
+        Some(space) if !is_write => {
+            space.swap_cache.insert(page, slot);
+        }
+        _ => area.free_slot(slot),
+    }
+    Ok(true)
+}
+
+fn find_active_region(page: VAddr) -> Option<(AddressSpaceId, PageType)> {
+    let swapper = SWAPPER.lock();
+    swapper.spaces.iter().find_map(|(&asid, space)| {
+        let (_, region) = space.region_containing(page)?;
+        let aspace = ADDRESS_SPACE_TABLE.get(asid)?;
+        if aspace.read().is_active() {
+            Some((asid, region.page_type))
+        } else {
+            None
+        }
+    })
+}
+
+/// Advance the clock over a single page, evicting it if it has not been accessed since the last
+/// visit. Returns whether a frame was freed.
+fn evict_page(swapper: &mut Swapper, asid: AddressSpaceId, page: VAddr) -> Result<bool, Error> {
+    let Swapper {
+        area,
+        spaces,
+        ..
+    } = swapper;
+    let (Some(area), Some(space)) = (area.as_mut(), spaces.get_mut(&asid)) else {
+        return Ok(false);
+    };
+    let Some(aspace) = ADDRESS_SPACE_TABLE.get(asid) else {
+        return Ok(false);
+    };
+    let Some((_, region)) = space.region_containing(page) else {
+        return Ok(false);
+    };
+    let page_type = region.page_type;
+    // the allocation that triggered reclaim may be mapping into this address space while holding
+    // its lock, so a contended address space is passed over like a recently accessed page
+    let Some(mut aspace) = aspace.try_write() else {
+        return Ok(false);
+    };
+    // unpopulated and already swapped out pages have nothing to evict
+    if !aspace.is_mapped(page)? {
+        return Ok(false);
+    }
+    if aspace.test_and_clear_accessed(page)? {
+        // a cached translation would keep the MMU from setting the flag again
+        ipi::flush_tlb(asid, page, 1);
+        return Ok(false);
+    }
+    let is_dirty = aspace.is_dirty(page)?;
+    let frame = match space.swap_cache.remove(&page) {
+        // the copy in swap is still up to date so the frame can simply be dropped
+        Some(slot) if !is_dirty => {
+            let frame = aspace.swap_out_page(page, slot)?;
+            ipi::flush_tlb(asid, page, 1);
+            frame
+        }
+        cached_slot => {
+            let slot = match cached_slot {
+                Some(slot) => slot,
+                None => area.allocate_slot().ok_or(Error::SwapFull)?,
+            };
+            // Unmap on every LP before writing so that the contents can no longer change
+            // underneath us.
+            let frame = aspace.swap_out_page(page, slot)?;
+            ipi::flush_tlb(asid, page, 1);
+            if let Err(err) = area.device.write_slot(slot, frame) {
+                area.free_slot(slot);
+                aspace.map_page(MemoryMapping {
+                    vaddr: page,
+                    paddr: frame,
+                    page_type,
+                })?;
+                return Err(err);
+            }
+            frame
+        }
+    };
+    free_frame(frame);
+    Ok(true)
+}
+
+fn free_frame(frame: PAddr) {
+    if let Err(err) = PHYSICAL_FRAME_ALLOCATOR.lock().deallocate_frame(frame) {
+        logln!("Error deallocating frame at {frame:?}: {err:?}");
+    }
+}
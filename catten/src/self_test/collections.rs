@@ -0,0 +1,33 @@
+use crate::common::collections::handle_table::{Full, HandleTable};
+use crate::logln;
+
+pub fn test_handle_table() {
+    logln!("Starting the handle table self-test...");
+    let table = HandleTable::<u64>::new(2);
+    let first = table.insert(1).expect("Failed to insert into an empty table");
+    let second = table.insert(2).expect("Failed to insert into a table with a free slot");
+    assert!(first != 0 && second != 0);
+    assert!(matches!(table.insert(3), Err(Full(3))));
+    assert_eq!(table.len(), 2);
+    assert_eq!(*table.get(first).unwrap().read(), 1);
+    *table.get(second).unwrap().write() = 20;
+    assert_eq!(*table.get(second).unwrap().read(), 20);
+    let object = table.get(first).unwrap();
+    assert!(table.remove(first));
+    assert!(!table.remove(first));
+    // a reference taken before the removal stays valid
+    assert_eq!(*object.read(), 1);
+    assert!(table.get(first).is_none());
+    let third = table.insert(3).expect("Failed to reuse a free slot");
+    // the stale handle does not refer to the object that reuses its slot
+    assert_ne!(third, first);
+    assert!(table.get(first).is_none());
+    assert_eq!(*table.get(third).unwrap().read(), 3);
+    let mut handles = table.handles();
+    handles.sort_unstable();
+    let mut expected = alloc::vec![second, third];
+    expected.sort_unstable();
+    assert_eq!(handles, expected);
+    assert_eq!(table.iter().map(|(_, object)| *object.read()).sum::<u64>(), 23);
+    logln!("Handle table self-test: Passed.");
+}
@@ -0,0 +1,68 @@
+use alloc::sync::Arc;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::cpu::isa::lp::ops::get_lp_id;
+use crate::cpu::multiprocessor::get_lp_count;
+use crate::cpu::scheduler::deferred::work_queue::{self, WorkQueue};
+use crate::cpu::scheduler::sync::semaphore::Semaphore;
+use crate::logln;
+
+pub fn test_work_queues() {
+    logln!("Starting the work queue self-test...");
+    if get_lp_count() < 2 {
+        logln!("Work queue self-test: Skipped since there is no other LP to run workers on.");
+        return;
+    }
+    let queue = WorkQueue::new("self-test-work", 2).expect("Failed to create a work queue");
+    let count = Arc::new(AtomicUsize::new(0));
+    for _ in 0..16 {
+        let count = count.clone();
+        queue
+            .queue(move || {
+                count.fetch_add(1, Ordering::AcqRel);
+            })
+            .expect("Failed to queue work");
+    }
+    queue.flush();
+    assert_eq!(count.load(Ordering::Acquire), 16);
+    logln!("Work queue self-test: Cancelling work...");
+    // hold the workers up so that the work queued after them can not start yet
+    let gate = Arc::new(Semaphore::new(0));
+    for _ in 0..2 {
+        let gate = gate.clone();
+        queue.queue(move || gate.acquire()).expect("Failed to queue work");
+    }
+    let cancelled = {
+        let count = count.clone();
+        queue
+            .queue(move || {
+                count.fetch_add(1, Ordering::AcqRel);
+            })
+            .expect("Failed to queue work")
+    };
+    assert!(queue.cancel(cancelled));
+    assert!(!queue.cancel(cancelled));
+    gate.release();
+    gate.release();
+    queue.flush_work(cancelled);
+    queue.flush();
+    assert_eq!(count.load(Ordering::Acquire), 16);
+    queue.destroy();
+    assert!(queue.queue(|| ()).is_err());
+    logln!("Work queue self-test: Running work on each other LP...");
+    // the worker of this LP only runs once the LP switches to a thread
+    let this_lp = get_lp_id();
+    let on_own_lp = Arc::new(AtomicUsize::new(0));
+    for lp_id in (0..get_lp_count()).filter(|&lp_id| lp_id != this_lp) {
+        let on_own_lp = on_own_lp.clone();
+        work_queue::queue_work_on(lp_id, move || {
+            if get_lp_id() == lp_id {
+                on_own_lp.fetch_add(1, Ordering::AcqRel);
+            }
+        })
+        .expect("Failed to queue work on an LP");
+    }
+    work_queue::get_per_lp_work_queue().unwrap().flush();
+    assert_eq!(on_own_lp.load(Ordering::Acquire), get_lp_count() as usize - 1);
+    logln!("Work queue self-test: Passed.");
+}
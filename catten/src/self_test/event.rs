@@ -0,0 +1,98 @@
+use alloc::sync::Arc;
+
+use crate::common::time::duration::ExtDuration;
+use crate::cpu::multiprocessor::get_lp_count;
+use crate::cpu::scheduler::threads::spawn::spawn_kernel_thread;
+use crate::event::wait::{wait_all, wait_any};
+use crate::event::{Completion, EventSource, Observer, Sentinel};
+use crate::logln;
+
+static TEST_SOURCE_A: EventSource = EventSource::new();
+static TEST_SOURCE_B: EventSource = EventSource::new();
+
+pub fn test_events() {
+    logln!("Starting the event self-test...");
+    let completion = Arc::new(Completion::new(None));
+    let sentinel = Arc::new(Sentinel::new(None));
+    let source = EventSource::new();
+    source.register(completion.clone());
+    source.register(sentinel.clone());
+    assert_eq!(source.raise(), 2);
+    // observers are only notified once per registration
+    assert_eq!(source.raise(), 0);
+    assert!(completion.poll());
+    assert!(!completion.complete());
+    sentinel.notify();
+    sentinel.notify();
+    assert_eq!(sentinel.get_times_notified(), 3);
+    assert_eq!(sentinel.take_new_edges(), 3);
+    assert_eq!(sentinel.take_new_edges(), 0);
+    let observer: Arc<dyn Observer> = Arc::new(Completion::new(None));
+    source.register(observer.clone());
+    source.unregister(&observer);
+    assert!(!source.raise_one());
+    logln!("Event self-test: Counting edges with a persistent observer...");
+    let edge_counter: Arc<dyn Observer> = sentinel.clone();
+    source.register_persistent(edge_counter.clone());
+    assert_eq!(source.raise(), 1);
+    assert_eq!(source.raise(), 1);
+    assert!(!source.raise_one());
+    assert_eq!(sentinel.take_new_edges(), 2);
+    source.unregister(&edge_counter);
+    assert_eq!(source.raise(), 0);
+    #[cfg(target_arch = "x86_64")]
+    {
+        use crate::cpu::isa::interrupts::irq;
+        use crate::cpu::isa::interrupts::x2apic::X2Apic;
+        use crate::cpu::isa::lp::ops::get_lp_id;
+
+        logln!("Event self-test: Raising an IRQ vector's event from its ISR...");
+        let vector = irq::allocate_vector().expect("Failed to allocate an IRQ vector");
+        let irq_sentinel = Arc::new(Sentinel::new(None));
+        let irq_observer: Arc<dyn Observer> = irq_sentinel.clone();
+        let irq_event = irq::get_vector_event(vector).unwrap();
+        irq_event.register_persistent(irq_observer.clone());
+        // self-IPIs on the vector stand in for device interrupts
+        for edge in 1..=2 {
+            assert!(X2Apic::send_fixed_ipi(get_lp_id(), vector).is_ok());
+            while irq_sentinel.get_times_notified() < edge {
+                core::hint::spin_loop();
+            }
+        }
+        assert_eq!(irq_sentinel.take_new_edges(), 2);
+        irq_event.unregister(&irq_observer);
+        irq::free_vector(vector);
+    }
+    assert!(matches!(wait_any(&[], None), Err(crate::event::wait::Error::NoSources)));
+    if get_lp_count() < 2 {
+        logln!("Event self-test: Skipped waiting since there is no other LP to run a thread on.");
+        return;
+    }
+    logln!("Event self-test: Waiting on any of two sources...");
+    let waiter = spawn_kernel_thread("self-test-wait-any", || {
+        wait_any(&[&TEST_SOURCE_A, &TEST_SOURCE_B], None).expect("Failed to wait on any source")
+    });
+    // the waiter is only registered once it runs
+    while TEST_SOURCE_B.raise() == 0 {
+        core::hint::spin_loop();
+    }
+    assert_eq!(waiter.join(), Some(Some(1)));
+    logln!("Event self-test: Waiting on all of two sources...");
+    let waiter = spawn_kernel_thread("self-test-wait-all", || {
+        wait_all(&[&TEST_SOURCE_A, &TEST_SOURCE_B], None).expect("Failed to wait on all sources")
+    });
+    while TEST_SOURCE_A.raise() == 0 {
+        core::hint::spin_loop();
+    }
+    while TEST_SOURCE_B.raise() == 0 {
+        core::hint::spin_loop();
+    }
+    assert_eq!(waiter.join(), Some(true));
+    logln!("Event self-test: Timing out...");
+    let waiter = spawn_kernel_thread("self-test-wait-timeout", || {
+        wait_any(&[&TEST_SOURCE_A], Some(ExtDuration::from_millis(1)))
+            .expect("Failed to wait with a timeout")
+    });
+    assert_eq!(waiter.join(), Some(None));
+    logln!("Event self-test: Passed.");
+}
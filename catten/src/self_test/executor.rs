@@ -0,0 +1,44 @@
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::common::time::duration::ExtDuration;
+use crate::cpu::multiprocessor::get_lp_count;
+use crate::cpu::scheduler::executor::futures::{Timer, raised, yield_now};
+use crate::cpu::scheduler::executor::{get_executor, spawn_on};
+use crate::event::EventSource;
+use crate::logln;
+
+static TEST_SOURCE: EventSource = EventSource::new();
+static TEST_STAGE: AtomicUsize = AtomicUsize::new(0);
+
+pub fn test_executor() {
+    logln!("Starting the async executor self-test...");
+    // the executor of this LP only runs once the LP switches to a thread
+    if get_lp_count() < 2 {
+        logln!(
+            "Async executor self-test: Skipped since there is no other LP to run an executor on."
+        );
+        return;
+    }
+    let executor = get_executor(1).expect("The executors have not been started");
+    spawn_on(1, async {
+        raised(&TEST_SOURCE).await;
+        TEST_STAGE.store(1, Ordering::Release);
+        Timer::after(ExtDuration::from_millis(1)).await;
+        yield_now().await;
+        TEST_STAGE.store(2, Ordering::Release);
+    })
+    .expect("Failed to spawn a task");
+    logln!("Async executor self-test: Waking a task with an event...");
+    // the observer is only registered once the task is first polled
+    while TEST_SOURCE.raise() == 0 {
+        core::hint::spin_loop();
+    }
+    logln!("Async executor self-test: Waiting for a timer...");
+    while TEST_STAGE.load(Ordering::Acquire) != 2 {
+        core::hint::spin_loop();
+    }
+    while executor.get_task_count() != 0 {
+        core::hint::spin_loop();
+    }
+    logln!("Async executor self-test: Passed.");
+}
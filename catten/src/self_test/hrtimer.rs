@@ -0,0 +1,60 @@
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use spin::Mutex;
+
+use crate::common::time::duration::ExtDuration;
+use crate::cpu::isa::timers::get_monotonic_time;
+use crate::cpu::multiprocessor::get_lp_count;
+use crate::cpu::scheduler::hrtimer::{self, sleep_for};
+use crate::cpu::scheduler::sync::condvar::Condvar;
+use crate::cpu::scheduler::sync::mutex;
+use crate::cpu::scheduler::sync::semaphore::Semaphore;
+use crate::cpu::scheduler::threads::spawn::spawn_kernel_thread;
+use crate::logln;
+
+pub fn test_hrtimers() {
+    logln!("Starting the high-resolution timer self-test...");
+    if get_lp_count() < 2 {
+        logln!("Timer self-test: Skipped since there is no other LP to run a thread on.");
+        return;
+    }
+    logln!("Timer self-test: Arming, cancelling and sleeping...");
+    // the boot LP has not yielded to the scheduler, so its timers would not fire yet
+    let tester = spawn_kernel_thread("self-test-hrtimer", || {
+        // only locked by the callbacks, which run with interrupts masked, once the timers fired
+        let fired = Arc::new(Mutex::new(Vec::new()));
+        let record = |id: usize| {
+            let fired = fired.clone();
+            move || fired.lock().push(id)
+        };
+        let late = hrtimer::arm_after(ExtDuration::from_millis(2), record(2));
+        let early = hrtimer::arm_after(ExtDuration::from_millis(1), record(1));
+        let cancelled = hrtimer::arm_after(ExtDuration::from_millis(1), record(3));
+        let is_cancelled = hrtimer::cancel(&cancelled);
+        let start = get_monotonic_time();
+        sleep_for(ExtDuration::from_millis(5));
+        let slept = get_monotonic_time() - start;
+        let is_expired_cancellable = hrtimer::cancel(&early) || hrtimer::cancel(&late);
+        let fired = fired.lock().clone();
+        (is_cancelled, is_expired_cancellable, slept, fired)
+    });
+    let (is_cancelled, is_expired_cancellable, slept, fired) =
+        tester.join().expect("The timer test thread did not finish");
+    assert!(is_cancelled);
+    assert!(!is_expired_cancellable);
+    assert!(slept >= ExtDuration::from_millis(5));
+    assert_eq!(fired, alloc::vec![1, 2]);
+    logln!("Timer self-test: Timing out blocking waits...");
+    let tester = spawn_kernel_thread("self-test-hrtimer-waits", || {
+        let semaphore = Semaphore::new(0);
+        let is_acquired = semaphore.acquire_timeout(ExtDuration::from_millis(1));
+        let condvar = Condvar::new();
+        let mutex = mutex::Mutex::new(());
+        let (guard, is_timed_out) = condvar.wait_timeout(mutex.lock(), ExtDuration::from_millis(1));
+        drop(guard);
+        (is_acquired, is_timed_out)
+    });
+    assert_eq!(tester.join(), Some((false, true)));
+    logln!("Timer self-test: Passed.");
+}
@@ -0,0 +1,52 @@
+use crate::common::size::kibibytes;
+use crate::cpu::isa::interface::memory::address::VirtualAddress;
+use crate::cpu::isa::memory::paging::PAGE_SIZE;
+use crate::logln;
+use crate::memory::linear::PageType;
+use crate::memory::object::{MemoryObject, Population};
+use crate::memory::{KERNEL_ASID, PAddr, PHYSICAL_FRAME_ALLOCATOR, compaction, reclaim};
+
+pub fn test_compaction() {
+    logln!("Starting the compaction self-test...");
+    logln!("Compaction self-test: Filling a memory object with a pattern...");
+    let object = MemoryObject::new(8, Population::Eager).expect("Error creating memory object");
+    let mapping =
+        object.map(KERNEL_ASID, PageType::KernelData).expect("Error mapping memory object");
+    let ptr: *mut u64 = mapping.base().into_mut();
+    const WORDS: usize = 8 * PAGE_SIZE / size_of::<u64>();
+    for i in 0..WORDS {
+        unsafe { ptr.add(i).write_volatile(!(i as u64)) };
+    }
+    logln!("Compaction self-test: Allocating an aligned contiguous range...");
+    const N_FRAMES: usize = 16;
+    const ALIGNMENT: usize = kibibytes(64);
+    let base = reclaim::allocate_contiguous(N_FRAMES, ALIGNMENT)
+        .expect("Error allocating contiguous frames");
+    assert_eq!(<PAddr as Into<usize>>::into(base) % ALIGNMENT, 0);
+    release_range(base, N_FRAMES);
+    logln!("Compaction self-test: Compacting a range of {} frames...", N_FRAMES);
+    let base = compaction::compact(N_FRAMES, ALIGNMENT).expect("Error compacting memory");
+    assert_eq!(<PAddr as Into<usize>>::into(base) % ALIGNMENT, 0);
+    {
+        let pfa = PHYSICAL_FRAME_ALLOCATOR.lock();
+        for frame_idx in 0..N_FRAMES {
+            assert!(!pfa.is_frame_free(base + (frame_idx * PAGE_SIZE) as isize).unwrap());
+        }
+    }
+    release_range(base, N_FRAMES);
+    logln!("Compaction self-test: Checking that the object contents survived...");
+    for i in 0..WORDS {
+        assert_eq!(unsafe { ptr.add(i).read_volatile() }, !(i as u64));
+    }
+    drop(mapping);
+    drop(object);
+    logln!("Compaction self-test: PASSED");
+}
+
+fn release_range(base: PAddr, n_frames: usize) {
+    let mut pfa = PHYSICAL_FRAME_ALLOCATOR.lock();
+    for frame_idx in 0..n_frames {
+        pfa.deallocate_frame(base + (frame_idx * PAGE_SIZE) as isize)
+            .expect("Error deallocating contiguous frames");
+    }
+}
@@ -1,3 +1,7 @@
 pub mod allocator;
+pub mod compaction;
+pub mod object;
 pub mod pmem;
+pub mod reclaim;
+pub mod swap;
 pub mod vmem;
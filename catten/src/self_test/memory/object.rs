@@ -0,0 +1,61 @@
+use crate::cpu::isa::interface::memory::address::VirtualAddress;
+use crate::cpu::isa::memory::paging::PAGE_SIZE;
+use crate::logln;
+use crate::memory::linear::PageType;
+use crate::memory::object::{MemoryObject, Population};
+use crate::memory::{AddressSpaceInterface, KERNEL_AS, KERNEL_ASID};
+
+pub fn test_memory_object() {
+    logln!("Starting the memory object self-test...");
+    logln!("Memory object self-test: Creating a 2 page lazily populated object...");
+    let object = MemoryObject::new(2, Population::Lazy).expect("Error creating memory object");
+    logln!("Memory object self-test: Mapping the object twice into the kernel address space...");
+    let rw_mapping =
+        object.map(KERNEL_ASID, PageType::KernelData).expect("Error mapping memory object");
+    let ro_mapping =
+        object.map(KERNEL_ASID, PageType::KernelRoData).expect("Error mapping memory object");
+    let (rw_base, ro_base) = (rw_mapping.base(), ro_mapping.base());
+    assert_ne!(rw_base, ro_base);
+    logln!("Memory object self-test: Mapped at {:?} and {:?}", rw_base, ro_base);
+    logln!("Memory object self-test: Writing through the writable mapping...");
+    let rw_ptr: *mut u64 = rw_base.into_mut();
+    let ro_ptr: *const u64 = ro_base.into_ptr();
+    const WORDS: usize = 2 * PAGE_SIZE / size_of::<u64>();
+    for i in 0..WORDS {
+        unsafe {
+            // freshly populated pages must start out zeroed
+            assert_eq!(rw_ptr.add(i).read_volatile(), 0);
+            rw_ptr.add(i).write_volatile(i as u64);
+        }
+    }
+    logln!("Memory object self-test: Reading back through the read only mapping...");
+    for i in 0..WORDS {
+        assert_eq!(unsafe { ro_ptr.add(i).read_volatile() }, i as u64);
+    }
+    let ro_range = KERNEL_AS
+        .lock()
+        .mappings()
+        .find(|range| range.contains(ro_base))
+        .expect("The read only mapping is missing from the kernel address space.");
+    assert_eq!(ro_range.flags.page_type(), PageType::KernelRoData);
+    logln!("Memory object self-test: Shrinking the object to a single page...");
+    object.resize(1).expect("Error shrinking memory object");
+    assert_eq!(object.n_pages(), 1);
+    {
+        let mut kas = KERNEL_AS.lock();
+        for base in [rw_base, ro_base] {
+            assert!(kas.is_mapped(base).unwrap());
+            assert!(!kas.is_mapped(base + PAGE_SIZE).unwrap());
+        }
+    }
+    logln!("Memory object self-test: Unmapping the object...");
+    drop(rw_mapping);
+    drop(ro_mapping);
+    drop(object);
+    {
+        let mut kas = KERNEL_AS.lock();
+        assert!(!kas.is_mapped(rw_base).unwrap());
+        assert!(!kas.is_mapped(ro_base).unwrap());
+    }
+    logln!("Memory object self-test: PASSED");
+}
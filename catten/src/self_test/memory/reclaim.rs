@@ -0,0 +1,66 @@
+use alloc::sync::Arc;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::event::{Completion, Observer};
+use crate::logln;
+use crate::memory::reclaim::{self, MEMORY_PRESSURE, PressureLevel, Shrinker};
+
+const N_RECLAIMABLE: usize = 8;
+
+/// A shrinker that pretends to free frames from a fixed budget
+struct TestShrinker {
+    n_reclaimable: AtomicUsize,
+    n_calls: AtomicUsize,
+}
+
+impl Shrinker for TestShrinker {
+    fn count_reclaimable(&self) -> usize {
+        self.n_reclaimable.load(Ordering::Relaxed)
+    }
+
+    fn shrink(&self, n_frames: usize) -> usize {
+        self.n_calls.fetch_add(1, Ordering::Relaxed);
+        let n_reclaimable = self.n_reclaimable.load(Ordering::Relaxed);
+        let n_freed = n_frames.min(n_reclaimable);
+        self.n_reclaimable.store(n_reclaimable - n_freed, Ordering::Relaxed);
+        n_freed
+    }
+}
+
+pub fn test_reclaim() {
+    logln!("Starting the reclaim self-test...");
+    let shrinker = Arc::new(TestShrinker {
+        n_reclaimable: AtomicUsize::new(N_RECLAIMABLE),
+        n_calls: AtomicUsize::new(0),
+    });
+    let shrinker_id = reclaim::register_shrinker(shrinker.clone());
+    logln!("Reclaim self-test: Shrinking directly...");
+    assert!(reclaim::shrink(N_RECLAIMABLE / 2) >= N_RECLAIMABLE / 2);
+    logln!("Reclaim self-test: Crossing the low watermark...");
+    let watermarks = reclaim::get_watermarks();
+    reclaim::update_pressure(watermarks.high);
+    assert_eq!(reclaim::pressure_level(), PressureLevel::Normal);
+    let n_calls = shrinker.n_calls.load(Ordering::Relaxed);
+    let pressure = Arc::new(Completion::new(None));
+    MEMORY_PRESSURE.register(pressure.clone());
+    reclaim::update_pressure(watermarks.low - 1);
+    assert_eq!(reclaim::pressure_level(), PressureLevel::Low);
+    assert!(pressure.poll());
+    assert!(shrinker.n_calls.load(Ordering::Relaxed) > n_calls);
+    logln!("Reclaim self-test: Staying below the low watermark...");
+    let n_calls = shrinker.n_calls.load(Ordering::Relaxed);
+    let pressure: Arc<dyn Observer> = Arc::new(Completion::new(None));
+    MEMORY_PRESSURE.register(pressure.clone());
+    reclaim::update_pressure(watermarks.low - 1);
+    // only crossing the watermark raises the level and reclaims
+    assert_eq!(shrinker.n_calls.load(Ordering::Relaxed), n_calls);
+    MEMORY_PRESSURE.unregister(&pressure);
+    reclaim::update_pressure(watermarks.high);
+    assert_eq!(reclaim::pressure_level(), PressureLevel::Normal);
+    logln!("Reclaim self-test: Unregistering the shrinker...");
+    reclaim::unregister_shrinker(shrinker_id);
+    let n_calls = shrinker.n_calls.load(Ordering::Relaxed);
+    reclaim::shrink(1);
+    assert_eq!(shrinker.n_calls.load(Ordering::Relaxed), n_calls);
+    logln!("Reclaim self-test: PASSED");
+}
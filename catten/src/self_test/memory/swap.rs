@@ -0,0 +1,100 @@
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+
+use crate::cpu::isa::interface::memory::address::VirtualAddress;
+use crate::cpu::isa::memory::paging::PAGE_SIZE;
+use crate::cpu::multiprocessor::ipi;
+use crate::logln;
+use crate::memory::linear::PageType;
+use crate::memory::linear::address_map::{LA_MAP, RegionType};
+use crate::memory::swap::{self, SwapDevice};
+use crate::memory::{
+    ADDRESS_SPACE_TABLE,
+    AddressSpace,
+    AddressSpaceInterface,
+    PAddr,
+    PHYSICAL_FRAME_ALLOCATOR,
+    with_address_space,
+};
+
+const N_PAGES: usize = 4;
+
+/// A swap device backed by kernel heap memory
+struct RamSwapDevice {
+    slots: Vec<Box<[u8; PAGE_SIZE]>>,
+}
+
+impl SwapDevice for RamSwapDevice {
+    fn n_slots(&self) -> usize {
+        self.slots.len()
+    }
+
+    fn read_slot(&mut self, slot: usize, frame: PAddr) -> Result<(), swap::Error> {
+        let frame: *mut u8 = frame.into();
+        unsafe { core::ptr::copy_nonoverlapping(self.slots[slot].as_ptr(), frame, PAGE_SIZE) };
+        Ok(())
+    }
+
+    fn write_slot(&mut self, slot: usize, frame: PAddr) -> Result<(), swap::Error> {
+        let frame: *mut u8 = frame.into();
+        unsafe { core::ptr::copy_nonoverlapping(frame, self.slots[slot].as_mut_ptr(), PAGE_SIZE) };
+        Ok(())
+    }
+}
+
+pub fn test_swap() {
+    logln!("Starting the swap self-test...");
+    logln!("Swap self-test: Registering a RAM backed swap device...");
+    let device = RamSwapDevice {
+        slots: (0..2 * N_PAGES).map(|_| Box::new([0; PAGE_SIZE])).collect(),
+    };
+    if let Err(err) = swap::register_swap_device(Box::new(device)) {
+        logln!("Swap self-test: Skipped since a swap device is already registered: {:?}", err);
+        return;
+    }
+    // The test address space shares the page tables of the kernel address space, so it is active
+    // wherever the kernel address space is and its pages can be accessed directly.
+    let asid = ADDRESS_SPACE_TABLE
+        .insert(AddressSpace::get_current())
+        .expect("Error inserting the test address space");
+    let base = LA_MAP.get_region(RegionType::Application).base;
+    assert!(!with_address_space(asid, |aspace| aspace.is_mapped(base).unwrap()).unwrap());
+    swap::register_anonymous_region(asid, base, N_PAGES, PageType::UserData)
+        .expect("Error registering an anonymous region");
+    logln!("Swap self-test: Filling {} demand-zero pages with a pattern...", N_PAGES);
+    let ptr: *mut u64 = base.into_mut();
+    const WORDS: usize = N_PAGES * PAGE_SIZE / size_of::<u64>();
+    for i in 0..WORDS {
+        unsafe {
+            assert_eq!(ptr.add(i).read_volatile(), 0);
+            ptr.add(i).write_volatile(!(i as u64));
+        }
+    }
+    logln!("Swap self-test: Evicting the pages...");
+    assert_eq!(swap::reclaim(N_PAGES), N_PAGES);
+    with_address_space(asid, |aspace| {
+        for page_idx in 0..N_PAGES {
+            let page = base + page_idx * PAGE_SIZE;
+            assert!(!aspace.is_mapped(page).unwrap());
+            assert!(aspace.get_swap_slot(page).unwrap().is_some());
+        }
+    });
+    logln!("Swap self-test: Faulting the pages back in...");
+    for i in 0..WORDS {
+        assert_eq!(unsafe { ptr.add(i).read_volatile() }, !(i as u64));
+    }
+    swap::unregister_anonymous_region(asid, base);
+    with_address_space(asid, |aspace| {
+        for page_idx in 0..N_PAGES {
+            let page = base + page_idx * PAGE_SIZE;
+            let frame = aspace.unmap_page(page).expect("Error unmapping a swapped in page");
+            ipi::flush_tlb(asid, page, 1);
+            PHYSICAL_FRAME_ALLOCATOR
+                .lock()
+                .deallocate_frame(frame)
+                .expect("Error deallocating a swapped in page");
+        }
+    });
+    ADDRESS_SPACE_TABLE.remove(asid);
+    logln!("Swap self-test: PASSED");
+}
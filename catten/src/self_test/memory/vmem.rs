@@ -1,9 +1,10 @@
 use crate::cpu::isa::interface::memory::AddressSpaceInterface;
 use crate::cpu::isa::interface::memory::address::VirtualAddress;
 use crate::cpu::isa::memory::paging::AddressSpace;
+use crate::cpu::multiprocessor::ipi;
 use crate::logln;
-use crate::memory::PHYSICAL_FRAME_ALLOCATOR;
 use crate::memory::linear::{MemoryMapping, PageType, VAddr};
+use crate::memory::{KERNEL_ASID, PHYSICAL_FRAME_ALLOCATOR};
 
 pub fn test_vmem() {
     logln!("Entering Virtual Memory Subsystem Self Test");
@@ -41,9 +42,25 @@ pub fn test_vmem() {
         let read_value = addr.read();
         assert_eq!(read_value, MAGIC_NUMBER);
         logln!("Magic number matches.");
+        logln!("Looking up the test page in the address space mapping iterator.");
+        let test_range = current_as
+            .mappings()
+            .find(|range| range.contains(higher_half_start))
+            .expect("The mapping iterator did not report the test page.");
+        assert_eq!(test_range.flags.page_type(), PageType::KernelData);
+        logln!("Mapping iterator reported the test page as {}", test_range);
+        logln!("Looking up the page tables of the test page in the page table iterator.");
+        let test_pt_vaddr = VAddr::from(0xffff_ffff_ffe0_0000usize);
+        assert!(
+            current_as.page_tables().any(|table| table.level == 3 && table.vaddr == test_pt_vaddr),
+            "The page table iterator did not report the table mapping the test page."
+        );
+        // the top level table is reported last, once everything below it has been walked
+        assert_eq!(current_as.page_tables().last().map(|table| table.level), Some(0));
         logln!("Test completed successfully.");
         logln!("Unmapping test page.");
         current_as.unmap_page(higher_half_start).expect("Error unmapping page.");
+        ipi::flush_tlb(KERNEL_ASID, higher_half_start, 1);
         logln!("Test page successfully unmapped.");
         logln!("All virtual memory tests passed!");
     }
@@ -5,7 +5,15 @@
 //! some tests in this module. In software engineering terminology the tests in this module should
 //! be whitebox integration tests that can be run after Catten initializes itself.
 
+pub mod collections;
+pub mod deferred;
+pub mod event;
+pub mod executor;
+pub mod hrtimer;
 pub mod memory;
+pub mod rcu;
+pub mod scheduler;
+pub mod sync;
 
 use crate::logln;
 
@@ -14,5 +22,31 @@ pub fn run_self_tests() {
     memory::pmem::test_pmem();
     memory::vmem::test_vmem();
     memory::allocator::test_allocator();
+    memory::object::test_memory_object();
+    memory::reclaim::test_reclaim();
+    memory::compaction::test_compaction();
+    memory::swap::test_swap();
+    collections::test_handle_table();
+    #[cfg(target_arch = "x86_64")]
+    scheduler::test_xstate();
+    #[cfg(target_arch = "x86_64")]
+    scheduler::test_user_bases();
+    scheduler::test_mlfq();
+    scheduler::test_edf();
+    scheduler::test_gang_scheduling();
+    scheduler::test_lp_set();
+    scheduler::test_scheduler();
+    scheduler::test_spawn();
+    scheduler::test_preemption();
+    scheduler::test_balance();
+    scheduler::test_idle();
+    sync::test_priority_lending();
+    sync::test_blocking_sync();
+    sync::test_lockdep();
+    rcu::test_rcu();
+    hrtimer::test_hrtimers();
+    event::test_events();
+    executor::test_executor();
+    deferred::test_work_queues();
     logln!("Testing Complete. All Tests Passed!");
 }
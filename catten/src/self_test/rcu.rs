@@ -0,0 +1,62 @@
+use alloc::boxed::Box;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use crate::common::time::duration::ExtDuration;
+use crate::cpu::isa::timers::get_monotonic_time;
+use crate::cpu::multiprocessor::get_lp_count;
+use crate::cpu::scheduler::sync::rcu::hash_map::RcuHashMap;
+use crate::cpu::scheduler::sync::rcu::{call_rcu, rcu_read_lock, synchronize_rcu};
+use crate::cpu::scheduler::threads::spawn::spawn_kernel_thread;
+use crate::logln;
+
+static TEST_MAP: RcuHashMap<u32, u64> = RcuHashMap::new();
+static IS_CALLBACK_RUN: AtomicBool = AtomicBool::new(false);
+static IS_SYNCHRONIZED: AtomicBool = AtomicBool::new(false);
+
+pub fn test_rcu() {
+    logln!("Starting the RCU self-test...");
+    assert!(TEST_MAP.is_empty());
+    assert_eq!(TEST_MAP.insert(1, 10), None);
+    assert_eq!(TEST_MAP.insert(2, 20), None);
+    assert_eq!(TEST_MAP.insert(1, 11), Some(10));
+    {
+        let guard = rcu_read_lock();
+        let value = TEST_MAP.get(&1, &guard).expect("The entry is missing");
+        // the copy the reference points into stays alive while the guard is held
+        assert_eq!(TEST_MAP.remove(&1), Some(11));
+        assert_eq!(*value, 11);
+        assert!(TEST_MAP.get(&1, &guard).is_none());
+    }
+    assert_eq!(TEST_MAP.get_cloned(&2), Some(20));
+    assert_eq!(TEST_MAP.keys(), alloc::vec![2]);
+    assert_eq!(TEST_MAP.len(), 1);
+    // a grace period ends right away if no other LP is reading
+    synchronize_rcu();
+    if get_lp_count() < 2 {
+        logln!("RCU self-test: Skipped waiting since there is no other LP to run a thread on.");
+        return;
+    }
+    logln!("RCU self-test: Waiting for a grace period...");
+    let guard = rcu_read_lock();
+    let synchronizer = spawn_kernel_thread("self-test-rcu-sync", || {
+        synchronize_rcu();
+        IS_SYNCHRONIZED.store(true, Ordering::Release);
+    });
+    let deadline = get_monotonic_time() + ExtDuration::from_millis(10);
+    while get_monotonic_time() < deadline {
+        assert!(
+            !IS_SYNCHRONIZED.load(Ordering::Acquire),
+            "A grace period ended during a read-side critical section"
+        );
+        core::hint::spin_loop();
+    }
+    drop(guard);
+    synchronizer.join();
+    assert!(IS_SYNCHRONIZED.load(Ordering::Acquire));
+    logln!("RCU self-test: Running a callback...");
+    call_rcu(Box::new(|| IS_CALLBACK_RUN.store(true, Ordering::Release)));
+    while !IS_CALLBACK_RUN.load(Ordering::Acquire) {
+        core::hint::spin_loop();
+    }
+    logln!("RCU self-test: Passed.");
+}
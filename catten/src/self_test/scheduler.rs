@@ -0,0 +1,587 @@
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use crate::common::time::duration::ExtDuration;
+use crate::cpu::isa::interface::memory::address::VirtualAddress;
+use crate::cpu::isa::lp::ops::{get_lp_id, halt};
+use crate::cpu::isa::timers::get_monotonic_time;
+use crate::cpu::multiprocessor::get_lp_count;
+use crate::cpu::multiprocessor::lp_set::LpSet;
+use crate::cpu::scheduler::hrtimer::sleep_for;
+use crate::cpu::scheduler::idle::{self, CState};
+use crate::cpu::scheduler::lp_schedulers::edf::{Edf, RtPolicy};
+use crate::cpu::scheduler::lp_schedulers::gang::GangScheduling;
+use crate::cpu::scheduler::lp_schedulers::mlfq::Mlfq;
+use crate::cpu::scheduler::lp_schedulers::strategy::{LsStratIfce, QueuedThread, RoundRobin};
+use crate::cpu::scheduler::system_scheduler::SYSTEM_SCHEDULER;
+use crate::cpu::scheduler::threads::spawn::spawn_kernel_thread;
+use crate::cpu::scheduler::threads::{
+    MASTER_THREAD_TABLE,
+    Priority,
+    RtParams,
+    Thread,
+    ThreadState,
+    add_thread,
+};
+use crate::logln;
+use crate::memory::{KERNEL_ASID, VAddr};
+
+static TEST_THREAD_ID: AtomicUsize = AtomicUsize::new(0);
+static TEST_THREAD_RAN: AtomicBool = AtomicBool::new(false);
+static TEST_SPINNER_RUNNING: AtomicBool = AtomicBool::new(false);
+
+pub fn test_scheduler() {
+    logln!("Starting the scheduler self-test...");
+    if get_lp_count() < 2 {
+        // the only LP does not run threads until it yields at the end of bsp_main
+        logln!("Scheduler self-test: Skipped since there is no other LP to run a thread on.");
+        return;
+    }
+    logln!("Scheduler self-test: Creating a kernel thread...");
+    let entry_point = VAddr::from_ptr(test_thread_entry as *const ());
+    let tid =
+        add_thread(Thread::new(false, KERNEL_ASID, entry_point)).expect("The thread table is full");
+    TEST_THREAD_ID.store(tid, Ordering::Release);
+    // this LP has not yielded yet so it must not be picked
+    let pinned_lp = (0..get_lp_count()).rev().find(|&lp_id| lp_id != get_lp_id()).unwrap();
+    assert!(matches!(
+        SYSTEM_SCHEDULER.set_thread_affinity(tid, Some(LpSet::new())),
+        Err(crate::cpu::scheduler::system_scheduler::Error::InvalidAffinity)
+    ));
+    SYSTEM_SCHEDULER
+        .set_thread_affinity(tid, Some(LpSet::from_lps(&[pinned_lp]).unwrap()))
+        .expect("Error setting the thread's affinity");
+    logln!("Scheduler self-test: Submitting thread {} pinned to LP{}...", tid, pinned_lp);
+    let lp_id = SYSTEM_SCHEDULER.submit_ready_thread(tid).expect("Error submitting the thread");
+    assert_eq!(lp_id, pinned_lp);
+    logln!("Scheduler self-test: Placed on LP{}, waiting for it to run...", lp_id);
+    let thread = MASTER_THREAD_TABLE.get(tid).unwrap();
+    while !matches!(thread.read().state, ThreadState::Terminated) {
+        core::hint::spin_loop();
+    }
+    assert!(TEST_THREAD_RAN.load(Ordering::Acquire));
+    // the thread is gone from the thread table once the reaper has freed it
+    assert!(matches!(
+        SYSTEM_SCHEDULER.submit_ready_thread(tid),
+        Err(crate::cpu::scheduler::system_scheduler::Error::InvalidThreadState
+            | crate::cpu::scheduler::system_scheduler::Error::InvalidThread)
+    ));
+    logln!("Scheduler self-test: Passed.");
+}
+
+pub fn test_spawn() {
+    logln!("Starting the kernel thread spawning self-test...");
+    if get_lp_count() < 2 {
+        logln!("Spawning self-test: Skipped since there is no other LP to run a thread on.");
+        return;
+    }
+    let handle = spawn_kernel_thread("self-test", || (1..=10u64).sum::<u64>());
+    let tid = handle.get_tid();
+    let thread = MASTER_THREAD_TABLE.get(tid).unwrap();
+    assert_eq!(thread.read().name.as_deref(), Some("self-test"));
+    logln!("Spawning self-test: Spawned thread {}, joining it...", tid);
+    assert_eq!(handle.join(), Some(55));
+    logln!("Spawning self-test: Spawning and detaching a thread...");
+    let detached =
+        spawn_kernel_thread("self-test-detached", || TEST_THREAD_RAN.load(Ordering::Acquire));
+    let detached_tid = detached.get_tid();
+    detached.detach();
+    let thread = MASTER_THREAD_TABLE.get(detached_tid).unwrap();
+    while !matches!(thread.read().state, ThreadState::Terminated) {
+        core::hint::spin_loop();
+    }
+    logln!("Spawning self-test: Terminating a thread running on another LP...");
+    let spinner = spawn_kernel_thread("self-test-spinner", || {
+        TEST_SPINNER_RUNNING.store(true, Ordering::Release);
+        while TEST_SPINNER_RUNNING.load(Ordering::Acquire) {
+            core::hint::spin_loop();
+        }
+    });
+    while !TEST_SPINNER_RUNNING.load(Ordering::Acquire) {
+        core::hint::spin_loop();
+    }
+    SYSTEM_SCHEDULER.terminate_threads(alloc::vec![spinner.get_tid()]);
+    // the closure never returned so there is no result
+    assert!(spinner.join().is_none());
+    logln!("Spawning self-test: Passed.");
+}
+
+pub fn test_preemption() {
+    use alloc::sync::Arc;
+
+    use crate::cpu::scheduler::preemption::PreemptGuard;
+
+    // several time slices of the default strategy
+    const SPIN_MILLIS: u128 = 50;
+    const TIMEOUT_MILLIS: u128 = 1000;
+
+    logln!("Starting the preemption self-test...");
+    if get_lp_count() < 2 {
+        logln!("Preemption self-test: Skipped since there is no LP to pin the test threads to.");
+        return;
+    }
+    // Both threads share an LP so the second one can only run there when the first one is
+    // preempted. A thread may first run on another LP until it next switches, so each one sleeps
+    // until it finds itself on the shared LP.
+    let lp = LpSet::from_lps(&[1]).unwrap();
+    let wait_for_lp = || {
+        while get_lp_id() != 1 {
+            sleep_for(ExtDuration::from_millis(1));
+        }
+    };
+    let spin_until = |flag: &AtomicBool, millis: u128| {
+        let deadline = get_monotonic_time() + ExtDuration::from_millis(millis);
+        while !flag.load(Ordering::SeqCst) && get_monotonic_time() < deadline {
+            core::hint::spin_loop();
+        }
+        flag.load(Ordering::SeqCst)
+    };
+    let run_pinned = |spinner: Box<dyn FnOnce() -> bool + Send>,
+                      is_spinning: Arc<AtomicBool>,
+                      has_run: Arc<AtomicBool>| {
+        let spinner = spawn_kernel_thread("self-test-preempt-spinner", spinner);
+        let runner = spawn_kernel_thread("self-test-preempt-runner", move || {
+            while get_lp_id() != 1 || !is_spinning.load(Ordering::SeqCst) {
+                sleep_for(ExtDuration::from_millis(1));
+            }
+            has_run.store(true, Ordering::SeqCst);
+        });
+        for tid in [spinner.get_tid(), runner.get_tid()] {
+            SYSTEM_SCHEDULER
+                .set_thread_affinity(tid, Some(lp.clone()))
+                .expect("Failed to pin a preemption test thread");
+        }
+        runner.join();
+        spinner.join()
+    };
+
+    logln!("Preemption self-test: Preempting a thread at the end of its time slice...");
+    let is_spinning = Arc::new(AtomicBool::new(false));
+    let has_run = Arc::new(AtomicBool::new(false));
+    let spinner = {
+        let is_spinning = is_spinning.clone();
+        let has_run = has_run.clone();
+        move || {
+            wait_for_lp();
+            is_spinning.store(true, Ordering::SeqCst);
+            // never yields, so the runner only gets the LP if this thread is preempted
+            spin_until(&has_run, TIMEOUT_MILLIS)
+        }
+    };
+    assert_eq!(run_pinned(Box::new(spinner), is_spinning, has_run), Some(true));
+
+    logln!("Preemption self-test: Deferring the switch until preemption is enabled again...");
+    let is_spinning = Arc::new(AtomicBool::new(false));
+    let has_run = Arc::new(AtomicBool::new(false));
+    let spinner = {
+        let is_spinning = is_spinning.clone();
+        let has_run = has_run.clone();
+        move || {
+            wait_for_lp();
+            let preempt_guard = PreemptGuard::new();
+            is_spinning.store(true, Ordering::SeqCst);
+            let has_run_while_disabled = spin_until(&has_run, SPIN_MILLIS);
+            // dropping the guard performs the switch that was deferred
+            drop(preempt_guard);
+            !has_run_while_disabled && spin_until(&has_run, TIMEOUT_MILLIS)
+        }
+    };
+    assert_eq!(run_pinned(Box::new(spinner), is_spinning, has_run), Some(true));
+    logln!("Preemption self-test: Passed.");
+}
+
+pub fn test_balance() {
+    use alloc::sync::Arc;
+    use alloc::vec::Vec;
+
+    use crate::cpu::isa::interface::system_info::LpTopology;
+    use crate::cpu::scheduler::system_scheduler::balance::{BALANCE_PERIOD_MILLIS, distance};
+
+    const N_SPINNERS: usize = 3;
+    const TIMEOUT_MILLIS: u128 = 1000;
+
+    logln!("Starting the load balancing self-test...");
+    let topology = |core_id, llc_id, numa_node| LpTopology {
+        core_id,
+        llc_id,
+        numa_node,
+    };
+    let lp = topology(0, 0, 0);
+    let distances = [topology(0, 0, 0), topology(1, 0, 0), topology(2, 1, 0), topology(3, 2, 1)]
+        .map(|other| distance(&lp, &other));
+    assert!(distances.is_sorted() && distances[0] < distances[3]);
+    if get_lp_count() < 3 {
+        logln!("Load balancing self-test: Skipped since there are too few LPs to move threads to.");
+        logln!("Load balancing self-test: Passed.");
+        return;
+    }
+    logln!("Load balancing self-test: Keeping threads on the only LP they may run on...");
+    let n_on_lp = Arc::new(AtomicUsize::new(0));
+    let has_moved = Arc::new(AtomicBool::new(false));
+    let has_strayed = Arc::new(AtomicBool::new(false));
+    let should_stop = Arc::new(AtomicBool::new(false));
+    let spinners: Vec<_> = (0..N_SPINNERS)
+        .map(|_| {
+            let (n_on_lp, has_moved, has_strayed, should_stop) =
+                (n_on_lp.clone(), has_moved.clone(), has_strayed.clone(), should_stop.clone());
+            let spinner = spawn_kernel_thread("self-test-balance-spinner", move || {
+                // the thread may first run on another LP until it next switches
+                while get_lp_id() != 1 {
+                    sleep_for(ExtDuration::from_millis(1));
+                }
+                n_on_lp.fetch_add(1, Ordering::SeqCst);
+                // never yields, so only the balancing LPs move the thread
+                while !should_stop.load(Ordering::SeqCst) {
+                    match get_lp_id() {
+                        1 => {}
+                        2 => has_moved.store(true, Ordering::SeqCst),
+                        _ => has_strayed.store(true, Ordering::SeqCst),
+                    }
+                    core::hint::spin_loop();
+                }
+            });
+            SYSTEM_SCHEDULER
+                .set_thread_affinity(spinner.get_tid(), Some(LpSet::from_lps(&[1]).unwrap()))
+                .expect("Failed to pin a load balancing test thread");
+            spinner
+        })
+        .collect();
+    while n_on_lp.load(Ordering::SeqCst) < N_SPINNERS {
+        sleep_for(ExtDuration::from_millis(1));
+    }
+    // the other LPs are idle and LP1 is overloaded for several balancing periods
+    sleep_for(ExtDuration::from_millis(4 * BALANCE_PERIOD_MILLIS));
+    assert!(!has_moved.load(Ordering::SeqCst) && !has_strayed.load(Ordering::SeqCst));
+    logln!("Load balancing self-test: Spreading threads over the LPs they may run on...");
+    for spinner in spinners.iter() {
+        SYSTEM_SCHEDULER
+            .set_thread_affinity(spinner.get_tid(), Some(LpSet::from_lps(&[1, 2]).unwrap()))
+            .expect("Failed to widen the affinity of a load balancing test thread");
+    }
+    let deadline = get_monotonic_time() + ExtDuration::from_millis(TIMEOUT_MILLIS);
+    while !has_moved.load(Ordering::SeqCst) && get_monotonic_time() < deadline {
+        sleep_for(ExtDuration::from_millis(1));
+    }
+    // give the threads the chance to move to an LP they may not run on
+    sleep_for(ExtDuration::from_millis(2 * BALANCE_PERIOD_MILLIS));
+    should_stop.store(true, Ordering::SeqCst);
+    for spinner in spinners {
+        spinner.join();
+    }
+    assert!(has_moved.load(Ordering::SeqCst) && !has_strayed.load(Ordering::SeqCst));
+    logln!("Load balancing self-test: Passed.");
+}
+
+#[cfg(target_arch = "x86_64")]
+pub fn test_xstate() {
+    use crate::cpu::isa::lp::xstate::{XSTATE_FORMAT, XstateArea, with_simd};
+
+    logln!("Starting the extended processor state self-test...");
+    logln!(
+        "Extended state self-test: Saving components {:#x} with {:?} in {} bytes",
+        (XSTATE_FORMAT.components),
+        (XSTATE_FORMAT.save_instr),
+        (XSTATE_FORMAT.size)
+    );
+    assert!(XSTATE_FORMAT.size >= 512);
+    let mut area = XstateArea::new().expect("Failed to allocate an XSAVE area");
+    let saved: u64 = 0x0123_4567_89ab_cdef;
+    let restored = with_simd(|| {
+        let restored: u64;
+        unsafe {
+            core::arch::asm!("movq xmm0, {}", in(reg) saved, options(nomem, nostack));
+            area.save();
+            core::arch::asm!("movq xmm0, {}", in(reg) !saved, options(nomem, nostack));
+            area.restore();
+            core::arch::asm!("movq {}, xmm0", out(reg) restored, options(nomem, nostack));
+        }
+        restored
+    });
+    assert_eq!(restored, saved);
+    if get_lp_count() < 2 {
+        logln!("Extended state self-test: Skipped switching threads since there is no other LP.");
+        logln!("Extended state self-test: Passed.");
+        return;
+    }
+    logln!("Extended state self-test: Switching between threads that use the registers...");
+    // both threads share an LP so that each one's registers are saved and restored lazily
+    let lp = LpSet::from_lps(&[1]).unwrap();
+    let testers = [0x1111_1111_1111_1111u64, 0x2222_2222_2222_2222].map(|value| {
+        let handle = spawn_kernel_thread("self-test-xstate", move || {
+            let mut is_preserved = true;
+            for _ in 0..4 {
+                // used outside of with_simd so that the first use after each switch raises #NM
+                unsafe {
+                    core::arch::asm!("movq xmm0, {}", in(reg) value, options(nomem, nostack))
+                };
+                sleep_for(ExtDuration::from_millis(1));
+                let read: u64;
+                unsafe {
+                    core::arch::asm!("movq {}, xmm0", out(reg) read, options(nomem, nostack))
+                };
+                is_preserved &= read == value;
+            }
+            is_preserved
+        });
+        SYSTEM_SCHEDULER
+            .set_thread_affinity(handle.get_tid(), Some(lp.clone()))
+            .expect("Failed to pin an extended state test thread");
+        handle
+    });
+    for tester in testers {
+        assert_eq!(tester.join(), Some(true));
+    }
+    logln!("Extended state self-test: Passed.");
+}
+
+#[cfg(target_arch = "x86_64")]
+pub fn test_user_bases() {
+    use crate::cpu::isa::lp::msrs;
+    use crate::cpu::isa::lp::ops::{get_lp_local_base, without_interrupts};
+    use crate::cpu::isa::lp::thread_context::ThreadContext;
+    use crate::cpu::multiprocessor::lp::LogicalProcessor;
+
+    logln!("Starting the LP local area self-test...");
+    let lp_local = get_lp_local_base().into_ptr::<LogicalProcessor>();
+    assert!(!lp_local.is_null());
+    assert_eq!(unsafe { (*lp_local).id }, get_lp_id());
+    logln!("LP local area self-test: Round-tripping user FS and GS bases through a context...");
+    let (fs_base, gs_base) = without_interrupts(|| {
+        let mut orig = ThreadContext::default();
+        let mut ctx = ThreadContext::default();
+        orig.save_user_bases();
+        unsafe {
+            core::arch::asm!("wrfsbase {}", in(reg) 0x1000u64, options(nomem, nostack));
+            msrs::write(msrs::KERNEL_GS_BASE, 0x2000);
+        }
+        ctx.save_user_bases();
+        orig.load_user_bases();
+        ctx.load_user_bases();
+        let fs_base: u64;
+        unsafe {
+            core::arch::asm!("rdfsbase {}", out(reg) fs_base, options(nomem, nostack));
+        }
+        let gs_base = unsafe { msrs::read(msrs::KERNEL_GS_BASE) };
+        orig.load_user_bases();
+        (fs_base, gs_base)
+    });
+    assert_eq!((fs_base, gs_base), (0x1000, 0x2000));
+    logln!("LP local area self-test: Passed.");
+}
+
+pub fn test_mlfq() {
+    logln!("Starting the MLFQ strategy self-test...");
+    let mut mlfq = Mlfq::with_params(3, ExtDuration::from_millis(1), 6);
+    let mut run_queue = BTreeMap::new();
+    run_queue.insert(KERNEL_ASID, alloc::vec![1, 2]);
+    mlfq.thread_added(1, &queued(0, false, None));
+    mlfq.thread_added(2, &queued(0, false, None));
+    assert_eq!(mlfq.next_thread(&mut run_queue), Some(1));
+    logln!("MLFQ self-test: Demoting a thread that used up its time slice...");
+    mlfq.quantum_expired(1);
+    assert_eq!(mlfq.next_thread(&mut run_queue), Some(2));
+    assert_eq!(mlfq.next_thread(&mut run_queue), Some(2));
+    assert_eq!(mlfq.get_quantum(1), ExtDuration::from_millis(2));
+    logln!("MLFQ self-test: Boosting a woken thread...");
+    run_queue.get_mut(&KERNEL_ASID).unwrap().push(3);
+    // base level 2 but queued on level 1 behind thread 1
+    mlfq.thread_added(3, &queued(2, true, None));
+    mlfq.quantum_expired(2);
+    assert_eq!(mlfq.next_thread(&mut run_queue), Some(1));
+    assert_eq!(mlfq.next_thread(&mut run_queue), Some(3));
+    logln!("MLFQ self-test: Aging demoted threads back to their base level...");
+    mlfq.quantum_expired(3);
+    // the sixth decision ages threads 1 and 2 back to level 0 but thread 3 stays at its base level
+    assert_eq!(mlfq.next_thread(&mut run_queue), Some(2));
+    assert_eq!(mlfq.get_quantum(1), ExtDuration::from_millis(1));
+    assert_eq!(mlfq.get_quantum(3), ExtDuration::from_millis(3));
+    mlfq.thread_removed(2);
+    assert_eq!(mlfq.next_thread(&mut run_queue), Some(1));
+    logln!("MLFQ self-test: Passed.");
+}
+
+pub fn test_edf() {
+    logln!("Starting the EDF strategy self-test...");
+    let mut edf = Edf::new(RtPolicy::EarliestDeadlineFirst, Box::new(RoundRobin::new()));
+    let mut run_queue = BTreeMap::new();
+    let short = RtParams::new(
+        ExtDuration::from_millis(100),
+        ExtDuration::from_millis(1),
+        ExtDuration::from_millis(100),
+    )
+    .unwrap();
+    let long = RtParams::new(
+        ExtDuration::from_secs(1),
+        ExtDuration::from_millis(500),
+        ExtDuration::from_secs(1),
+    )
+    .unwrap();
+    assert!(RtParams::new(long.get_period(), long.get_period(), long.get_budget()).is_none());
+    edf.thread_added(1, &queued(0, false, None));
+    edf.thread_added(2, &queued(0, false, Some(short)));
+    edf.thread_added(3, &queued(0, false, Some(long)));
+    assert_eq!(edf.get_reserved_ppm(), 510_000);
+    logln!("EDF self-test: Refusing threads beyond the utilisation bound...");
+    assert!(!edf.can_admit(&long));
+    let half_long =
+        RtParams::new(long.get_period(), ExtDuration::from_millis(250), long.get_period()).unwrap();
+    assert!(edf.can_admit(&half_long));
+    logln!("EDF self-test: Running the thread with the earliest deadline first...");
+    assert_eq!(edf.next_thread(&mut run_queue), Some(2));
+    assert!(edf.get_quantum(2) <= short.get_budget());
+    logln!("EDF self-test: Throttling a thread that used up its budget...");
+    let start = get_monotonic_time();
+    while get_monotonic_time() - start < short.get_budget() {
+        core::hint::spin_loop();
+    }
+    assert_eq!(edf.next_thread(&mut run_queue), Some(3));
+    // thread 2 has to be able to preempt thread 3 once it is released again
+    assert!(edf.get_quantum(3) < short.get_period());
+    logln!("EDF self-test: Keeping a throttled thread throttled when it blocks and wakes...");
+    let period = edf.thread_removed(2);
+    assert!(period.is_some_and(|period| period.remaining == ExtDuration::default()));
+    edf.thread_added(
+        2,
+        &QueuedThread {
+            rt_period: period,
+            ..queued(0, true, Some(short))
+        },
+    );
+    assert_eq!(edf.next_thread(&mut run_queue), Some(3));
+    edf.thread_removed(3);
+    assert_eq!(edf.get_reserved_ppm(), 10_000);
+    logln!("EDF self-test: Running best-effort threads while real-time threads are throttled...");
+    assert_eq!(edf.next_thread(&mut run_queue), Some(1));
+    assert!(edf.get_idle_timeout().is_some_and(|timeout| timeout < short.get_period()));
+    logln!("EDF self-test: Passed.");
+}
+
+pub fn test_lp_set() {
+    logln!("Starting the LP set self-test...");
+    let n_lps = get_lp_count();
+    let all = LpSet::all();
+    assert_eq!(all.len(), n_lps as usize);
+    assert!(LpSet::new().is_empty());
+    let last = LpSet::parse(&alloc::format!("{}", n_lps - 1)).unwrap();
+    assert!(last.contains(n_lps - 1) && last.len() == 1);
+    assert!(all.intersects(&last));
+    assert_eq!(LpSet::parse(&alloc::format!("0-{}", n_lps - 1)).unwrap(), all);
+    assert!(LpSet::parse(&alloc::format!("{}", n_lps)).is_err());
+    assert!(LpSet::parse("1-0").is_err());
+    let mut set = all;
+    set.remove(0);
+    assert!(!set.contains(0) && set.iter().eq(1..n_lps));
+    logln!("LP set self-test: Passed.");
+}
+
+pub fn test_gang_scheduling() {
+    logln!("Starting the gang scheduling self-test...");
+    // address spaces that do not exist are never switched to by this test
+    let (other_asid, gang_asid) = (KERNEL_ASID + 1, KERNEL_ASID + 2);
+    let mut gang = GangScheduling::new(Box::new(RoundRobin::new()));
+    let mut run_queue = BTreeMap::new();
+    run_queue.insert(other_asid, alloc::vec![1]);
+    run_queue.insert(gang_asid, alloc::vec![2, 3]);
+    for tid in 1..=3 {
+        gang.thread_added(tid, &queued(0, false, None));
+    }
+    assert!(SYSTEM_SCHEDULER.get_gang_window(get_monotonic_time()).is_none());
+    SYSTEM_SCHEDULER
+        .register_gang(gang_asid, LpSet::from_lps(&[get_lp_id()]).unwrap())
+        .expect("Error registering the gang");
+    assert!(matches!(
+        SYSTEM_SCHEDULER.register_gang(gang_asid, LpSet::all()),
+        Err(crate::cpu::scheduler::system_scheduler::Error::GangExists)
+    ));
+    logln!("Gang scheduling self-test: Waiting for the gang's window...");
+    let window = loop {
+        let window = SYSTEM_SCHEDULER.get_gang_window(get_monotonic_time()).unwrap();
+        if window.asid == Some(gang_asid) {
+            break window;
+        }
+        core::hint::spin_loop();
+    };
+    logln!("Gang scheduling self-test: Preferring the gang's threads during its window...");
+    assert_eq!(gang.next_thread(&mut run_queue), Some(2));
+    assert_eq!(gang.next_thread(&mut run_queue), Some(3));
+    assert_eq!(gang.get_curr_as(), gang_asid);
+    // every LP of the gang has to switch away at the end of the window
+    assert!(gang.get_quantum(3) <= window.end.saturating_sub(get_monotonic_time()));
+    logln!("Gang scheduling self-test: Running other threads outside of the window...");
+    while get_monotonic_time() < window.end {
+        core::hint::spin_loop();
+    }
+    assert_eq!(gang.next_thread(&mut run_queue), Some(1));
+    SYSTEM_SCHEDULER.unregister_gang(gang_asid);
+    assert!(SYSTEM_SCHEDULER.get_gang_window(get_monotonic_time()).is_none());
+    logln!("Gang scheduling self-test: Passed.");
+}
+
+fn queued(priority: Priority, is_woken: bool, rt_params: Option<RtParams>) -> QueuedThread {
+    QueuedThread {
+        asid: KERNEL_ASID,
+        priority,
+        is_woken,
+        rt_params,
+        rt_period: None,
+    }
+}
+
+extern "C" fn test_thread_entry() -> ! {
+    TEST_THREAD_RAN.store(true, Ordering::Release);
+    let tid = TEST_THREAD_ID.load(Ordering::Acquire);
+    SYSTEM_SCHEDULER.terminate_threads(alloc::vec![tid]);
+    // the LP switches away as soon as the terminate IPI arrives
+    halt!()
+}
+
+pub fn test_idle() {
+    logln!("Starting the idle LP self-test...");
+    logln!("Idle self-test: Selecting C-states...");
+    let cstate = |acpi_type: u8, exit_latency_micros: u128| CState {
+        acpi_type,
+        exit_latency: ExtDuration::from_micros(exit_latency_micros),
+        hint: Some(((acpi_type as u32) - 1) << 4),
+    };
+    let cstates = [cstate(1, 1), cstate(2, 50), cstate(3, 200)];
+    let select = |idle_for: Option<ExtDuration>, is_timer_always_running: bool| {
+        idle::select_cstate(&cstates, idle_for, is_timer_always_running)
+            .map(|cstate| cstate.acpi_type)
+    };
+    assert_eq!(select(None, true), Some(3));
+    assert_eq!(select(Some(ExtDuration::from_micros(200)), true), Some(2));
+    assert_eq!(select(Some(ExtDuration::from_micros(1)), true), None);
+    // deeper states may stop the timer the LP relies on to wake up
+    assert_eq!(select(None, false), Some(1));
+    assert!(idle::get_cstates().is_sorted_by_key(|cstate| cstate.exit_latency));
+    if get_lp_count() < 2 {
+        logln!(
+            "Idle self-test: Skipped waking idle LPs since there is no other LP to run a thread \
+             on."
+        );
+        logln!("Idle self-test: Passed.");
+        return;
+    }
+    logln!("Idle self-test: Waking idle LPs...");
+    let flag_wakes = || (0..get_lp_count()).map(idle::get_flag_wake_count).sum::<u64>();
+    let initial_flag_wakes = flag_wakes();
+    for i in 0..8u64 {
+        // give the other LPs time to go idle again
+        sleep_for(ExtDuration::from_millis(1));
+        assert_eq!(spawn_kernel_thread("self-test-idle", move || i).join(), Some(i));
+    }
+    let n_flag_wakes = flag_wakes() - initial_flag_wakes;
+    logln!("Idle self-test: {} wakeups went through wake flags instead of IPIs.", n_flag_wakes);
+    // idle LPs that can wait for a memory write always wait on their wake flags
+    #[cfg(target_arch = "x86_64")]
+    {
+        use crate::cpu::isa::interface::system_info::CpuInfoIfce;
+        use crate::cpu::isa::system_info::{CpuInfo, IsaExtension};
+
+        if CpuInfo::is_extension_supported(IsaExtension::MonitorMwait) {
+            assert!(n_flag_wakes > 0);
+        }
+    }
+    logln!("Idle self-test: Passed.");
+}
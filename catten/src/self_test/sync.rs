@@ -0,0 +1,162 @@
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use crate::cpu::isa::interface::memory::address::VirtualAddress;
+use crate::cpu::isa::lp::ops::without_interrupts;
+use crate::cpu::multiprocessor::get_lp_count;
+use crate::cpu::scheduler::sync::condvar::Condvar;
+use crate::cpu::scheduler::sync::lockdep::{self, LockClass};
+use crate::cpu::scheduler::sync::mutex::Mutex;
+use crate::cpu::scheduler::sync::rwlock::RwLock;
+use crate::cpu::scheduler::sync::semaphore::Semaphore;
+use crate::cpu::scheduler::sync::wait_queue::WaitQueue;
+use crate::cpu::scheduler::system_scheduler::SYSTEM_SCHEDULER;
+use crate::cpu::scheduler::threads::spawn::spawn_kernel_thread;
+use crate::cpu::scheduler::threads::{DEFAULT_PRIORITY, MASTER_THREAD_TABLE, Priority, Thread};
+use crate::logln;
+use crate::memory::{KERNEL_ASID, VAddr};
+
+const TEST_THREAD_COUNT: usize = 4;
+const TEST_ITERATIONS: u64 = 100;
+
+static TEST_COUNTER: Mutex<u64> = Mutex::new(0);
+static TEST_TABLE: RwLock<u64> = RwLock::new(0);
+static TEST_DONE: Semaphore = Semaphore::new(0);
+static TEST_READY: Mutex<bool> = Mutex::new(false);
+static TEST_READY_CONDVAR: Condvar = Condvar::new();
+static TEST_GATE_OPEN: AtomicBool = AtomicBool::new(false);
+static TEST_GATE: WaitQueue = WaitQueue::new();
+static TEST_CHAIN_OUTER: Mutex<()> = Mutex::new(());
+static TEST_CHAIN_INNER: Mutex<()> = Mutex::new(());
+/// 1 once the low priority thread holds the inner mutex and 2 once the middle one holds the outer
+static TEST_CHAIN_STAGE: AtomicUsize = AtomicUsize::new(0);
+static TEST_CHAIN_RELEASE: AtomicBool = AtomicBool::new(false);
+const TEST_LOW_PRIORITY: Priority = 7;
+const TEST_MID_PRIORITY: Priority = 5;
+static TEST_LOCK_A: lockdep::Mutex<()> = lockdep::Mutex::new(());
+static TEST_LOCK_B: lockdep::RwLock<()> =
+    lockdep::RwLock::with_class((), LockClass::named("self-test lock B"));
+
+pub fn test_priority_lending() {
+    logln!("Starting the priority inheritance self-test...");
+    let mut thread =
+        Thread::new(false, KERNEL_ASID, VAddr::from_ptr(test_priority_lending as *const ()));
+    thread.base_priority = 5;
+    assert!(thread.lend_priority(1, Some(2)));
+    assert!(!thread.lend_priority(2, Some(3)));
+    assert_eq!(thread.get_effective_priority(), 2);
+    assert!(thread.lend_priority(1, None));
+    assert_eq!(thread.get_effective_priority(), 3);
+    // less important priorities are never lent in effect
+    assert!(thread.lend_priority(2, Some(7)));
+    assert_eq!(thread.get_effective_priority(), 5);
+    unsafe { thread.context.deallocate_stacks() }.expect("Failed to free the test thread's stacks");
+    logln!("Priority inheritance self-test: Passed.");
+}
+
+pub fn test_blocking_sync() {
+    logln!("Starting the blocking synchronization self-test...");
+    if get_lp_count() < 2 {
+        logln!("Blocking sync self-test: Skipped since there is no other LP to run a thread on.");
+        return;
+    }
+    logln!("Blocking sync self-test: Contending for a mutex, an rwlock and a semaphore...");
+    let handles: Vec<_> = (0..TEST_THREAD_COUNT)
+        .map(|_| {
+            spawn_kernel_thread("self-test-sync", || {
+                TEST_GATE.wait_until(|| TEST_GATE_OPEN.load(Ordering::Acquire));
+                for _ in 0..TEST_ITERATIONS {
+                    *TEST_COUNTER.lock() += 1;
+                    *TEST_TABLE.write() += 1;
+                    let _ = *TEST_TABLE.read();
+                }
+                TEST_DONE.release();
+            })
+        })
+        .collect();
+    TEST_GATE_OPEN.store(true, Ordering::Release);
+    TEST_GATE.wake_all();
+    for _ in 0..TEST_THREAD_COUNT {
+        TEST_DONE.acquire();
+    }
+    assert_eq!(*TEST_COUNTER.lock(), TEST_THREAD_COUNT as u64 * TEST_ITERATIONS);
+    assert_eq!(*TEST_TABLE.read(), TEST_THREAD_COUNT as u64 * TEST_ITERATIONS);
+    assert_eq!(TEST_DONE.available_permits(), 0);
+    for handle in handles {
+        assert_eq!(handle.join(), Some(()));
+    }
+    logln!("Blocking sync self-test: Waiting on a condition variable...");
+    let waiter = spawn_kernel_thread("self-test-condvar", || {
+        let ready = TEST_READY_CONDVAR.wait_while(TEST_READY.lock(), |is_ready| !*is_ready);
+        *ready
+    });
+    *TEST_READY.lock() = true;
+    TEST_READY_CONDVAR.notify_all();
+    assert_eq!(waiter.join(), Some(true));
+    logln!("Blocking sync self-test: Passing a priority along a chain of mutexes...");
+    let low = spawn_kernel_thread("self-test-pi-low", || {
+        set_own_base_priority(TEST_LOW_PRIORITY);
+        let _inner = TEST_CHAIN_INNER.lock();
+        TEST_CHAIN_STAGE.store(1, Ordering::Release);
+        while !TEST_CHAIN_RELEASE.load(Ordering::Acquire) {
+            core::hint::spin_loop();
+        }
+    });
+    while TEST_CHAIN_STAGE.load(Ordering::Acquire) < 1 {
+        core::hint::spin_loop();
+    }
+    let mid = spawn_kernel_thread("self-test-pi-mid", || {
+        set_own_base_priority(TEST_MID_PRIORITY);
+        let _outer = TEST_CHAIN_OUTER.lock();
+        TEST_CHAIN_STAGE.store(2, Ordering::Release);
+        let _inner = TEST_CHAIN_INNER.lock();
+    });
+    // the middle thread must be parked on the inner mutex before the high priority thread comes
+    // along, so that its priority only reaches the low priority thread by being passed on
+    while SYSTEM_SCHEDULER.get_effective_priority(low.get_tid()) != Some(TEST_MID_PRIORITY) {
+        core::hint::spin_loop();
+    }
+    assert_eq!(TEST_CHAIN_STAGE.load(Ordering::Acquire), 2);
+    let high = spawn_kernel_thread("self-test-pi-high", || {
+        let _outer = TEST_CHAIN_OUTER.lock();
+    });
+    while SYSTEM_SCHEDULER.get_effective_priority(low.get_tid()) != Some(DEFAULT_PRIORITY) {
+        core::hint::spin_loop();
+    }
+    TEST_CHAIN_RELEASE.store(true, Ordering::Release);
+    for handle in [low, mid, high] {
+        assert_eq!(handle.join(), Some(()));
+    }
+    logln!("Blocking sync self-test: Passed.");
+}
+
+fn set_own_base_priority(priority: Priority) {
+    let tid = SYSTEM_SCHEDULER.get_current_tid().expect("Self-test threads run on a thread");
+    let thread = MASTER_THREAD_TABLE.get(tid).unwrap();
+    without_interrupts(|| thread.write().base_priority = priority);
+}
+
+pub fn test_lockdep() {
+    logln!("Starting the lock dependency validator self-test...");
+    let report_count = lockdep::get_report_count();
+    {
+        let _a = TEST_LOCK_A.lock();
+        let _b = TEST_LOCK_B.read();
+        // nested reads do not block each other
+        let _b_again = TEST_LOCK_B.read();
+        assert!(TEST_LOCK_A.try_lock().is_none());
+    }
+    assert_eq!(lockdep::get_report_count(), report_count);
+    {
+        // taking the locks in the inverted order can deadlock against another LP
+        let _b = TEST_LOCK_B.write();
+        let _a = TEST_LOCK_A.lock();
+    }
+    let expected_count = if cfg!(feature = "lockdep") {
+        report_count + 1
+    } else {
+        report_count
+    };
+    assert_eq!(lockdep::get_report_count(), expected_count);
+    logln!("Lock dependency validator self-test: Passed.");
+}